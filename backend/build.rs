@@ -0,0 +1,50 @@
+//! Embeds the frontend's static export when `SOLACE_UI_EXPORT` names its
+//! directory, e.g. `SOLACE_UI_EXPORT=../frontent/out cargo build --release`,
+//! so the binary serves the UI itself. Without it nothing is embedded.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SOLACE_UI_EXPORT");
+
+    let mut files = Vec::new();
+    if let Ok(dir) = env::var("SOLACE_UI_EXPORT") {
+        let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+        let export = manifest_dir
+            .join(dir)
+            .canonicalize()
+            .unwrap_or_else(|e| panic!("SOLACE_UI_EXPORT is not a directory: {}", e));
+        println!("cargo:rerun-if-changed={}", export.display());
+        collect(&export, &export, &mut files);
+        files.sort();
+    }
+
+    let mut code = String::from("pub static EMBEDDED: &[(&str, &[u8])] = &[\n");
+    for (name, path) in files {
+        code.push_str(&format!("    ({:?}, include_bytes!({:?})),\n", name, path));
+    }
+    code.push_str("];\n");
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("ui_assets.rs");
+    fs::write(out, code).unwrap();
+}
+
+/// Files under `dir` as (path relative to `root` with `/` separators, absolute path)
+fn collect(root: &Path, dir: &Path, files: &mut Vec<(String, PathBuf)>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect(root, &path, files);
+            continue;
+        }
+        let name = path
+            .strip_prefix(root)
+            .unwrap()
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.push((name, path));
+    }
+}
@@ -0,0 +1,421 @@
+use crate::engine::ResourceType;
+use crate::sites::host_matches;
+
+/// Outcome of parsing a single filter list line
+pub enum ParsedLine {
+    Filter(NetworkFilter),
+    /// Comments, headers and blank lines
+    Ignored,
+    /// Cosmetic, scriptlet or regex rules we do not implement
+    Unsupported,
+}
+
+/// How the start of a pattern is anchored in the URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    None,
+    /// `|` - the pattern must match at the start of the URL
+    Start,
+    /// `||` - the pattern must match at the start of the host or one of its subdomains
+    Hostname,
+}
+
+/// A compiled EasyList / uBlock Origin network filter
+#[derive(Debug, Clone)]
+pub struct NetworkFilter {
+    /// The line the filter was parsed from, reported with blocked requests
+    pub raw: String,
+    pub exception: bool,
+    pub important: bool,
+    pub anchor: Anchor,
+    pub end_anchor: bool,
+    pub pattern: String,
+    pub match_case: bool,
+    /// `Some(true)` for `$third-party`, `Some(false)` for `$~third-party`
+    pub third_party: Option<bool>,
+    /// Resource types the filter applies to; empty means all
+    pub include_types: Vec<ResourceType>,
+    pub exclude_types: Vec<ResourceType>,
+    /// First-party domains from `$domain=`
+    pub include_domains: Vec<String>,
+    pub exclude_domains: Vec<String>,
+}
+
+/// Details of a request needed to evaluate filters against it
+pub struct RequestContext<'a> {
+    pub url: &'a str,
+    pub url_lower: &'a str,
+    pub host: &'a str,
+    pub first_party_host: Option<&'a str>,
+    pub resource_type: ResourceType,
+    pub third_party: bool,
+}
+
+pub fn parse_line(line: &str) -> ParsedLine {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+        return ParsedLine::Ignored;
+    }
+
+    // Cosmetic and scriptlet filters
+    if line.contains("##") || line.contains("#@#") || line.contains("#?#") || line.contains("#$#") {
+        return ParsedLine::Unsupported;
+    }
+
+    // Hosts file entries such as `0.0.0.0 ads.example.com`
+    if let Some(host) = parse_hosts_entry(line) {
+        return match NetworkFilter::parse(&format!("||{}^", host)) {
+            Some(filter) => ParsedLine::Filter(filter),
+            None => ParsedLine::Unsupported,
+        };
+    }
+
+    match NetworkFilter::parse(line) {
+        Some(filter) => ParsedLine::Filter(filter),
+        None => ParsedLine::Unsupported,
+    }
+}
+
+fn parse_hosts_entry(line: &str) -> Option<&str> {
+    let mut parts = line.split_whitespace();
+    let address = parts.next()?;
+    if !matches!(address, "0.0.0.0" | "127.0.0.1" | "::" | "::1") {
+        return None;
+    }
+    let host = parts.next()?;
+    if host == "localhost" || host == "0.0.0.0" || host.starts_with('#') {
+        return None;
+    }
+    Some(host)
+}
+
+impl NetworkFilter {
+    pub fn parse(line: &str) -> Option<Self> {
+        let raw = line.to_string();
+        let (exception, rest) = match line.strip_prefix("@@") {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+
+        // Regex filters are not supported by the token matcher
+        if rest.starts_with('/') && rest.len() > 1 && rest.ends_with('/') {
+            return None;
+        }
+
+        let (pattern, options) = match rest.rfind('$') {
+            Some(index) if !rest[index + 1..].contains('/') => (&rest[..index], Some(&rest[index + 1..])),
+            _ => (rest, None),
+        };
+
+        let mut filter = Self {
+            raw,
+            exception,
+            important: false,
+            anchor: Anchor::None,
+            end_anchor: false,
+            pattern: String::new(),
+            match_case: false,
+            third_party: None,
+            include_types: Vec::new(),
+            exclude_types: Vec::new(),
+            include_domains: Vec::new(),
+            exclude_domains: Vec::new(),
+        };
+
+        if let Some(options) = options {
+            for option in options.split(',') {
+                filter.apply_option(option.trim())?;
+            }
+        }
+
+        let mut pattern = pattern;
+        if let Some(stripped) = pattern.strip_prefix("||") {
+            filter.anchor = Anchor::Hostname;
+            pattern = stripped;
+        } else if let Some(stripped) = pattern.strip_prefix('|') {
+            filter.anchor = Anchor::Start;
+            pattern = stripped;
+        }
+        if let Some(stripped) = pattern.strip_suffix('|') {
+            filter.end_anchor = true;
+            pattern = stripped;
+        }
+
+        // Collapse redundant wildcards at the edges
+        let pattern = pattern.trim_start_matches('*');
+        if pattern.len() != pattern.trim_end_matches('*').len() {
+            filter.end_anchor = false;
+        }
+        let pattern = pattern.trim_end_matches('*');
+
+        filter.pattern = if filter.match_case {
+            pattern.to_string()
+        } else {
+            pattern.to_ascii_lowercase()
+        };
+        Some(filter)
+    }
+
+    /// Apply a `$` option, returning `None` for options we cannot honour so
+    /// the whole filter is dropped rather than applied too broadly
+    fn apply_option(&mut self, option: &str) -> Option<()> {
+        let (negated, name) = match option.strip_prefix('~') {
+            Some(name) => (true, name),
+            None => (false, option),
+        };
+
+        if let Some(domains) = name.strip_prefix("domain=").or_else(|| name.strip_prefix("from=")) {
+            for domain in domains.split('|') {
+                match domain.strip_prefix('~') {
+                    Some(domain) => self.exclude_domains.push(domain.to_ascii_lowercase()),
+                    None => self.include_domains.push(domain.to_ascii_lowercase()),
+                }
+            }
+            return Some(());
+        }
+
+        match name {
+            "third-party" | "3p" => self.third_party = Some(!negated),
+            "first-party" | "1p" => self.third_party = Some(negated),
+            "important" => self.important = true,
+            "match-case" => self.match_case = true,
+            _ => {
+                let resource_type = parse_resource_type(name)?;
+                if negated {
+                    self.exclude_types.push(resource_type);
+                } else {
+                    self.include_types.push(resource_type);
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Check the request-level options; the pattern is checked separately
+    pub fn options_match(&self, request: &RequestContext) -> bool {
+        if let Some(third_party) = self.third_party {
+            if third_party != request.third_party {
+                return false;
+            }
+        }
+
+        // Top-level documents are only blocked by filters that ask for them
+        if request.resource_type == ResourceType::Document
+            && !self.include_types.contains(&ResourceType::Document)
+        {
+            return false;
+        }
+        if !self.include_types.is_empty() && !self.include_types.contains(&request.resource_type) {
+            return false;
+        }
+        if self.exclude_types.contains(&request.resource_type) {
+            return false;
+        }
+
+        if !self.include_domains.is_empty() || !self.exclude_domains.is_empty() {
+            let Some(first_party) = request.first_party_host else {
+                return self.include_domains.is_empty();
+            };
+            if self.exclude_domains.iter().any(|d| host_matches(first_party, d)) {
+                return false;
+            }
+            if !self.include_domains.is_empty()
+                && !self.include_domains.iter().any(|d| host_matches(first_party, d))
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn pattern_matches(&self, request: &RequestContext) -> bool {
+        let url = if self.match_case { request.url } else { request.url_lower };
+        let url = url.as_bytes();
+        let pattern = self.pattern.as_bytes();
+
+        match self.anchor {
+            Anchor::Start => glob_match(pattern, url, self.end_anchor),
+            Anchor::Hostname => {
+                // Try the host itself and every subdomain boundary within it
+                let Some(host_start) = find_host_start(url) else {
+                    return false;
+                };
+                let host_end = host_start + request.host.len();
+                (host_start..host_end)
+                    .filter(|&i| i == host_start || url[i - 1] == b'.')
+                    .any(|i| glob_match(pattern, &url[i..], self.end_anchor))
+            }
+            Anchor::None => (0..url.len().max(1))
+                .any(|i| glob_match(pattern, &url[i..], self.end_anchor)),
+        }
+    }
+}
+
+fn parse_resource_type(name: &str) -> Option<ResourceType> {
+    Some(match name {
+        "document" | "doc" => ResourceType::Document,
+        "subdocument" | "frame" => ResourceType::Subdocument,
+        "stylesheet" | "css" => ResourceType::Stylesheet,
+        "script" => ResourceType::Script,
+        "image" => ResourceType::Image,
+        "font" => ResourceType::Font,
+        "media" => ResourceType::Media,
+        "xmlhttprequest" | "xhr" => ResourceType::Xhr,
+        "websocket" => ResourceType::WebSocket,
+        "ping" | "beacon" => ResourceType::Ping,
+        "other" => ResourceType::Other,
+        _ => return None,
+    })
+}
+
+fn find_host_start(url: &[u8]) -> Option<usize> {
+    let scheme_end = url.windows(3).position(|w| w == b"://")? + 3;
+    // Skip credentials if present
+    let authority_end = url[scheme_end..]
+        .iter()
+        .position(|&c| c == b'/' || c == b'?' || c == b'#')
+        .map(|i| scheme_end + i)
+        .unwrap_or(url.len());
+    match url[scheme_end..authority_end].iter().rposition(|&c| c == b'@') {
+        Some(at) => Some(scheme_end + at + 1),
+        None => Some(scheme_end),
+    }
+}
+
+/// The `^` placeholder matches anything but a letter, digit or one of `_-.%`
+fn is_separator(c: u8) -> bool {
+    !(c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'.' | b'%'))
+}
+
+/// Match `pattern` against the start of `text`, supporting `*` and `^`.
+///
+/// Without `end_anchor` the pattern only has to match a prefix of `text`.
+fn glob_match(pattern: &[u8], text: &[u8], end_anchor: bool) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    loop {
+        if p == pattern.len() && (!end_anchor || t == text.len()) {
+            return true;
+        }
+
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                b'^' if t == text.len() => {
+                    // The end of the URL also counts as a separator
+                    p += 1;
+                    continue;
+                }
+                b'^' if is_separator(text[t]) => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                c if t < text.len() && c != b'^' && c == text[t] => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        // Let the last `*` swallow one more character and retry
+        match backtrack {
+            Some((star_p, star_t)) if star_t < text.len() => {
+                backtrack = Some((star_p, star_t + 1));
+                p = star_p + 1;
+                t = star_t + 1;
+            }
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str) -> RequestContext<'_> {
+        let start = find_host_start(url.as_bytes()).unwrap();
+        let end = url[start..].find(['/', '?', '#']).map_or(url.len(), |i| start + i);
+        RequestContext {
+            url,
+            url_lower: url,
+            host: &url[start..end],
+            first_party_host: None,
+            resource_type: ResourceType::Script,
+            third_party: true,
+        }
+    }
+
+    fn matches(filter: &str, url: &str) -> bool {
+        NetworkFilter::parse(filter).unwrap().pattern_matches(&request(url))
+    }
+
+    #[test]
+    fn glob_wildcards_and_separators() {
+        assert!(glob_match(b"ads/*.js", b"ads/banner.js", true));
+        assert!(!glob_match(b"ads/*.js", b"ads/banner.css", true));
+        assert!(glob_match(b"ads/", b"ads/banner.js", false));
+        assert!(glob_match(b"example.com^", b"example.com/path", false));
+        assert!(glob_match(b"example.com^", b"example.com", true));
+        assert!(!glob_match(b"example.com^", b"example.com.evil", false));
+    }
+
+    #[test]
+    fn glob_separator_at_end_of_text() {
+        // A `^` in the middle of the pattern used to index past the text
+        assert!(!glob_match(b"example.com^foo", b"example.com", false));
+        assert!(!glob_match(b"ad^x", b"ad", false));
+        assert!(glob_match(b"ad^*", b"ad", true));
+        assert!(glob_match(b"ad^^", b"ad", true));
+        assert!(!glob_match(b"*^x", b"", false));
+    }
+
+    #[test]
+    fn pattern_anchors() {
+        assert!(matches("||example.com^", "https://example.com/ad.js"));
+        assert!(matches("||example.com^", "https://cdn.example.com/ad.js"));
+        assert!(!matches("||example.com^", "https://notexample.com/ad.js"));
+        assert!(!matches("||example.com^", "https://example.com.evil.net/"));
+        assert!(matches("|https://ads.", "https://ads.example.com/"));
+        assert!(!matches("|https://ads.", "http://x.com/?https://ads."));
+        assert!(matches("banner.gif|", "https://x.com/banner.gif"));
+        assert!(!matches("banner.gif|", "https://x.com/banner.gif?x"));
+        assert!(matches("/ads/*/track", "https://x.com/ads/1/track"));
+    }
+
+    #[test]
+    fn pattern_separator_in_middle_does_not_panic() {
+        assert!(matches("||example.com^foo", "https://example.com/foo"));
+        assert!(!matches("||example.com^foo", "https://example.com"));
+        assert!(!matches("ad^x", "https://x.com/ad"));
+        assert!(matches("ad^x", "https://x.com/ad/x"));
+    }
+
+    #[test]
+    fn parse_options_and_hosts_entries() {
+        let filter = NetworkFilter::parse("@@||example.com^$script,~third-party,domain=a.com|~b.a.com").unwrap();
+        assert!(filter.exception);
+        assert_eq!(filter.anchor, Anchor::Hostname);
+        assert_eq!(filter.pattern, "example.com^");
+        assert_eq!(filter.include_types, vec![ResourceType::Script]);
+        assert_eq!(filter.third_party, Some(false));
+        assert_eq!(filter.include_domains, vec!["a.com"]);
+        assert_eq!(filter.exclude_domains, vec!["b.a.com"]);
+        assert!(NetworkFilter::parse("||example.com^$unknown-option").is_none());
+        assert!(matches!(parse_line("! comment"), ParsedLine::Ignored));
+        assert!(matches!(parse_line("example.com##.ad"), ParsedLine::Unsupported));
+        match parse_line("0.0.0.0 ads.example.com") {
+            ParsedLine::Filter(filter) => assert_eq!(filter.pattern, "ads.example.com^"),
+            _ => panic!("hosts entry not parsed"),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use super::filter::{parse_line, NetworkFilter, ParsedLine, RequestContext};
+use std::collections::HashMap;
+
+/// Filters shorter than this have no usable token and are always checked
+const MIN_TOKEN_LEN: usize = 3;
+
+/// Outcome of matching a request against the filter set
+pub enum MatchResult<'a> {
+    Block(&'a NetworkFilter),
+    Allow,
+    NoMatch,
+}
+
+/// Counts reported after compiling filter lists
+#[derive(Debug, Clone, Default)]
+pub struct CompileStats {
+    pub filters: usize,
+    pub exceptions: usize,
+    pub unsupported: usize,
+}
+
+/// A compiled set of network filters indexed by token.
+///
+/// Each filter is filed under the rarest token of its pattern, so a request
+/// only has to be checked against the filters whose token appears in its URL
+/// plus the small set of filters that have no token at all.
+#[derive(Default)]
+pub struct FilterSet {
+    blocking: TokenIndex,
+    exceptions: TokenIndex,
+    stats: CompileStats,
+}
+
+#[derive(Default)]
+struct TokenIndex {
+    buckets: HashMap<u64, Vec<NetworkFilter>>,
+    untokenized: Vec<NetworkFilter>,
+}
+
+impl FilterSet {
+    pub fn compile<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut blocking = Vec::new();
+        let mut exceptions = Vec::new();
+        let mut stats = CompileStats::default();
+
+        for line in lines {
+            match parse_line(line) {
+                ParsedLine::Filter(filter) if filter.exception => exceptions.push(filter),
+                ParsedLine::Filter(filter) => blocking.push(filter),
+                ParsedLine::Ignored => {}
+                ParsedLine::Unsupported => stats.unsupported += 1,
+            }
+        }
+
+        stats.filters = blocking.len();
+        stats.exceptions = exceptions.len();
+
+        Self {
+            blocking: TokenIndex::build(blocking),
+            exceptions: TokenIndex::build(exceptions),
+            stats,
+        }
+    }
+
+    pub fn stats(&self) -> &CompileStats {
+        &self.stats
+    }
+
+    pub fn check(&self, request: &RequestContext) -> MatchResult<'_> {
+        let tokens = url_tokens(request.url_lower);
+
+        let Some(block) = self.blocking.find(request, &tokens) else {
+            return MatchResult::NoMatch;
+        };
+
+        // `$important` filters cannot be overridden by exceptions
+        if !block.important && self.exceptions.find(request, &tokens).is_some() {
+            return MatchResult::Allow;
+        }
+
+        MatchResult::Block(block)
+    }
+}
+
+impl TokenIndex {
+    fn build(filters: Vec<NetworkFilter>) -> Self {
+        // Count how often each token occurs so filters land in the rarest bucket
+        let mut frequency: HashMap<u64, usize> = HashMap::new();
+        let filter_tokens: Vec<Vec<u64>> = filters.iter().map(filter_tokens).collect();
+        for tokens in &filter_tokens {
+            for token in tokens {
+                *frequency.entry(*token).or_default() += 1;
+            }
+        }
+
+        let mut index = Self::default();
+        for (filter, tokens) in filters.into_iter().zip(filter_tokens) {
+            match tokens.iter().min_by_key(|token| frequency[token]) {
+                Some(token) => index.buckets.entry(*token).or_default().push(filter),
+                None => index.untokenized.push(filter),
+            }
+        }
+        index
+    }
+
+    fn find(&self, request: &RequestContext, tokens: &[u64]) -> Option<&NetworkFilter> {
+        let mut found = None;
+        let candidates = tokens
+            .iter()
+            .filter_map(|token| self.buckets.get(token))
+            .flatten()
+            .chain(self.untokenized.iter());
+
+        for filter in candidates {
+            if filter.options_match(request) && filter.pattern_matches(request) {
+                if filter.important {
+                    return Some(filter);
+                }
+                found.get_or_insert(filter);
+            }
+        }
+        found
+    }
+}
+
+fn hash_token(token: &[u8]) -> u64 {
+    // FNV-1a; tokens are short and the index is rebuilt on every load
+    token.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn is_token_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'%'
+}
+
+fn url_tokens(url: &str) -> Vec<u64> {
+    let mut tokens: Vec<u64> = url
+        .as_bytes()
+        .split(|&c| !is_token_char(c))
+        .filter(|token| token.len() >= MIN_TOKEN_LEN)
+        .map(hash_token)
+        .collect();
+    tokens.sort_unstable();
+    tokens.dedup();
+    tokens
+}
+
+/// Tokens of a pattern that are guaranteed to appear whole in any matching URL
+fn filter_tokens(filter: &NetworkFilter) -> Vec<u64> {
+    let pattern = filter.pattern.to_ascii_lowercase();
+    let bytes = pattern.as_bytes();
+    let mut tokens = Vec::new();
+    let mut start = 0;
+
+    for end in 0..=bytes.len() {
+        if end < bytes.len() && is_token_char(bytes[end]) {
+            continue;
+        }
+
+        // A token touching a wildcard or an unanchored edge may be a fragment
+        let bounded_left = (start > 0 && bytes[start - 1] != b'*')
+            || (start == 0 && filter.anchor != super::filter::Anchor::None);
+        let bounded_right =
+            (end < bytes.len() && bytes[end] != b'*') || (end == bytes.len() && filter.end_anchor);
+
+        if end - start >= MIN_TOKEN_LEN && bounded_left && bounded_right {
+            tokens.push(hash_token(&bytes[start..end]));
+        }
+        start = end + 1;
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ResourceType;
+
+    fn check(set: &FilterSet, url: &str) -> Option<String> {
+        let request = RequestContext {
+            url,
+            url_lower: url,
+            host: "ads.example.com",
+            first_party_host: Some("news.example.org"),
+            resource_type: ResourceType::Script,
+            third_party: true,
+        };
+        match set.check(&request) {
+            MatchResult::Block(filter) => Some(filter.raw.clone()),
+            MatchResult::Allow => Some("allow".to_string()),
+            MatchResult::NoMatch => None,
+        }
+    }
+
+    #[test]
+    fn exceptions_and_important() {
+        let set = FilterSet::compile([
+            "! Title: test",
+            "||ads.example.com^",
+            "@@||ads.example.com/ok.js",
+            "||ads.example.com/pixel.js$important",
+            "@@||ads.example.com/pixel.js",
+            "example.com##.banner",
+        ]);
+        assert_eq!(set.stats().filters, 2);
+        assert_eq!(set.stats().exceptions, 2);
+        assert_eq!(set.stats().unsupported, 1);
+        assert_eq!(check(&set, "https://ads.example.com/ad.js").as_deref(), Some("||ads.example.com^"));
+        assert_eq!(check(&set, "https://ads.example.com/ok.js").as_deref(), Some("allow"));
+        assert_eq!(
+            check(&set, "https://ads.example.com/pixel.js").as_deref(),
+            Some("||ads.example.com/pixel.js$important")
+        );
+        assert_eq!(check(&set, "https://cdn.example.net/app.js"), None);
+    }
+
+    #[test]
+    fn tokens_touching_wildcards_are_not_indexed() {
+        let filter = NetworkFilter::parse("banner*ads.js").unwrap();
+        assert!(filter_tokens(&filter).is_empty());
+        let filter = NetworkFilter::parse("||tracker.net/collect^").unwrap();
+        assert_eq!(filter_tokens(&filter).len(), 3);
+        let set = FilterSet::compile(["ads"]);
+        assert!(check(&set, "https://x.com/leads.js").is_some());
+    }
+}
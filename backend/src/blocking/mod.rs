@@ -0,0 +1,163 @@
+mod filter;
+mod matcher;
+
+pub use matcher::CompileStats;
+
+use crate::engine::{InterceptedRequest, RequestAction, RequestInterceptor};
+use crate::ipc::IPCEvent;
+use crate::sites::{base_domain, host_matches, host_of, normalize_site};
+use crate::storage;
+use anyhow::Result;
+use filter::RequestContext;
+use matcher::{FilterSet, MatchResult};
+use parking_lot::RwLock;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Tracker and ad blocker driven by EasyList / uBlock Origin style filter lists
+pub struct ContentBlocker {
+    filters: RwLock<Arc<FilterSet>>,
+    enabled: AtomicBool,
+    /// Sites on which the user has turned the shields off
+    allowlist: RwLock<BTreeSet<String>>,
+    list_dir: PathBuf,
+    allowlist_path: PathBuf,
+    event_tx: broadcast::Sender<IPCEvent>,
+}
+
+impl ContentBlocker {
+    pub fn new(list_dir: PathBuf, allowlist_path: PathBuf, event_tx: broadcast::Sender<IPCEvent>) -> Self {
+        Self {
+            filters: RwLock::new(Arc::new(FilterSet::default())),
+            enabled: AtomicBool::new(true),
+            allowlist: RwLock::new(BTreeSet::new()),
+            list_dir,
+            allowlist_path,
+            event_tx,
+        }
+    }
+
+    /// Compile every `.txt` filter list in the list directory and load the allow-list
+    pub async fn load(&self) -> Result<CompileStats> {
+        let mut contents = Vec::new();
+        match tokio::fs::read_dir(&self.list_dir).await {
+            Ok(mut entries) => {
+                while let Some(entry) = entries.next_entry().await? {
+                    let path = entry.path();
+                    if path.extension().and_then(|e| e.to_str()) != Some("txt") {
+                        continue;
+                    }
+                    match tokio::fs::read_to_string(&path).await {
+                        Ok(list) => contents.push(list),
+                        Err(e) => warn!("Skipping filter list {}: {}", path.display(), e),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("No filter lists found in {}", self.list_dir.display());
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        // Compiling large lists is CPU bound, keep it off the async workers
+        let set = tokio::task::spawn_blocking(move || {
+            FilterSet::compile(contents.iter().flat_map(|list| list.lines()))
+        })
+        .await?;
+        let stats = set.stats().clone();
+        *self.filters.write() = Arc::new(set);
+
+        if let Some(sites) = storage::load_json::<BTreeSet<String>>(&self.allowlist_path).await? {
+            *self.allowlist.write() = sites;
+        }
+
+        info!(
+            "Loaded {} blocking filters and {} exceptions ({} unsupported rules skipped)",
+            stats.filters, stats.exceptions, stats.unsupported
+        );
+        Ok(stats)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Turn the shields on or off for a site and persist the allow-list
+    pub async fn set_site_enabled(&self, site: &str, enabled: bool) -> Result<()> {
+        let site = normalize_site(site)?;
+        let sites = {
+            let mut allowlist = self.allowlist.write();
+            if enabled {
+                allowlist.remove(&site);
+            } else {
+                allowlist.insert(site);
+            }
+            allowlist.clone()
+        };
+        storage::save_json(&self.allowlist_path, &sites).await
+    }
+
+    pub fn allowlisted_sites(&self) -> Vec<String> {
+        self.allowlist.read().iter().cloned().collect()
+    }
+
+    fn is_allowlisted(&self, host: &str) -> bool {
+        self.allowlist.read().iter().any(|site| host_matches(host, site))
+    }
+}
+
+impl RequestInterceptor for ContentBlocker {
+    fn intercept(&self, request: &InterceptedRequest) -> RequestAction {
+        if !self.is_enabled() {
+            return RequestAction::Continue;
+        }
+
+        let Some(host) = host_of(&request.url) else {
+            return RequestAction::Continue;
+        };
+        let first_party_host = request.first_party_url.as_deref().and_then(host_of);
+
+        if let Some(first_party) = &first_party_host {
+            if self.is_allowlisted(first_party) {
+                return RequestAction::Continue;
+            }
+        }
+
+        let url_lower = request.url.to_ascii_lowercase();
+        let context = RequestContext {
+            url: &request.url,
+            url_lower: &url_lower,
+            host: &host,
+            first_party_host: first_party_host.as_deref(),
+            resource_type: request.resource_type,
+            third_party: first_party_host
+                .as_deref()
+                .map(|first_party| base_domain(first_party) != base_domain(&host))
+                .unwrap_or(false),
+        };
+
+        let filters = self.filters.read().clone();
+        match filters.check(&context) {
+            MatchResult::Block(filter) => {
+                let _ = self.event_tx.send(IPCEvent::BlockedRequest {
+                    tab: request.tab,
+                    url: request.url.clone(),
+                    first_party: request.first_party_url.clone(),
+                    filter: filter.raw.clone(),
+                });
+                RequestAction::Block
+            }
+            MatchResult::Allow | MatchResult::NoMatch => RequestAction::Continue,
+        }
+    }
+}
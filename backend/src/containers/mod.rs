@@ -0,0 +1,116 @@
+use crate::ipc::{Container, ContainerId, IPCEvent, SharedState};
+use crate::storage;
+use anyhow::Result;
+use parking_lot::RwLock;
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+
+/// The user's containers, mirrored into the shared state for the UI. Each is
+/// a browser context in an engine all containers share.
+pub struct ContainerManager {
+    containers: RwLock<Vec<Container>>,
+    path: PathBuf,
+    shared_state: SharedState,
+    event_tx: broadcast::Sender<IPCEvent>,
+}
+
+impl ContainerManager {
+    pub fn new(path: PathBuf, shared_state: SharedState, event_tx: broadcast::Sender<IPCEvent>) -> Self {
+        Self {
+            containers: RwLock::new(Vec::new()),
+            path,
+            shared_state,
+            event_tx,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        if let Some(containers) = storage::load_json::<Vec<Container>>(&self.path).await? {
+            *self.containers.write() = containers;
+        }
+        self.publish();
+        Ok(())
+    }
+
+    pub fn containers(&self) -> Vec<Container> {
+        self.containers.read().clone()
+    }
+
+    /// Fail unless `id` is a known container
+    pub fn check(&self, id: ContainerId) -> Result<()> {
+        match self.containers.read().iter().any(|container| container.id == id) {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!("Unknown container: {}", id)),
+        }
+    }
+
+    pub async fn create(&self, name: &str, color: &str) -> Result<Container> {
+        let container = {
+            let mut containers = self.containers.write();
+            let container = Container {
+                id: containers.iter().map(|container| container.id).max().unwrap_or(0) + 1,
+                name: validate_name(name, &containers, None)?,
+                color: color.trim().to_string(),
+            };
+            containers.push(container.clone());
+            container
+        };
+        self.save().await?;
+        Ok(container)
+    }
+
+    /// Rename or recolor a container
+    pub async fn update(&self, mut container: Container) -> Result<()> {
+        {
+            let mut containers = self.containers.write();
+            container.name = validate_name(&container.name, &containers, Some(container.id))?;
+            container.color = container.color.trim().to_string();
+            let known = containers
+                .iter_mut()
+                .find(|known| known.id == container.id)
+                .ok_or_else(|| anyhow::anyhow!("Unknown container: {}", container.id))?;
+            *known = container;
+        }
+        self.save().await
+    }
+
+    /// Remove a container; its tabs must be closed first, since their
+    /// cookies and storage would otherwise outlive it
+    pub async fn remove(&self, id: ContainerId) -> Result<()> {
+        self.check(id)?;
+        let in_use = self.shared_state.read().tabs.iter().any(|tab| tab.container == Some(id));
+        if in_use {
+            return Err(anyhow::anyhow!("Container {} still has open tabs", id));
+        }
+        self.containers.write().retain(|container| container.id != id);
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<()> {
+        let containers = self.containers();
+        self.publish();
+        storage::save_json(&self.path, &containers).await
+    }
+
+    fn publish(&self) {
+        let mut state = self.shared_state.write();
+        state.containers = self.containers();
+        // Having no subscribers yet is not an error
+        let _ = self.event_tx.send(IPCEvent::StateUpdate(Box::new(state.clone())));
+    }
+}
+
+/// A trimmed name no other container has
+fn validate_name(name: &str, containers: &[Container], id: Option<ContainerId>) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(anyhow::anyhow!("Container names cannot be empty"));
+    }
+    let taken = containers
+        .iter()
+        .any(|container| Some(container.id) != id && container.name.eq_ignore_ascii_case(name));
+    if taken {
+        return Err(anyhow::anyhow!("A container called {} already exists", name));
+    }
+    Ok(name.to_string())
+}
@@ -0,0 +1,155 @@
+use crate::engine::{InterceptedRequest, RequestAction, RequestInterceptor};
+use crate::ipc::{CookieInfo, CookieSettings};
+use crate::sites::{host_matches, host_of, normalize_site};
+use crate::storage;
+use anyhow::Result;
+use parking_lot::RwLock;
+use std::path::PathBuf;
+
+/// Cookie settings: third-party handling, sites that may not keep cookies,
+/// and which sites keep theirs when their last tab closes
+pub struct CookieManager {
+    settings: RwLock<CookieSettings>,
+    path: PathBuf,
+}
+
+impl CookieManager {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            settings: RwLock::new(CookieSettings::default()),
+            path,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        if let Some(settings) = storage::load_json::<CookieSettings>(&self.path).await? {
+            *self.settings.write() = settings;
+        }
+        Ok(())
+    }
+
+    pub fn settings(&self) -> CookieSettings {
+        self.settings.read().clone()
+    }
+
+    pub async fn set_settings(&self, mut settings: CookieSettings) -> Result<()> {
+        settings.blocked = normalize_sites(&settings.blocked)?;
+        settings.keep = normalize_sites(&settings.keep)?;
+        *self.settings.write() = settings.clone();
+        storage::save_json(&self.path, &settings).await
+    }
+
+    pub async fn set_blocked(&self, site: &str, blocked: bool) -> Result<()> {
+        let site = normalize_site(site)?;
+        let mut settings = self.settings();
+        settings.blocked.retain(|known| *known != site);
+        if blocked {
+            settings.blocked.push(site);
+        }
+        self.set_settings(settings).await
+    }
+
+    pub fn is_blocked(&self, cookie: &CookieInfo) -> bool {
+        self.settings.read().blocked.iter().any(|site| cookie_matches(cookie, site))
+    }
+
+    /// Whether a site's cookies go when its last tab closes
+    pub fn clears_on_close(&self, site: &str) -> bool {
+        let settings = self.settings.read();
+        settings.clear_on_close && !settings.keep.iter().any(|kept| host_matches(site, kept))
+    }
+}
+
+/// Engines ask before storing a site's cookies, so blocked sites never get
+/// to keep any
+impl RequestInterceptor for CookieManager {
+    fn intercept(&self, _request: &InterceptedRequest) -> RequestAction {
+        RequestAction::Continue
+    }
+
+    fn blocks_cookies(&self, request: &InterceptedRequest) -> bool {
+        let Some(host) = host_of(&request.url) else {
+            return false;
+        };
+        self.settings.read().blocked.iter().any(|site| host_matches(&host, site))
+    }
+}
+
+/// Whether a cookie belongs to `site` or one of its subdomains
+pub fn cookie_matches(cookie: &CookieInfo, site: &str) -> bool {
+    host_matches(cookie.domain.trim_start_matches('.'), site)
+}
+
+fn normalize_sites(sites: &[String]) -> Result<Vec<String>> {
+    let mut normalized = Vec::new();
+    for site in sites {
+        let site = normalize_site(site)?;
+        if !normalized.contains(&site) {
+            normalized.push(site);
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ResourceType;
+
+    fn cookie(domain: &str) -> CookieInfo {
+        CookieInfo {
+            name: "id".to_string(),
+            value: "1".to_string(),
+            domain: domain.to_string(),
+            path: "/".to_string(),
+            expires: None,
+            http_only: false,
+            secure: true,
+            partition: None,
+        }
+    }
+
+    #[test]
+    fn cookies_match_their_site_and_its_subdomains() {
+        assert!(cookie_matches(&cookie("example.com"), "example.com"));
+        assert!(cookie_matches(&cookie(".example.com"), "example.com"));
+        assert!(cookie_matches(&cookie("shop.example.com"), "example.com"));
+        assert!(!cookie_matches(&cookie("example.com"), "shop.example.com"));
+        assert!(!cookie_matches(&cookie("badexample.com"), "example.com"));
+    }
+
+    #[tokio::test]
+    async fn settings_are_saved_normalized() {
+        let path = std::env::temp_dir().join(format!("cookies-test-{}.json", std::process::id()));
+        let manager = CookieManager::new(path.clone());
+        let settings = CookieSettings {
+            blocked: vec!["https://www.Tracker.example/page".to_string(), "tracker.example.".to_string()],
+            keep: vec!["WWW.Mail.example".to_string()],
+            ..CookieSettings::default()
+        };
+        let saved = manager.set_settings(settings).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        saved.unwrap();
+
+        let settings = manager.settings();
+        assert_eq!(settings.blocked, ["tracker.example"]);
+        assert_eq!(settings.keep, ["mail.example"]);
+        assert!(!manager.clears_on_close("mail.example"));
+        assert!(manager.is_blocked(&cookie(".cdn.tracker.example")));
+
+        let request = |url: &str| InterceptedRequest {
+            tab: 1,
+            url: url.to_string(),
+            first_party_url: None,
+            resource_type: ResourceType::Script,
+        };
+        assert!(manager.blocks_cookies(&request("https://cdn.tracker.example/t.js")));
+        assert!(!manager.blocks_cookies(&request("https://example.com/")));
+
+        let invalid = CookieSettings {
+            blocked: vec!["https://".to_string()],
+            ..CookieSettings::default()
+        };
+        assert!(manager.set_settings(invalid).await.is_err());
+    }
+}
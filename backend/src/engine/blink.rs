@@ -1,13 +1,50 @@
-use crate::ipc::{BrowserEngine, BrowserState, CertificateInfo, IPCEvent, SharedState};
+use super::{
+    same_cookie, CommittedNavigation, InterceptedRequest, LaunchOptions, PermissionHandler, PermissionPrompt,
+    RequestAction, RequestInterceptor, ResourceType, ZoomLevels, WINDOW_SIZE,
+};
+use crate::ipc::{
+    BrowserEngine, BrowserState, CertificateInfo, CookieInfo, DeviceEmulation, FindMatches, IPCEvent, ImageFormat, PdfOptions, Permission,
+    ScreenshotOptions, SharedState, TabId, ThirdPartyCookies,
+};
+use crate::security::fingerprint::FingerprintProfile;
 use anyhow::Result;
 use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
 use chromiumoxide::{Browser, BrowserConfig, Page};
+use chromiumoxide_cdp::cdp::browser_protocol::browser::{
+    BrowserContextId, PermissionDescriptor, PermissionSetting, SetPermissionParams,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::fetch::{
+    ContinueRequestParams, ContinueResponseParams, EnableParams, EventRequestPaused, FailRequestParams, RequestPattern,
+    RequestStage,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::emulation::{
+    ClearDeviceMetricsOverrideParams, SetDeviceMetricsOverrideParams, SetEmitTouchEventsForMouseConfiguration,
+    SetDocumentCookieDisabledParams, SetEmitTouchEventsForMouseParams, SetHardwareConcurrencyOverrideParams,
+    SetLocaleOverrideParams,
+    SetTimezoneOverrideParams, SetTouchEmulationEnabledParams, SetUserAgentOverrideParams, UserAgentBrandVersion,
+    UserAgentMetadata,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::network::{
+    self, Cookie, CookieParam, CookiePartitionKey, ErrorReason, EventLoadingFailed,
+};
+use chromiumoxide_cdp::cdp::browser_protocol::page::{
+    AddScriptToEvaluateOnNewDocumentParams, CaptureScreenshotFormat, CaptureScreenshotParams, EventFrameNavigated,
+    GetLayoutMetricsParams, PrintToPdfParams, Viewport,
+};
 use chromiumoxide_cdp::cdp::browser_protocol::page::EventPageLifecycle;
+use chromiumoxide_cdp::cdp::browser_protocol::storage::{GetCookiesParams, SetCookiesParams};
+use chromiumoxide_cdp::cdp::browser_protocol::target::{CreateBrowserContextParams, CreateTargetParams};
+use chromiumoxide_cdp::cdp::js_protocol::runtime::{
+    AddBindingParams, EvaluateParams, EventBindingCalled, EventExecutionContextCreated, EventExecutionContextDestroyed,
+    ExecutionContextId,
+};
 use futures::StreamExt;
 use parking_lot::RwLock;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::broadcast;
-use tracing::{error, info};
+use tokio::sync::{broadcast, mpsc};
+use tracing::error;
 
 pub struct BlinkEngine {
     browser: Arc<Browser>,
@@ -17,127 +54,829 @@ pub struct BlinkEngine {
     favicon: Arc<RwLock<Option<String>>>,
     certificate_info: Arc<RwLock<Option<CertificateInfo>>>,
     is_secure: Arc<RwLock<bool>>,
+    interceptor: Arc<RwLock<Option<TabInterceptor>>>,
+    permission_handler: Arc<RwLock<Option<TabPermissionHandler>>>,
+    navigations: Arc<RwLock<Option<TabNavigations>>>,
+    fingerprint: Option<FingerprintProfile>,
+    /// Browser context of a container's pages, shared by the engines of its tabs
+    context: Option<BrowserContextId>,
+    search: RwLock<Option<Search>>,
+    view: Arc<RwLock<View>>,
+    zoom_levels: Arc<RwLock<Option<Arc<dyn ZoomLevels>>>>,
     shared_state: SharedState,
     event_tx: broadcast::Sender<IPCEvent>,
 }
 
+/// Interceptor of the page's requests, and the tab they are reported as from
+type TabInterceptor = (TabId, Arc<dyn RequestInterceptor>);
+
+/// Handler deciding the page's permission requests, and the tab it is told they are from
+type TabPermissionHandler = (TabId, Arc<dyn PermissionHandler>);
+
+/// Where the page's committed documents are reported, and the tab they are from
+type TabNavigations = (TabId, mpsc::UnboundedSender<CommittedNavigation>);
+
+/// How the page's viewport is sized and scaled
+#[derive(Clone, Copy)]
+struct View {
+    emulation: Option<DeviceEmulation>,
+    zoom: f64,
+}
+
+/// A find-in-page search, kept so it can be repeated on the page as it is now
+#[derive(Clone)]
+struct Search {
+    text: String,
+    case_sensitive: bool,
+    forward: bool,
+    /// Index of the selected match
+    active: Option<usize>,
+}
+
 // Implement Send for BlinkEngine
 unsafe impl Send for BlinkEngine {}
 unsafe impl Sync for BlinkEngine {}
 
 impl BlinkEngine {
-    pub async fn new() -> Result<Self> {
-        let current_url = Arc::new(RwLock::new(String::from("about:blank")));
-        let page = Arc::new(RwLock::new(None));
-        let title = Arc::new(RwLock::new(None));
-        let favicon = Arc::new(RwLock::new(None));
-        let certificate_info = Arc::new(RwLock::new(None));
-        let is_secure = Arc::new(RwLock::new(false));
+    pub async fn new(options: &LaunchOptions) -> Result<Self> {
+        let (browser, shared_state, event_tx) = launch(options).await?;
+        Ok(Self::with_browser(browser, None, options.fingerprint.clone(), shared_state, event_tx))
+    }
+    
+    /// An engine for a page of its own in a browser already launched, opened
+    /// in the given browser context
+    fn with_browser(
+        browser: Arc<Browser>,
+        context: Option<BrowserContextId>,
+        fingerprint: Option<FingerprintProfile>,
+        shared_state: SharedState,
+        event_tx: broadcast::Sender<IPCEvent>,
+    ) -> Self {
+        Self {
+            browser,
+            page: Arc::new(RwLock::new(None)),
+            current_url: Arc::new(RwLock::new(String::from("about:blank"))),
+            title: Arc::new(RwLock::new(None)),
+            favicon: Arc::new(RwLock::new(None)),
+            certificate_info: Arc::new(RwLock::new(None)),
+            is_secure: Arc::new(RwLock::new(false)),
+            interceptor: Arc::new(RwLock::new(None)),
+            permission_handler: Arc::new(RwLock::new(None)),
+            navigations: Arc::new(RwLock::new(None)),
+            fingerprint,
+            context,
+            search: RwLock::new(None),
+            view: Arc::new(RwLock::new(View { emulation: None, zoom: 1.0 })),
+            zoom_levels: Arc::new(RwLock::new(None)),
+            shared_state,
+            event_tx,
+        }
+    }
+    
+    /// Clone the current page out of the lock so it is not held across awaits
+    fn page(&self) -> Option<Page> {
+        self.page.read().clone()
+    }
+    
+    /// Every cookie in the page's browser context
+    async fn jar(&self) -> Result<Vec<Cookie>> {
+        let params = GetCookiesParams {
+            browser_context_id: self.context.clone(),
+        };
+        let cookies = self
+            .browser
+            .execute(params)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read cookies: {}", e))?;
+        Ok(cookies.result.cookies)
+    }
+    
+    /// Get the page, creating it on first use
+    async fn ensure_page(&self) -> Result<Page> {
+        if let Some(page) = self.page() {
+            return Ok(page);
+        }
         
-        // Create browser config
-        let config = BrowserConfig::builder()
-            .window_size(1280, 800)
-            .with_head()
+        // Start blank so interception is in place before the first real request
+        let mut params = CreateTargetParams::new("about:blank");
+        params.browser_context_id = self.context.clone();
+        let page = self.browser.new_page(params)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create new page: {}", e))?;
+        self.apply_fingerprint(&page).await?;
+        let view = *self.view.read();
+        apply_view(&page, self.fingerprint.as_ref(), &view).await?;
+        self.follow_zoom_levels(&page).await?;
+        self.follow_navigations(&page).await?;
+        self.enable_interception(&page).await?;
+        self.enable_permission_prompts(&page).await?;
+        *self.page.write() = Some(page.clone());
+        Ok(page)
+    }
+    
+    /// Run a find script in the page's utility world, where page scripts can
+    /// neither see its state nor tamper with the DOM APIs it uses
+    async fn evaluate_find(&self, expression: String) -> Result<serde_json::Value> {
+        let page = self.page().ok_or_else(|| anyhow::anyhow!("No page to search"))?;
+        let error = |e| anyhow::anyhow!("Failed to search page: {}", e);
+        let context = page
+            .secondary_execution_context()
+            .await
+            .map_err(error)?
+            .ok_or_else(|| anyhow::anyhow!("Page is not ready to search"))?;
+        let params = EvaluateParams::builder()
+            .expression(expression)
+            .context_id(context)
+            .return_by_value(true)
             .build()
-            .map_err(|e| anyhow::anyhow!("Failed to build browser config: {}", e))?;
-            
-        // Launch browser
-        let (browser, mut handler) = Browser::launch(config)
+            .map_err(|e| anyhow::anyhow!("Failed to search page: {}", e))?;
+        let evaluation = page.execute(params).await.map_err(error)?;
+        if let Some(exception) = &evaluation.result.exception_details {
+            return Err(anyhow::anyhow!("Failed to search page: {}", exception.text));
+        }
+        Ok(evaluation.result.result.value.clone().unwrap_or_default())
+    }
+    
+    /// Highlight the search's matches and select the one after its active match
+    async fn run_search(&self, mut search: Search) -> Result<FindMatches> {
+        let args = serde_json::json!([search.text, search.case_sensitive, search.active, search.forward]);
+        let result = self.evaluate_find(format!("({})(...{})", FIND_SCRIPT, args)).await?;
+        search.active = result["active"].as_u64().map(|active| active as usize);
+        let matches = FindMatches {
+            active_match: search.active.map_or(0, |active| active + 1),
+            total_matches: result["total"].as_u64().unwrap_or(0) as usize,
+        };
+        *self.search.write() = Some(search);
+        Ok(matches)
+    }
+    
+    /// Give each page the main frame loads its site's zoom level
+    async fn follow_zoom_levels(&self, page: &Page) -> Result<()> {
+        let mut navigated = page
+            .event_listener::<EventFrameNavigated>()
             .await
-            .map_err(|e| anyhow::anyhow!("Failed to launch browser: {}", e))?;
-        let browser = Arc::new(browser);
+            .map_err(|e| anyhow::anyhow!("Failed to follow zoom levels: {}", e))?;
+        let levels = self.zoom_levels.clone();
+        let view = self.view.clone();
+        let fingerprint = self.fingerprint.clone();
+        let page = page.clone();
+        tokio::spawn(async move {
+            while let Some(event) = navigated.next().await {
+                if event.frame.parent_id.is_some() {
+                    continue;
+                }
+                let Some(levels) = levels.read().clone() else {
+                    continue;
+                };
+                let zoom = levels.zoom_for(&event.frame.url);
+                let current = {
+                    let mut view = view.write();
+                    if view.zoom == zoom {
+                        continue;
+                    }
+                    view.zoom = zoom;
+                    *view
+                };
+                if let Err(e) = apply_view(&page, fingerprint.as_ref(), &current).await {
+                    error!("Failed to apply zoom level: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+    
+    /// Track the documents the main frame commits, however they were reached:
+    /// typed, by a link or form, or through redirects
+    async fn follow_navigations(&self, page: &Page) -> Result<()> {
+        let mut navigated = page
+            .event_listener::<EventFrameNavigated>()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to follow navigations: {}", e))?;
+        let current_url = self.current_url.clone();
+        let navigations = self.navigations.clone();
+        tokio::spawn(async move {
+            while let Some(event) = navigated.next().await {
+                if event.frame.parent_id.is_some() {
+                    continue;
+                }
+                *current_url.write() = event.frame.url.clone();
+                if let Some((tab, listener)) = navigations.read().clone() {
+                    let _ = listener.send(CommittedNavigation {
+                        tab,
+                        url: event.frame.url.clone(),
+                    });
+                }
+            }
+        });
+        Ok(())
+    }
+    
+    /// Report the profile's values to pages through CDP `Emulation`, and add
+    /// noise to canvas, WebGL and audio readback with a script run in every frame
+    async fn apply_fingerprint(&self, page: &Page) -> Result<()> {
+        let Some(profile) = &self.fingerprint else {
+            return Ok(());
+        };
+        let error = |e| anyhow::anyhow!("Failed to apply fingerprinting protection: {}", e);
+        
+        let version = self.browser.version().await.map_err(error)?;
+        let major = version
+            .product
+            .split('/')
+            .nth(1)
+            .and_then(|version| version.split('.').next())
+            .unwrap_or_default()
+            .to_string();
+        let brands = |full: bool| -> Vec<UserAgentBrandVersion> {
+            profile
+                .brands(&major)
+                .into_iter()
+                .map(|(brand, version)| {
+                    let version = if full { format!("{}.0.0.0", version) } else { version };
+                    UserAgentBrandVersion::new(brand, version)
+                })
+                .collect()
+        };
+        let (platform, platform_version, architecture, bitness) = profile.client_hints();
+        let mut user_agent = SetUserAgentOverrideParams::new(profile.user_agent(&major));
+        user_agent.platform = Some(profile.platform().to_string());
+        user_agent.accept_language = profile.accept_language.clone();
+        user_agent.user_agent_metadata = Some(UserAgentMetadata {
+            brands: Some(brands(false)),
+            full_version_list: Some(brands(true)),
+            platform: platform.to_string(),
+            platform_version: platform_version.to_string(),
+            architecture: architecture.to_string(),
+            model: String::new(),
+            mobile: false,
+            bitness: Some(bitness.to_string()),
+            wow64: Some(false),
+        });
+        page.execute(user_agent).await.map_err(error)?;
+        
+        page.execute(SetHardwareConcurrencyOverrideParams::new(profile.hardware_concurrency))
+            .await
+            .map_err(error)?;
+        if let Some(timezone) = &profile.timezone {
+            page.execute(SetTimezoneOverrideParams::new(timezone.clone())).await.map_err(error)?;
+        }
+        if let Some(locale) = &profile.locale {
+            let params = SetLocaleOverrideParams { locale: Some(locale.clone()) };
+            page.execute(params).await.map_err(error)?;
+        }
+        page.execute(AddScriptToEvaluateOnNewDocumentParams::new(profile.init_script()))
+            .await
+            .map_err(error)?;
+        Ok(())
+    }
+    
+    /// Pause every request with CDP `Fetch` and let the interceptor decide its fate
+    async fn enable_interception(&self, page: &Page) -> Result<()> {
+        let Some((tab, interceptor)) = self.interceptor.read().clone() else {
+            return Ok(());
+        };
         
-        // Create event channel
-        let (event_tx, _) = broadcast::channel(100);
-        let event_tx_clone = event_tx.clone();
+        page.execute(
+            EnableParams::builder()
+                .pattern(RequestPattern::builder().url_pattern("*").request_stage(RequestStage::Request).build())
+                .build(),
+        )
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to enable request interception: {}", e))?;
         
-        // Create shared state
-        let shared_state = Arc::new(RwLock::new(BrowserState::default()));
-        let shared_state_clone = shared_state.clone();
+        let error = |e| anyhow::anyhow!("Failed to listen for paused requests: {}", e);
+        let mut paused = page.event_listener::<EventRequestPaused>().await.map_err(error)?;
+        let mut failed = page.event_listener::<EventLoadingFailed>().await.map_err(error)?;
+        let page = page.clone();
         
-        // Handle browser events
         tokio::spawn(async move {
-            while let Some(event) = handler.next().await {
-                match event {
-                    Ok(event) => {
-                        let mut state = shared_state_clone.write();
-                        
-                        // Update state based on event type
-                        match event {
-                            EventPageLifecycle::NavigationStarted(nav) => {
-                                state.current_url = nav.url.to_string();
-                                state.is_loading = true;
-                            }
-                            EventPageLifecycle::NavigationCompleted(nav) => {
-                                state.current_url = nav.url.to_string();
-                                state.is_loading = false;
-                            }
-                            EventPageLifecycle::TitleChanged(title) => {
-                                state.title = Some(title.title);
+            let mut document_url: Option<String> = None;
+            // The main frame's current document request, reported if it fails
+            let mut document: Option<(network::RequestId, InterceptedRequest)> = None;
+            // Whether the main frame's scripts are kept from setting cookies
+            let mut document_cookie_disabled = false;
+            
+            loop {
+                let event = tokio::select! {
+                    Some(event) = paused.next() => event,
+                    Some(event) = failed.next() => {
+                        // Navigating away cancels a load, which is no failure
+                        if let Some((_, request)) = document.take_if(|(id, _)| *id == event.request_id) {
+                            if event.canceled != Some(true) {
+                                interceptor.document_failed(&request, &event.error_text);
                             }
-                            EventPageLifecycle::SecurityStateChanged(security) => {
-                                state.is_secure = security.secure;
-                                if let Some(cert) = security.certificate {
-                                    state.certificate_info = Some(CertificateInfo {
-                                        issuer: cert.issuer,
-                                        valid_from: cert.valid_from,
-                                        valid_to: cert.valid_to,
-                                    });
+                        }
+                        continue;
+                    }
+                    else => break,
+                };
+                
+                // Only responses of sites blocked from keeping cookies are
+                // paused, to go on without the cookies they set
+                if event.response_status_code.is_some() || event.response_error_reason.is_some() {
+                    let result = match &event.response_headers {
+                        Some(headers) if event.response_error_reason.is_none() => {
+                            let mut params = ContinueResponseParams::new(event.request_id.clone());
+                            params.response_headers = Some(
+                                headers
+                                    .iter()
+                                    .filter(|header| !header.name.eq_ignore_ascii_case("set-cookie"))
+                                    .cloned()
+                                    .collect(),
+                            );
+                            page.execute(params).await.map(|_| ())
+                        }
+                        _ => page
+                            .execute(ContinueRequestParams::new(event.request_id.clone()))
+                            .await
+                            .map(|_| ()),
+                    };
+                    if let Err(e) = result {
+                        error!("Failed to resolve paused response {}: {}", event.request.url, e);
+                    }
+                    continue;
+                }
+                
+                let mut resource_type = resource_type(&event.resource_type);
+                if resource_type == ResourceType::Document {
+                    let main_frame = page.mainframe().await.ok().flatten();
+                    if main_frame.as_ref() == Some(&event.frame_id) {
+                        document_url = Some(event.request.url.clone());
+                    } else {
+                        resource_type = ResourceType::Subdocument;
+                    }
+                }
+                
+                let mut request = InterceptedRequest {
+                    tab,
+                    url: event.request.url.clone(),
+                    first_party_url: document_url.clone(),
+                    resource_type,
+                };
+                
+                let action = interceptor.intercept(&request);
+                let blocks_cookies = interceptor.blocks_cookies(&request);
+                if resource_type == ResourceType::Document
+                    && action != RequestAction::Block
+                    && blocks_cookies != document_cookie_disabled
+                {
+                    // Set before the document loads, so none of its scripts run first
+                    match page.execute(SetDocumentCookieDisabledParams::new(blocks_cookies)).await {
+                        Ok(_) => document_cookie_disabled = blocks_cookies,
+                        Err(e) => error!("Failed to block cookies of {}: {}", event.request.url, e),
+                    }
+                }
+                let result = match &action {
+                    RequestAction::Block => page
+                        .execute(FailRequestParams::new(event.request_id.clone(), ErrorReason::BlockedByClient))
+                        .await
+                        .map(|_| ()),
+                    RequestAction::Continue | RequestAction::Redirect { .. } => {
+                        let mut params = ContinueRequestParams::new(event.request_id.clone());
+                        if let RequestAction::Redirect { url } = &action {
+                            params.url = Some(url.clone());
+                        }
+                        if blocks_cookies {
+                            params.intercept_response = Some(true);
+                        }
+                        page.execute(params).await.map(|_| ())
+                    }
+                };
+                
+                if let Err(e) = result {
+                    error!("Failed to resolve paused request {}: {}", event.request.url, e);
+                }
+                if resource_type == ResourceType::Document && action != RequestAction::Block {
+                    if let RequestAction::Redirect { url } = action {
+                        request.url = url;
+                    }
+                    document = event.network_id.clone().map(|id| (id, request));
+                }
+            }
+        });
+        
+        Ok(())
+    }
+    
+    /// Deny guarded permissions to every origin, and have pages ask the
+    /// handler through a binding before each use; an allowed origin is
+    /// granted the permission before the page's call goes ahead
+    async fn enable_permission_prompts(&self, page: &Page) -> Result<()> {
+        let Some((tab, handler)) = self.permission_handler.read().clone() else {
+            return Ok(());
+        };
+        let error = |e| anyhow::anyhow!("Failed to enable permission prompts: {}", e);
+        
+        for permission in PERMISSIONS {
+            for descriptor in permission_descriptors(permission) {
+                let mut params = SetPermissionParams::new(descriptor, PermissionSetting::Denied);
+                params.browser_context_id = self.context.clone();
+                self.browser.execute(params).await.map_err(error)?;
+            }
+        }
+        
+        // Origins come from the engine, not the page, so frames cannot ask for others
+        let origins: Arc<RwLock<HashMap<ExecutionContextId, (String, String)>>> = Arc::new(RwLock::new(HashMap::new()));
+        let mut created = page.event_listener::<EventExecutionContextCreated>().await.map_err(error)?;
+        let mut destroyed = page.event_listener::<EventExecutionContextDestroyed>().await.map_err(error)?;
+        let mut calls = page.event_listener::<EventBindingCalled>().await.map_err(error)?;
+        page.execute(AddBindingParams::new(PERMISSION_BINDING)).await.map_err(error)?;
+        page.execute(AddScriptToEvaluateOnNewDocumentParams::new(PERMISSION_SCRIPT))
+            .await
+            .map_err(error)?;
+        
+        let context_origins = origins.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Some(event) = created.next() => {
+                        let context = &event.context;
+                        context_origins.write().insert(context.id, (context.unique_id.clone(), context.origin.clone()));
+                    }
+                    Some(event) = destroyed.next() => {
+                        context_origins
+                            .write()
+                            .retain(|_, (unique_id, _)| *unique_id != event.execution_context_unique_id);
+                    }
+                    else => break,
+                }
+            }
+        });
+        
+        let browser = self.browser.clone();
+        let browser_context = self.context.clone();
+        let page = page.clone();
+        tokio::spawn(async move {
+            while let Some(call) = calls.next().await {
+                if call.name != PERMISSION_BINDING {
+                    continue;
+                }
+                let Ok(request) = serde_json::from_str::<serde_json::Value>(&call.payload) else {
+                    continue;
+                };
+                let (Some(id), Some(permission)) = (
+                    request["id"].as_u64(),
+                    serde_json::from_value::<Permission>(request["permission"].clone()).ok(),
+                ) else {
+                    continue;
+                };
+                let context = call.execution_context_id;
+                let origin = origins.read().get(&context).map(|(_, origin)| origin.clone());
+                
+                let handler = handler.clone();
+                let browser = browser.clone();
+                let browser_context = browser_context.clone();
+                let page = page.clone();
+                tokio::spawn(async move {
+                    // Opaque origins, like sandboxed frames, cannot be granted anything
+                    let allowed = match origin {
+                        Some(origin) if origin.contains("://") => {
+                            let prompt = PermissionPrompt {
+                                tab,
+                                origin: origin.clone(),
+                                permission,
+                            };
+                            let allowed = handler.decide(prompt).await;
+                            let setting = if allowed { PermissionSetting::Granted } else { PermissionSetting::Denied };
+                            for descriptor in permission_descriptors(&permission) {
+                                let mut params = SetPermissionParams::new(descriptor, setting.clone());
+                                params.origin = Some(origin.clone());
+                                params.browser_context_id = browser_context.clone();
+                                if let Err(e) = browser.execute(params).await {
+                                    error!("Failed to apply permission for {}: {}", origin, e);
                                 }
                             }
-                            _ => {}
+                            allowed
                         }
-                        
-                        // Send state update
-                        if let Err(e) = event_tx_clone.send(IPCEvent::StateUpdate(state.clone())) {
-                            error!("Failed to send state update: {}", e);
+                        _ => false,
+                    };
+                    
+                    let resolve = EvaluateParams::builder()
+                        .expression(format!("{}({}, {})", PERMISSION_RESOLVE, id, allowed))
+                        .context_id(context)
+                        .build();
+                    match resolve {
+                        Ok(params) => {
+                            let _ = page.execute(params).await;
                         }
+                        Err(e) => error!("Failed to answer permission request: {}", e),
                     }
-                    Err(e) => error!("Browser event error: {}", e),
-                }
+                });
             }
         });
         
+        Ok(())
+    }
+}
+
+const PERMISSIONS: &[Permission] = &[
+    Permission::Camera,
+    Permission::Microphone,
+    Permission::Geolocation,
+    Permission::Notifications,
+    Permission::Clipboard,
+    Permission::Midi,
+];
+
+/// Chromium permission names behind a permission
+fn permission_descriptors(permission: &Permission) -> Vec<PermissionDescriptor> {
+    match permission {
+        Permission::Camera => vec![PermissionDescriptor::new("camera")],
+        Permission::Microphone => vec![PermissionDescriptor::new("microphone")],
+        Permission::Geolocation => vec![PermissionDescriptor::new("geolocation")],
+        Permission::Notifications => vec![PermissionDescriptor::new("notifications")],
+        Permission::Clipboard => vec![PermissionDescriptor::new("clipboard-read")],
+        Permission::Midi => {
+            let mut sysex = PermissionDescriptor::new("midi");
+            sysex.sysex = Some(true);
+            vec![PermissionDescriptor::new("midi"), sysex]
+        }
+    }
+}
+
+/// Size the viewport like the emulated device, or like the window as
+/// fingerprinting letterboxes it, then zoom it the way browser zoom does:
+/// fewer CSS pixels, each drawn larger
+async fn apply_view(page: &Page, fingerprint: Option<&FingerprintProfile>, view: &View) -> Result<()> {
+    let error = |e| anyhow::anyhow!("Failed to apply viewport: {}", e);
+    
+    // Width, height, scale factor, mobile, and whether the screen is the viewport too
+    let metrics = match (&view.emulation, fingerprint) {
+        (Some(device), _) => Some((device.width, device.height, device.device_scale_factor, device.mobile, true)),
+        // Letterboxing: the page sees a stepped size as both viewport and screen
+        (None, Some(profile)) => Some((profile.screen.0, profile.screen.1, 1.0, false, true)),
+        (None, None) if view.zoom != 1.0 => Some((WINDOW_SIZE.0, WINDOW_SIZE.1, 1.0, false, false)),
+        (None, None) => None,
+    };
+    match metrics {
+        Some((width, height, scale, mobile, screen)) => {
+            let width = (f64::from(width) / view.zoom).round() as i64;
+            let height = (f64::from(height) / view.zoom).round() as i64;
+            let mut metrics = SetDeviceMetricsOverrideParams::new(width, height, scale * view.zoom, mobile);
+            if screen {
+                metrics.screen_width = Some(width);
+                metrics.screen_height = Some(height);
+                metrics.position_x = Some(0);
+                metrics.position_y = Some(0);
+            }
+            page.execute(metrics).await.map_err(error)?;
+        }
+        None => {
+            page.execute(ClearDeviceMetricsOverrideParams::default()).await.map_err(error)?;
+        }
+    }
+    
+    let touch = view.emulation.is_some_and(|device| device.touch);
+    let mut touch_points = SetTouchEmulationEnabledParams::new(touch);
+    touch_points.max_touch_points = touch.then_some(5);
+    page.execute(touch_points).await.map_err(error)?;
+    let mut mouse = SetEmitTouchEventsForMouseParams::new(touch);
+    mouse.configuration = touch.then_some(SetEmitTouchEventsForMouseConfiguration::Mobile);
+    page.execute(mouse).await.map_err(error)?;
+    Ok(())
+}
+
+/// CDP sends binary data as base64
+fn decode_binary(data: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(data)
+        .map_err(|e| anyhow::anyhow!("Engine sent invalid base64: {}", e))
+}
+
+/// A cookie as `Storage.setCookies` takes it; what the engine does not report
+/// back, like `SameSite`, is left to its defaults
+fn cookie_param(cookie: &CookieInfo) -> CookieParam {
+    let mut param = CookieParam::new(cookie.name.clone(), cookie.value.clone());
+    param.domain = Some(cookie.domain.clone());
+    param.path = Some(cookie.path.clone());
+    param.expires = cookie.expires.map(network::TimeSinceEpoch::new);
+    param.http_only = Some(cookie.http_only);
+    param.secure = Some(cookie.secure);
+    param.partition_key = cookie.partition.clone().map(|top_level_site| CookiePartitionKey {
+        top_level_site,
+        has_cross_site_ancestor: false,
+    });
+    param
+}
+
+/// An already expired cookie in the place of `cookie`, which storing deletes it
+fn expired_cookie_param(cookie: &Cookie) -> CookieParam {
+    let mut param = CookieParam::new(cookie.name.clone(), String::new());
+    if cookie.domain.starts_with('.') {
+        param.domain = Some(cookie.domain.clone());
+    } else {
+        // A host-only cookie is only replaced by one set from its host
+        param.url = Some(format!("https://{}{}", cookie.domain, cookie.path));
+    }
+    param.path = Some(cookie.path.clone());
+    param.secure = Some(cookie.secure);
+    param.http_only = Some(cookie.http_only);
+    param.same_site = cookie.same_site.clone();
+    param.partition_key = cookie.partition_key.clone();
+    // Zero would make a session cookie
+    param.expires = Some(network::TimeSinceEpoch::new(1.0));
+    param
+}
+
+fn cookie_info(cookie: &Cookie) -> CookieInfo {
+    CookieInfo {
+        name: cookie.name.clone(),
+        value: cookie.value.clone(),
+        domain: cookie.domain.clone(),
+        path: cookie.path.clone(),
+        expires: (!cookie.session).then_some(cookie.expires),
+        http_only: cookie.http_only,
+        secure: cookie.secure,
+        partition: cookie.partition_key.as_ref().map(|key| key.top_level_site.clone()),
+    }
+}
+
+fn resource_type(resource_type: &network::ResourceType) -> ResourceType {
+    match resource_type {
+        network::ResourceType::Document => ResourceType::Document,
+        network::ResourceType::Stylesheet => ResourceType::Stylesheet,
+        network::ResourceType::Script => ResourceType::Script,
+        network::ResourceType::Image => ResourceType::Image,
+        network::ResourceType::Font => ResourceType::Font,
+        network::ResourceType::Media | network::ResourceType::TextTrack => ResourceType::Media,
+        network::ResourceType::Xhr | network::ResourceType::Fetch | network::ResourceType::EventSource => {
+            ResourceType::Xhr
+        }
+        network::ResourceType::WebSocket => ResourceType::WebSocket,
+        network::ResourceType::Ping | network::ResourceType::CspViolationReport => ResourceType::Ping,
+        _ => ResourceType::Other,
+    }
+}
+
+/// Launch Chromium as the options ask, following its events
+async fn launch(options: &LaunchOptions) -> Result<(Arc<Browser>, SharedState, broadcast::Sender<IPCEvent>)> {
+    let mut builder = BrowserConfig::builder()
+        .window_size(WINDOW_SIZE.0, WINDOW_SIZE.1)
+        .with_head();
+    if let Some(locale) = options.fingerprint.as_ref().and_then(|profile| profile.locale.as_ref()) {
+        builder = builder.arg(format!("--lang={}", locale));
+    }
+    // Resolve nothing locally so DNS follows the proxy's route
+    builder = builder.arg("--host-resolver-rules=MAP * ~NOTFOUND , EXCLUDE 127.0.0.1");
+    if let Some(proxy_server) = &options.proxy_server {
+        builder = builder.arg(format!("--proxy-server={}", proxy_server));
+    }
+    // Chromium blocks third-party cookies except partitioned (CHIPS) ones;
+    // without partitioned cookies, those are blocked too
+    match options.third_party_cookies {
+        ThirdPartyCookies::Allow => {}
+        ThirdPartyCookies::Partition => builder = builder.arg("--test-third-party-cookie-phaseout"),
+        ThirdPartyCookies::Block => {
+            // Chromium takes only the last `--disable-features`, so this one
+            // repeats the `TranslateUI` that chromiumoxide's defaults disable
+            builder = builder
+                .arg("--test-third-party-cookie-phaseout")
+                .arg("--disable-features=TranslateUI,PartitionedCookies");
+        }
+    }
+    if options.leak_protection {
+        // WebRTC may only use UDP the proxy carries, which SOCKS5 here never does
+        builder = builder
+            .arg("--force-webrtc-ip-handling-policy=disable_non_proxied_udp")
+            .arg("--dns-prefetch-disable")
+            .arg("--disable-quic");
+    }
+    let config = builder
+        .build()
+        .map_err(|e| anyhow::anyhow!("Failed to build browser config: {}", e))?;
+        
+    // Launch browser
+    let (browser, mut handler) = Browser::launch(config)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to launch browser: {}", e))?;
+    let browser = Arc::new(browser);
+    
+    // Create event channel
+    let (event_tx, _) = broadcast::channel(100);
+    let event_tx_clone = event_tx.clone();
+    
+    // Create shared state
+    let shared_state = Arc::new(RwLock::new(BrowserState::default()));
+    let shared_state_clone = shared_state.clone();
+    
+    // Handle browser events
+    tokio::spawn(async move {
+        while let Some(event) = handler.next().await {
+            match event {
+                Ok(event) => {
+                    let mut state = shared_state_clone.write();
+                    
+                    // Update state based on event type
+                    match event {
+                        EventPageLifecycle::NavigationStarted(nav) => {
+                            state.current_url = nav.url.to_string();
+                            state.is_loading = true;
+                        }
+                        EventPageLifecycle::NavigationCompleted(nav) => {
+                            state.current_url = nav.url.to_string();
+                            state.is_loading = false;
+                        }
+                        EventPageLifecycle::TitleChanged(title) => {
+                            state.title = Some(title.title);
+                        }
+                        EventPageLifecycle::SecurityStateChanged(security) => {
+                            state.is_secure = security.secure;
+                            if let Some(cert) = security.certificate {
+                                state.certificate_info = Some(CertificateInfo {
+                                    issuer: cert.issuer,
+                                    valid_from: cert.valid_from,
+                                    valid_to: cert.valid_to,
+                                });
+                            }
+                        }
+                        _ => {}
+                    }
+                    
+                    // Send state update
+                    if let Err(e) = event_tx_clone.send(IPCEvent::StateUpdate(Box::new(state.clone()))) {
+                        error!("Failed to send state update: {}", e);
+                    }
+                }
+                Err(e) => error!("Browser event error: {}", e),
+            }
+        }
+    });
+    
+    Ok((browser, shared_state, event_tx))
+}
+
+/// One Chromium whose browser contexts hold the containers, each proxied
+/// through its own network endpoint
+pub struct BlinkContainerHost {
+    browser: Arc<Browser>,
+    shared_state: SharedState,
+    event_tx: broadcast::Sender<IPCEvent>,
+}
+
+impl BlinkContainerHost {
+    pub async fn new(options: &LaunchOptions) -> Result<Self> {
+        let (browser, shared_state, event_tx) = launch(options).await?;
         Ok(Self {
             browser,
-            page,
-            current_url,
-            title,
-            favicon,
-            certificate_info,
-            is_secure,
             shared_state,
             event_tx,
         })
     }
 }
 
+#[async_trait]
+impl super::ContainerHost for BlinkContainerHost {
+    async fn open_context(
+        &self,
+        proxy_server: String,
+        fingerprint: Option<FingerprintProfile>,
+    ) -> Result<Arc<dyn super::Engine>> {
+        let params = CreateBrowserContextParams::builder().proxy_server(proxy_server).build();
+        let context = self
+            .browser
+            .create_browser_context(params)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create browser context: {}", e))?;
+        Ok(Arc::new(BlinkEngine::with_browser(
+            self.browser.clone(),
+            Some(context),
+            fingerprint,
+            self.shared_state.clone(),
+            self.event_tx.clone(),
+        )))
+    }
+}
+
 #[async_trait]
 impl super::Engine for BlinkEngine {
     fn engine_type(&self) -> BrowserEngine {
         BrowserEngine::Blink
     }
     
+    async fn open_sibling(&self, fingerprint: Option<FingerprintProfile>) -> Result<Arc<dyn super::Engine>> {
+        Ok(Arc::new(Self::with_browser(
+            self.browser.clone(),
+            self.context.clone(),
+            fingerprint,
+            self.shared_state.clone(),
+            self.event_tx.clone(),
+        )))
+    }
+    
     async fn navigate(&self, url: String) -> Result<()> {
-        let page = if let Some(page) = &*self.page.read() {
-            page.clone()
-        } else {
-            let page = self.browser.new_page(&url)
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to create new page: {}", e))?;
-            *self.page.write() = Some(page.clone());
-            page
-        };
+        let page = self.ensure_page().await?;
         
         page.goto(&url)
             .await
             .map_err(|e| anyhow::anyhow!("Failed to navigate to {}: {}", url, e))?;
-        *self.current_url.write() = url;
+        // Where the page ended up, after any redirects
+        let committed = page.url().await.ok().flatten().unwrap_or(url);
+        *self.current_url.write() = committed;
         Ok(())
     }
     
     async fn reload(&self) -> Result<()> {
-        if let Some(page) = &*self.page.read() {
+        if let Some(page) = self.page() {
             page.reload()
                 .await
                 .map_err(|e| anyhow::anyhow!("Failed to reload page: {}", e))?;
@@ -146,7 +885,7 @@ impl super::Engine for BlinkEngine {
     }
     
     async fn stop(&self) -> Result<()> {
-        if let Some(page) = &*self.page.read() {
+        if let Some(page) = self.page() {
             page.evaluate("window.stop()")
                 .await
                 .map_err(|e| anyhow::anyhow!("Failed to stop page: {}", e))?;
@@ -154,24 +893,6 @@ impl super::Engine for BlinkEngine {
         Ok(())
     }
     
-    async fn go_back(&self) -> Result<()> {
-        if let Some(page) = &*self.page.read() {
-            page.evaluate("window.history.back()")
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to go back: {}", e))?;
-        }
-        Ok(())
-    }
-    
-    async fn go_forward(&self) -> Result<()> {
-        if let Some(page) = &*self.page.read() {
-            page.evaluate("window.history.forward()")
-                .await
-                .map_err(|e| anyhow::anyhow!("Failed to go forward: {}", e))?;
-        }
-        Ok(())
-    }
-    
     fn current_url(&self) -> String {
         self.current_url.read().clone()
     }
@@ -191,6 +912,200 @@ impl super::Engine for BlinkEngine {
     fn is_secure(&self) -> bool {
         *self.is_secure.read()
     }
+    
+    async fn set_request_interceptor(&self, tab: TabId, interceptor: Arc<dyn RequestInterceptor>) -> Result<()> {
+        *self.interceptor.write() = Some((tab, interceptor));
+        if let Some(page) = self.page() {
+            self.enable_interception(&page).await?;
+        }
+        Ok(())
+    }
+    
+    async fn set_permission_handler(&self, tab: TabId, handler: Arc<dyn PermissionHandler>) -> Result<()> {
+        *self.permission_handler.write() = Some((tab, handler));
+        if let Some(page) = self.page() {
+            self.enable_permission_prompts(&page).await?;
+        }
+        Ok(())
+    }
+    
+    async fn set_navigation_listener(
+        &self,
+        tab: TabId,
+        listener: mpsc::UnboundedSender<CommittedNavigation>,
+    ) -> Result<()> {
+        *self.navigations.write() = Some((tab, listener));
+        Ok(())
+    }
+    
+    async fn cookies(&self) -> Result<Vec<CookieInfo>> {
+        Ok(self.jar().await?.iter().map(cookie_info).collect())
+    }
+    
+    async fn delete_cookies(&self, cookies: &[CookieInfo]) -> Result<()> {
+        // Deleting goes through the browser, so engines without a page do not open one
+        let expired: Vec<CookieParam> = self
+            .jar()
+            .await?
+            .iter()
+            .filter(|cookie| cookies.iter().any(|wanted| same_cookie(wanted, &cookie_info(cookie))))
+            .map(expired_cookie_param)
+            .collect();
+        if expired.is_empty() {
+            return Ok(());
+        }
+        let params = SetCookiesParams {
+            cookies: expired,
+            browser_context_id: self.context.clone(),
+        };
+        self.browser
+            .execute(params)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to delete cookies: {}", e))?;
+        Ok(())
+    }
+    
+    async fn set_cookies(&self, cookies: &[CookieInfo]) -> Result<()> {
+        if cookies.is_empty() {
+            return Ok(());
+        }
+        let params = SetCookiesParams {
+            cookies: cookies.iter().map(cookie_param).collect(),
+            browser_context_id: self.context.clone(),
+        };
+        self.browser
+            .execute(params)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to set cookies: {}", e))?;
+        Ok(())
+    }
+    
+    async fn capture_screenshot(&self, options: &ScreenshotOptions) -> Result<Vec<u8>> {
+        let page = self.ensure_page().await?;
+        let error = |e| anyhow::anyhow!("Failed to capture screenshot: {}", e);
+        
+        // Clipping to the document's size captures past the viewport without
+        // resizing it, which would undo fingerprinting's device metrics
+        let clip = match (options.clip, options.full_page) {
+            (Some(clip), _) => Some(Viewport {
+                x: clip.x,
+                y: clip.y,
+                width: clip.width,
+                height: clip.height,
+                scale: 1.0,
+            }),
+            (None, true) => {
+                let metrics = page.execute(GetLayoutMetricsParams::default()).await.map_err(error)?;
+                let size = &metrics.result.css_content_size;
+                Some(Viewport {
+                    x: 0.0,
+                    y: 0.0,
+                    width: size.width,
+                    height: size.height,
+                    scale: 1.0,
+                })
+            }
+            (None, false) => None,
+        };
+        let params = CaptureScreenshotParams {
+            format: Some(match options.format {
+                ImageFormat::Png => CaptureScreenshotFormat::Png,
+                ImageFormat::Jpeg => CaptureScreenshotFormat::Jpeg,
+                ImageFormat::Webp => CaptureScreenshotFormat::Webp,
+            }),
+            quality: options.quality.map(|quality| i64::from(quality.min(100))),
+            capture_beyond_viewport: Some(clip.is_some()),
+            clip,
+            from_surface: Some(true),
+            ..Default::default()
+        };
+        let screenshot = page.execute(params).await.map_err(error)?;
+        decode_binary(screenshot.result.data.as_ref())
+    }
+    
+    async fn print_to_pdf(&self, options: &PdfOptions) -> Result<Vec<u8>> {
+        let page = self.ensure_page().await?;
+        let params = PrintToPdfParams {
+            landscape: Some(options.landscape),
+            print_background: Some(options.print_background),
+            scale: options.scale,
+            paper_width: options.paper_width,
+            paper_height: options.paper_height,
+            margin_top: options.margin_top,
+            margin_bottom: options.margin_bottom,
+            margin_left: options.margin_left,
+            margin_right: options.margin_right,
+            page_ranges: options.page_ranges.clone(),
+            prefer_css_page_size: Some(options.prefer_css_page_size),
+            ..Default::default()
+        };
+        let pdf = page
+            .execute(params)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to print to PDF: {}", e))?;
+        decode_binary(pdf.result.data.as_ref())
+    }
+    
+    async fn find(&self, text: &str, case_sensitive: bool, forward: bool) -> Result<FindMatches> {
+        let active = self
+            .search
+            .read()
+            .as_ref()
+            .filter(|search| search.text == text && search.case_sensitive == case_sensitive)
+            .and_then(|search| search.active);
+        let search = Search {
+            text: text.to_string(),
+            case_sensitive,
+            forward,
+            active,
+        };
+        self.run_search(search).await
+    }
+    
+    async fn find_next(&self) -> Result<FindMatches> {
+        let search = self
+            .search
+            .read()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No search in progress"))?;
+        self.run_search(search).await
+    }
+    
+    async fn stop_finding(&self) -> Result<()> {
+        if self.search.write().take().is_some() && self.page().is_some() {
+            self.evaluate_find(format!("({})()", STOP_FINDING_SCRIPT)).await?;
+        }
+        Ok(())
+    }
+    
+    async fn set_zoom_levels(&self, levels: Arc<dyn ZoomLevels>) -> Result<()> {
+        *self.zoom_levels.write() = Some(levels);
+        Ok(())
+    }
+    
+    async fn set_zoom(&self, level: f64) -> Result<()> {
+        let view = {
+            let mut view = self.view.write();
+            view.zoom = level;
+            *view
+        };
+        if let Some(page) = self.page() {
+            apply_view(&page, self.fingerprint.as_ref(), &view).await?;
+        }
+        Ok(())
+    }
+    
+    async fn set_device_emulation(&self, emulation: Option<DeviceEmulation>) -> Result<()> {
+        let view = {
+            let mut view = self.view.write();
+            view.emulation = emulation;
+            *view
+        };
+        if let Some(page) = self.page() {
+            apply_view(&page, self.fingerprint.as_ref(), &view).await?;
+        }
+        Ok(())
+    }
 }
 
 impl Drop for BlinkEngine {
@@ -199,4 +1114,156 @@ impl Drop for BlinkEngine {
             let _ = futures::executor::block_on(page.evaluate("window.close()"));
         }
     }
-} 
\ No newline at end of file
+} 
+
+const PERMISSION_BINDING: &str = "__solacePermission";
+const PERMISSION_RESOLVE: &str = "__solacePermissionAnswer";
+
+/// Wraps the APIs behind each permission so they wait for the handler first.
+/// The binding is taken off the global object before page scripts run.
+const PERMISSION_SCRIPT: &str = r#"(() => {
+  const binding = self.__solacePermission;
+  if (typeof binding !== 'function') return;
+  delete self.__solacePermission;
+
+  const pending = new Map();
+  let nextId = 0;
+  Object.defineProperty(self, '__solacePermissionAnswer', {
+    value: (id, allowed) => {
+      const resolve = pending.get(id);
+      pending.delete(id);
+      if (resolve) resolve(allowed);
+    },
+  });
+  const ask = (permission) => new Promise((resolve) => {
+    const id = ++nextId;
+    pending.set(id, resolve);
+    binding(JSON.stringify({ id, permission }));
+  });
+
+  // The original call still runs when denied, and fails the way the API does
+  const guard = (object, name, permissions) => {
+    if (!object || typeof object[name] !== 'function') return;
+    const original = object[name];
+    object[name] = async function (...args) {
+      for (const permission of permissions(...args)) await ask(permission);
+      return original.apply(this, args);
+    };
+  };
+  guard(self.MediaDevices && MediaDevices.prototype, 'getUserMedia', (constraints = {}) =>
+    [constraints.video && 'Camera', constraints.audio && 'Microphone'].filter(Boolean));
+  guard(self.Notification, 'requestPermission', () => ['Notifications']);
+  guard(self.Clipboard && Clipboard.prototype, 'read', () => ['Clipboard']);
+  guard(self.Clipboard && Clipboard.prototype, 'readText', () => ['Clipboard']);
+  guard(self.Navigator && Navigator.prototype, 'requestMIDIAccess', () => ['Midi']);
+
+  // Geolocation answers through callbacks, and watch ids are returned at once
+  if (self.Geolocation) {
+    const geolocation = Geolocation.prototype;
+    const { getCurrentPosition, watchPosition, clearWatch } = geolocation;
+    const watches = new Map();
+    let nextWatch = 0;
+    geolocation.getCurrentPosition = function (...args) {
+      ask('Geolocation').then(() => getCurrentPosition.apply(this, args));
+    };
+    geolocation.watchPosition = function (...args) {
+      const id = ++nextWatch;
+      watches.set(id, null);
+      ask('Geolocation').then(() => {
+        if (watches.has(id)) watches.set(id, watchPosition.apply(this, args));
+      });
+      return id;
+    };
+    geolocation.clearWatch = function (id) {
+      const watch = watches.get(id);
+      watches.delete(id);
+      if (watch !== null && watch !== undefined) clearWatch.call(this, watch);
+    };
+  }
+})();
+"#;
+
+/// Finds `text` in the document's visible text, matching across element
+/// boundaries, and highlights the matches with the CSS Custom Highlight API
+/// so the DOM is left alone. Selects the match after `active` in the given
+/// direction, or the first one when `active` is null, scrolling it into view.
+const FIND_SCRIPT: &str = r#"(text, caseSensitive, active, forward) => {
+  const root = document.body || document.documentElement;
+  if (!root || !text) return { active: -1, total: 0 };
+
+  const nodes = [];
+  let content = '';
+  const walker = document.createTreeWalker(root, NodeFilter.SHOW_TEXT, {
+    acceptNode: (node) => {
+      const parent = node.parentElement;
+      if (!parent || parent.closest('script, style, noscript, template')) return NodeFilter.FILTER_REJECT;
+      if (parent.checkVisibility && !parent.checkVisibility()) return NodeFilter.FILTER_REJECT;
+      return NodeFilter.FILTER_ACCEPT;
+    },
+  });
+  for (let node = walker.nextNode(); node; node = walker.nextNode()) {
+    nodes.push({ node, start: content.length });
+    content += node.data;
+  }
+
+  const escaped = text.replace(/[.*+?^${}()|[\]\\]/g, '\\$&');
+  const pattern = new RegExp(escaped, caseSensitive ? 'gu' : 'giu');
+  const ranges = [];
+  let first = 0;
+  for (const match of content.matchAll(pattern)) {
+    const end = match.index + match[0].length;
+    while (nodes[first].start + nodes[first].node.data.length <= match.index) first++;
+    let last = first;
+    while (nodes[last].start + nodes[last].node.data.length < end) last++;
+    const range = document.createRange();
+    range.setStart(nodes[first].node, match.index - nodes[first].start);
+    range.setEnd(nodes[last].node, end - nodes[last].start);
+    ranges.push(range);
+  }
+
+  const total = ranges.length;
+  let selected = -1;
+  if (total && active === null) {
+    selected = forward ? 0 : total - 1;
+  } else if (total) {
+    selected = (Math.min(active, total - 1) + (forward ? 1 : total - 1)) % total;
+  }
+
+  if (self.CSS && CSS.highlights) {
+    if (!self.findSheet) {
+      self.findSheet = new CSSStyleSheet();
+      self.findSheet.replaceSync(
+        '::highlight(solace-find) { background-color: #fde047; color: #000; }' +
+        '::highlight(solace-find-active) { background-color: #f97316; color: #000; }');
+    }
+    if (!document.adoptedStyleSheets.includes(self.findSheet)) {
+      document.adoptedStyleSheets = [...document.adoptedStyleSheets, self.findSheet];
+    }
+    CSS.highlights.set('solace-find', new Highlight(...ranges));
+    if (selected >= 0) {
+      const highlight = new Highlight(ranges[selected]);
+      highlight.priority = 1;
+      CSS.highlights.set('solace-find-active', highlight);
+    } else {
+      CSS.highlights.delete('solace-find-active');
+    }
+  }
+
+  if (selected >= 0) {
+    const rect = ranges[selected].getBoundingClientRect();
+    if (rect.top < 0 || rect.bottom > innerHeight || rect.left < 0 || rect.right > innerWidth) {
+      ranges[selected].startContainer.parentElement.scrollIntoView({ block: 'center', inline: 'nearest' });
+    }
+  }
+  return { active: selected, total };
+}"#;
+
+/// Removes the highlights `FIND_SCRIPT` added
+const STOP_FINDING_SCRIPT: &str = r#"() => {
+  if (!self.CSS || !CSS.highlights) return;
+  CSS.highlights.delete('solace-find');
+  CSS.highlights.delete('solace-find-active');
+  if (self.findSheet) {
+    document.adoptedStyleSheets = document.adoptedStyleSheets.filter((sheet) => sheet !== self.findSheet);
+  }
+}"#;
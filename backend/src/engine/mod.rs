@@ -1,10 +1,196 @@
 pub mod blink;
 
-use crate::ipc::{BrowserEngine, CertificateInfo, IPCEvent, SharedState};
+use crate::ipc::{
+    BrowserEngine, CertificateInfo, ContainerId, CookieInfo, DeviceEmulation, FindMatches, IPCEvent, NetworkRoute, PdfOptions, Permission, PrivacyMode,
+    ScreenshotOptions, SharedState, TabId, TabInfo, ThirdPartyCookies, WindowId, WindowInfo, MAIN_WINDOW,
+};
+use crate::network::{http_proxy, NetworkManager};
+use crate::security::fingerprint::{self, FingerprintProfile, Protection};
+use crate::security::leak_check::{LeakCheck, LeakReport};
 use anyhow::Result;
 use async_trait::async_trait;
-use std::sync::Arc;
-use tokio::sync::broadcast;
+use parking_lot::{Mutex, RwLock};
+use std::collections::BTreeMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tokio::sync::{broadcast, mpsc};
+
+/// Kind of resource an intercepted request is loading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceType {
+    Document,
+    Subdocument,
+    Stylesheet,
+    Script,
+    Image,
+    Font,
+    Media,
+    Xhr,
+    WebSocket,
+    Ping,
+    Other,
+}
+
+/// An outgoing request paused by the engine for inspection
+#[derive(Debug, Clone)]
+pub struct InterceptedRequest {
+    pub tab: TabId,
+    pub url: String,
+    /// URL of the top-level document that issued the request
+    pub first_party_url: Option<String>,
+    pub resource_type: ResourceType,
+}
+
+/// What the engine should do with an intercepted request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestAction {
+    Continue,
+    Block,
+    Redirect { url: String },
+}
+
+/// Hook consulted by engines for every outgoing request
+pub trait RequestInterceptor: Send + Sync {
+    fn intercept(&self, request: &InterceptedRequest) -> RequestAction;
+
+    /// A tab's top-level document failed to load, at the URL it was sent to
+    /// after interception
+    fn document_failed(&self, _request: &InterceptedRequest, _error: &str) {}
+
+    /// Whether the request's site may not keep cookies, so the engine drops
+    /// any its response sets and, for a top-level document, any its scripts set
+    fn blocks_cookies(&self, _request: &InterceptedRequest) -> bool {
+        false
+    }
+}
+
+/// Runs interceptors in order; the first one that does not continue wins
+#[derive(Default)]
+pub struct InterceptorChain {
+    interceptors: RwLock<Vec<Arc<dyn RequestInterceptor>>>,
+}
+
+impl InterceptorChain {
+    pub fn push(&self, interceptor: Arc<dyn RequestInterceptor>) {
+        self.interceptors.write().push(interceptor);
+    }
+}
+
+impl RequestInterceptor for InterceptorChain {
+    fn intercept(&self, request: &InterceptedRequest) -> RequestAction {
+        for interceptor in self.interceptors.read().iter() {
+            match interceptor.intercept(request) {
+                RequestAction::Continue => continue,
+                action => return action,
+            }
+        }
+        RequestAction::Continue
+    }
+
+    fn document_failed(&self, request: &InterceptedRequest, error: &str) {
+        for interceptor in self.interceptors.read().iter() {
+            interceptor.document_failed(request, error);
+        }
+    }
+
+    fn blocks_cookies(&self, request: &InterceptedRequest) -> bool {
+        self.interceptors
+            .read()
+            .iter()
+            .any(|interceptor| interceptor.blocks_cookies(request))
+    }
+}
+
+/// A top-level document an engine committed in a tab, at its engine URL
+#[derive(Debug, Clone)]
+pub struct CommittedNavigation {
+    pub tab: TabId,
+    pub url: String,
+}
+
+/// A page asking to use a guarded capability
+#[derive(Debug, Clone)]
+pub struct PermissionPrompt {
+    pub tab: TabId,
+    /// Origin of the frame that asked, as the engine reports it
+    pub origin: String,
+    pub permission: Permission,
+}
+
+/// Hook consulted by engines before a page may use a permission
+#[async_trait]
+pub trait PermissionHandler: Send + Sync {
+    async fn decide(&self, prompt: PermissionPrompt) -> bool;
+}
+
+/// Zoom levels remembered per site, applied by engines as pages load
+pub trait ZoomLevels: Send + Sync {
+    /// Zoom for a page at `url`, 1.0 when none is remembered
+    fn zoom_for(&self, url: &str) -> f64;
+}
+
+/// Looks zoom levels up by the URL the user sees, e.g. `ipfs://` rather
+/// than the gateway URL the engine loads
+struct DisplayedZoomLevels {
+    levels: Arc<dyn ZoomLevels>,
+    protocols: Arc<ProtocolHandlers>,
+}
+
+impl ZoomLevels for DisplayedZoomLevels {
+    fn zoom_for(&self, url: &str) -> f64 {
+        self.levels.zoom_for(&self.protocols.display_url(url.to_string()))
+    }
+}
+
+/// Serves a URL scheme the engines cannot load themselves, like `ipfs://`,
+/// by pointing them at an equivalent URL they can
+pub trait ProtocolHandler: Send + Sync {
+    /// URL for the engine to load instead, or `None` if the scheme is not handled here
+    fn engine_url(&self, url: &str) -> Option<Result<String>>;
+
+    /// The handled URL an engine URL stands for, shown to the user
+    fn display_url(&self, engine_url: &str) -> Option<String>;
+
+    /// Content-addressed origin of a handled URL, e.g. `ipfs://<cid>`
+    fn content_origin(&self, url: &str) -> Option<String>;
+}
+
+/// The registered protocol handlers; URLs none of them handle pass unchanged
+#[derive(Default)]
+pub struct ProtocolHandlers {
+    handlers: RwLock<Vec<Arc<dyn ProtocolHandler>>>,
+}
+
+impl ProtocolHandlers {
+    pub fn push(&self, handler: Arc<dyn ProtocolHandler>) {
+        self.handlers.write().push(handler);
+    }
+
+    pub fn engine_url(&self, url: String) -> Result<String> {
+        let handlers = self.handlers.read();
+        handlers
+            .iter()
+            .find_map(|handler| handler.engine_url(&url))
+            .unwrap_or(Ok(url))
+    }
+
+    pub fn display_url(&self, engine_url: String) -> String {
+        let handlers = self.handlers.read();
+        handlers
+            .iter()
+            .find_map(|handler| handler.display_url(&engine_url))
+            .unwrap_or(engine_url)
+    }
+
+    pub fn content_origin(&self, url: &str) -> Option<String> {
+        self.handlers
+            .read()
+            .iter()
+            .find_map(|handler| handler.content_origin(url))
+    }
+}
 
 /// Trait that must be implemented by all browser engines
 #[async_trait]
@@ -12,6 +198,10 @@ pub trait Engine: Send + Sync {
     /// Get the engine type
     fn engine_type(&self) -> BrowserEngine;
     
+    /// Open another page in this engine's process and browser context, for a
+    /// tab sharing its cookies and storage, protected as `fingerprint` asks
+    async fn open_sibling(&self, fingerprint: Option<FingerprintProfile>) -> Result<Arc<dyn Engine>>;
+    
     /// Navigate to a URL
     async fn navigate(&self, url: String) -> Result<()>;
     
@@ -21,12 +211,6 @@ pub trait Engine: Send + Sync {
     /// Reload the current page
     async fn reload(&self) -> Result<()>;
     
-    /// Go back in history
-    async fn go_back(&self) -> Result<()>;
-    
-    /// Go forward in history
-    async fn go_forward(&self) -> Result<()>;
-    
     /// Get the current URL
     fn current_url(&self) -> String;
     
@@ -41,63 +225,1117 @@ pub trait Engine: Send + Sync {
     
     /// Check if the current connection is secure
     fn is_secure(&self) -> bool;
+    
+    /// Route every request of this engine through an interceptor
+    async fn set_request_interceptor(&self, tab: TabId, interceptor: Arc<dyn RequestInterceptor>) -> Result<()>;
+    
+    /// Hold permission requests from pages until the handler decides them
+    async fn set_permission_handler(&self, tab: TabId, handler: Arc<dyn PermissionHandler>) -> Result<()>;
+    
+    /// Report every top-level document the engine commits
+    async fn set_navigation_listener(
+        &self,
+        tab: TabId,
+        listener: mpsc::UnboundedSender<CommittedNavigation>,
+    ) -> Result<()>;
+    
+    /// Get every cookie in the engine's cookie jar
+    async fn cookies(&self) -> Result<Vec<CookieInfo>>;
+    
+    /// Delete cookies, matched by name, domain, path and partition
+    async fn delete_cookies(&self, cookies: &[CookieInfo]) -> Result<()>;
+    
+    /// Add cookies to the engine's cookie jar, replacing any with the same name,
+    /// domain, path and partition
+    async fn set_cookies(&self, cookies: &[CookieInfo]) -> Result<()>;
+    
+    /// Capture what the page displays as an image
+    async fn capture_screenshot(&self, options: &ScreenshotOptions) -> Result<Vec<u8>>;
+    
+    /// Print the page to a PDF document
+    async fn print_to_pdf(&self, options: &PdfOptions) -> Result<Vec<u8>>;
+    
+    /// Highlight matches of `text` and select the first in the given
+    /// direction, or the next one if `text` is already being searched for
+    async fn find(&self, text: &str, case_sensitive: bool, forward: bool) -> Result<FindMatches>;
+    
+    /// Select the next match of the current search, in its direction
+    async fn find_next(&self) -> Result<FindMatches>;
+    
+    /// End the current search and remove its highlights
+    async fn stop_finding(&self) -> Result<()>;
+    
+    /// Set where each page the engine loads from now on gets its zoom level
+    async fn set_zoom_levels(&self, levels: Arc<dyn ZoomLevels>) -> Result<()>;
+    
+    /// Zoom the current page; the next page loaded gets its site's level
+    async fn set_zoom(&self, level: f64) -> Result<()>;
+    
+    /// Size and scale the viewport like a device's, or like the engine
+    /// window again with `None`
+    async fn set_device_emulation(&self, emulation: Option<DeviceEmulation>) -> Result<()>;
 }
 
-/// Manages multiple browser engines and handles switching between them
+/// Navigation history recorded for a tab
+#[derive(Debug, Clone, Default)]
+pub struct TabHistory {
+    pub entries: Vec<String>,
+    pub index: usize,
+    /// Whether the engine is loading the entry stepped to, which the next
+    /// commit lands on instead of adding one
+    traversing: bool,
+}
+
+impl TabHistory {
+    pub fn new(entries: Vec<String>, index: usize) -> Self {
+        let index = index.min(entries.len().saturating_sub(1));
+        Self {
+            entries,
+            index,
+            traversing: false,
+        }
+    }
+
+    /// Record a new navigation, discarding any forward entries
+    pub fn push(&mut self, url: String) {
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.index + 1);
+        }
+        self.entries.push(url);
+        self.index = self.entries.len() - 1;
+    }
+
+    /// Record a document the engine committed. Returns whether the history changed.
+    pub fn commit(&mut self, url: String) -> bool {
+        // A site may redirect an entry somewhere else when it is gone back to
+        if std::mem::take(&mut self.traversing) {
+            return match self.entries.get_mut(self.index) {
+                Some(entry) if *entry != url => {
+                    *entry = url;
+                    true
+                }
+                _ => false,
+            };
+        }
+        // Reloads, and restored tabs loading their current entry
+        if self.current() == Some(url.as_str()) {
+            return false;
+        }
+        self.push(url);
+        true
+    }
+
+    /// Leave a step back or forward the engine never committed, so the next
+    /// commit adds an entry again
+    pub fn end_traversal(&mut self) {
+        self.traversing = false;
+    }
+
+    pub fn back(&mut self) -> Option<String> {
+        if self.index == 0 || self.entries.is_empty() {
+            return None;
+        }
+        self.index -= 1;
+        self.traversing = true;
+        self.entries.get(self.index).cloned()
+    }
+
+    pub fn forward(&mut self) -> Option<String> {
+        if self.index + 1 >= self.entries.len() {
+            return None;
+        }
+        self.index += 1;
+        self.traversing = true;
+        self.entries.get(self.index).cloned()
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.entries.get(self.index).map(String::as_str)
+    }
+}
+
+/// A browser tab and the engine instance rendering it
+pub struct Tab {
+    pub id: TabId,
+    pub engine: Arc<dyn Engine>,
+    pub privacy_mode: PrivacyMode,
+    pub network_route: NetworkRoute,
+    pub pinned: bool,
+    pub history: TabHistory,
+    /// Whether the current page was upgraded from HTTP by HTTPS-Only mode
+    pub https_upgraded: bool,
+    /// Fingerprinting protection the engine was launched with
+    pub fingerprint: Option<FingerprintProfile>,
+    pub container: Option<ContainerId>,
+    pub window: WindowId,
+    pub emulation: Option<DeviceEmulation>,
+}
+
+impl Tab {
+    pub fn info(&self) -> TabInfo {
+        TabInfo {
+            id: self.id,
+            url: self.engine.current_url(),
+            title: self.engine.title(),
+            engine: self.engine.engine_type(),
+            privacy_mode: self.privacy_mode.clone(),
+            network_route: self.network_route.clone(),
+            network_path: crate::network::describe_path(&self.network_route),
+            pinned: self.pinned,
+            https_upgraded: self.https_upgraded,
+            content_origin: None,
+            container: self.container,
+            window: self.window,
+            emulation: self.emulation,
+        }
+    }
+}
+
+/// Settings used when opening a new tab
+#[derive(Debug, Clone)]
+pub struct TabOptions {
+    pub engine: BrowserEngine,
+    pub privacy_mode: PrivacyMode,
+    pub network_route: NetworkRoute,
+    pub pinned: bool,
+    pub history: TabHistory,
+    pub container: Option<ContainerId>,
+    pub window: WindowId,
+}
+
+impl Default for TabOptions {
+    fn default() -> Self {
+        Self {
+            engine: BrowserEngine::Blink,
+            privacy_mode: PrivacyMode::Normal,
+            network_route: NetworkRoute::default(),
+            pinned: false,
+            history: TabHistory::default(),
+            container: None,
+            window: MAIN_WINDOW,
+        }
+    }
+}
+
+/// Size of engine windows, which fingerprinting protection letterboxes
+pub const WINDOW_SIZE: (u32, u32) = (1280, 800);
+
+/// Process-level settings an engine is launched with
+#[derive(Debug, Clone, Default)]
+pub struct LaunchOptions {
+    /// Proxy all traffic goes through, e.g. `socks5://127.0.0.1:1080`.
+    /// Host names are passed to the proxy unresolved.
+    pub proxy_server: Option<String>,
+    /// Values reported to pages instead of the real ones
+    pub fingerprint: Option<FingerprintProfile>,
+    /// Keep WebRTC, DNS prefetching and QUIC from reaching the network
+    /// around the proxy
+    pub leak_protection: bool,
+    pub third_party_cookies: ThirdPartyCookies,
+}
+
+/// An engine process holding containers, each in a browser context of its
+/// own with its own cookies, storage and proxy
+#[async_trait]
+pub trait ContainerHost: Send + Sync {
+    /// Open a page in a new browser context proxied through `proxy_server`
+    async fn open_context(
+        &self,
+        proxy_server: String,
+        fingerprint: Option<FingerprintProfile>,
+    ) -> Result<Arc<dyn Engine>>;
+}
+
+/// Where traffic of a container host outside its containers' contexts is
+/// sent: nothing listens there, so it fails instead of going direct
+const NO_PROXY: &str = "socks5://127.0.0.1:9";
+
+/// Whether an engine on this route is launched with leak protection. Direct
+/// tabs expose their address anyway, and WebRTC calls need UDP.
+pub fn leak_protection(route: &NetworkRoute) -> bool {
+    !matches!(route, NetworkRoute::Direct { .. })
+}
+
+/// Create a new engine instance of the given type
+pub async fn create_engine(engine_type: &BrowserEngine, options: &LaunchOptions) -> Result<Arc<dyn Engine>> {
+    let engine: Arc<dyn Engine> = match engine_type {
+        BrowserEngine::Blink => Arc::new(blink::BlinkEngine::new(options).await?),
+        BrowserEngine::Gecko => todo!("Implement Gecko engine"),
+        BrowserEngine::Webkit => todo!("Implement WebKit engine"),
+        BrowserEngine::Servo => todo!("Implement Servo engine"),
+    };
+    Ok(engine)
+}
+
+/// Launch an engine process for containers
+async fn create_container_host(engine_type: &BrowserEngine, options: &LaunchOptions) -> Result<Arc<dyn ContainerHost>> {
+    let host: Arc<dyn ContainerHost> = match engine_type {
+        BrowserEngine::Blink => Arc::new(blink::BlinkContainerHost::new(options).await?),
+        BrowserEngine::Gecko => todo!("Implement Gecko engine"),
+        BrowserEngine::Webkit => todo!("Implement WebKit engine"),
+        BrowserEngine::Servo => todo!("Implement Servo engine"),
+    };
+    Ok(host)
+}
+
+/// Whether two cookies are the same entry of a cookie jar
+pub fn same_cookie(a: &CookieInfo, b: &CookieInfo) -> bool {
+    a.name == b.name && a.domain == b.domain && a.path == b.path && a.partition == b.partition
+}
+
+/// Cookies of the engines, each listed once
+async fn collect_cookies(engines: Vec<Arc<dyn Engine>>) -> Result<Vec<CookieInfo>> {
+    let mut cookies: Vec<CookieInfo> = Vec::new();
+    for engine in engines {
+        for cookie in engine.cookies().await? {
+            if !cookies.iter().any(|known| same_cookie(known, &cookie)) {
+                cookies.push(cookie);
+            }
+        }
+    }
+    cookies.sort_by(|a, b| (&a.domain, &a.name, &a.path).cmp(&(&b.domain, &b.name, &b.path)));
+    Ok(cookies)
+}
+
+/// Manages the open tabs and the windows they are shown in. Each tab has an
+/// engine instance of its own, except container tabs: every container is a
+/// browser context in one shared engine, and its tabs are pages in it.
 pub struct EngineManager {
-    current_engine: Box<dyn Engine>,
+    tabs: RwLock<Vec<Tab>>,
+    /// Active tab of the active window
+    active_tab: RwLock<Option<TabId>>,
+    next_tab_id: AtomicU64,
+    /// Open windows and the tab each shows
+    windows: RwLock<BTreeMap<WindowId, Option<TabId>>>,
+    active_window: RwLock<WindowId>,
+    next_window_id: AtomicU64,
+    interceptors: Arc<InterceptorChain>,
+    permission_handler: RwLock<Option<Arc<dyn PermissionHandler>>>,
+    zoom_levels: RwLock<Option<Arc<dyn ZoomLevels>>>,
+    third_party_cookies: RwLock<ThirdPartyCookies>,
+    protocols: Arc<ProtocolHandlers>,
+    /// Engines holding the containers, one per engine type in use
+    container_hosts: tokio::sync::Mutex<Vec<(BrowserEngine, Arc<dyn ContainerHost>)>>,
+    /// Documents the tabs' engines commit, recorded into their history
+    committed_tx: mpsc::UnboundedSender<CommittedNavigation>,
+    committed_rx: Mutex<Option<mpsc::UnboundedReceiver<CommittedNavigation>>>,
+    network: Arc<dyn NetworkManager>,
     shared_state: SharedState,
     event_tx: broadcast::Sender<IPCEvent>,
 }
 
 impl EngineManager {
     pub fn new(
-        initial_engine: Box<dyn Engine>,
+        network: Arc<dyn NetworkManager>,
         shared_state: SharedState,
         event_tx: broadcast::Sender<IPCEvent>,
     ) -> Self {
+        let (committed_tx, committed_rx) = mpsc::unbounded_channel();
         Self {
-            current_engine: initial_engine,
+            tabs: RwLock::new(Vec::new()),
+            active_tab: RwLock::new(None),
+            next_tab_id: AtomicU64::new(1),
+            windows: RwLock::new(BTreeMap::from([(MAIN_WINDOW, None)])),
+            active_window: RwLock::new(MAIN_WINDOW),
+            next_window_id: AtomicU64::new(MAIN_WINDOW + 1),
+            interceptors: Arc::new(InterceptorChain::default()),
+            permission_handler: RwLock::new(None),
+            zoom_levels: RwLock::new(None),
+            third_party_cookies: RwLock::new(ThirdPartyCookies::default()),
+            protocols: Arc::new(ProtocolHandlers::default()),
+            container_hosts: tokio::sync::Mutex::new(Vec::new()),
+            committed_tx,
+            committed_rx: Mutex::new(Some(committed_rx)),
+            network,
             shared_state,
             event_tx,
         }
     }
-    
-    pub async fn switch_engine(&mut self, engine_type: BrowserEngine) -> Result<()> {
-        // Create new engine instance based on type
-        let new_engine: Box<dyn Engine> = match engine_type {
-            BrowserEngine::Blink => Box::new(blink::BlinkEngine::new().await?),
-            BrowserEngine::Gecko => todo!("Implement Gecko engine"),
-            BrowserEngine::Webkit => todo!("Implement WebKit engine"),
-            BrowserEngine::Servo => todo!("Implement Servo engine"),
+
+    /// Record the documents tabs commit into their history for as long as the
+    /// manager lives
+    pub fn start_history(self: &Arc<Self>) {
+        let Some(mut committed) = self.committed_rx.lock().take() else {
+            return;
+        };
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            while let Some(navigation) = committed.recv().await {
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                manager.record_navigation(navigation);
+            }
+        });
+    }
+
+    /// Add a committed document to its tab's history. Pages loaded outside
+    /// the history, like error pages and the fingerprint test page, are skipped.
+    fn record_navigation(&self, navigation: CommittedNavigation) {
+        if ["about:", "data:", "chrome-error:"]
+            .iter()
+            .any(|scheme| navigation.url.starts_with(scheme))
+        {
+            return;
+        }
+        let url = self.protocols.display_url(navigation.url);
+        // The tab may have closed since
+        let changed = self
+            .tabs
+            .write()
+            .iter_mut()
+            .find(|tab| tab.id == navigation.tab)
+            .is_some_and(|tab| tab.history.commit(url));
+        if changed {
+            self.publish_state();
+        }
+    }
+
+    /// Add an interceptor consulted for the requests of every tab
+    pub fn add_request_interceptor(&self, interceptor: Arc<dyn RequestInterceptor>) {
+        self.interceptors.push(interceptor);
+    }
+
+    /// Set the handler deciding permission requests in tabs opened from now on
+    pub fn set_permission_handler(&self, handler: Arc<dyn PermissionHandler>) {
+        *self.permission_handler.write() = Some(handler);
+    }
+
+    /// Set where tabs opened from now on get each site's zoom level
+    pub fn set_zoom_levels(&self, levels: Arc<dyn ZoomLevels>) {
+        let levels = DisplayedZoomLevels {
+            levels,
+            protocols: self.protocols.clone(),
+        };
+        *self.zoom_levels.write() = Some(Arc::new(levels));
+    }
+
+    /// Add a handler for a scheme, used for every navigation
+    pub fn add_protocol_handler(&self, handler: Arc<dyn ProtocolHandler>) {
+        self.protocols.push(handler);
+    }
+
+    pub fn protocols(&self) -> &ProtocolHandlers {
+        &self.protocols
+    }
+
+    /// Launch an engine for a tab against the tab's own network endpoint,
+    /// protected against fingerprinting as its privacy mode asks. A container
+    /// tab gets a page in a new browser context of the container host
+    /// instead, or with a `host` tab, a page in the host's context, sharing
+    /// its network endpoint.
+    async fn create_tab_engine(
+        &self,
+        id: TabId,
+        engine_type: &BrowserEngine,
+        route: &NetworkRoute,
+        privacy_mode: &PrivacyMode,
+        container: Option<ContainerId>,
+        host: Option<(TabId, Arc<dyn Engine>)>,
+    ) -> Result<(Arc<dyn Engine>, Option<FingerprintProfile>)> {
+        let fingerprint = FingerprintProfile::for_mode(privacy_mode, WINDOW_SIZE);
+        let engine = match (host, container) {
+            (Some((host, host_engine)), _) => {
+                if host != id {
+                    self.network.share_endpoint(id, host).await?;
+                }
+                host_engine.open_sibling(fingerprint.clone()).await?
+            }
+            (None, Some(_)) => {
+                let proxy_server = self.network.open_endpoint(id, route).await?;
+                self.container_host(engine_type)
+                    .await?
+                    .open_context(proxy_server, fingerprint.clone())
+                    .await?
+            }
+            (None, None) => {
+                let options = LaunchOptions {
+                    proxy_server: Some(self.network.open_endpoint(id, route).await?),
+                    fingerprint: fingerprint.clone(),
+                    leak_protection: leak_protection(route),
+                    third_party_cookies: *self.third_party_cookies.read(),
+                };
+                create_engine(engine_type, &options).await?
+            }
         };
-        
-        // Get current URL to restore in new engine
-        let current_url = self.current_engine.current_url();
-        
-        // Switch engines
-        self.current_engine = new_engine;
-        
+        engine.set_request_interceptor(id, self.interceptors.clone()).await?;
+        engine.set_navigation_listener(id, self.committed_tx.clone()).await?;
+        let permission_handler = self.permission_handler.read().clone();
+        if let Some(handler) = permission_handler {
+            engine.set_permission_handler(id, handler).await?;
+        }
+        let zoom_levels = self.zoom_levels.read().clone();
+        if let Some(levels) = zoom_levels {
+            engine.set_zoom_levels(levels).await?;
+        }
+        Ok((engine, fingerprint))
+    }
+
+    /// The engine holding the containers, launched when the first one opens.
+    /// It is shared by containers on any route, so it always runs with leak
+    /// protection.
+    async fn container_host(&self, engine_type: &BrowserEngine) -> Result<Arc<dyn ContainerHost>> {
+        let mut hosts = self.container_hosts.lock().await;
+        if let Some((_, host)) = hosts.iter().find(|(host_type, _)| host_type == engine_type) {
+            return Ok(host.clone());
+        }
+        let options = LaunchOptions {
+            proxy_server: Some(String::from(NO_PROXY)),
+            fingerprint: None,
+            leak_protection: true,
+            third_party_cookies: *self.third_party_cookies.read(),
+        };
+        let host = create_container_host(engine_type, &options).await?;
+        hosts.push((engine_type.clone(), host.clone()));
+        Ok(host)
+    }
+
+    /// Let go of container hosts no container tab uses any more; each closes
+    /// once its last page has
+    async fn release_container_hosts(&self) {
+        let in_use: Vec<BrowserEngine> = self
+            .tabs
+            .read()
+            .iter()
+            .filter(|tab| tab.container.is_some())
+            .map(|tab| tab.engine.engine_type())
+            .collect();
+        self.container_hosts
+            .lock()
+            .await
+            .retain(|(engine_type, _)| in_use.contains(engine_type));
+    }
+
+    /// An open tab of a container, whose engine the container's new tabs join
+    fn container_tab(&self, container: ContainerId) -> Option<(TabId, Arc<dyn Engine>)> {
+        self.tabs
+            .read()
+            .iter()
+            .find(|tab| tab.container == Some(container))
+            .map(|tab| (tab.id, tab.engine.clone()))
+    }
+
+    /// Open a new tab and make it the active one, activating its window. A tab
+    /// opened in a container with open tabs joins their engine, and its route.
+    pub async fn open_tab(&self, options: TabOptions, url: Option<String>) -> Result<TabId> {
+        if !self.windows.read().contains_key(&options.window) {
+            return Err(anyhow::anyhow!("Unknown window: {}", options.window));
+        }
+        let id = self.next_tab_id.fetch_add(1, Ordering::SeqCst);
+        let host = options.container.and_then(|container| self.container_tab(container));
+        let (engine_type, network_route) = match &host {
+            Some((host, _)) => self.with_tab(*host, |tab| (tab.engine.engine_type(), tab.network_route.clone()))?,
+            None => (options.engine.clone(), options.network_route.clone()),
+        };
+        let created = self
+            .create_tab_engine(
+                id,
+                &engine_type,
+                &network_route,
+                &options.privacy_mode,
+                options.container,
+                host,
+            )
+            .await;
+        let (engine, fingerprint) = match created {
+            Ok(created) => created,
+            Err(e) => {
+                self.network.close_endpoint(id).await;
+                return Err(e);
+            }
+        };
+        let mut history = options.history;
+
+        // Load the requested page, or the current history entry of a restored tab
+        let initial_url = url.clone().or_else(|| history.current().map(String::from));
+        if let Some(initial_url) = initial_url {
+            let loaded = match initial_url.as_str() {
+                "about:blank" => Ok(()),
+                _ => match self.protocols.engine_url(initial_url.clone()) {
+                    Ok(engine_url) => engine.navigate(engine_url).await,
+                    Err(e) => Err(e),
+                },
+            };
+            if let Err(e) = loaded {
+                // The engine closes as it is dropped, so only the endpoint is left
+                drop(engine);
+                self.network.close_endpoint(id).await;
+                return Err(e);
+            }
+            // The committed URL, after any redirect or HTTPS upgrade; the
+            // engine's own report of it comes before the tab is listed
+            let committed = engine.current_url();
+            if url.is_some() && !committed.starts_with("about:") {
+                history.commit(self.protocols.display_url(committed));
+            }
+        }
+
+        self.tabs.write().push(Tab {
+            id,
+            engine,
+            privacy_mode: options.privacy_mode,
+            network_route,
+            pinned: options.pinned,
+            history,
+            https_upgraded: false,
+            fingerprint,
+            container: options.container,
+            window: options.window,
+            emulation: None,
+        });
+        self.show_tab(options.window, Some(id));
+        *self.active_window.write() = options.window;
+        *self.active_tab.write() = Some(id);
+
+        self.publish_state();
+        Ok(id)
+    }
+
+    /// Close a tab, activating its neighbour in the window if it was the
+    /// window's active one
+    pub async fn close_tab(&self, id: TabId) -> Result<()> {
+        {
+            let mut tabs = self.tabs.write();
+            let position = tabs
+                .iter()
+                .position(|tab| tab.id == id)
+                .ok_or_else(|| anyhow::anyhow!("Unknown tab: {}", id))?;
+            let window = tabs.remove(position).window;
+
+            if self.windows.read().get(&window) == Some(&Some(id)) {
+                let in_window = |tab: &&Tab| tab.window == window;
+                let neighbour = tabs[position..]
+                    .iter()
+                    .find(in_window)
+                    .or_else(|| tabs.iter().rfind(in_window))
+                    .map(|tab| tab.id);
+                self.show_tab(window, neighbour);
+            }
+        }
+
+        self.network.close_endpoint(id).await;
+        self.release_container_hosts().await;
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Make a tab the active one, activating its window
+    pub fn activate_tab(&self, id: TabId) -> Result<()> {
+        let window = self.with_tab(id, |tab| tab.window)?;
+        self.show_tab(window, Some(id));
+        *self.active_window.write() = window;
+        *self.active_tab.write() = Some(id);
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Record the tab a window shows, which is the active tab if the window is active
+    fn show_tab(&self, window: WindowId, tab: Option<TabId>) {
+        if let Some(shown) = self.windows.write().get_mut(&window) {
+            *shown = tab;
+        }
+        if *self.active_window.read() == window {
+            *self.active_tab.write() = tab;
+        }
+    }
+
+    /// Add a window with no tabs yet
+    pub fn open_window(&self) -> WindowId {
+        let id = self.next_window_id.fetch_add(1, Ordering::SeqCst);
+        self.windows.write().insert(id, None);
+        self.publish_state();
+        id
+    }
+
+    /// Close a window's tabs and the window, activating another window if
+    /// it was the active one
+    pub async fn close_window(&self, window: WindowId) -> Result<()> {
+        if !self.windows.read().contains_key(&window) {
+            return Err(anyhow::anyhow!("Unknown window: {}", window));
+        }
+        let tabs: Vec<TabId> = self
+            .tabs
+            .read()
+            .iter()
+            .filter(|tab| tab.window == window)
+            .map(|tab| tab.id)
+            .collect();
+        for id in tabs {
+            self.close_tab(id).await?;
+        }
+
+        let next = {
+            let mut windows = self.windows.write();
+            windows.remove(&window);
+            windows.iter().next().map(|(id, tab)| (*id, *tab))
+        };
+        if *self.active_window.read() == window {
+            if let Some((id, tab)) = next {
+                *self.active_window.write() = id;
+                *self.active_tab.write() = tab;
+            }
+        }
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Make a window the active one, so commands act on the tab it shows
+    pub fn activate_window(&self, window: WindowId) -> Result<()> {
+        let tab = *self
+            .windows
+            .read()
+            .get(&window)
+            .ok_or_else(|| anyhow::anyhow!("Unknown window: {}", window))?;
+        *self.active_window.write() = window;
+        *self.active_tab.write() = tab;
+        self.publish_state();
+        Ok(())
+    }
+
+    pub fn active_window(&self) -> WindowId {
+        *self.active_window.read()
+    }
+
+    pub fn windows(&self) -> Vec<WindowInfo> {
+        self.windows
+            .read()
+            .iter()
+            .map(|(id, active_tab)| WindowInfo {
+                id: *id,
+                active_tab: *active_tab,
+            })
+            .collect()
+    }
+
+    pub fn set_pinned(&self, id: TabId, pinned: bool) -> Result<()> {
+        self.update_tab(id, |tab| tab.pinned = pinned)
+    }
+
+    /// Change a tab's privacy mode, relaunching its engine when the mode
+    /// calls for different fingerprinting protection. A container tab gets a
+    /// new page in the container's engine instead.
+    pub async fn set_privacy_mode(&self, id: TabId, mode: PrivacyMode) -> Result<()> {
+        let (engine, protection, container) = self.with_tab(id, |tab| {
+            (
+                tab.engine.clone(),
+                tab.fingerprint.as_ref().map(|profile| profile.protection),
+                tab.container,
+            )
+        })?;
+        if protection != Protection::for_mode(&mode) {
+            let engine_type = engine.engine_type();
+            let host = container.map(|_| (id, engine));
+            let (engine, fingerprint) = self.launch_replacement(id, &engine_type, &mode, host).await?;
+            self.update_tab(id, |tab| {
+                tab.engine = engine;
+                tab.fingerprint = fingerprint;
+            })?;
+        }
+        self.update_tab(id, |tab| tab.privacy_mode = mode)
+    }
+
+    /// Move a tab to another route, relaunching its engine when leak
+    /// protection turns on or off or the engine's proxy scheme changes. A
+    /// container's tabs share an engine, so they all move.
+    pub async fn set_network_route(&self, id: TabId, route: NetworkRoute) -> Result<()> {
+        let (engine_type, old_route, container) = self.with_tab(id, |tab| {
+            (
+                tab.engine.engine_type(),
+                tab.network_route.clone(),
+                tab.container,
+            )
+        })?;
+        self.network.set_route(id, &route).await?;
+        // Container hosts always run with leak protection
+        let protection_changed = container.is_none() && leak_protection(&old_route) != leak_protection(&route);
+        if protection_changed || http_proxy(&old_route) != http_proxy(&route) {
+            if let Err(e) = self.relaunch(id, engine_type).await {
+                // No tab moved to a new engine, so the endpoint goes back to
+                // the route their engines were launched for
+                if let Err(rollback) = self.network.set_route(id, &old_route).await {
+                    return Err(anyhow::anyhow!("{}; restoring the old route failed: {}", e, rollback));
+                }
+                return Err(e);
+            }
+        }
+        {
+            let mut tabs = self.tabs.write();
+            let moved = tabs
+                .iter_mut()
+                .filter(|tab| tab.id == id || (container.is_some() && tab.container == container));
+            for tab in moved {
+                tab.network_route = route.clone();
+            }
+        }
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Emulate a device in a tab, or stop with `None`; kept when the tab's
+    /// engine is relaunched
+    pub async fn set_device_emulation(&self, id: TabId, emulation: Option<DeviceEmulation>) -> Result<()> {
+        if let Some(device) = &emulation {
+            if !(1..=10_000).contains(&device.width) || !(1..=10_000).contains(&device.height) {
+                return Err(anyhow::anyhow!("Invalid viewport size: {}x{}", device.width, device.height));
+            }
+            if !(device.device_scale_factor > 0.0 && device.device_scale_factor <= 10.0) {
+                return Err(anyhow::anyhow!("Invalid device scale factor: {}", device.device_scale_factor));
+            }
+        }
+        self.engine_for(id)?.set_device_emulation(emulation).await?;
+        self.update_tab(id, |tab| tab.emulation = emulation)
+    }
+
+    pub fn set_https_upgraded(&self, id: TabId, upgraded: bool) -> Result<()> {
+        self.update_tab(id, |tab| tab.https_upgraded = upgraded)
+    }
+
+    fn with_tab<R>(&self, id: TabId, f: impl FnOnce(&Tab) -> R) -> Result<R> {
+        self.tabs
+            .read()
+            .iter()
+            .find(|tab| tab.id == id)
+            .map(f)
+            .ok_or_else(|| anyhow::anyhow!("Unknown tab: {}", id))
+    }
+
+    fn update_tab(&self, id: TabId, update: impl FnOnce(&mut Tab)) -> Result<()> {
+        {
+            let mut tabs = self.tabs.write();
+            let tab = tabs
+                .iter_mut()
+                .find(|tab| tab.id == id)
+                .ok_or_else(|| anyhow::anyhow!("Unknown tab: {}", id))?;
+            update(tab);
+        }
+        self.publish_state();
+        Ok(())
+    }
+
+    pub fn active_tab_id(&self) -> Result<TabId> {
+        self.active_tab
+            .read()
+            .ok_or_else(|| anyhow::anyhow!("No active tab"))
+    }
+
+    /// The tab a command names, or the active tab when it names none
+    pub fn resolve_tab(&self, tab: Option<TabId>) -> Result<TabId> {
+        match tab {
+            Some(tab) => Ok(tab),
+            None => self.active_tab_id(),
+        }
+    }
+
+    /// Get the engine rendering the active tab
+    pub fn current_engine(&self) -> Result<Arc<dyn Engine>> {
+        self.engine_for(self.active_tab_id()?)
+    }
+
+    pub fn engine_for(&self, id: TabId) -> Result<Arc<dyn Engine>> {
+        self.with_tab(id, |tab| tab.engine.clone())
+    }
+
+    /// Load the fingerprint test page in the active tab, outside its history.
+    /// Returns the profile the page checks against.
+    pub async fn check_fingerprint(&self) -> Result<Option<FingerprintProfile>> {
+        let id = self.active_tab_id()?;
+        let (engine, profile) = self.with_tab(id, |tab| (tab.engine.clone(), tab.fingerprint.clone()))?;
+        engine.navigate(fingerprint::test_page(profile.as_ref())).await?;
+        Ok(profile)
+    }
+
+    /// Navigate the active tab. Its history records the document the engine
+    /// commits, wherever the URL redirects to.
+    pub async fn navigate(&self, url: String) -> Result<()> {
+        let id = self.active_tab_id()?;
+        let engine_url = self.protocols.engine_url(url)?;
+        let (engine, _) = self.step_history(id, |history| {
+            history.end_traversal();
+            None
+        })?;
+        engine.navigate(engine_url).await
+    }
+
+    /// Go back using the recorded history, which also covers restored tabs
+    /// whose engine has no history of its own
+    pub async fn go_back(&self) -> Result<()> {
+        let id = self.active_tab_id()?;
+        let (engine, url) = self.step_history(id, TabHistory::back)?;
+        if let Some(url) = url {
+            engine.navigate(self.protocols.engine_url(url)?).await?;
+            self.publish_state();
+        }
+        Ok(())
+    }
+
+    pub async fn go_forward(&self) -> Result<()> {
+        let id = self.active_tab_id()?;
+        let (engine, url) = self.step_history(id, TabHistory::forward)?;
+        if let Some(url) = url {
+            engine.navigate(self.protocols.engine_url(url)?).await?;
+            self.publish_state();
+        }
+        Ok(())
+    }
+
+    fn step_history(
+        &self,
+        id: TabId,
+        step: fn(&mut TabHistory) -> Option<String>,
+    ) -> Result<(Arc<dyn Engine>, Option<String>)> {
+        let mut tabs = self.tabs.write();
+        let tab = tabs
+            .iter_mut()
+            .find(|tab| tab.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown tab: {}", id))?;
+        Ok((tab.engine.clone(), step(&mut tab.history)))
+    }
+
+    /// Run the leak self-test in a separate engine launched like the active
+    /// tab's, against stand-ins instead of the tab's route
+    pub async fn check_leaks(&self) -> Result<LeakReport> {
+        let id = self.active_tab_id()?;
+        let (engine_type, fingerprint, protected) = self.with_tab(id, |tab| {
+            (
+                tab.engine.engine_type(),
+                tab.fingerprint.clone(),
+                tab.container.is_some() || leak_protection(&tab.network_route),
+            )
+        })?;
+
+        let check = LeakCheck::start().await?;
+        let options = LaunchOptions {
+            proxy_server: Some(check.proxy_url()),
+            fingerprint,
+            leak_protection: protected,
+            third_party_cookies: *self.third_party_cookies.read(),
+        };
+        let engine = create_engine(&engine_type, &options).await?;
+        engine.navigate(check.page_url()).await?;
+        check.finish(protected).await
+    }
+
+    /// Change third-party cookie handling, relaunching every tab's engine
+    pub async fn set_third_party_cookies(&self, mode: ThirdPartyCookies) -> Result<()> {
+        if std::mem::replace(&mut *self.third_party_cookies.write(), mode) == mode {
+            return Ok(());
+        }
+        // Containers move to a host launched with the new mode
+        self.container_hosts.lock().await.clear();
+        let tabs: Vec<_> = self
+            .tabs
+            .read()
+            .iter()
+            .map(|tab| (tab.id, tab.engine.engine_type(), tab.container))
+            .collect();
+        let mut relaunched: Vec<ContainerId> = Vec::new();
+        for (id, engine_type, container) in tabs {
+            if let Some(container) = container {
+                if relaunched.contains(&container) {
+                    continue;
+                }
+                relaunched.push(container);
+            }
+            self.relaunch(id, engine_type).await?;
+        }
+        Ok(())
+    }
+
+    fn engines(&self) -> Vec<Arc<dyn Engine>> {
+        self.tabs.read().iter().map(|tab| tab.engine.clone()).collect()
+    }
+
+    /// Cookies of every tab's engine, each listed once
+    pub async fn cookies(&self) -> Result<Vec<CookieInfo>> {
+        collect_cookies(self.engines()).await
+    }
+
+    /// Delete the cookies `filter` selects from every tab's engine, returning
+    /// how many distinct cookies went
+    pub async fn delete_cookies(&self, filter: impl Fn(&CookieInfo) -> bool) -> Result<usize> {
+        let mut deleted: Vec<CookieInfo> = Vec::new();
+        for engine in self.engines() {
+            let cookies: Vec<_> = engine.cookies().await?.into_iter().filter(|cookie| filter(cookie)).collect();
+            if cookies.is_empty() {
+                continue;
+            }
+            engine.delete_cookies(&cookies).await?;
+            for cookie in cookies {
+                if !deleted.iter().any(|known| same_cookie(known, &cookie)) {
+                    deleted.push(cookie);
+                }
+            }
+        }
+        Ok(deleted.len())
+    }
+
+    /// Replace the engine of the active tab, keeping its current page. In a
+    /// container, every tab of the container switches.
+    pub async fn switch_engine(&self, engine_type: BrowserEngine) -> Result<()> {
+        let id = self.active_tab_id()?;
+        self.relaunch(id, engine_type).await
+    }
+
+    /// Relaunch the engine of a tab, or the engine a container's tabs share,
+    /// moving the container's tabs over to the new one
+    async fn relaunch(&self, id: TabId, engine_type: BrowserEngine) -> Result<()> {
+        let container = self.with_tab(id, |tab| tab.container)?;
+        let tabs: Vec<_> = self
+            .tabs
+            .read()
+            .iter()
+            .filter(|tab| tab.id == id || (container.is_some() && tab.container == container))
+            .map(|tab| (tab.id, tab.privacy_mode.clone()))
+            .collect();
+        // Every new engine is ready before any tab moves over, so a failure
+        // leaves them all on their old one
+        let mut launched: Vec<(TabId, Arc<dyn Engine>, Option<FingerprintProfile>)> = Vec::new();
+        for (tab, privacy_mode) in tabs {
+            let host = launched.first().map(|(host, engine, _)| (*host, engine.clone()));
+            let (engine, fingerprint) = self.launch_replacement(tab, &engine_type, &privacy_mode, host).await?;
+            launched.push((tab, engine, fingerprint));
+        }
+        for (tab, engine, fingerprint) in launched {
+            self.update_tab(tab, |tab| {
+                tab.engine = engine;
+                tab.fingerprint = fingerprint;
+            })?;
+        }
+        self.release_container_hosts().await;
+        Ok(())
+    }
+
+    /// Launch a new engine for a tab, or open one in the `host` tab's, and
+    /// load the tab's current page in it
+    async fn launch_replacement(
+        &self,
+        id: TabId,
+        engine_type: &BrowserEngine,
+        privacy_mode: &PrivacyMode,
+        host: Option<(TabId, Arc<dyn Engine>)>,
+    ) -> Result<(Arc<dyn Engine>, Option<FingerprintProfile>)> {
+        // Create new engine instance based on type
+        let route = self
+            .network
+            .get_route(id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No network endpoint for tab {}", id))?;
+        let (container, emulation) = self.with_tab(id, |tab| (tab.container, tab.emulation))?;
+        let joins_host = host.is_some();
+        let (new_engine, fingerprint) = self
+            .create_tab_engine(id, engine_type, &route, privacy_mode, container, host)
+            .await?;
+        // A container relaunched in a new browser context takes its cookies along
+        if container.is_some() && !joins_host {
+            new_engine.set_cookies(&self.engine_for(id)?.cookies().await?).await?;
+        }
+        if emulation.is_some() {
+            new_engine.set_device_emulation(emulation).await?;
+        }
+
         // Restore current URL in new engine
+        let current_url = self.engine_for(id)?.current_url();
         if !current_url.is_empty() {
-            self.current_engine.navigate(current_url).await?;
+            new_engine.navigate(current_url).await?;
         }
-        
-        // Update shared state
-        {
+        Ok((new_engine, fingerprint))
+    }
+
+    /// Info on the open tabs, with the URLs engines load shown as the user's
+    pub fn tabs(&self) -> Vec<TabInfo> {
+        self.tabs
+            .read()
+            .iter()
+            .map(|tab| {
+                let mut info = tab.info();
+                info.url = self.protocols.display_url(info.url);
+                info.content_origin = self.protocols.content_origin(&info.url);
+                info
+            })
+            .collect()
+    }
+
+    /// Run a closure over the open tabs without cloning them
+    pub fn with_tabs<R>(&self, f: impl FnOnce(&[Tab], Option<TabId>) -> R) -> R {
+        f(&self.tabs.read(), *self.active_tab.read())
+    }
+
+    /// Mirror the tab set into the shared state and notify the UI
+    pub fn publish_state(&self) {
+        let tabs = self.tabs();
+        let active_tab = *self.active_tab.read();
+        // Engines of background tabs report their pages too, so the active
+        // tab's are put back
+        let page = active_tab.and_then(|id| {
+            self.with_tab(id, |tab| (tab.engine.favicon(), tab.engine.is_secure(), tab.engine.certificate_info()))
+                .ok()
+        });
+
+        let snapshot = {
             let mut state = self.shared_state.write();
-            state.current_engine = engine_type;
-        }
-        
-        // Notify UI of engine switch
-        self.event_tx
-            .send(IPCEvent::StateUpdate(self.shared_state.read().clone()))
-            .map_err(|e| anyhow::anyhow!("Failed to send state update: {}", e))?;
-            
-        Ok(())
+            if let Some(active) = tabs.iter().find(|tab| Some(tab.id) == active_tab) {
+                state.current_url = active.url.clone();
+                state.title = active.title.clone();
+                state.current_engine = active.engine.clone();
+                state.privacy_mode = active.privacy_mode.clone();
+                state.network_route = active.network_route.clone();
+                state.network_path = active.network_path.clone();
+                state.https_upgraded = active.https_upgraded;
+                state.content_origin = active.content_origin.clone();
+            }
+            if let Some((favicon, is_secure, certificate_info)) = page {
+                state.favicon = favicon;
+                state.is_secure = is_secure;
+                state.certificate_info = certificate_info;
+            }
+            state.tabs = tabs;
+            state.active_tab = active_tab;
+            state.windows = self.windows();
+            state.active_window = self.active_window();
+            state.clone()
+        };
+
+        // Having no subscribers yet is not an error
+        let _ = self.event_tx.send(IPCEvent::StateUpdate(Box::new(snapshot)));
     }
-    
-    pub fn current_engine(&self) -> &dyn Engine {
-        self.current_engine.as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str], index: usize) -> TabHistory {
+        TabHistory::new(entries.iter().map(|url| url.to_string()).collect(), index)
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn commits_add_entries_after_the_current_one() {
+        let mut history = history(&["https://a.example/", "https://b.example/"], 0);
+        assert!(history.commit("https://c.example/".to_string()));
+        assert_eq!(history.entries, ["https://a.example/", "https://c.example/"]);
+        assert_eq!(history.index, 1);
+
+        // Reloading the current entry adds nothing
+        assert!(!history.commit("https://c.example/".to_string()));
+        assert_eq!(history.entries.len(), 2);
+    }
+
+    #[test]
+    fn commits_after_a_step_land_on_the_entry_stepped_to() {
+        let mut history = history(&["https://a.example/", "https://b.example/"], 1);
+        assert_eq!(history.back().as_deref(), Some("https://a.example/"));
+        assert!(!history.commit("https://a.example/".to_string()));
+        assert_eq!(history.entries.len(), 2);
+
+        // Redirected on the way back
+        history.forward();
+        assert!(history.commit("https://b.example/moved".to_string()));
+        assert_eq!(history.entries, ["https://a.example/", "https://b.example/moved"]);
+
+        // A step the engine never committed is not taken for the next navigation
+        history.back();
+        history.end_traversal();
+        assert!(history.commit("https://c.example/".to_string()));
+        assert_eq!(history.entries, ["https://a.example/", "https://c.example/"]);
+    }
+}
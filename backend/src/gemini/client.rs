@@ -0,0 +1,193 @@
+use super::known_hosts::KnownHosts;
+use crate::network::dns::dot::subject_public_key_info;
+use anyhow::Result;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest request URL the protocol allows
+pub const MAX_URL: usize = 1024;
+/// Status, space, a 1024 byte meta and CRLF
+const MAX_HEADER: usize = 1029;
+const MAX_BODY: usize = 64 * 1024 * 1024;
+
+/// A response header and body
+pub struct GeminiResponse {
+    pub status: u8,
+    pub meta: String,
+    pub body: Vec<u8>,
+}
+
+/// Fetches `gemini://` URLs over TLS, trusting server keys on first use
+pub struct GeminiClient {
+    tls: TlsConnector,
+    known_hosts: Arc<KnownHosts>,
+}
+
+impl GeminiClient {
+    pub fn new(known_hosts: Arc<KnownHosts>) -> Self {
+        let verifier = TofuVerifier {
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        };
+        let config = rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth();
+        Self {
+            tls: TlsConnector::from(Arc::new(config)),
+            known_hosts,
+        }
+    }
+
+    pub async fn fetch(&self, url: &url::Url) -> Result<GeminiResponse> {
+        tokio::time::timeout(REQUEST_TIMEOUT, self.request(url))
+            .await
+            .map_err(|_| anyhow::anyhow!("Gemini request to {} timed out", url))?
+    }
+
+    async fn request(&self, url: &url::Url) -> Result<GeminiResponse> {
+        if url.as_str().len() > MAX_URL {
+            return Err(anyhow::anyhow!("Gemini URLs are limited to {} bytes", MAX_URL));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("{} has no host", url))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = url.port().unwrap_or(super::DEFAULT_PORT);
+
+        let stream = TcpStream::connect((host, port))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to {}:{}: {}", host, port, e))?;
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|e| anyhow::anyhow!("Invalid server name {}: {}", host, e))?;
+        let mut stream = self.tls.connect(server_name, stream).await?;
+
+        // The request, which may carry user input, is only sent once the key checks out
+        let certificate = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .ok_or_else(|| anyhow::anyhow!("{} sent no certificate", host))?;
+        let spki = subject_public_key_info(certificate)
+            .ok_or_else(|| anyhow::anyhow!("{} sent a malformed certificate", host))?;
+        self.known_hosts.check(&format!("{}:{}", host, port), spki).await?;
+
+        stream.write_all(format!("{}\r\n", url).as_bytes()).await?;
+        stream.flush().await?;
+
+        let mut response = Vec::new();
+        let mut buffer = [0u8; 16 * 1024];
+        loop {
+            match stream.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(length) => response.extend_from_slice(&buffer[..length]),
+                // Many servers close without a TLS close_notify; the body is complete anyway
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            if response.len() > MAX_HEADER + MAX_BODY {
+                return Err(anyhow::anyhow!("Response from {} is too large", host));
+            }
+        }
+        parse_response(response)
+    }
+}
+
+/// Split `<status> <meta>\r\n<body>`
+fn parse_response(mut response: Vec<u8>) -> Result<GeminiResponse> {
+    let end = response
+        .iter()
+        .take(MAX_HEADER)
+        .position(|&byte| byte == b'\n')
+        .ok_or_else(|| anyhow::anyhow!("Malformed Gemini response header"))?;
+    let body = response.split_off(end + 1);
+    let header = String::from_utf8(response).map_err(|_| anyhow::anyhow!("Gemini response header is not UTF-8"))?;
+    let header = header.trim_end_matches(['\r', '\n']);
+
+    let (status, meta) = header.split_once(' ').unwrap_or((header, ""));
+    if status.len() != 2 || !status.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(anyhow::anyhow!("Malformed Gemini status {:?}", status));
+    }
+    Ok(GeminiResponse {
+        status: status.parse()?,
+        meta: meta.trim().to_string(),
+        body,
+    })
+}
+
+/// Gemini servers mostly present self-signed certificates, so they are not
+/// checked against CAs; `KnownHosts` pins each server's key instead. The
+/// handshake signatures are still verified, proving the server holds the key.
+#[derive(Debug)]
+struct TofuVerifier {
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for TofuVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_response_header() {
+        let response = parse_response(b"20 text/gemini; lang=en\r\n# Hi\n".to_vec()).unwrap();
+        assert_eq!((response.status, response.meta.as_str()), (20, "text/gemini; lang=en"));
+        assert_eq!(response.body, b"# Hi\n");
+
+        // Bare LF and a missing meta are tolerated
+        let response = parse_response(b"51\n".to_vec()).unwrap();
+        assert_eq!((response.status, response.meta.as_str()), (51, ""));
+        assert!(response.body.is_empty());
+
+        for malformed in [&b"20 text/gemini"[..], b"2 x\r\n", b"ab x\r\n", b"\xff\xfe x\r\n"] {
+            assert!(parse_response(malformed.to_vec()).is_err(), "{:?}", malformed);
+        }
+        let mut long = b"20 ".to_vec();
+        long.extend([b'x'; MAX_HEADER]);
+        long.extend(b"\r\n");
+        assert!(parse_response(long).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt::Write;
+
+const STYLE: &str = "body{max-width:46em;margin:2em auto;padding:0 1em;font:1.05em/1.5 sans-serif}\
+pre{overflow-x:auto;padding:.5em;background:#8881}blockquote{margin-left:0;padding-left:1em;border-left:3px solid #8888}\
+.link{margin:.25em 0}.link::before{content:\"\\21D2  \"}";
+
+enum Block {
+    Paragraph,
+    List,
+    Preformatted,
+}
+
+/// Render gemtext as an HTML page. All text is escaped; `link` gives the
+/// href for a link target, or `None` to show the link as plain text.
+pub fn to_html(source: &str, lang: Option<&str>, link: impl Fn(&str) -> Option<String>) -> String {
+    let mut body = String::new();
+    let mut title = None;
+    let mut block = Block::Paragraph;
+
+    for line in source.lines() {
+        if let Block::Preformatted = block {
+            if line.starts_with("```") {
+                body.push_str("</pre>\n");
+                block = Block::Paragraph;
+            } else {
+                let _ = writeln!(body, "{}", escape_html(line));
+            }
+            continue;
+        }
+
+        let in_list = matches!(block, Block::List);
+        if in_list && !line.starts_with("* ") {
+            body.push_str("</ul>\n");
+            block = Block::Paragraph;
+        }
+
+        if let Some(alt) = line.strip_prefix("```") {
+            let alt = alt.trim();
+            match alt.is_empty() {
+                true => body.push_str("<pre>"),
+                false => {
+                    let _ = write!(body, "<pre aria-label=\"{}\">", escape_html(alt));
+                }
+            }
+            block = Block::Preformatted;
+        } else if let Some(rest) = line.strip_prefix("=>") {
+            let rest = rest.trim();
+            let (target, label) = rest
+                .split_once(char::is_whitespace)
+                .map(|(target, label)| (target, label.trim()))
+                .unwrap_or((rest, ""));
+            let label = if label.is_empty() { target } else { label };
+            match link(target) {
+                Some(href) => {
+                    let _ = writeln!(
+                        body,
+                        "<p class=\"link\"><a href=\"{}\">{}</a></p>",
+                        escape_html(&href),
+                        escape_html(label)
+                    );
+                }
+                None => {
+                    let _ = writeln!(body, "<p class=\"link\">{} <small>{}</small></p>", escape_html(label), escape_html(target));
+                }
+            }
+        } else if let Some(item) = line.strip_prefix("* ") {
+            if !in_list {
+                body.push_str("<ul>\n");
+                block = Block::List;
+            }
+            let _ = writeln!(body, "<li>{}</li>", escape_html(item.trim()));
+        } else if let Some(quote) = line.strip_prefix('>') {
+            let _ = writeln!(body, "<blockquote>{}</blockquote>", escape_html(quote.trim()));
+        } else if line.starts_with('#') {
+            let level = line.chars().take_while(|&c| c == '#').count().min(3);
+            let text = line[level..].trim();
+            title.get_or_insert_with(|| text.to_string());
+            let _ = writeln!(body, "<h{0}>{1}</h{0}>", level, escape_html(text));
+        } else if !line.trim().is_empty() {
+            let _ = writeln!(body, "<p>{}</p>", escape_html(line));
+        }
+    }
+    match block {
+        Block::Preformatted => body.push_str("</pre>\n"),
+        Block::List => body.push_str("</ul>\n"),
+        Block::Paragraph => {}
+    }
+
+    page(title.as_deref().unwrap_or_default(), lang, &body)
+}
+
+/// A complete page around already escaped `body` HTML
+pub fn page(title: &str, lang: Option<&str>, body: &str) -> String {
+    let lang = lang.map(|lang| format!(" lang=\"{}\"", escape_html(lang))).unwrap_or_default();
+    format!(
+        "<!DOCTYPE html>\n<html{}><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width\">\
+         <title>{}</title><style>{}</style></head>\n<body>\n{}</body></html>\n",
+        lang,
+        escape_html(title),
+        STYLE,
+        body
+    )
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(source: &str) -> String {
+        let html = to_html(source, None, |target| {
+            (!target.starts_with("javascript:")).then(|| format!("/go?{}", target))
+        });
+        let start = html.find("<body>\n").unwrap() + "<body>\n".len();
+        html[start..html.len() - "</body></html>\n".len()].to_string()
+    }
+
+    #[test]
+    fn renders_line_types() {
+        let source = "# Title\n\
+                      ## Section\n\
+                      #### Deep\n\
+                      text & more\n\
+                      \n\
+                      * one\n\
+                      * two\n\
+                      > quote\n\
+                      => gemini://example.org/ Example\n\
+                      => /bare\n\
+                      => javascript:alert(1) Click";
+        assert_eq!(
+            body(source),
+            "<h1>Title</h1>\n\
+             <h2>Section</h2>\n\
+             <h3># Deep</h3>\n\
+             <p>text &amp; more</p>\n\
+             <ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+             <blockquote>quote</blockquote>\n\
+             <p class=\"link\"><a href=\"/go?gemini://example.org/\">Example</a></p>\n\
+             <p class=\"link\"><a href=\"/go?/bare\">/bare</a></p>\n\
+             <p class=\"link\">Click <small>javascript:alert(1)</small></p>\n"
+        );
+        assert!(to_html(source, Some("en"), |_| None).contains("<html lang=\"en\"><head>"));
+        assert!(to_html(source, None, |_| None).contains("<title>Title</title>"));
+    }
+
+    #[test]
+    fn preformatted_text_is_kept_verbatim() {
+        assert_eq!(
+            body("```rust code\n# not a heading\n=> not a link\n```\nafter"),
+            "<pre aria-label=\"rust code\"># not a heading\n=&gt; not a link\n</pre>\n<p>after</p>\n"
+        );
+        // Blocks left open at the end are closed
+        assert_eq!(body("```\nx"), "<pre>x\n</pre>\n");
+        assert_eq!(body("* item"), "<ul>\n<li>item</li>\n</ul>\n");
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            body("<script>alert('x')</script>\n=> \"><img> \"label\""),
+            "<p>&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;</p>\n\
+             <p class=\"link\"><a href=\"/go?&quot;&gt;&lt;img&gt;\">&quot;label&quot;</a></p>\n"
+        );
+        assert!(page("<t>", Some("\"x"), "").contains("lang=\"&quot;x\"><head>"));
+    }
+}
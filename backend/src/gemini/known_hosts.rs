@@ -0,0 +1,110 @@
+use crate::ipc::KnownGeminiHost;
+use crate::storage;
+use anyhow::Result;
+use base64::Engine as _;
+use parking_lot::RwLock;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A server presented a different key than the one pinned for it
+#[derive(Debug)]
+pub struct CertificateChanged {
+    pub host: String,
+    pub pinned: String,
+    pub presented: String,
+}
+
+impl fmt::Display for CertificateChanged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The certificate of {} changed: {} was pinned, {} was presented",
+            self.host, self.pinned, self.presented
+        )
+    }
+}
+
+impl std::error::Error for CertificateChanged {}
+
+/// Gemini server keys, trusted on first use and pinned from then on
+pub struct KnownHosts {
+    hosts: RwLock<HashMap<String, KnownGeminiHost>>,
+    path: PathBuf,
+}
+
+impl KnownHosts {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            hosts: RwLock::new(HashMap::new()),
+            path,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        if let Some(hosts) = storage::load_json::<Vec<KnownGeminiHost>>(&self.path).await? {
+            *self.hosts.write() = hosts.into_iter().map(|host| (host.host.clone(), host)).collect();
+        }
+        Ok(())
+    }
+
+    pub fn hosts(&self) -> Vec<KnownGeminiHost> {
+        let mut hosts: Vec<_> = self.hosts.read().values().cloned().collect();
+        hosts.sort_by(|a, b| a.host.cmp(&b.host));
+        hosts
+    }
+
+    /// Forget a `host` or `host:port`, so its next certificate is trusted anew
+    pub async fn forget(&self, host: &str) -> Result<()> {
+        let host = host.trim().to_ascii_lowercase();
+        let host = match host.contains(':') {
+            true => host,
+            false => format!("{}:{}", host, super::DEFAULT_PORT),
+        };
+        if self.hosts.write().remove(&host).is_none() {
+            return Err(anyhow::anyhow!("No certificate is pinned for {}", host));
+        }
+        self.save().await
+    }
+
+    /// Pin the key of a server seen for the first time, or check it against the pin
+    pub async fn check(&self, host: &str, spki: &[u8]) -> Result<()> {
+        let digest = Sha256::digest(spki);
+        let fingerprint = format!("sha256/{}", base64::engine::general_purpose::STANDARD.encode(digest));
+        {
+            let mut hosts = self.hosts.write();
+            match hosts.get(host) {
+                Some(known) if known.fingerprint == fingerprint => return Ok(()),
+                Some(known) => {
+                    return Err(CertificateChanged {
+                        host: host.to_string(),
+                        pinned: known.fingerprint.clone(),
+                        presented: fingerprint,
+                    }
+                    .into())
+                }
+                None => {
+                    let first_seen = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0);
+                    hosts.insert(
+                        host.to_string(),
+                        KnownGeminiHost {
+                            host: host.to_string(),
+                            fingerprint,
+                            first_seen,
+                        },
+                    );
+                }
+            }
+        }
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<()> {
+        storage::save_json(&self.path, &self.hosts()).await
+    }
+}
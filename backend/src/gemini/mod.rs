@@ -0,0 +1,406 @@
+pub mod client;
+pub mod gemtext;
+pub mod known_hosts;
+
+use crate::engine::ProtocolHandler;
+use crate::ipc::{IPCEvent, KnownGeminiHost};
+use anyhow::Result;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Router;
+use client::{GeminiClient, GeminiResponse, MAX_URL};
+use gemtext::escape_html;
+use known_hosts::{CertificateChanged, KnownHosts};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+pub const DEFAULT_PORT: u16 = 1965;
+/// Longest DNS label, which bounds the subdomain form of a host
+const MAX_LABEL: usize = 63;
+/// Characters escaped in user input sent as a query
+const QUERY: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+/// Generated pages may not run scripts or load anything
+const PAGE_POLICY: &str = "default-src 'none'; style-src 'unsafe-inline'; sandbox allow-forms";
+
+/// Serves `gemini://` capsules to the engines as HTML.
+///
+/// Like the IPFS gateway, it runs on a loopback port with an origin per
+/// server: `gemini://example.org/page` loads from
+/// `http://example-org.gemini.localhost:<port>/page`, with `.p<port>` added
+/// for servers off port 1965. Gemtext is rendered to HTML with every line
+/// escaped; other content is passed through with scripts sandboxed.
+pub struct GeminiGateway {
+    client: GeminiClient,
+    known_hosts: Arc<KnownHosts>,
+    port: AtomicU16,
+    event_tx: broadcast::Sender<IPCEvent>,
+}
+
+impl GeminiGateway {
+    pub fn new(path: PathBuf, event_tx: broadcast::Sender<IPCEvent>) -> Self {
+        let known_hosts = Arc::new(KnownHosts::new(path));
+        Self {
+            client: GeminiClient::new(known_hosts.clone()),
+            known_hosts,
+            port: AtomicU16::new(0),
+            event_tx,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        self.known_hosts.load().await
+    }
+
+    pub fn known_hosts(&self) -> Vec<KnownGeminiHost> {
+        self.known_hosts.hosts()
+    }
+
+    pub async fn forget_host(&self, host: &str) -> Result<()> {
+        self.known_hosts.forget(host).await
+    }
+
+    /// Start serving on a loopback port. Engines reach `*.localhost` directly,
+    /// never through a tab's proxy.
+    pub async fn start(self: &Arc<Self>) -> Result<()> {
+        let listener = tokio::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        self.port.store(addr.port(), Ordering::Relaxed);
+
+        let app = Router::new().fallback(handle).with_state(self.clone());
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                error!("Gemini gateway stopped: {}", e);
+            }
+        });
+        info!("Gemini gateway listening on {}", addr);
+        Ok(())
+    }
+
+    /// The gateway URL a `gemini://` URL loads from
+    fn gateway_url(&self, url: &url::Url) -> Result<String> {
+        let port = self.port.load(Ordering::Relaxed);
+        if port == 0 {
+            return Err(anyhow::anyhow!("Gemini gateway is not running"));
+        }
+        let host = url.host_str().ok_or_else(|| anyhow::anyhow!("{} has no host", url))?;
+        // Hosts of non-special schemes stay percent-encoded and Unicode
+        let host = percent_decode_str(host).decode_utf8()?;
+        let host = idna::domain_to_ascii(&host).map_err(|e| anyhow::anyhow!("Invalid host {}: {}", host, e))?;
+        if host.is_empty() || host.contains(':') {
+            return Err(anyhow::anyhow!("{} is not a host name the gateway can serve", host));
+        }
+
+        let mut label = host.replace('-', "--").replace('.', "-");
+        // A leading `xn--` would make the label invalid punycode; hosts never start with `-`
+        if label.starts_with("xn--") {
+            label.insert(0, '-');
+        }
+        if label.len() > MAX_LABEL {
+            return Err(anyhow::anyhow!("{} is too long for a subdomain origin", host));
+        }
+        let label = match url.port() {
+            Some(server_port) if server_port != DEFAULT_PORT => format!("{}.p{}", label, server_port),
+            _ => label,
+        };
+        let rest = &url[url::Position::BeforePath..];
+        let slash = if rest.starts_with('/') { "" } else { "/" };
+        Ok(format!("http://{}.gemini.localhost:{}{}{}", label, port, slash, rest))
+    }
+
+    async fn serve(&self, host: &str, port: u16, method: Method, uri: &Uri, body: Bytes) -> Response {
+        let authority = match port {
+            DEFAULT_PORT => host.to_string(),
+            port => format!("{}:{}", host, port),
+        };
+
+        // Answers to input prompts are posted here and sent on as the query
+        if method == Method::POST {
+            let input = url::form_urlencoded::parse(&body)
+                .find(|(key, _)| key == "input")
+                .map(|(_, value)| value.into_owned())
+                .unwrap_or_default();
+            let target = format!("{}?{}", uri.path(), utf8_percent_encode(&input, QUERY));
+            if format!("gemini://{}{}", authority, target).len() > MAX_URL {
+                return message(StatusCode::URI_TOO_LONG, "Input too long", "The input does not fit in a Gemini request.");
+            }
+            return Redirect::to(&target).into_response();
+        }
+
+        let path_and_query = uri.path_and_query().map(|path| path.as_str()).unwrap_or("/");
+        let url = match url::Url::parse(&format!("gemini://{}{}", authority, path_and_query)) {
+            Ok(url) => url,
+            Err(e) => return message(StatusCode::BAD_REQUEST, "Invalid URL", &e.to_string()),
+        };
+
+        let response = match self.client.fetch(&url).await {
+            Ok(response) => response,
+            Err(e) => {
+                if let Some(changed) = e.downcast_ref::<CertificateChanged>() {
+                    let _ = self.event_tx.send(IPCEvent::CertificateError {
+                        url: url.to_string(),
+                        error: changed.to_string(),
+                    });
+                    return message(StatusCode::BAD_GATEWAY, "Certificate changed", &format!(
+                        "{}. This can mean someone is intercepting the connection. If the server really changed its key, forget its pinned certificate to trust the new one.",
+                        changed
+                    ));
+                }
+                warn!("Gemini gateway could not fetch {}: {}", url, e);
+                return message(StatusCode::BAD_GATEWAY, "Could not load capsule", &e.to_string());
+            }
+        };
+        self.respond(&url, response)
+    }
+
+    fn respond(&self, url: &url::Url, response: GeminiResponse) -> Response {
+        let meta = response.meta.as_str();
+        match response.status / 10 {
+            1 => {
+                // 11 asks for sensitive input
+                let kind = if response.status == 11 { "password" } else { "text" };
+                let body = format!(
+                    "<form method=\"post\"><p><label for=\"input\">{}</label></p>\
+                     <p><input id=\"input\" name=\"input\" type=\"{}\" autofocus> <button>Send</button></p></form>\n",
+                    escape_html(meta),
+                    kind
+                );
+                page(StatusCode::OK, gemtext::page(meta, None, &body))
+            }
+            2 => self.success(url, meta, response.body),
+            3 => {
+                let target = match url.join(meta) {
+                    Ok(target) => target,
+                    Err(e) => return message(StatusCode::BAD_GATEWAY, "Invalid redirect", &e.to_string()),
+                };
+                // Leaving Gemini is the user's call, so it is offered as a link
+                if target.scheme() != "gemini" {
+                    let body = format!(
+                        "<h1>Redirect</h1><p>The capsule redirects to <a href=\"{0}\">{0}</a>.</p>\n",
+                        escape_html(target.as_str())
+                    );
+                    return page(StatusCode::OK, gemtext::page("Redirect", None, &body));
+                }
+                match self.gateway_url(&target) {
+                    Ok(location) if response.status == 31 => Redirect::permanent(&location).into_response(),
+                    Ok(location) => Redirect::temporary(&location).into_response(),
+                    Err(e) => message(StatusCode::BAD_GATEWAY, "Invalid redirect", &e.to_string()),
+                }
+            }
+            4 => message(StatusCode::SERVICE_UNAVAILABLE, "Temporary failure", &failure(response.status, meta)),
+            5 => {
+                let status = match response.status {
+                    51 => StatusCode::NOT_FOUND,
+                    52 => StatusCode::GONE,
+                    53 => StatusCode::MISDIRECTED_REQUEST,
+                    59 => StatusCode::BAD_REQUEST,
+                    _ => StatusCode::BAD_GATEWAY,
+                };
+                message(status, "Permanent failure", &failure(response.status, meta))
+            }
+            6 => message(
+                StatusCode::FORBIDDEN,
+                "Client certificate required",
+                &format!("{} Client certificates are not supported yet.", failure(response.status, meta)),
+            ),
+            _ => message(
+                StatusCode::BAD_GATEWAY,
+                "Invalid response",
+                &format!("Unknown Gemini status {}", response.status),
+            ),
+        }
+    }
+
+    fn success(&self, url: &url::Url, meta: &str, body: Vec<u8>) -> Response {
+        let meta = if meta.is_empty() { "text/gemini; charset=utf-8" } else { meta };
+        let mut params = meta.split(';').map(str::trim);
+        let mime = params.next().unwrap_or_default().to_ascii_lowercase();
+        if mime != "text/gemini" {
+            let content_type = HeaderValue::from_str(meta)
+                .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream"));
+            return (
+                [
+                    (header::CONTENT_TYPE, content_type),
+                    (header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("sandbox")),
+                    (header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")),
+                    (header::REFERRER_POLICY, HeaderValue::from_static("no-referrer")),
+                    (header::CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+                ],
+                body,
+            )
+                .into_response();
+        }
+
+        let lang = params
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case("lang"))
+            .map(|(_, lang)| lang.trim_matches('"'));
+        let html = gemtext::to_html(&String::from_utf8_lossy(&body), lang, |target| self.link(url, target));
+        page(StatusCode::OK, html)
+    }
+
+    /// Where a link on a page points: Gemini links through the gateway, web
+    /// links as they are, and anything else nowhere
+    fn link(&self, base: &url::Url, target: &str) -> Option<String> {
+        let target = base.join(target).ok()?;
+        match target.scheme() {
+            "gemini" => self.gateway_url(&target).ok(),
+            "http" | "https" => Some(target.into()),
+            _ => None,
+        }
+    }
+}
+
+impl ProtocolHandler for GeminiGateway {
+    fn engine_url(&self, url: &str) -> Option<Result<String>> {
+        let (scheme, _) = url.split_once("://")?;
+        if !scheme.eq_ignore_ascii_case("gemini") {
+            return None;
+        }
+        Some(
+            url::Url::parse(url)
+                .map_err(|e| anyhow::anyhow!("Invalid URL {}: {}", url, e))
+                .and_then(|url| self.gateway_url(&url)),
+        )
+    }
+
+    fn display_url(&self, engine_url: &str) -> Option<String> {
+        let url = url::Url::parse(engine_url).ok()?;
+        if url.scheme() != "http" || url.port() != Some(self.port.load(Ordering::Relaxed)) {
+            return None;
+        }
+        let (host, port) = parse_host(url.host_str()?)?;
+        let port = match port {
+            DEFAULT_PORT => String::new(),
+            port => format!(":{}", port),
+        };
+        Some(format!("gemini://{}{}{}", host, port, &url[url::Position::BeforePath..]))
+    }
+
+    fn content_origin(&self, _url: &str) -> Option<String> {
+        None
+    }
+}
+
+async fn handle(
+    State(gateway): State<Arc<GeminiGateway>>,
+    method: Method,
+    headers: HeaderMap,
+    uri: Uri,
+    body: Bytes,
+) -> Response {
+    if !matches!(method, Method::GET | Method::HEAD | Method::POST) {
+        return message(StatusCode::METHOD_NOT_ALLOWED, "Not allowed", "Gemini is read-only");
+    }
+    // Only `*.gemini.localhost` names are served, which also keeps out DNS rebinding
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| url::Url::parse(&format!("http://{}", host)).ok());
+    let Some((host, port)) = host.as_ref().and_then(|host| parse_host(host.host_str()?)) else {
+        return message(StatusCode::NOT_FOUND, "Not found", "Not a Gemini origin");
+    };
+    gateway.serve(&host, port, method, &uri, body).await
+}
+
+/// `<label>.gemini.localhost` or `<label>.p<port>.gemini.localhost`, with
+/// `-` for `.` and `--` for `-` in the label
+fn parse_host(host: &str) -> Option<(String, u16)> {
+    let subdomain = host.strip_suffix(".gemini.localhost")?;
+    let (label, port) = match subdomain.split_once('.') {
+        Some((label, port)) => (label, port.strip_prefix('p')?.parse().ok()?),
+        None => (subdomain, DEFAULT_PORT),
+    };
+    let label = label.strip_prefix('-').unwrap_or(label);
+    if label.is_empty() {
+        return None;
+    }
+    let host = label
+        .split("--")
+        .map(|part| part.replace('-', "."))
+        .collect::<Vec<_>>()
+        .join("-");
+    Some((host, port))
+}
+
+fn failure(status: u8, meta: &str) -> String {
+    match meta.is_empty() {
+        true => format!("The capsule answered with status {}.", status),
+        false => format!("The capsule answered with status {}: {}", status, meta),
+    }
+}
+
+fn message(status: StatusCode, title: &str, text: &str) -> Response {
+    let body = format!("<h1>{}</h1><p>{}</p>\n", escape_html(title), escape_html(text));
+    page(status, gemtext::page(title, None, &body))
+}
+
+fn page(status: StatusCode, html: String) -> Response {
+    (
+        status,
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::CONTENT_SECURITY_POLICY, PAGE_POLICY),
+            (header::REFERRER_POLICY, "no-referrer"),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        html,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway() -> GeminiGateway {
+        let (event_tx, _) = broadcast::channel(1);
+        let gateway = GeminiGateway::new(PathBuf::from("/nonexistent/known_hosts.json"), event_tx);
+        gateway.port.store(8000, Ordering::Relaxed);
+        gateway
+    }
+
+    #[test]
+    fn gateway_origins_round_trip() {
+        let gateway = gateway();
+        for (url, expected, host, port) in [
+            (
+                "gemini://example.org/page?q",
+                "http://example-org.gemini.localhost:8000/page?q",
+                "example.org",
+                DEFAULT_PORT,
+            ),
+            ("gemini://my-site.example", "http://my--site-example.gemini.localhost:8000/", "my-site.example", 1965),
+            ("gemini://a.example:1966/", "http://a-example.p1966.gemini.localhost:8000/", "a.example", 1966),
+            (
+                "gemini://xn--bcher-kva.example/",
+                "http://-xn----bcher--kva-example.gemini.localhost:8000/",
+                "xn--bcher-kva.example",
+                1965,
+            ),
+        ] {
+            let gateway_url = gateway.gateway_url(&url::Url::parse(url).unwrap()).unwrap();
+            assert_eq!(gateway_url, expected);
+            let origin = url::Url::parse(&gateway_url).unwrap();
+            assert_eq!(parse_host(origin.host_str().unwrap()), Some((host.to_string(), port)));
+        }
+
+        assert!(gateway.gateway_url(&url::Url::parse("gemini://[::1]/").unwrap()).is_err());
+        let long = format!("gemini://{}.example/", "a".repeat(60));
+        assert!(gateway.gateway_url(&url::Url::parse(&long).unwrap()).is_err());
+    }
+
+    #[test]
+    fn only_gateway_hosts_are_served() {
+        assert_eq!(parse_host("example.com"), None);
+        assert_eq!(parse_host("gemini.localhost"), None);
+        assert_eq!(parse_host("-.gemini.localhost"), None);
+        assert_eq!(parse_host("a.x1966.gemini.localhost"), None);
+    }
+}
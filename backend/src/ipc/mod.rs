@@ -9,12 +9,13 @@ use std::sync::Arc;
 #[async_trait::async_trait]
 pub trait IPCHandler: Send + Sync {
     /// Handle an IPC command and return a response
-    async fn handle_command(&self, command: IPCCommand) -> Result<IPCResponse>;
+    async fn handle_command(&self, command: IPCCommand) -> Result<IPCResponse<serde_json::Value>>;
     
     /// Subscribe to IPC events
     async fn subscribe(&self) -> tokio::sync::broadcast::Receiver<IPCEvent>;
     
     /// Send an IPC event to all subscribers
+    #[allow(dead_code)]
     async fn send_event(&self, event: IPCEvent) -> Result<()>;
 }
 
@@ -1,6 +1,8 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum BrowserEngine {
     Blink,
     Gecko,
@@ -8,7 +10,7 @@ pub enum BrowserEngine {
     Servo,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PrivacyMode {
     Normal,
     Private,
@@ -16,12 +18,414 @@ pub enum PrivacyMode {
     Vpn,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How strictly a DNS-over-TLS server must be authenticated (RFC 8310)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DotPrivacy {
+    /// Fail unless the server presents a valid certificate or matching pin
+    #[default]
+    Strict,
+    /// Encrypt when possible, accepting unauthenticated servers
+    Opportunistic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DotUpstream {
+    pub address: SocketAddr,
+    /// Name the server certificate is checked against
+    pub server_name: String,
+    /// Base64 SHA-256 digests of accepted SubjectPublicKeyInfo structures
+    #[serde(default)]
+    pub spki_pins: Vec<String>,
+}
+
+/// What to do when every encrypted upstream fails
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DnsFallback {
+    /// Fail the lookup
+    #[default]
+    None,
+    /// Retry with plaintext system DNS; only allowed in opportunistic mode
+    System,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DnsMode {
+    /// Plaintext DNS through the operating system
+    #[default]
+    System,
+    /// DNS-over-HTTPS (RFC 8484); `bootstrap` holds the provider's addresses
+    Doh {
+        url: String,
+        #[serde(default)]
+        bootstrap: Vec<IpAddr>,
+    },
+    /// DNS-over-TLS (RFC 7858) with upstreams tried in order
+    Dot {
+        upstreams: Vec<DotUpstream>,
+        #[serde(default)]
+        privacy: DotPrivacy,
+        #[serde(default)]
+        fallback: DnsFallback,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ProxyProtocol {
+    Socks5,
+    /// HTTP proxy supporting the CONNECT method
+    Http,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyServer {
+    pub protocol: ProxyProtocol,
+    /// `host:port` of the proxy
+    pub address: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+/// One hop of a chained route
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RouteHop {
+    Proxy { server: ProxyServer },
+    Vpn { config: PathBuf },
+    Tor,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
 pub enum NetworkRoute {
-    Direct,
+    Direct {
+        #[serde(default)]
+        dns: DnsMode,
+    },
     Tor,
-    Vpn,
-    Proxy,
+    /// WireGuard tunnel described by a `wg-quick` style config file
+    Vpn {
+        config: PathBuf,
+    },
+    Proxy {
+        server: ProxyServer,
+    },
+    /// Hops traversed in order, e.g. a VPN then Tor for Onion-over-VPN
+    Chain {
+        hops: Vec<RouteHop>,
+    },
+    /// Proxy picked per connection by a proxy auto-config script at `url`
+    Pac {
+        url: String,
+    },
+    /// The operating system's proxy settings or the `HTTP_PROXY` family of variables
+    System,
+    /// `.i2p` sites through a local I2P router's SAM v3 bridge
+    I2p {
+        #[serde(default = "default_sam_address")]
+        sam: SocketAddr,
+    },
+}
+
+impl Serialize for NetworkRoute {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        NetworkRoute::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NetworkRoute {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// `Direct` was a unit variant before it carried DNS settings, so
+        /// saved rules and sessions may still hold the bare string
+        #[derive(Deserialize)]
+        enum Legacy {
+            Direct,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Legacy(Legacy),
+            Route(#[serde(with = "NetworkRoute")] NetworkRoute),
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Legacy(Legacy::Direct) => NetworkRoute::Direct {
+                dns: DnsMode::default(),
+            },
+            Repr::Route(route) => route,
+        })
+    }
+}
+
+/// SAM bridge of a router on this machine
+pub fn default_sam_address() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], crate::network::i2p::DEFAULT_SAM_PORT))
+}
+
+/// Sends destinations matching `pattern` over `route` instead of the tab's route.
+/// Patterns are a domain with its subdomains (`corp.example.com`), names below
+/// a suffix (`*.corp.example.com`, `.onion`) or a CIDR (`10.0.0.0/8`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoutingRule {
+    pub pattern: String,
+    pub route: NetworkRoute,
+}
+
+impl NetworkRoute {
+    /// The hops this route traverses; empty for routes that cannot be part
+    /// of a chain, like Direct or I2P
+    pub fn hops(&self) -> Vec<RouteHop> {
+        match self {
+            NetworkRoute::Direct { .. }
+            | NetworkRoute::Pac { .. }
+            | NetworkRoute::System
+            | NetworkRoute::I2p { .. } => Vec::new(),
+            NetworkRoute::Tor => vec![RouteHop::Tor],
+            NetworkRoute::Vpn { config } => vec![RouteHop::Vpn { config: config.clone() }],
+            NetworkRoute::Proxy { server } => vec![RouteHop::Proxy { server: server.clone() }],
+            NetworkRoute::Chain { hops } => hops.clone(),
+        }
+    }
+}
+
+impl Default for NetworkRoute {
+    fn default() -> Self {
+        NetworkRoute::Direct { dns: DnsMode::System }
+    }
+}
+
+/// DNS server that knows Handshake names
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandshakeServer {
+    /// DNS-over-HTTPS endpoint of a Handshake resolver
+    Doh {
+        url: String,
+        #[serde(default)]
+        bootstrap: Vec<IpAddr>,
+    },
+    /// Plaintext DNS over UDP, e.g. a local `hnsd` on 127.0.0.1:5350
+    Dns { address: SocketAddr },
+}
+
+/// Resolution of blockchain domains; both are off until configured
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NameSettings {
+    /// Ethereum JSON-RPC endpoint used to look up `.eth` names in ENS
+    #[serde(default)]
+    pub ens_rpc: Option<String>,
+    /// Resolver asked for names the route's DNS cannot find
+    #[serde(default)]
+    pub handshake: Option<HandshakeServer>,
+}
+
+/// A Gemini server whose certificate was trusted on first use
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownGeminiHost {
+    /// `host:port`
+    pub host: String,
+    /// SHA-256 of the certificate's public key, as `sha256/<base64>`
+    pub fingerprint: String,
+    /// Unix time the certificate was first seen
+    pub first_seen: u64,
+}
+
+/// A cookie in an engine's cookie jar
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CookieInfo {
+    pub name: String,
+    pub value: String,
+    /// Host the cookie belongs to, with a leading `.` when subdomains share it
+    pub domain: String,
+    pub path: String,
+    /// Unix time the cookie expires, or `None` for a session cookie
+    pub expires: Option<f64>,
+    pub http_only: bool,
+    pub secure: bool,
+    /// Top-level site a partitioned (CHIPS) cookie is kept for
+    pub partition: Option<String>,
+}
+
+/// How cookies of sites other than the page's own are treated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThirdPartyCookies {
+    Allow,
+    /// Only cookies partitioned by top-level site, which cannot track across sites
+    #[default]
+    Partition,
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CookieSettings {
+    #[serde(default)]
+    pub third_party: ThirdPartyCookies,
+    /// Sites that may not keep cookies at all
+    #[serde(default)]
+    pub blocked: Vec<String>,
+    /// Delete a site's cookies when its last tab closes
+    #[serde(default = "default_true")]
+    pub clear_on_close: bool,
+    /// Sites whose cookies are kept when their last tab closes
+    #[serde(default)]
+    pub keep: Vec<String>,
+}
+
+impl Default for CookieSettings {
+    fn default() -> Self {
+        Self {
+            third_party: ThirdPartyCookies::default(),
+            blocked: Vec::new(),
+            clear_on_close: true,
+            keep: Vec::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A capability pages must ask the user for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Permission {
+    Camera,
+    Microphone,
+    Geolocation,
+    Notifications,
+    /// Reading the clipboard; writing needs no permission
+    Clipboard,
+    Midi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PermissionDecision {
+    Allow,
+    Deny,
+    /// Prompt every time
+    Ask,
+}
+
+/// A remembered decision for one origin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SitePermission {
+    /// `scheme://host[:port]`
+    pub origin: String,
+    pub permission: Permission,
+    pub decision: PermissionDecision,
+    /// Unix time the decision lapses, or `None` to keep it
+    #[serde(default)]
+    pub expires: Option<u64>,
+    /// Made in a Private tab, so held in memory only
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// Where `ipfs://` and `ipns://` content is fetched from; blocks are verified
+/// against their CIDs either way
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IpfsBackend {
+    /// RPC API of a local IPFS node, e.g. Kubo on port 5001
+    Api { url: String },
+    /// Trustless gateway serving raw blocks, e.g. `https://trustless-gateway.link`
+    Gateway { url: String },
+}
+
+impl Default for IpfsBackend {
+    fn default() -> Self {
+        IpfsBackend::Api {
+            url: String::from("http://127.0.0.1:5001"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Webp,
+}
+
+/// Area of a page in CSS pixels, measured from the top of the document
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Clip {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScreenshotOptions {
+    pub format: ImageFormat,
+    /// Compression quality from 0 to 100, for JPEG and WebP
+    pub quality: Option<u8>,
+    /// Capture the whole document rather than what the viewport shows
+    pub full_page: bool,
+    /// Capture only this area; takes precedence over `full_page`
+    pub clip: Option<Clip>,
+}
+
+/// Page setup for printing; sizes and margins are in inches
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PdfOptions {
+    pub landscape: bool,
+    pub print_background: bool,
+    pub scale: Option<f64>,
+    pub paper_width: Option<f64>,
+    pub paper_height: Option<f64>,
+    pub margin_top: Option<f64>,
+    pub margin_bottom: Option<f64>,
+    pub margin_left: Option<f64>,
+    pub margin_right: Option<f64>,
+    /// Pages to print, e.g. `1-5, 8`
+    pub page_ranges: Option<String>,
+    /// Use the page's CSS `@page` size over the paper size
+    pub prefer_css_page_size: bool,
+}
+
+/// Viewport of an emulated device, in CSS pixels
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DeviceEmulation {
+    pub width: u32,
+    pub height: u32,
+    pub device_scale_factor: f64,
+    /// Lay pages out as a mobile browser does, honoring `<meta name="viewport">`
+    pub mobile: bool,
+    /// Report a touch screen, and turn mouse input into touch events
+    pub touch: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DevicePreset {
+    Phone,
+    Tablet,
+}
+
+impl DevicePreset {
+    pub fn emulation(self) -> DeviceEmulation {
+        let (width, height, device_scale_factor) = match self {
+            DevicePreset::Phone => (390, 844, 3.0),
+            DevicePreset::Tablet => (820, 1180, 2.0),
+        };
+        DeviceEmulation {
+            width,
+            height,
+            device_scale_factor,
+            mobile: true,
+            touch: true,
+        }
+    }
+}
+
+/// Where a find-in-page search stands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FindMatches {
+    /// 1-based position of the selected match, 0 when nothing matched
+    pub active_match: usize,
+    pub total_matches: usize,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +435,51 @@ pub struct CertificateInfo {
     pub valid_to: String,
 }
 
+pub type TabId = u64;
+
+pub type WindowId = u64;
+
+/// The window opened at startup, which tabs go to unless told otherwise
+pub const MAIN_WINDOW: WindowId = 1;
+
+/// A browser window and the tab it shows
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowInfo {
+    pub id: WindowId,
+    pub active_tab: Option<TabId>,
+}
+
+pub type ContainerId = u64;
+
+/// A Firefox-style container: its tabs keep cookies and storage apart from
+/// those of other containers and of tabs outside any container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Container {
+    pub id: ContainerId,
+    pub name: String,
+    /// Color the UI marks the container's tabs with, e.g. `blue`
+    pub color: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TabInfo {
+    pub id: TabId,
+    pub url: String,
+    pub title: Option<String>,
+    pub engine: BrowserEngine,
+    pub privacy_mode: PrivacyMode,
+    pub network_route: NetworkRoute,
+    /// Hops traffic takes on the route, e.g. `WireGuard (home.conf) → Tor → site`
+    pub network_path: String,
+    pub pinned: bool,
+    pub https_upgraded: bool,
+    /// `ipfs://<cid>` the page was loaded from, for content-addressed URLs
+    pub content_origin: Option<String>,
+    pub container: Option<ContainerId>,
+    pub window: WindowId,
+    pub emulation: Option<DeviceEmulation>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BrowserState {
     pub current_url: String,
@@ -38,10 +487,22 @@ pub struct BrowserState {
     pub current_engine: BrowserEngine,
     pub privacy_mode: PrivacyMode,
     pub network_route: NetworkRoute,
+    pub network_path: String,
     pub title: Option<String>,
     pub favicon: Option<String>,
     pub is_secure: bool,
     pub certificate_info: Option<CertificateInfo>,
+    pub tabs: Vec<TabInfo>,
+    pub active_tab: Option<TabId>,
+    pub session_restore_available: bool,
+    pub shields_enabled: bool,
+    pub https_only: bool,
+    pub https_upgraded: bool,
+    pub content_origin: Option<String>,
+    pub containers: Vec<Container>,
+    pub windows: Vec<WindowInfo>,
+    /// Window whose active tab commands without a tab act on
+    pub active_window: WindowId,
 }
 
 impl Default for BrowserState {
@@ -51,11 +512,22 @@ impl Default for BrowserState {
             is_loading: false,
             current_engine: BrowserEngine::Blink,
             privacy_mode: PrivacyMode::Normal,
-            network_route: NetworkRoute::Direct,
+            network_route: NetworkRoute::default(),
+            network_path: crate::network::describe_path(&NetworkRoute::default()),
             title: None,
             favicon: None,
             is_secure: false,
             certificate_info: None,
+            tabs: Vec::new(),
+            active_tab: None,
+            session_restore_available: false,
+            shields_enabled: true,
+            https_only: false,
+            https_upgraded: false,
+            content_origin: None,
+            containers: Vec::new(),
+            windows: Vec::new(),
+            active_window: MAIN_WINDOW,
         }
     }
 }
@@ -66,20 +538,135 @@ pub enum IPCCommand {
     Navigate { url: String },
     SwitchEngine { engine: BrowserEngine },
     SetPrivacyMode { mode: PrivacyMode },
-    SetNetworkRoute { route: NetworkRoute },
+    SetNetworkRoute { tab: TabId, route: NetworkRoute },
     Reload,
     Stop,
     GoBack,
     GoForward,
+    NewTab {
+        url: Option<String>,
+        engine: Option<BrowserEngine>,
+        privacy_mode: Option<PrivacyMode>,
+        container: Option<ContainerId>,
+        /// Window to open the tab in, the active one if not set
+        window: Option<WindowId>,
+    },
+    CloseTab { tab: TabId },
+    NewWindow { url: Option<String> },
+    /// Close a window and its tabs; closing the last one quits
+    CloseWindow { window: WindowId },
+    /// Make a window the active one, as focusing it does
+    ActivateWindow { window: WindowId },
+    ActivateTab { tab: TabId },
+    PinTab { tab: TabId, pinned: bool },
+    RestoreSession,
+    DiscardSession,
+    SetShieldsEnabled { enabled: bool },
+    SetSiteShields { site: String, enabled: bool },
+    ReloadFilterLists,
+    SetHttpsOnly { enabled: bool },
+    SetHttpsException { site: String, allowed: bool },
+    GetResolverStats,
+    SetRoutingRules { rules: Vec<RoutingRule> },
+    GetRoutingRules,
+    SetIpfsBackend { backend: IpfsBackend },
+    GetIpfsBackend,
+    SetNameSettings { settings: NameSettings },
+    GetNameSettings,
+    /// Look up a blockchain domain's records without loading it
+    ResolveName { name: String },
+    /// Open a page in the active tab checking what it reveals against its
+    /// privacy mode's fingerprinting protection
+    CheckFingerprint,
+    /// Load a self-test page in an engine launched like the active tab's and
+    /// report how it leaked past its proxy
+    LeakCheck,
+    GetGeminiHosts,
+    /// Drop a Gemini server's pinned certificate, so its next one is trusted
+    ForgetGeminiHost { host: String },
+    /// Answer a `PermissionRequest`; `remember` keeps the decision for the
+    /// origin, for `expires_in` seconds if set
+    RespondPermission { id: u64, allow: bool, remember: bool, expires_in: Option<u64> },
+    GetPermissions,
+    SetPermission { origin: String, permission: Permission, decision: PermissionDecision, expires_in: Option<u64> },
+    /// Forget remembered decisions, for one origin or permission or all of them
+    ResetPermissions { origin: Option<String>, permission: Option<Permission> },
+    /// Cookies of every tab, or only those of `site` and its subdomains
+    GetCookies { site: Option<String> },
+    /// Cookies whose domain, name or value contains `query`
+    SearchCookies { query: String },
+    /// Delete the cookies of `site`, or only those called `name`
+    DeleteCookies { site: String, name: Option<String> },
+    SetCookieBlocked { site: String, blocked: bool },
+    GetCookieSettings,
+    SetCookieSettings { settings: CookieSettings },
+    GetContainers,
+    CreateContainer { name: String, color: String },
+    UpdateContainer { container: Container },
+    /// Remove a container that has no open tabs
+    RemoveContainer { id: ContainerId },
+    /// Capture a tab, the active one if not set. The image is returned as
+    /// base64, or written to `path`, which must be absolute and not exist yet.
+    CaptureScreenshot {
+        tab: Option<TabId>,
+        #[serde(default)]
+        format: ImageFormat,
+        quality: Option<u8>,
+        #[serde(default)]
+        full_page: bool,
+        clip: Option<Clip>,
+        path: Option<String>,
+    },
+    /// Print a tab to PDF, returned or written like `CaptureScreenshot`'s image
+    PrintToPdf {
+        tab: Option<TabId>,
+        #[serde(default)]
+        options: PdfOptions,
+        path: Option<String>,
+    },
+    /// Highlight every match of `text` in a tab, the active one if not set,
+    /// and select the first one in the given direction. Repeating the tab's
+    /// current search selects its next match instead.
+    FindInPage {
+        tab: Option<TabId>,
+        text: String,
+        #[serde(default)]
+        case_sensitive: bool,
+        #[serde(default = "default_true")]
+        forward: bool,
+    },
+    /// Select the next match of the tab's search, in its direction
+    FindNext { tab: Option<TabId> },
+    /// Clear the tab's search and its highlights
+    StopFinding { tab: Option<TabId> },
+    /// Zoom a tab, the active one if not set, remembering the level for its
+    /// site unless the tab is Private; 1.0 is the default size
+    SetZoom { tab: Option<TabId>, level: f64 },
+    GetZoomLevels,
+    /// Emulate a device's viewport in a tab until `StopEmulation`
+    EmulateDevice { tab: Option<TabId>, emulation: DeviceEmulation },
+    EmulateDevicePreset { tab: Option<TabId>, preset: DevicePreset },
+    StopEmulation { tab: Option<TabId> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "payload")]
 pub enum IPCEvent {
-    StateUpdate(BrowserState),
+    StateUpdate(Box<BrowserState>),
     Error { code: String, message: String },
     DownloadProgress { id: String, progress: f64 },
     CertificateError { url: String, error: String },
+    SessionRestoreAvailable { tabs: usize, saved_at: u64 },
+    BlockedRequest { tab: TabId, url: String, first_party: Option<String>, filter: String },
+    HttpsUpgradeFailed { tab: TabId, url: String, upgraded_url: String, error: String },
+    /// A tab's route failed and its traffic is blocked until it recovers
+    RouteDown { tab: TabId, route: NetworkRoute, reason: String },
+    RouteUp { tab: TabId, route: NetworkRoute },
+    /// A page asked for a permission with no remembered decision; answer with
+    /// `RespondPermission`
+    PermissionRequest { id: u64, tab: TabId, origin: String, permission: Permission },
+    /// Answers `FindInPage` and `FindNext`
+    FindResult { tab: TabId, active_match: usize, total_matches: usize },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -95,9 +682,55 @@ pub struct ErrorInfo {
     pub message: String,
 }
 
+#[allow(dead_code)]
 pub const CACHE_KEYS: &[&str] = &[
     "browserState",
     "browserHistory",
     "downloads",
     "certificates",
-]; 
\ No newline at end of file
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_route_accepts_legacy_unit_form() {
+        let route: NetworkRoute = serde_json::from_str(r#""Direct""#).unwrap();
+        assert_eq!(
+            route,
+            NetworkRoute::Direct {
+                dns: DnsMode::System
+            }
+        );
+        let route: NetworkRoute = serde_json::from_str(r#"{"Direct":{}}"#).unwrap();
+        assert_eq!(
+            route,
+            NetworkRoute::Direct {
+                dns: DnsMode::System
+            }
+        );
+    }
+
+    #[test]
+    fn routes_round_trip() {
+        let routes = [
+            NetworkRoute::Direct {
+                dns: DnsMode::Doh {
+                    url: "https://dns.example/dns-query".to_string(),
+                    bootstrap: vec![],
+                },
+            },
+            NetworkRoute::Tor,
+            NetworkRoute::System,
+            NetworkRoute::Pac {
+                url: "http://wpad/wpad.dat".to_string(),
+            },
+        ];
+        for route in routes {
+            let json = serde_json::to_string(&route).unwrap();
+            assert_eq!(serde_json::from_str::<NetworkRoute>(&json).unwrap(), route);
+        }
+        assert!(serde_json::from_str::<NetworkRoute>(r#""Teleport""#).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+
+/// Codecs of the blocks we can read
+pub const DAG_PB: u64 = 0x70;
+pub const RAW: u64 = 0x55;
+/// Codec of IPNS keys, e.g. `k51...`
+pub const LIBP2P_KEY: u64 = 0x72;
+
+const IDENTITY: u64 = 0x00;
+const SHA2_256: u64 = 0x12;
+const SHA2_512: u64 = 0x13;
+
+const BASE32_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+const BASE36_ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// A content identifier: what a block is and the hash it must have
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cid {
+    pub version: u64,
+    pub codec: u64,
+    hash_code: u64,
+    digest: Vec<u8>,
+}
+
+impl Cid {
+    /// Parse a CIDv0 (`Qm...`) or a multibase CIDv1 (`bafy...`, `k51...`, `z...`)
+    pub fn parse(text: &str) -> Result<Self> {
+        if text.len() == 46 && text.starts_with("Qm") {
+            return Self::from_bytes(&base_decode(text, BASE58_ALPHABET)?);
+        }
+        // Peer IDs of Ed25519 keys, `12D3KooW...`, are bare multihashes
+        if text.starts_with('1') {
+            return Self::from_multihash(LIBP2P_KEY, &base_decode(text, BASE58_ALPHABET)?);
+        }
+        let mut chars = text.chars();
+        let bytes = match chars.next() {
+            Some('b') => base32_decode(chars.as_str())?,
+            Some('B') => base32_decode(&chars.as_str().to_ascii_lowercase())?,
+            Some('k') | Some('K') => base_decode(&chars.as_str().to_ascii_lowercase(), BASE36_ALPHABET)?,
+            Some('z') => base_decode(chars.as_str(), BASE58_ALPHABET)?,
+            Some('f') | Some('F') => hex_decode(chars.as_str())?,
+            _ => return Err(anyhow::anyhow!("Unsupported CID encoding: {}", text)),
+        };
+        Self::from_bytes(&bytes)
+    }
+
+    /// Parse a binary CID, as found in dag-pb links
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        // A CIDv0 is a bare sha2-256 multihash
+        if bytes.len() == 34 && bytes[0] == SHA2_256 as u8 && bytes[1] == 32 {
+            return Ok(Self {
+                version: 0,
+                codec: DAG_PB,
+                hash_code: SHA2_256,
+                digest: bytes[2..].to_vec(),
+            });
+        }
+
+        let mut reader = bytes;
+        let version = read_varint(&mut reader)?;
+        if version != 1 {
+            return Err(anyhow::anyhow!("Unsupported CID version {}", version));
+        }
+        let codec = read_varint(&mut reader)?;
+        Self::from_multihash(codec, reader)
+    }
+
+    fn from_multihash(codec: u64, mut bytes: &[u8]) -> Result<Self> {
+        let hash_code = read_varint(&mut bytes)?;
+        let length = read_varint(&mut bytes)? as usize;
+        if bytes.len() != length {
+            return Err(anyhow::anyhow!("CID digest has the wrong length"));
+        }
+        Ok(Self {
+            version: 1,
+            codec,
+            hash_code,
+            digest: bytes.to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if self.version == 0 {
+            bytes.extend([SHA2_256 as u8, 32]);
+        } else {
+            write_varint(&mut bytes, 1);
+            write_varint(&mut bytes, self.codec);
+            write_varint(&mut bytes, self.hash_code);
+            write_varint(&mut bytes, self.digest.len() as u64);
+        }
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    /// The same content as a CIDv1, which can be written in lowercase
+    pub fn to_v1(&self) -> Self {
+        Self {
+            version: 1,
+            ..self.clone()
+        }
+    }
+
+    /// Reinterpret the hash with another codec, e.g. a peer ID as an IPNS key
+    pub fn with_codec(&self, codec: u64) -> Self {
+        Self {
+            version: 1,
+            codec,
+            ..self.clone()
+        }
+    }
+
+    /// Base36 form, short enough for a DNS label even with long keys
+    pub fn to_base36(&self) -> String {
+        format!("k{}", base_encode(&self.to_v1().to_bytes(), BASE36_ALPHABET))
+    }
+
+    /// Block data carried in the CID itself, with the identity hash
+    pub fn inline_data(&self) -> Option<&[u8]> {
+        (self.hash_code == IDENTITY).then_some(self.digest.as_slice())
+    }
+
+    /// Check that a fetched block is the one this CID names
+    pub fn verify(&self, block: &[u8]) -> Result<()> {
+        let matches = match self.hash_code {
+            IDENTITY => self.digest == block,
+            SHA2_256 => Sha256::digest(block).as_slice() == self.digest,
+            SHA2_512 => Sha512::digest(block).as_slice() == self.digest,
+            other => return Err(anyhow::anyhow!("Unsupported hash function 0x{:x} in {}", other, self)),
+        };
+        match matches {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!("Block does not match its CID {}", self)),
+        }
+    }
+}
+
+/// Base32 for a CIDv1, base58 for a CIDv0
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.version {
+            0 => write!(f, "{}", base_encode(&self.to_bytes(), BASE58_ALPHABET)),
+            _ => write!(f, "b{}", base32_encode(&self.to_bytes())),
+        }
+    }
+}
+
+pub fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Truncated varint"))?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow::anyhow!("Varint too long"))
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// RFC 4648 base32 without padding, lowercase
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            text.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        text.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    text
+}
+
+fn base32_decode(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| anyhow::anyhow!("Invalid base32 character {:?}", c as char))?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+pub fn hex_decode(text: &str) -> Result<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return Err(anyhow::anyhow!("Invalid hex digits"));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).map_err(|_| anyhow::anyhow!("Invalid hex digits")))
+        .collect()
+}
+
+/// Big-number base conversion for base58btc and base36, where each leading
+/// zero byte is written as the alphabet's first character
+fn base_encode(bytes: &[u8], alphabet: &[u8]) -> String {
+    let base = alphabet.len() as u32;
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    let mut digits: Vec<u8> = Vec::new();
+    for &byte in &bytes[zeros..] {
+        let mut carry = u32::from(byte);
+        for digit in digits.iter_mut() {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % base) as u8;
+            carry /= base;
+        }
+        while carry > 0 {
+            digits.push((carry % base) as u8);
+            carry /= base;
+        }
+    }
+    std::iter::repeat_n(alphabet[0] as char, zeros)
+        .chain(digits.iter().rev().map(|&digit| alphabet[digit as usize] as char))
+        .collect()
+}
+
+fn base_decode(text: &str, alphabet: &[u8]) -> Result<Vec<u8>> {
+    let base = alphabet.len() as u32;
+    let zeros = text.bytes().take_while(|&c| c == alphabet[0]).count();
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in text.bytes().skip(zeros) {
+        let mut carry = alphabet
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| anyhow::anyhow!("Invalid character {:?}", c as char))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += u32::from(*byte) * base;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    Ok(std::iter::repeat_n(0, zeros).chain(bytes.into_iter().rev()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The empty UnixFS directory, in each encoding we accept
+    const EMPTY_DIR_V0: &str = "QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn";
+    const EMPTY_DIR_V1: &str = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+    const EMPTY_DIR_BASE36: &str = "k2jmtxtlhjl3fhmgndf92e48by79ryjuvqp3y2qgehpao6v3lurvnmcv";
+    const EMPTY_DIR_HEX: &str = "f0170122059948439065f29619ef41280cbb932be52c56d99c5966b65e0111239f098bbef";
+    const EMPTY_DIR_BLOCK: &[u8] = &[0x0a, 0x02, 0x08, 0x01];
+
+    #[test]
+    fn parses_and_formats_every_encoding() {
+        let v0 = Cid::parse(EMPTY_DIR_V0).unwrap();
+        assert_eq!((v0.version, v0.codec), (0, DAG_PB));
+        assert_eq!(v0.to_string(), EMPTY_DIR_V0);
+        assert_eq!(Cid::from_bytes(&v0.to_bytes()).unwrap(), v0);
+
+        let v1 = v0.to_v1();
+        assert_eq!(v1.to_string(), EMPTY_DIR_V1);
+        assert_eq!(v1.to_base36(), EMPTY_DIR_BASE36);
+        for text in [EMPTY_DIR_V1, EMPTY_DIR_BASE36, EMPTY_DIR_HEX, &EMPTY_DIR_V1.to_ascii_uppercase()] {
+            assert_eq!(Cid::parse(text).unwrap(), v1, "{}", text);
+        }
+        let base58 = format!("z{}", base_encode(&v1.to_bytes(), BASE58_ALPHABET));
+        assert_eq!(Cid::parse(&base58).unwrap(), v1);
+
+        assert!(Cid::parse("").is_err());
+        assert!(Cid::parse("xyz").is_err());
+        assert!(Cid::parse("b!!").is_err());
+        // Digest shorter than its declared length
+        assert!(Cid::parse(&EMPTY_DIR_HEX[..EMPTY_DIR_HEX.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn verifies_blocks_against_their_hash() {
+        let cid = Cid::parse(EMPTY_DIR_V1).unwrap();
+        assert!(cid.verify(EMPTY_DIR_BLOCK).is_ok());
+        assert!(cid.verify(b"tampered").is_err());
+
+        let raw = Cid::parse("bafkreicysg23kiwv34eg2d7qweipxwosdo2py4ldv42nbauguluen5v6am").unwrap();
+        assert_eq!(raw.codec, RAW);
+        assert!(raw.verify(b"hello\n").is_ok());
+        assert_eq!(raw.inline_data(), None);
+    }
+
+    #[test]
+    fn peer_ids_are_identity_hashed_keys() {
+        let mut multihash = vec![0x00, 0x24, 0x08, 0x01, 0x12, 0x20];
+        multihash.extend([7; 32]);
+        let peer_id = base_encode(&multihash, BASE58_ALPHABET);
+        assert!(peer_id.starts_with("12D3KooW"));
+
+        let cid = Cid::parse(&peer_id).unwrap();
+        assert_eq!((cid.version, cid.codec), (1, LIBP2P_KEY));
+        assert_eq!(cid.inline_data(), Some(&multihash[2..]));
+        assert!(cid.to_base36().starts_with("k51"));
+        assert_eq!(Cid::parse(&cid.to_base36()).unwrap(), cid);
+    }
+
+    #[test]
+    fn varints_and_codecs() {
+        for value in [0, 1, 127, 128, 300, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            let mut reader = bytes.as_slice();
+            assert_eq!(read_varint(&mut reader).unwrap(), value);
+            assert!(reader.is_empty());
+        }
+        assert!(read_varint(&mut &[0x80][..]).is_err());
+        assert!(read_varint(&mut &[0xff; 10][..]).is_err());
+
+        assert_eq!(base32_encode(b"foobar"), "mzxw6ytboi");
+        assert_eq!(base32_decode("mzxw6ytboi").unwrap(), b"foobar");
+        assert_eq!(hex_decode("00ff10").unwrap(), vec![0, 255, 16]);
+        assert!(hex_decode("abc").is_err());
+        assert!(hex_decode("zz").is_err());
+        assert_eq!(base_decode("1112", BASE58_ALPHABET).unwrap(), vec![0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,161 @@
+use super::cid::Cid;
+use crate::ipc::IpfsBackend;
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty, Limited};
+use hyper::header::{ACCEPT, HOST};
+use hyper::{HeaderMap, Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Blocks are at most 2 MiB on the IPFS network; name replies are far smaller
+const MAX_RESPONSE_SIZE: usize = 2 * 1024 * 1024 + 1024;
+const RAW_BLOCK: &str = "application/vnd.ipld.raw";
+
+/// Fetches blocks and resolves names through a node's RPC API or a trustless
+/// gateway. Neither is trusted with content: every block is checked against
+/// its CID before use.
+pub struct IpfsClient {
+    backend: IpfsBackend,
+    url: url::Url,
+    tls: TlsConnector,
+}
+
+impl IpfsClient {
+    pub fn new(backend: &IpfsBackend) -> Result<Self> {
+        let url = match backend {
+            IpfsBackend::Api { url } | IpfsBackend::Gateway { url } => url,
+        };
+        let mut url = url::Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid IPFS backend URL {}: {}", url, e))?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err(anyhow::anyhow!("IPFS backend URL must be http(s)://host: {}", url));
+        }
+        // Request paths are joined below the configured one
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let mut config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Self {
+            backend: backend.clone(),
+            url,
+            tls: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    pub fn backend(&self) -> &IpfsBackend {
+        &self.backend
+    }
+
+    /// Fetch a block and verify it hashes to its CID
+    pub async fn block(&self, cid: &Cid) -> Result<Vec<u8>> {
+        if let Some(data) = cid.inline_data() {
+            return Ok(data.to_vec());
+        }
+        let (_, block) = match self.backend {
+            IpfsBackend::Api { .. } => self.request(Method::POST, &format!("api/v0/block/get?arg={}", cid)).await?,
+            IpfsBackend::Gateway { .. } => self.request(Method::GET, &format!("ipfs/{}?format=raw", cid)).await?,
+        };
+        cid.verify(&block)?;
+        Ok(block)
+    }
+
+    /// Resolve an IPNS key or DNSLink domain to a CID and the path below it
+    pub async fn resolve(&self, name: &str) -> Result<(Cid, Vec<String>)> {
+        match self.backend {
+            IpfsBackend::Api { .. } => {
+                let path = format!("api/v0/name/resolve?arg=/ipns/{}&recursive=true", name);
+                let (_, body) = self.request(Method::POST, &path).await?;
+                let reply: serde_json::Value = serde_json::from_slice(&body)?;
+                let path = reply["Path"]
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("IPFS node returned no path for {}", name))?;
+                let mut segments = path
+                    .strip_prefix("/ipfs/")
+                    .ok_or_else(|| anyhow::anyhow!("{} resolved to {}, not an IPFS path", name, path))?
+                    .split('/')
+                    .filter(|segment| !segment.is_empty());
+                let cid = Cid::parse(segments.next().unwrap_or_default())?;
+                Ok((cid, segments.map(String::from).collect()))
+            }
+            IpfsBackend::Gateway { .. } => {
+                // The gateway names the resolved root; its block is checked like any other
+                let (headers, block) = self.request(Method::GET, &format!("ipns/{}?format=raw", name)).await?;
+                let root = headers
+                    .get("x-ipfs-roots")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|roots| roots.split(',').next())
+                    .ok_or_else(|| anyhow::anyhow!("Gateway did not say what {} resolves to", name))?;
+                let cid = Cid::parse(root.trim())?;
+                cid.verify(&block)?;
+                Ok((cid, Vec::new()))
+            }
+        }
+    }
+
+    async fn request(&self, method: Method, path: &str) -> Result<(HeaderMap, Vec<u8>)> {
+        let url = self.url.join(path)?;
+        tokio::time::timeout(REQUEST_TIMEOUT, self.send(method, &url))
+            .await
+            .map_err(|_| anyhow::anyhow!("IPFS request {} timed out", url))?
+            .map_err(|e| anyhow::anyhow!("IPFS request {} failed: {}", url, e))
+    }
+
+    async fn send(&self, method: Method, url: &url::Url) -> Result<(HeaderMap, Vec<u8>)> {
+        let host = url
+            .host_str()
+            .unwrap_or_default()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url.port_or_known_default().unwrap_or(80);
+        let stream = TcpStream::connect((host.as_str(), port)).await?;
+        let request = Request::builder()
+            .method(method)
+            .uri(url.as_str())
+            .header(HOST, url.host_str().unwrap_or(&host))
+            .header(ACCEPT, RAW_BLOCK)
+            .body(Empty::<Bytes>::new())?;
+
+        if url.scheme() == "https" {
+            let server_name = ServerName::try_from(host.clone())
+                .map_err(|e| anyhow::anyhow!("Invalid server name {}: {}", host, e))?;
+            send_request(self.tls.connect(server_name, stream).await?, request).await
+        } else {
+            send_request(stream, request).await
+        }
+    }
+}
+
+async fn send_request<S>(stream: S, request: Request<Empty<Bytes>>) -> Result<(HeaderMap, Vec<u8>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    let response = sender.send_request(request).await?;
+    if response.status() != StatusCode::OK {
+        return Err(anyhow::anyhow!("server returned {}", response.status()));
+    }
+    let (parts, body) = response.into_parts();
+    let body = Limited::new(body, MAX_RESPONSE_SIZE)
+        .collect()
+        .await
+        .map_err(|e| anyhow::anyhow!("reading the response failed: {}", e))?;
+    Ok((parts.headers, body.to_bytes().to_vec()))
+}
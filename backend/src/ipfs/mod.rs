@@ -0,0 +1,571 @@
+pub mod cid;
+pub mod client;
+pub mod unixfs;
+
+use crate::engine::{InterceptedRequest, ProtocolHandler, RequestAction, RequestInterceptor};
+use crate::ipc::IpfsBackend;
+use crate::network::names::{ens::ContentHash, is_ens, BlockchainNames};
+use crate::storage;
+use anyhow::Result;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Router;
+use cid::{Cid, LIBP2P_KEY};
+use client::IpfsClient;
+use parking_lot::{Mutex, RwLock};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+use unixfs::{Node, NodeType};
+
+/// Largest file assembled for the engine
+const MAX_FILE_SIZE: usize = 256 * 1024 * 1024;
+/// Deepest file DAG followed; real ones are a few levels deep
+const MAX_DAG_DEPTH: usize = 32;
+/// Verified blocks kept in memory
+const BLOCK_CACHE_SIZE: usize = 64 * 1024 * 1024;
+/// How long an IPNS or DNSLink resolution is reused
+const NAME_TTL: Duration = Duration::from_secs(60);
+/// Longest DNS label, which bounds the subdomain form of a CID or name
+const MAX_LABEL: usize = 63;
+/// Characters escaped in directory listing links
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'\'')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace {
+    Ipfs,
+    Ipns,
+}
+
+impl Namespace {
+    fn of(scheme: &str) -> Option<Self> {
+        match scheme {
+            "ipfs" => Some(Namespace::Ipfs),
+            "ipns" => Some(Namespace::Ipns),
+            _ => None,
+        }
+    }
+
+    fn scheme(self) -> &'static str {
+        match self {
+            Namespace::Ipfs => "ipfs",
+            Namespace::Ipns => "ipns",
+        }
+    }
+}
+
+/// An IPNS or DNSLink name and what it pointed to when last resolved
+struct Resolution {
+    root: Cid,
+    path: Vec<String>,
+    resolved_at: Instant,
+}
+
+/// Verified blocks, evicted oldest first
+#[derive(Default)]
+struct BlockCache {
+    blocks: HashMap<Cid, Arc<Vec<u8>>>,
+    order: VecDeque<Cid>,
+    size: usize,
+}
+
+impl BlockCache {
+    fn get(&self, cid: &Cid) -> Option<Arc<Vec<u8>>> {
+        self.blocks.get(cid).cloned()
+    }
+
+    fn insert(&mut self, cid: Cid, block: Arc<Vec<u8>>) {
+        if self.blocks.contains_key(&cid) {
+            return;
+        }
+        self.size += block.len();
+        self.order.push_back(cid.clone());
+        self.blocks.insert(cid, block);
+        while self.size > BLOCK_CACHE_SIZE {
+            let Some(oldest) = self.order.pop_front() else { break };
+            if let Some(block) = self.blocks.remove(&oldest) {
+                self.size -= block.len();
+            }
+        }
+    }
+}
+
+/// Serves `ipfs://` and `ipns://` content to the engines.
+///
+/// Engines cannot load these schemes, so they are pointed at a loopback
+/// subdomain gateway instead: `ipfs://<cid>/page` loads from
+/// `http://<cid>.ipfs.localhost:<port>/page`, giving every root CID and name
+/// its own origin. Blocks come from the configured backend and are verified
+/// against their CIDs before anything is served.
+///
+/// Sites on `.eth` names load the same way, from their ENS content hash.
+pub struct IpfsGateway {
+    client: RwLock<Arc<IpfsClient>>,
+    names: Arc<BlockchainNames>,
+    path: PathBuf,
+    port: AtomicU16,
+    blocks: Mutex<BlockCache>,
+    resolved: Mutex<HashMap<String, Resolution>>,
+}
+
+impl IpfsGateway {
+    pub fn new(path: PathBuf, names: Arc<BlockchainNames>) -> Self {
+        let client = IpfsClient::new(&IpfsBackend::default()).expect("default IPFS backend is valid");
+        Self {
+            client: RwLock::new(Arc::new(client)),
+            names,
+            path,
+            port: AtomicU16::new(0),
+            blocks: Mutex::new(BlockCache::default()),
+            resolved: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        if let Some(backend) = storage::load_json::<IpfsBackend>(&self.path).await? {
+            *self.client.write() = Arc::new(IpfsClient::new(&backend)?);
+        }
+        Ok(())
+    }
+
+    pub async fn set_backend(&self, backend: IpfsBackend) -> Result<()> {
+        *self.client.write() = Arc::new(IpfsClient::new(&backend)?);
+        // Verified blocks stay valid, but names may resolve differently
+        self.resolved.lock().clear();
+        storage::save_json(&self.path, &backend).await
+    }
+
+    pub fn backend(&self) -> IpfsBackend {
+        self.client.read().backend().clone()
+    }
+
+    /// Start serving on a loopback port. Engines reach `*.localhost` directly,
+    /// never through a tab's proxy.
+    pub async fn start(self: &Arc<Self>) -> Result<()> {
+        let listener = tokio::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let addr = listener.local_addr()?;
+        self.port.store(addr.port(), Ordering::Relaxed);
+
+        let app = Router::new().fallback(handle).with_state(self.clone());
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                error!("IPFS gateway stopped: {}", e);
+            }
+        });
+        info!("IPFS gateway listening on {}", addr);
+        Ok(())
+    }
+
+    /// The gateway URL a web URL on an `.eth` name loads from, when ENS is set up
+    fn ens_url(&self, url: &str) -> Option<Result<String>> {
+        let parsed = url::Url::parse(url).ok()?;
+        let host = parsed.host_str()?;
+        if !matches!(parsed.scheme(), "http" | "https") || !is_ens(host) || !self.names.ens_enabled() {
+            return None;
+        }
+        let url = format!("ipns://{}{}", host, &parsed[url::Position::BeforePath..]);
+        Some(self.gateway_url(Namespace::Ipns, &url))
+    }
+
+    /// The gateway URL an `ipfs://` or `ipns://` URL loads from
+    fn gateway_url(&self, namespace: Namespace, url: &str) -> Result<String> {
+        let port = self.port.load(Ordering::Relaxed);
+        if port == 0 {
+            return Err(anyhow::anyhow!("IPFS gateway is not running"));
+        }
+        let parsed = url::Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid URL {}: {}", url, e))?;
+        let root = parsed
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("{} names no CID or IPNS name", url))?;
+        let label = match namespace {
+            Namespace::Ipfs => Cid::parse(root)?.to_v1().to_string(),
+            Namespace::Ipns => ipns_label(root)?,
+        };
+        if label.len() > MAX_LABEL {
+            return Err(anyhow::anyhow!("{} is too long for a subdomain origin", root));
+        }
+
+        let rest = &parsed[url::Position::BeforePath..];
+        let slash = if rest.starts_with('/') { "" } else { "/" };
+        Ok(format!("http://{}.{}.localhost:{}{}{}", label, namespace.scheme(), port, slash, rest))
+    }
+
+    async fn resolve(&self, name: &str) -> Result<(Cid, Vec<String>)> {
+        if let Some(resolution) = self.resolved.lock().get(name) {
+            if resolution.resolved_at.elapsed() < NAME_TTL {
+                return Ok((resolution.root.clone(), resolution.path.clone()));
+            }
+        }
+
+        let client = self.client.read().clone();
+        let (root, path) = match is_ens(name) {
+            true => match self.names.content(name).await? {
+                ContentHash::Ipfs(cid) => (cid, Vec::new()),
+                ContentHash::Ipns(name) => client.resolve(&name).await?,
+            },
+            false => client.resolve(name).await?,
+        };
+        self.resolved.lock().insert(
+            name.to_string(),
+            Resolution {
+                root: root.clone(),
+                path: path.clone(),
+                resolved_at: Instant::now(),
+            },
+        );
+        Ok((root, path))
+    }
+
+    async fn node(&self, cid: &Cid) -> Result<Node, GatewayError> {
+        let cached = self.blocks.lock().get(cid);
+        let block = match cached {
+            Some(block) => block,
+            None => {
+                let client = self.client.read().clone();
+                let block = Arc::new(client.block(cid).await.map_err(GatewayError::bad_gateway)?);
+                self.blocks.lock().insert(cid.clone(), block.clone());
+                block
+            }
+        };
+        Node::decode(cid, &block).map_err(GatewayError::bad_gateway)
+    }
+
+    async fn serve(&self, namespace: Namespace, label: &str, uri: &Uri) -> Result<Response, GatewayError> {
+        let (root, mut segments) = match namespace {
+            Namespace::Ipfs => (Cid::parse(label).map_err(GatewayError::bad_request)?, Vec::new()),
+            Namespace::Ipns => self.resolve(&ipns_name(label)).await.map_err(GatewayError::bad_gateway)?,
+        };
+        for segment in uri.path().split('/').filter(|segment| !segment.is_empty()) {
+            let segment = percent_decode_str(segment)
+                .decode_utf8()
+                .map_err(GatewayError::bad_request)?;
+            segments.push(segment.into_owned());
+        }
+
+        let mut node = self.node(&root).await?;
+        for segment in &segments {
+            match node.kind {
+                NodeType::Directory => {}
+                NodeType::HamtShard => return Err(GatewayError::unsupported("Sharded directories")),
+                _ => return Err(GatewayError::not_found(uri.path())),
+            }
+            let link = node
+                .links
+                .iter()
+                .find(|link| &link.name == segment)
+                .ok_or_else(|| GatewayError::not_found(uri.path()))?;
+            let cid = link.cid.clone();
+            node = self.node(&cid).await?;
+        }
+
+        let mut name = segments.last().map(String::as_str).unwrap_or_default();
+        match node.kind {
+            NodeType::Directory if !uri.path().ends_with('/') => {
+                let query = uri.query().map(|query| format!("?{}", query)).unwrap_or_default();
+                return Ok(Redirect::permanent(&format!("{}/{}", uri.path(), query)).into_response());
+            }
+            NodeType::Directory => match node.links.iter().find(|link| link.name == "index.html") {
+                Some(index) => {
+                    let cid = index.cid.clone();
+                    node = self.node(&cid).await?;
+                    name = "index.html";
+                }
+                None => return Ok(content(namespace, "text/html; charset=utf-8", listing(uri.path(), &node))),
+            },
+            NodeType::HamtShard => return Err(GatewayError::unsupported("Sharded directories")),
+            NodeType::Symlink => return Err(GatewayError::unsupported("Symlinks")),
+            _ => {}
+        }
+        if !node.is_file() {
+            return Err(GatewayError::unsupported("Metadata nodes"));
+        }
+
+        let data = self.read_file(node).await?;
+        Ok(content(namespace, mime_type(name), data))
+    }
+
+    /// Assemble a file from its DAG, depth first, as its bytes come in order
+    async fn read_file(&self, root: Node) -> Result<Vec<u8>, GatewayError> {
+        enum Part {
+            Data(Vec<u8>),
+            Block(Cid, usize),
+        }
+        fn push(stack: &mut Vec<Part>, node: Node, depth: usize) {
+            for link in node.links.into_iter().rev() {
+                stack.push(Part::Block(link.cid, depth + 1));
+            }
+            stack.push(Part::Data(node.data));
+        }
+
+        let mut data = Vec::new();
+        let mut stack = Vec::new();
+        push(&mut stack, root, 0);
+        while let Some(part) = stack.pop() {
+            match part {
+                Part::Data(bytes) => {
+                    if data.len() + bytes.len() > MAX_FILE_SIZE {
+                        return Err(GatewayError::new(StatusCode::PAYLOAD_TOO_LARGE, "File is too large to load"));
+                    }
+                    data.extend_from_slice(&bytes);
+                }
+                Part::Block(cid, depth) => {
+                    if depth > MAX_DAG_DEPTH {
+                        return Err(GatewayError::bad_gateway("File DAG is too deep"));
+                    }
+                    let node = self.node(&cid).await?;
+                    if !node.is_file() {
+                        return Err(GatewayError::bad_gateway(format!("File links to a non-file block {}", cid)));
+                    }
+                    push(&mut stack, node, depth);
+                }
+            }
+        }
+        Ok(data)
+    }
+}
+
+impl ProtocolHandler for IpfsGateway {
+    fn engine_url(&self, url: &str) -> Option<Result<String>> {
+        let (scheme, _) = url.split_once("://")?;
+        match Namespace::of(&scheme.to_ascii_lowercase()) {
+            Some(namespace) => Some(self.gateway_url(namespace, url)),
+            None => self.ens_url(url),
+        }
+    }
+
+    fn display_url(&self, engine_url: &str) -> Option<String> {
+        let url = url::Url::parse(engine_url).ok()?;
+        if url.scheme() != "http" || url.port() != Some(self.port.load(Ordering::Relaxed)) {
+            return None;
+        }
+        let (namespace, label) = parse_host(url.host_str()?)?;
+        let name = match namespace {
+            Namespace::Ipfs => label.to_string(),
+            Namespace::Ipns => ipns_name(label),
+        };
+        Some(format!("{}://{}{}", namespace.scheme(), name, &url[url::Position::BeforePath..]))
+    }
+
+    fn content_origin(&self, url: &str) -> Option<String> {
+        let url = url::Url::parse(url).ok()?;
+        let host = url.host_str()?;
+        let (root, path) = match Namespace::of(url.scheme())? {
+            Namespace::Ipfs => (Cid::parse(host).ok()?, Vec::new()),
+            Namespace::Ipns => {
+                let resolved = self.resolved.lock();
+                let resolution = resolved.get(host)?;
+                (resolution.root.clone(), resolution.path.clone())
+            }
+        };
+        let path: String = path.iter().map(|segment| format!("/{}", segment)).collect();
+        Some(format!("ipfs://{}{}", root.to_v1(), path))
+    }
+}
+
+/// Sends pages and resources on `.eth` names that the engine requests itself,
+/// like links, to the gateway
+impl RequestInterceptor for IpfsGateway {
+    fn intercept(&self, request: &InterceptedRequest) -> RequestAction {
+        match self.ens_url(&request.url) {
+            Some(Ok(url)) => RequestAction::Redirect { url },
+            _ => RequestAction::Continue,
+        }
+    }
+}
+
+async fn handle(State(gateway): State<Arc<IpfsGateway>>, method: Method, headers: HeaderMap, uri: Uri) -> Response {
+    if method != Method::GET && method != Method::HEAD {
+        return GatewayError::new(StatusCode::METHOD_NOT_ALLOWED, "The IPFS gateway is read-only").into_response();
+    }
+    // Only `*.localhost` names are served, which also keeps out DNS rebinding
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| url::Url::parse(&format!("http://{}", host)).ok());
+    let Some((namespace, label)) = host.as_ref().and_then(|host| parse_host(host.host_str()?)) else {
+        return GatewayError::new(StatusCode::NOT_FOUND, "Not an IPFS or IPNS origin").into_response();
+    };
+
+    match gateway.serve(namespace, label, &uri).await {
+        Ok(response) => response,
+        Err(e) => {
+            if e.status == StatusCode::BAD_GATEWAY {
+                warn!("IPFS gateway could not serve {}.{}{}: {}", label, namespace.scheme(), uri, e);
+            }
+            e.into_response()
+        }
+    }
+}
+
+fn content(namespace: Namespace, content_type: &str, body: impl Into<axum::body::Body>) -> Response {
+    // Content under a CID never changes; a name may be repointed
+    let cache = match namespace {
+        Namespace::Ipfs => "public, max-age=29030400, immutable",
+        Namespace::Ipns => "public, max-age=60",
+    };
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CACHE_CONTROL, cache.to_string()),
+        ],
+        body.into(),
+    )
+        .into_response()
+}
+
+/// `<label>.ipfs.localhost` or `<label>.ipns.localhost`
+fn parse_host(host: &str) -> Option<(Namespace, &str)> {
+    let (label, namespace) = host.strip_suffix(".localhost")?.split_once('.')?;
+    Some((Namespace::of(namespace)?, label)).filter(|(_, label)| !label.is_empty())
+}
+
+/// Subdomain form of an IPNS name: keys in base36, which survives the engine
+/// lowercasing host names, and DNSLink domains with `-` for `.` and `--` for `-`
+fn ipns_label(name: &str) -> Result<String> {
+    if let Ok(key) = Cid::parse(name) {
+        return Ok(key.with_codec(LIBP2P_KEY).to_base36());
+    }
+    if !name.contains('.') {
+        return Err(anyhow::anyhow!("{} is neither an IPNS key nor a DNSLink domain", name));
+    }
+    Ok(name.to_ascii_lowercase().replace('-', "--").replace('.', "-"))
+}
+
+/// The name a subdomain label stands for; only DNSLink labels contain `-`
+fn ipns_name(label: &str) -> String {
+    if !label.contains('-') {
+        return label.to_string();
+    }
+    label
+        .split("--")
+        .map(|part| part.replace('-', "."))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn listing(path: &str, node: &Node) -> String {
+    let title = escape_html(&percent_decode_str(path).decode_utf8_lossy());
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n<body><h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if path != "/" {
+        html.push_str("<li><a href=\"../\">..</a></li>\n");
+    }
+    for link in &node.links {
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{}\">{}</a> <small>{} bytes</small></li>",
+            utf8_percent_encode(&link.name, SEGMENT),
+            escape_html(&link.name),
+            link.size
+        );
+    }
+    html.push_str("</ul></body></html>\n");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn mime_type(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt" | "md") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[derive(Debug)]
+struct GatewayError {
+    status: StatusCode,
+    message: String,
+}
+
+impl GatewayError {
+    fn new(status: StatusCode, message: impl fmt::Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    fn bad_request(e: impl fmt::Display) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, e)
+    }
+
+    /// The backend failed or sent content that does not match its CID
+    fn bad_gateway(e: impl fmt::Display) -> Self {
+        Self::new(StatusCode::BAD_GATEWAY, e)
+    }
+
+    fn not_found(path: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, format!("No such file or directory: {}", path))
+    }
+
+    fn unsupported(what: &str) -> Self {
+        Self::new(StatusCode::NOT_IMPLEMENTED, format!("{} are not supported yet", what))
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl IntoResponse for GatewayError {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            self.message,
+        )
+            .into_response()
+    }
+}
@@ -0,0 +1,196 @@
+use super::cid::{read_varint, Cid, DAG_PB, RAW};
+use anyhow::Result;
+
+/// A link from a dag-pb node to a child block
+#[derive(Debug, Clone)]
+pub struct Link {
+    pub cid: Cid,
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeType {
+    Raw,
+    Directory,
+    File,
+    Metadata,
+    Symlink,
+    HamtShard,
+}
+
+/// A UnixFS node: a file chunk, a file's root, or a directory
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeType,
+    /// File bytes held in this node itself, before those of its children
+    pub data: Vec<u8>,
+    pub links: Vec<Link>,
+}
+
+impl Node {
+    /// Decode a verified block; raw blocks are file chunks without links
+    pub fn decode(cid: &Cid, block: &[u8]) -> Result<Self> {
+        match cid.codec {
+            RAW => Ok(Self {
+                kind: NodeType::Raw,
+                data: block.to_vec(),
+                links: Vec::new(),
+            }),
+            DAG_PB => decode_dag_pb(block),
+            other => Err(anyhow::anyhow!("Unsupported block codec 0x{:x} in {}", other, cid)),
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        matches!(self.kind, NodeType::Raw | NodeType::File)
+    }
+}
+
+/// One protobuf field: its number and either a varint or a byte string
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Other,
+}
+
+fn read_field<'a>(bytes: &mut &'a [u8]) -> Result<(u64, Field<'a>)> {
+    let key = read_varint(bytes)?;
+    let field = match key & 7 {
+        0 => Field::Varint(read_varint(bytes)?),
+        2 => {
+            let length = read_varint(bytes)? as usize;
+            if length > bytes.len() {
+                return Err(anyhow::anyhow!("Truncated protobuf field"));
+            }
+            let (value, rest) = bytes.split_at(length);
+            *bytes = rest;
+            Field::Bytes(value)
+        }
+        wire @ (1 | 5) => {
+            let length = if wire == 1 { 8 } else { 4 };
+            if length > bytes.len() {
+                return Err(anyhow::anyhow!("Truncated protobuf field"));
+            }
+            *bytes = &bytes[length..];
+            Field::Other
+        }
+        other => return Err(anyhow::anyhow!("Unsupported protobuf wire type {}", other)),
+    };
+    Ok((key >> 3, field))
+}
+
+/// PBNode { Data = 1, Links = 2 } holding a UnixFS Data { Type = 1, Data = 2 }
+fn decode_dag_pb(mut block: &[u8]) -> Result<Node> {
+    let mut unixfs = None;
+    let mut links = Vec::new();
+    while !block.is_empty() {
+        match read_field(&mut block)? {
+            (1, Field::Bytes(data)) => unixfs = Some(data),
+            (2, Field::Bytes(link)) => links.push(decode_link(link)?),
+            _ => {}
+        }
+    }
+
+    let mut fields = unixfs.ok_or_else(|| anyhow::anyhow!("dag-pb node is not UnixFS"))?;
+    let mut kind = None;
+    let mut data = Vec::new();
+    while !fields.is_empty() {
+        match read_field(&mut fields)? {
+            (1, Field::Varint(value)) => {
+                kind = Some(match value {
+                    0 => NodeType::Raw,
+                    1 => NodeType::Directory,
+                    2 => NodeType::File,
+                    3 => NodeType::Metadata,
+                    4 => NodeType::Symlink,
+                    5 => NodeType::HamtShard,
+                    other => return Err(anyhow::anyhow!("Unknown UnixFS node type {}", other)),
+                })
+            }
+            (2, Field::Bytes(value)) => data = value.to_vec(),
+            _ => {}
+        }
+    }
+    Ok(Node {
+        kind: kind.ok_or_else(|| anyhow::anyhow!("UnixFS node without a type"))?,
+        data,
+        links,
+    })
+}
+
+/// PBLink { Hash = 1, Name = 2, Tsize = 3 }
+fn decode_link(mut bytes: &[u8]) -> Result<Link> {
+    let mut cid = None;
+    let mut name = String::new();
+    let mut size = 0;
+    while !bytes.is_empty() {
+        match read_field(&mut bytes)? {
+            (1, Field::Bytes(hash)) => cid = Some(Cid::from_bytes(hash)?),
+            (2, Field::Bytes(value)) => name = String::from_utf8_lossy(value).into_owned(),
+            (3, Field::Varint(value)) => size = value,
+            _ => {}
+        }
+    }
+    Ok(Link {
+        cid: cid.ok_or_else(|| anyhow::anyhow!("dag-pb link without a hash"))?,
+        name,
+        size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(number: u64, value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![((number << 3) | 2) as u8, value.len() as u8];
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    #[test]
+    fn decodes_directory_with_links() {
+        let child = Cid::parse("QmUNLLsPACCz1vLxQVkXqqLX5R1X345qqfHbsf67hvA3Nn").unwrap();
+        let mut link = field(1, &child.to_bytes());
+        link.extend(field(2, b"index.html"));
+        link.extend([3 << 3, 4]);
+        let mut block = field(2, &link);
+        block.extend(field(1, &[0x08, 0x01]));
+
+        let cid = Cid::parse("bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354").unwrap();
+        let node = Node::decode(&cid, &block).unwrap();
+        assert_eq!(node.kind, NodeType::Directory);
+        assert!(!node.is_file());
+        assert_eq!(node.links.len(), 1);
+        assert_eq!(node.links[0].cid, child);
+        assert_eq!(node.links[0].name, "index.html");
+        assert_eq!(node.links[0].size, 4);
+    }
+
+    #[test]
+    fn decodes_file_and_raw_blocks() {
+        let cid = Cid::parse("bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354").unwrap();
+        let mut unixfs = vec![0x08, 0x02];
+        unixfs.extend(field(2, b"hello\n"));
+        let node = Node::decode(&cid, &field(1, &unixfs)).unwrap();
+        assert_eq!(node.kind, NodeType::File);
+        assert_eq!(node.data, b"hello\n");
+        assert!(node.links.is_empty());
+
+        let raw = cid.with_codec(RAW);
+        let node = Node::decode(&raw, b"bytes").unwrap();
+        assert_eq!((node.kind, node.data.as_slice()), (NodeType::Raw, &b"bytes"[..]));
+        assert!(node.is_file());
+    }
+
+    #[test]
+    fn rejects_malformed_nodes() {
+        let cid = Cid::parse("bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354").unwrap();
+        // No UnixFS data, no type, unknown type, truncated field
+        for block in [vec![], field(1, &[]), field(1, &[0x08, 0x09]), vec![0x0a, 0x05, 0x08]] {
+            assert!(Node::decode(&cid, &block).is_err(), "{:?}", block);
+        }
+        assert!(Node::decode(&cid.with_codec(0x71), &[]).is_err());
+    }
+}
@@ -1,102 +1,795 @@
+mod blocking;
+mod containers;
+mod cookies;
 mod engine;
 mod ipc;
+mod gemini;
+mod ipfs;
 mod network;
+mod permissions;
 mod security;
+mod session;
+mod sites;
+mod storage;
 mod ui;
+mod zoom;
 
 use anyhow::Result;
-use engine::{blink::BlinkEngine, EngineManager};
-use ipc::{BrowserState, IPCCommand, IPCEvent, IPCHandler, IPCResponse, NetworkRoute, PrivacyMode, SharedState};
-use network::{DefaultNetworkManager, NetworkManager};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use blocking::ContentBlocker;
+use containers::ContainerManager;
+use cookies::{cookie_matches, CookieManager};
+use engine::{EngineManager, TabOptions};
+use gemini::GeminiGateway;
+use ipfs::IpfsGateway;
+use ipc::{
+    BrowserEngine, BrowserState, ErrorInfo, FindMatches, IPCCommand, IPCEvent, IPCHandler, IPCResponse, NetworkRoute,
+    PrivacyMode, ScreenshotOptions, SharedState, TabId, WindowId, MAIN_WINDOW,
+};
+use network::{names::BlockchainNames, rules::RoutingRules, DefaultNetworkManager, NetworkManager};
 use parking_lot::RwLock;
-use security::{DefaultSecurityManager, SecurityManager};
-use std::{net::SocketAddr, sync::Arc};
+use permissions::PermissionManager;
+use security::{https_only::HttpsOnlyMode, DefaultSecurityManager, SecurityManager};
+use session::{SessionSnapshot, SessionStore};
+use sites::{base_domain, host_of, normalize_site};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio::io::AsyncWriteExt;
 use tokio::sync::broadcast;
 use tracing::{info, warn};
+use ui::{NativeWindows, WindowHandler, WindowHost, WindowRequest};
+use zoom::ZoomManager;
 
 struct App {
-    engine_manager: Arc<RwLock<EngineManager>>,
+    engine_manager: Arc<EngineManager>,
     network_manager: Arc<DefaultNetworkManager>,
     security_manager: Arc<DefaultSecurityManager>,
+    content_blocker: Arc<ContentBlocker>,
+    https_only: Arc<HttpsOnlyMode>,
+    routing_rules: Arc<RoutingRules>,
+    ipfs_gateway: Arc<IpfsGateway>,
+    blockchain_names: Arc<BlockchainNames>,
+    gemini_gateway: Arc<GeminiGateway>,
+    permission_manager: Arc<PermissionManager>,
+    cookie_manager: Arc<CookieManager>,
+    container_manager: Arc<ContainerManager>,
+    zoom_manager: Arc<ZoomManager>,
+    /// Native windows, unless running headless
+    native_windows: Option<Arc<NativeWindows>>,
+    session_store: SessionStore,
+    pending_session: RwLock<Option<SessionSnapshot>>,
     shared_state: SharedState,
     event_tx: broadcast::Sender<IPCEvent>,
 }
 
 impl App {
-    async fn new() -> Result<Self> {
+    async fn new(native_windows: Option<Arc<NativeWindows>>) -> Result<Self> {
         // Set up event channel
         let (event_tx, _) = broadcast::channel(100);
         
         // Create initial state
         let shared_state = Arc::new(RwLock::new(BrowserState::default()));
         
+        // Load routing rules, e.g. onion services through Tor
+        let routing_rules = Arc::new(RoutingRules::new(storage::data_dir().join("routing_rules.json")));
+        if let Err(e) = routing_rules.load().await {
+            warn!("Failed to load routing rules: {}", e);
+        }
+        
+        // Load ENS and Handshake settings
+        let blockchain_names = Arc::new(BlockchainNames::new(storage::data_dir().join("names.json")));
+        if let Err(e) = blockchain_names.load().await {
+            warn!("Failed to load blockchain name settings: {}", e);
+        }
+        
         // Create managers
-        let network_manager = Arc::new(DefaultNetworkManager::new());
+        let network_manager = Arc::new(DefaultNetworkManager::new(
+            routing_rules.clone(),
+            blockchain_names.clone(),
+            event_tx.clone(),
+        ));
+        network_manager.start_monitor();
         let security_manager = Arc::new(DefaultSecurityManager::new());
         
-        // Create initial engine (Blink)
-        let initial_engine = Box::new(BlinkEngine::new().await?);
+        // Load tracker and ad blocking filter lists
+        let content_blocker = Arc::new(ContentBlocker::new(
+            storage::data_dir().join("filters"),
+            storage::data_dir().join("shields.json"),
+            event_tx.clone(),
+        ));
+        if let Err(e) = content_blocker.load().await {
+            warn!("Failed to load filter lists: {}", e);
+        }
+        
+        // Load the HTTPS-Only toggle and exceptions
+        let https_only = Arc::new(HttpsOnlyMode::new(
+            storage::data_dir().join("https_exceptions.json"),
+            event_tx.clone(),
+        ));
+        if let Err(e) = https_only.load().await {
+            warn!("Failed to load HTTPS-Only settings: {}", e);
+        }
+        shared_state.write().https_only = https_only.is_enabled();
+        
+        // Serve ipfs://, ipns:// and .eth content through a local gateway
+        let ipfs_gateway = Arc::new(IpfsGateway::new(
+            storage::data_dir().join("ipfs.json"),
+            blockchain_names.clone(),
+        ));
+        if let Err(e) = ipfs_gateway.load().await {
+            warn!("Failed to load IPFS settings: {}", e);
+        }
+        ipfs_gateway.start().await?;
+        
+        // Serve gemini:// capsules as HTML, pinning server keys on first use
+        let gemini_gateway = Arc::new(GeminiGateway::new(
+            storage::data_dir().join("gemini_hosts.json"),
+            event_tx.clone(),
+        ));
+        if let Err(e) = gemini_gateway.load().await {
+            warn!("Failed to load pinned Gemini certificates: {}", e);
+        }
+        gemini_gateway.start().await?;
+        
+        // Load remembered camera, location and other permission decisions
+        let permission_manager = Arc::new(PermissionManager::new(
+            storage::data_dir().join("permissions.json"),
+            shared_state.clone(),
+            event_tx.clone(),
+        ));
+        if let Err(e) = permission_manager.load().await {
+            warn!("Failed to load permission decisions: {}", e);
+        }
+        
+        // Load cookie blocking and clearing settings
+        let cookie_manager = Arc::new(CookieManager::new(storage::data_dir().join("cookies.json")));
+        if let Err(e) = cookie_manager.load().await {
+            warn!("Failed to load cookie settings: {}", e);
+        }
+        
+        let container_manager = Arc::new(ContainerManager::new(
+            storage::data_dir().join("containers.json"),
+            shared_state.clone(),
+            event_tx.clone(),
+        ));
+        if let Err(e) = container_manager.load().await {
+            warn!("Failed to load containers: {}", e);
+        }
+        
+        let zoom_manager = Arc::new(ZoomManager::new(storage::data_dir().join("zoom.json")));
+        if let Err(e) = zoom_manager.load().await {
+            warn!("Failed to load zoom levels: {}", e);
+        }
         
         // Create engine manager
-        let engine_manager = Arc::new(RwLock::new(EngineManager::new(
-            initial_engine,
+        let engine_manager = Arc::new(EngineManager::new(
+            network_manager.clone(),
             shared_state.clone(),
             event_tx.clone(),
-        )));
+        ));
+        engine_manager.start_history();
+        engine_manager.add_request_interceptor(content_blocker.clone());
+        engine_manager.add_request_interceptor(ipfs_gateway.clone());
+        engine_manager.add_request_interceptor(https_only.clone());
+        engine_manager.add_request_interceptor(cookie_manager.clone());
+        engine_manager.add_protocol_handler(ipfs_gateway.clone());
+        engine_manager.add_protocol_handler(gemini_gateway.clone());
+        engine_manager.set_permission_handler(permission_manager.clone());
+        engine_manager.set_zoom_levels(zoom_manager.clone());
+        engine_manager
+            .set_third_party_cookies(cookie_manager.settings().third_party)
+            .await?;
+        
+        // Check for a session left behind by the previous run
+        let session_store = SessionStore::default();
+        let pending_session = match session_store.load().await {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!("Ignoring unreadable session journal: {}", e);
+                None
+            }
+        };
+        shared_state.write().session_restore_available = pending_session.is_some();
+        
+        // Create initial tab (Blink)
+        engine_manager.open_tab(TabOptions::default(), None).await?;
+        
+        if let Some(snapshot) = &pending_session {
+            info!("Previous session with {} tabs available for restore", snapshot.tabs.len());
+            let _ = event_tx.send(IPCEvent::SessionRestoreAvailable {
+                tabs: snapshot.tabs.len(),
+                saved_at: snapshot.saved_at,
+            });
+        }
         
         Ok(Self {
             engine_manager,
             network_manager,
             security_manager,
+            content_blocker,
+            https_only,
+            routing_rules,
+            ipfs_gateway,
+            blockchain_names,
+            gemini_gateway,
+            permission_manager,
+            cookie_manager,
+            container_manager,
+            zoom_manager,
+            native_windows,
+            session_store,
+            pending_session: RwLock::new(pending_session),
             shared_state,
             event_tx,
         })
     }
+    
+    /// Navigate the active tab, upgrading to HTTPS when HTTPS-Only mode applies.
+    ///
+    /// A failed upgrade never falls back to HTTP on its own; the UI gets an
+    /// interstitial event and can add an exception before retrying.
+    async fn navigate(&self, url: String) -> Result<IPCResponse<serde_json::Value>> {
+        let tab = self.engine_manager.active_tab_id()?;
+        
+        let Some(upgraded_url) = self.https_only.upgrade(&url) else {
+            self.engine_manager.navigate(url).await?;
+            self.engine_manager.set_https_upgraded(tab, false)?;
+            return Ok(IPCResponse { success: true, data: None, error: None });
+        };
+        
+        match self.engine_manager.navigate(upgraded_url.clone()).await {
+            Ok(()) => {
+                self.engine_manager.set_https_upgraded(tab, true)?;
+                Ok(IPCResponse { success: true, data: None, error: None })
+            }
+            Err(e) => {
+                warn!("HTTPS upgrade of {} failed: {}", url, e);
+                // Having no subscribers yet is not an error
+                let _ = self.event_tx.send(IPCEvent::HttpsUpgradeFailed {
+                    tab,
+                    url: url.clone(),
+                    upgraded_url,
+                    error: e.to_string(),
+                });
+                Ok(IPCResponse {
+                    success: false,
+                    data: None,
+                    error: Some(ErrorInfo {
+                        code: String::from("HTTPS_UPGRADE_FAILED"),
+                        message: format!("{} is not available over HTTPS", url),
+                    }),
+                })
+            }
+        }
+    }
+    
+    /// Write the current tab set to the session journal.
+    ///
+    /// Skipped while a previous session is still on offer, so starting up
+    /// does not overwrite it before the user has decided.
+    async fn persist_session(&self) {
+        if self.pending_session.read().is_some() {
+            return;
+        }
+        
+        let snapshot = self
+            .engine_manager
+            .with_tabs(|tabs, active| SessionSnapshot::capture(tabs, active, self.engine_manager.protocols()));
+        if let Err(e) = self.session_store.save(&snapshot).await {
+            warn!("Failed to journal session: {}", e);
+        }
+    }
+    
+    async fn restore_session(&self) -> Result<()> {
+        let snapshot = self
+            .pending_session
+            .write()
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("No session to restore"))?;
+        
+        // Untouched blank tabs opened at startup are replaced by the restored ones
+        let blank_tabs: Vec<TabId> = self.engine_manager.with_tabs(|tabs, _| {
+            tabs.iter()
+                .filter(|tab| tab.history.entries.is_empty())
+                .map(|tab| tab.id)
+                .collect()
+        });
+        
+        let restored = snapshot
+            .restore_into(&self.engine_manager, &self.container_manager)
+            .await?;
+        if !restored.is_empty() {
+            for id in blank_tabs {
+                self.engine_manager.close_tab(id).await?;
+            }
+        }
+        
+        self.apply_active_tab_settings().await?;
+        self.finish_session_offer().await;
+        Ok(())
+    }
+    
+    async fn discard_session(&self) -> Result<()> {
+        self.pending_session.write().take();
+        self.session_store.clear().await?;
+        self.finish_session_offer().await;
+        Ok(())
+    }
+    
+    async fn finish_session_offer(&self) {
+        let state = {
+            let mut state = self.shared_state.write();
+            state.session_restore_available = false;
+            state.clone()
+        };
+        let _ = self.event_tx.send(IPCEvent::StateUpdate(Box::new(state)));
+        self.persist_session().await;
+    }
+    
+    /// Delete the cookies of a closing tab's site, unless another tab is on
+    /// the same site or the site is kept
+    async fn clear_cookies_on_close(&self, tab: TabId) -> Result<()> {
+        let sites: Vec<(TabId, Option<String>)> = self
+            .shared_state
+            .read()
+            .tabs
+            .iter()
+            .map(|info| (info.id, host_of(&info.url).map(|host| base_domain(&host).to_string())))
+            .collect();
+        let Some((_, Some(site))) = sites.iter().find(|(id, _)| *id == tab) else {
+            return Ok(());
+        };
+        let open_elsewhere = sites.iter().any(|(id, other)| *id != tab && other.as_ref() == Some(site));
+        if open_elsewhere || !self.cookie_manager.clears_on_close(site) {
+            return Ok(());
+        }
+        
+        let deleted = self.engine_manager.delete_cookies(|cookie| cookie_matches(cookie, site)).await?;
+        info!("Deleted {} cookies of {} as its last tab closed", deleted, site);
+        Ok(())
+    }
+    
+    /// Close a window's tabs, clearing cookies as each closes, and then the window
+    async fn close_window_tabs(&self, window: WindowId) -> Result<()> {
+        let tabs: Vec<TabId> = self
+            .engine_manager
+            .tabs()
+            .iter()
+            .filter(|tab| tab.window == window)
+            .map(|tab| tab.id)
+            .collect();
+        for tab in tabs {
+            if let Err(e) = self.clear_cookies_on_close(tab).await {
+                warn!("Failed to clear cookies of closed tab {}: {}", tab, e);
+            }
+            self.engine_manager.close_tab(tab).await?;
+        }
+        self.engine_manager.close_window(window).await?;
+        if self.engine_manager.active_tab_id().is_ok() {
+            self.apply_active_tab_settings().await?;
+        }
+        Ok(())
+    }
+    
+    fn request_window(&self, request: WindowRequest) -> Result<()> {
+        match &self.native_windows {
+            Some(windows) => windows.request(request),
+            None => Ok(()),
+        }
+    }
+    
+    /// Privacy mode is still applied globally, so make it follow whichever
+    /// tab is active; network routes are bound to each tab's endpoint
+    async fn apply_active_tab_settings(&self) -> Result<()> {
+        let id = self.engine_manager.active_tab_id()?;
+        let tab = self
+            .engine_manager
+            .tabs()
+            .into_iter()
+            .find(|tab| tab.id == id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown tab: {}", id))?;
+        
+        self.security_manager.set_privacy_mode(&tab.privacy_mode).await?;
+        Ok(())
+    }
+    
+    /// Zoom a tab. Outside Private tabs the level is remembered for the
+    /// tab's site and applied to every tab showing it, as engines will to
+    /// pages they load from it later.
+    async fn set_zoom(&self, tab: TabId, level: f64) -> Result<()> {
+        zoom::check_level(level)?;
+        let tabs = self.engine_manager.tabs();
+        let info = tabs
+            .iter()
+            .find(|info| info.id == tab)
+            .ok_or_else(|| anyhow::anyhow!("Unknown tab: {}", tab))?;
+        // Pages like `about:blank` have no site to remember a level for
+        let site_of = |url: &str| url.contains("://").then(|| normalize_site(url).ok()).flatten();
+        let Some(site) = site_of(&info.url).filter(|_| info.privacy_mode != PrivacyMode::Private) else {
+            return self.engine_manager.engine_for(tab)?.set_zoom(level).await;
+        };
+        
+        self.zoom_manager.set(&site, level).await?;
+        for info in tabs.iter().filter(|info| site_of(&info.url).as_ref() == Some(&site)) {
+            self.engine_manager.engine_for(info.id)?.set_zoom(level).await?;
+        }
+        Ok(())
+    }
+    
+    /// Send a search's matches to the UI's find bar, and answer with them too
+    fn report_find(&self, tab: TabId, matches: FindMatches) -> Result<IPCResponse<serde_json::Value>> {
+        let _ = self.event_tx.send(IPCEvent::FindResult {
+            tab,
+            active_match: matches.active_match,
+            total_matches: matches.total_matches,
+        });
+        Ok(IPCResponse { success: true, data: Some(serde_json::to_value(matches)?), error: None })
+    }
 }
 
 #[async_trait::async_trait]
 impl IPCHandler for App {
-    async fn handle_command(&self, command: IPCCommand) -> Result<IPCResponse> {
-        match command {
-            IPCCommand::Navigate { url } => {
-                self.engine_manager.read().current_engine().navigate(url).await?;
-                Ok(IPCResponse { success: true, data: None, error: None })
-            }
+    async fn handle_command(&self, command: IPCCommand) -> Result<IPCResponse<serde_json::Value>> {
+        let response = match command {
+            IPCCommand::Navigate { url } => self.navigate(url).await?,
             IPCCommand::SwitchEngine { engine } => {
-                self.engine_manager.write().switch_engine(engine).await?;
-                Ok(IPCResponse { success: true, data: None, error: None })
+                self.engine_manager.switch_engine(engine).await?;
+                IPCResponse { success: true, data: None, error: None }
             }
             IPCCommand::SetPrivacyMode { mode } => {
+                let tab = self.engine_manager.active_tab_id()?;
+                // VPN mode uses the default WireGuard config unless the tab already has a tunnel
+                if mode == PrivacyMode::Vpn && !matches!(self.network_manager.get_route(tab).await, Some(NetworkRoute::Vpn { .. })) {
+                    let route = NetworkRoute::Vpn { config: storage::data_dir().join("wireguard.conf") };
+                    self.engine_manager.set_network_route(tab, route).await?;
+                }
                 self.security_manager.set_privacy_mode(&mode).await?;
-                let mut state = self.shared_state.write();
-                state.privacy_mode = mode.clone();
-                self.event_tx.send(IPCEvent::StateUpdate(state.clone()))?;
-                Ok(IPCResponse { success: true, data: None, error: None })
+                self.engine_manager.set_privacy_mode(tab, mode).await?;
+                IPCResponse { success: true, data: None, error: None }
             }
-            IPCCommand::SetNetworkRoute { route } => {
-                self.network_manager.set_route(&route).await?;
-                let mut state = self.shared_state.write();
-                state.network_route = route.clone();
-                self.event_tx.send(IPCEvent::StateUpdate(state.clone()))?;
-                Ok(IPCResponse { success: true, data: None, error: None })
+            IPCCommand::SetNetworkRoute { tab, route } => {
+                self.engine_manager.set_network_route(tab, route).await?;
+                IPCResponse { success: true, data: None, error: None }
             }
             IPCCommand::Reload => {
-                self.engine_manager.read().current_engine().reload().await?;
-                Ok(IPCResponse { success: true, data: None, error: None })
+                self.engine_manager.current_engine()?.reload().await?;
+                IPCResponse { success: true, data: None, error: None }
             }
             IPCCommand::Stop => {
-                self.engine_manager.read().current_engine().stop().await?;
-                Ok(IPCResponse { success: true, data: None, error: None })
+                self.engine_manager.current_engine()?.stop().await?;
+                IPCResponse { success: true, data: None, error: None }
             }
             IPCCommand::GoBack => {
-                self.engine_manager.read().current_engine().go_back().await?;
-                Ok(IPCResponse { success: true, data: None, error: None })
+                self.engine_manager.go_back().await?;
+                IPCResponse { success: true, data: None, error: None }
             }
             IPCCommand::GoForward => {
-                self.engine_manager.read().current_engine().go_forward().await?;
-                Ok(IPCResponse { success: true, data: None, error: None })
+                self.engine_manager.go_forward().await?;
+                IPCResponse { success: true, data: None, error: None }
             }
-        }
+            IPCCommand::NewTab { url, engine, privacy_mode, container, window } => {
+                if let Some(container) = container {
+                    self.container_manager.check(container)?;
+                }
+                let options = TabOptions {
+                    engine: engine.unwrap_or(BrowserEngine::Blink),
+                    privacy_mode: match privacy_mode {
+                        Some(mode) => mode,
+                        None => self.security_manager.get_current_mode().await,
+                    },
+                    // New tabs stay on the route of the tab they were opened from
+                    network_route: match self.engine_manager.active_tab_id() {
+                        Ok(active) => self.network_manager.get_route(active).await.unwrap_or_default(),
+                        Err(_) => NetworkRoute::default(),
+                    },
+                    container,
+                    window: window.unwrap_or_else(|| self.engine_manager.active_window()),
+                    ..TabOptions::default()
+                };
+                let id = self.engine_manager.open_tab(options, url).await?;
+                self.apply_active_tab_settings().await?;
+                IPCResponse { success: true, data: Some(serde_json::json!({ "tab": id })), error: None }
+            }
+            IPCCommand::CloseTab { tab } => {
+                // Cleared first, so the closing tab's own engine is cleared too
+                if let Err(e) = self.clear_cookies_on_close(tab).await {
+                    warn!("Failed to clear cookies of closed tab {}: {}", tab, e);
+                }
+                self.engine_manager.close_tab(tab).await?;
+                if self.engine_manager.active_tab_id().is_ok() {
+                    self.apply_active_tab_settings().await?;
+                }
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::NewWindow { url } => {
+                let window = self.engine_manager.open_window();
+                let options = TabOptions {
+                    privacy_mode: self.security_manager.get_current_mode().await,
+                    window,
+                    ..TabOptions::default()
+                };
+                let tab = match self.engine_manager.open_tab(options, url).await {
+                    Ok(tab) => tab,
+                    Err(e) => {
+                        self.engine_manager.close_window(window).await?;
+                        return Err(e);
+                    }
+                };
+                self.apply_active_tab_settings().await?;
+                self.request_window(WindowRequest::Open(window))?;
+                IPCResponse { success: true, data: Some(serde_json::json!({ "window": window, "tab": tab })), error: None }
+            }
+            IPCCommand::CloseWindow { window } => {
+                let windows = self.engine_manager.windows();
+                if !windows.iter().any(|info| info.id == window) {
+                    return Err(anyhow::anyhow!("Unknown window: {}", window));
+                }
+                // Closing the last window quits, leaving its tabs in the session
+                if windows.len() == 1 {
+                    if self.native_windows.is_none() {
+                        return Err(anyhow::anyhow!("The last window cannot be closed when running headless"));
+                    }
+                } else {
+                    self.close_window_tabs(window).await?;
+                }
+                self.request_window(WindowRequest::Close(window))?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::ActivateWindow { window } => {
+                self.engine_manager.activate_window(window)?;
+                if self.engine_manager.active_tab_id().is_ok() {
+                    self.apply_active_tab_settings().await?;
+                }
+                self.request_window(WindowRequest::Focus(window))?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::ActivateTab { tab } => {
+                self.engine_manager.activate_tab(tab)?;
+                self.apply_active_tab_settings().await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::PinTab { tab, pinned } => {
+                self.engine_manager.set_pinned(tab, pinned)?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::RestoreSession => {
+                self.restore_session().await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::DiscardSession => {
+                self.discard_session().await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::SetShieldsEnabled { enabled } => {
+                self.content_blocker.set_enabled(enabled);
+                let state = {
+                    let mut state = self.shared_state.write();
+                    state.shields_enabled = enabled;
+                    state.clone()
+                };
+                // Having no subscribers yet is not an error
+                let _ = self.event_tx.send(IPCEvent::StateUpdate(Box::new(state)));
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::SetSiteShields { site, enabled } => {
+                self.content_blocker.set_site_enabled(&site, enabled).await?;
+                let sites = self.content_blocker.allowlisted_sites();
+                IPCResponse { success: true, data: Some(serde_json::json!({ "allowlist": sites })), error: None }
+            }
+            IPCCommand::SetHttpsOnly { enabled } => {
+                self.https_only.set_enabled(enabled).await?;
+                let state = {
+                    let mut state = self.shared_state.write();
+                    state.https_only = enabled;
+                    state.clone()
+                };
+                // Having no subscribers yet is not an error
+                let _ = self.event_tx.send(IPCEvent::StateUpdate(Box::new(state)));
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::SetHttpsException { site, allowed } => {
+                self.https_only.set_exception(&site, allowed).await?;
+                let sites = self.https_only.exceptions();
+                IPCResponse { success: true, data: Some(serde_json::json!({ "exceptions": sites })), error: None }
+            }
+            IPCCommand::GetResolverStats => {
+                let tab = self.engine_manager.active_tab_id()?;
+                let stats = self.network_manager.resolver_stats(tab).await?;
+                IPCResponse { success: true, data: Some(serde_json::to_value(stats)?), error: None }
+            }
+            IPCCommand::SetRoutingRules { rules } => {
+                self.routing_rules.set(rules).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::GetRoutingRules => {
+                let rules = self.routing_rules.rules();
+                IPCResponse { success: true, data: Some(serde_json::to_value(rules)?), error: None }
+            }
+            IPCCommand::SetIpfsBackend { backend } => {
+                self.ipfs_gateway.set_backend(backend).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::GetIpfsBackend => {
+                let backend = self.ipfs_gateway.backend();
+                IPCResponse { success: true, data: Some(serde_json::to_value(backend)?), error: None }
+            }
+            IPCCommand::SetNameSettings { settings } => {
+                self.blockchain_names.set(settings).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::GetNameSettings => {
+                let settings = self.blockchain_names.settings();
+                IPCResponse { success: true, data: Some(serde_json::to_value(settings)?), error: None }
+            }
+            IPCCommand::CaptureScreenshot { tab, format, quality, full_page, clip, path } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                let options = ScreenshotOptions { format, quality, full_page, clip };
+                let image = self.engine_manager.engine_for(tab)?.capture_screenshot(&options).await?;
+                IPCResponse { success: true, data: Some(deliver_capture(image, path).await?), error: None }
+            }
+            IPCCommand::PrintToPdf { tab, options, path } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                let pdf = self.engine_manager.engine_for(tab)?.print_to_pdf(&options).await?;
+                IPCResponse { success: true, data: Some(deliver_capture(pdf, path).await?), error: None }
+            }
+            IPCCommand::FindInPage { tab, text, case_sensitive, forward } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                let matches = self.engine_manager.engine_for(tab)?.find(&text, case_sensitive, forward).await?;
+                self.report_find(tab, matches)?
+            }
+            IPCCommand::FindNext { tab } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                let matches = self.engine_manager.engine_for(tab)?.find_next().await?;
+                self.report_find(tab, matches)?
+            }
+            IPCCommand::StopFinding { tab } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                self.engine_manager.engine_for(tab)?.stop_finding().await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::SetZoom { tab, level } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                self.set_zoom(tab, level).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::GetZoomLevels => {
+                let levels = self.zoom_manager.levels();
+                IPCResponse { success: true, data: Some(serde_json::to_value(levels)?), error: None }
+            }
+            IPCCommand::EmulateDevice { tab, emulation } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                self.engine_manager.set_device_emulation(tab, Some(emulation)).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::EmulateDevicePreset { tab, preset } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                self.engine_manager
+                    .set_device_emulation(tab, Some(preset.emulation()))
+                    .await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::StopEmulation { tab } => {
+                let tab = self.engine_manager.resolve_tab(tab)?;
+                self.engine_manager.set_device_emulation(tab, None).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::ResolveName { name } => {
+                let records = self.blockchain_names.lookup(&name).await?;
+                IPCResponse { success: true, data: Some(serde_json::to_value(records)?), error: None }
+            }
+            IPCCommand::CheckFingerprint => {
+                let profile = self.engine_manager.check_fingerprint().await?;
+                IPCResponse { success: true, data: Some(serde_json::to_value(profile)?), error: None }
+            }
+            IPCCommand::LeakCheck => {
+                let report = self.engine_manager.check_leaks().await?;
+                IPCResponse { success: true, data: Some(serde_json::to_value(report)?), error: None }
+            }
+            IPCCommand::GetGeminiHosts => {
+                let hosts = self.gemini_gateway.known_hosts();
+                IPCResponse { success: true, data: Some(serde_json::to_value(hosts)?), error: None }
+            }
+            IPCCommand::ForgetGeminiHost { host } => {
+                self.gemini_gateway.forget_host(&host).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::RespondPermission { id, allow, remember, expires_in } => {
+                self.permission_manager.respond(id, allow, remember, expires_in)?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::GetPermissions => {
+                let permissions = self.permission_manager.permissions();
+                IPCResponse { success: true, data: Some(serde_json::to_value(permissions)?), error: None }
+            }
+            IPCCommand::SetPermission { origin, permission, decision, expires_in } => {
+                self.permission_manager.set(&origin, permission, decision, expires_in).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::ResetPermissions { origin, permission } => {
+                self.permission_manager.reset(origin.as_deref(), permission).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::GetCookies { site } => {
+                let site = site.as_deref().map(normalize_site).transpose()?;
+                let mut cookies = self.engine_manager.cookies().await?;
+                if let Some(site) = site {
+                    cookies.retain(|cookie| cookie_matches(cookie, &site));
+                }
+                IPCResponse { success: true, data: Some(serde_json::to_value(cookies)?), error: None }
+            }
+            IPCCommand::SearchCookies { query } => {
+                let query = query.to_lowercase();
+                let mut cookies = self.engine_manager.cookies().await?;
+                cookies.retain(|cookie| {
+                    [&cookie.domain, &cookie.name, &cookie.value]
+                        .iter()
+                        .any(|field| field.to_lowercase().contains(&query))
+                });
+                IPCResponse { success: true, data: Some(serde_json::to_value(cookies)?), error: None }
+            }
+            IPCCommand::DeleteCookies { site, name } => {
+                let site = normalize_site(&site)?;
+                let deleted = self
+                    .engine_manager
+                    .delete_cookies(|cookie| {
+                        cookie_matches(cookie, &site) && name.as_ref().is_none_or(|name| cookie.name == *name)
+                    })
+                    .await?;
+                IPCResponse { success: true, data: Some(serde_json::json!({ "deleted": deleted })), error: None }
+            }
+            IPCCommand::SetCookieBlocked { site, blocked } => {
+                self.cookie_manager.set_blocked(&site, blocked).await?;
+                if blocked {
+                    let site = normalize_site(&site)?;
+                    self.engine_manager.delete_cookies(|cookie| cookie_matches(cookie, &site)).await?;
+                }
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::GetCookieSettings => {
+                let settings = self.cookie_manager.settings();
+                IPCResponse { success: true, data: Some(serde_json::to_value(settings)?), error: None }
+            }
+            IPCCommand::SetCookieSettings { settings } => {
+                let third_party = settings.third_party;
+                self.cookie_manager.set_settings(settings).await?;
+                // Newly blocked sites lose the cookies they already have
+                self.engine_manager
+                    .delete_cookies(|cookie| self.cookie_manager.is_blocked(cookie))
+                    .await?;
+                self.engine_manager.set_third_party_cookies(third_party).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::GetContainers => {
+                let containers = self.container_manager.containers();
+                IPCResponse { success: true, data: Some(serde_json::to_value(containers)?), error: None }
+            }
+            IPCCommand::CreateContainer { name, color } => {
+                let container = self.container_manager.create(&name, &color).await?;
+                IPCResponse { success: true, data: Some(serde_json::to_value(container)?), error: None }
+            }
+            IPCCommand::UpdateContainer { container } => {
+                self.container_manager.update(container).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::RemoveContainer { id } => {
+                self.container_manager.remove(id).await?;
+                IPCResponse { success: true, data: None, error: None }
+            }
+            IPCCommand::ReloadFilterLists => {
+                let stats = self.content_blocker.load().await?;
+                IPCResponse {
+                    success: true,
+                    data: Some(serde_json::json!({
+                        "filters": stats.filters,
+                        "exceptions": stats.exceptions,
+                        "unsupported": stats.unsupported,
+                    })),
+                    error: None,
+                }
+            }
+        };
+        
+        self.persist_session().await;
+        Ok(response)
     }
     
     async fn subscribe(&self) -> broadcast::Receiver<IPCEvent> {
@@ -109,29 +802,106 @@ impl IPCHandler for App {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[async_trait::async_trait]
+impl WindowHandler for App {
+    async fn window_focused(&self, window: WindowId) {
+        if let Err(e) = self.engine_manager.activate_window(window) {
+            warn!("Failed to activate window {}: {}", window, e);
+            return;
+        }
+        if self.engine_manager.active_tab_id().is_ok() {
+            if let Err(e) = self.apply_active_tab_settings().await {
+                warn!("Failed to apply settings of window {}: {}", window, e);
+            }
+        }
+    }
+    
+    async fn window_closed(&self, window: WindowId) {
+        if let Err(e) = self.close_window_tabs(window).await {
+            warn!("Failed to close tabs of window {}: {}", window, e);
+        }
+        self.persist_session().await;
+    }
+    
+    /// Journal the tabs for the next start, then close their engines
+    async fn shutdown(&self) {
+        info!("Last window closed, shutting down");
+        self.persist_session().await;
+        let tabs: Vec<TabId> = self.engine_manager.tabs().iter().map(|tab| tab.id).collect();
+        for tab in tabs {
+            if let Err(e) = self.engine_manager.close_tab(tab).await {
+                warn!("Failed to close tab {}: {}", tab, e);
+            }
+        }
+    }
+}
+
+/// Return a capture as base64, or write it to a new file at `path`; existing
+/// files are never overwritten
+async fn deliver_capture(data: Vec<u8>, path: Option<String>) -> Result<serde_json::Value> {
+    let Some(path) = path.map(PathBuf::from) else {
+        return Ok(serde_json::json!({ "data": STANDARD.encode(&data) }));
+    };
+    if !path.is_absolute() {
+        return Err(anyhow::anyhow!("Capture path must be absolute: {}", path.display()));
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", path.display(), e))?;
+    file.write_all(&data)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path.display(), e))?;
+    Ok(serde_json::json!({ "path": path, "bytes": data.len() }))
+}
+
+fn main() -> Result<()> {
     // Initialize logging
     tracing_subscriber::fmt::init();
     
     info!("Starting Solace Browser");
     
-    // Create application
-    let app = App::new().await?;
+    // Windows need the main thread, so the event loop is created on it
+    // before anything else and the backend runs on a runtime beside it
+    let runtime = tokio::runtime::Runtime::new()?;
+    let headless = std::env::var_os("SOLACE_HEADLESS").is_some();
+    let (host, native_windows) = match headless {
+        true => (None, None),
+        false => {
+            let (host, windows) = WindowHost::new();
+            (Some(host), Some(Arc::new(windows)))
+        }
+    };
     
-    // Create shared app state for use with web server
-    let app_state = Arc::new(app);
+    // Create application
+    let app = Arc::new(runtime.block_on(App::new(native_windows.clone()))?);
     
     // Start the web server for UI communication
     let addr = SocketAddr::from(([127, 0, 0, 1], 3001));
-    let app = ui::router(app_state);
-    
+    let assets = Arc::new(runtime.block_on(ui::UiAssets::load())?);
+    if assets.is_empty() {
+        info!("No UI export to serve; run the frontend's dev server against the API");
+    }
+    // The UI comes from this server, or from the frontend's dev server
+    let ui_url = std::env::var("SOLACE_UI_URL").unwrap_or_else(|_| format!("http://{}", addr));
+    let ui_url = ui_url.trim_end_matches('/').to_string();
+    let mut origins = vec![format!("http://{}", addr), format!("http://localhost:{}", addr.port())];
+    if let Ok(url) = url::Url::parse(&ui_url) {
+        origins.push(url.origin().ascii_serialization());
+    }
+    let router = ui::router(app.clone(), assets, origins);
+    let listener = runtime.block_on(tokio::net::TcpListener::bind(addr))?;
     info!("UI server listening on {}", addr);
+    let server = runtime.spawn(async move { axum::serve(listener, router).await });
     
-    // Run the server
-    axum::serve(tokio::net::TcpListener::bind(addr).await?, app)
-        .await
-        .map_err(|e| anyhow::anyhow!("Server error: {}", e))?;
+    let (Some(host), Some(native_windows)) = (host, native_windows) else {
+        return runtime
+            .block_on(server)?
+            .map_err(|e| anyhow::anyhow!("Server error: {}", e));
+    };
     
-    Ok(())
+    native_windows.request(WindowRequest::Open(MAIN_WINDOW))?;
+    host.run(ui_url, app, runtime.handle().clone())
 }
@@ -0,0 +1,416 @@
+use anyhow::Result;
+use boa_engine::{js_string, Context, JsArgs, JsResult, JsString, JsValue, NativeFunction, Source};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Empty};
+use hyper::header::HOST;
+use hyper::{Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use std::net::{IpAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio_rustls::TlsConnector;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const EVALUATE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_SCRIPT_SIZE: usize = 1024 * 1024;
+/// Bounds on a single loop and on call depth, so a script cannot hang the evaluator
+const LOOP_ITERATION_LIMIT: u64 = 1_000_000;
+const RECURSION_LIMIT: usize = 256;
+
+/// The helper functions PAC scripts may call, besides the native DNS ones
+const PAC_UTILS: &str = r#"
+var PAC_WEEKDAYS = { SUN: 0, MON: 1, TUE: 2, WED: 3, THU: 4, FRI: 5, SAT: 6 };
+var PAC_MONTHS = { JAN: 0, FEB: 1, MAR: 2, APR: 3, MAY: 4, JUN: 5, JUL: 6, AUG: 7, SEP: 8, OCT: 9, NOV: 10, DEC: 11 };
+
+function isPlainHostName(host) {
+    return host.indexOf('.') == -1;
+}
+
+function dnsDomainIs(host, domain) {
+    return host.length >= domain.length && host.substring(host.length - domain.length) == domain;
+}
+
+function localHostOrDomainIs(host, hostdom) {
+    return host == hostdom || hostdom.lastIndexOf(host + '.', 0) == 0;
+}
+
+function dnsDomainLevels(host) {
+    return host.split('.').length - 1;
+}
+
+function convert_addr(ipchars) {
+    var bytes = ipchars.split('.');
+    return ((bytes[0] & 0xff) << 24) | ((bytes[1] & 0xff) << 16) | ((bytes[2] & 0xff) << 8) | (bytes[3] & 0xff);
+}
+
+function isInNet(ipaddr, pattern, maskstr) {
+    if (!/^\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}$/.test(ipaddr)) {
+        ipaddr = dnsResolve(ipaddr);
+        if (ipaddr == null) {
+            return false;
+        }
+    }
+    var mask = convert_addr(maskstr);
+    return (convert_addr(ipaddr) & mask) == (convert_addr(pattern) & mask);
+}
+
+function shExpMatch(str, shexp) {
+    var pattern = shexp.replace(/[.+^${}()|[\]\\]/g, '\\$&').replace(/\*/g, '.*').replace(/\?/g, '.');
+    return new RegExp('^' + pattern + '$').test(str);
+}
+
+function pacArguments(args) {
+    var values = Array.prototype.slice.call(args);
+    var gmt = values[values.length - 1] == 'GMT';
+    if (gmt) {
+        values.pop();
+    }
+    return { values: values, gmt: gmt, now: new Date() };
+}
+
+function weekdayRange() {
+    var a = pacArguments(arguments);
+    if (a.values.length < 1 || a.values.length > 2) {
+        return false;
+    }
+    var day = a.gmt ? a.now.getUTCDay() : a.now.getDay();
+    var first = PAC_WEEKDAYS[a.values[0]];
+    var last = a.values.length == 2 ? PAC_WEEKDAYS[a.values[1]] : first;
+    if (first === undefined || last === undefined) {
+        return false;
+    }
+    return first <= last ? first <= day && day <= last : day >= first || day <= last;
+}
+
+// Each argument is a day of the month, a month name or a year
+function dateRange() {
+    var a = pacArguments(arguments);
+    var n = a.values.length;
+    if (n < 1 || n > 6 || (n > 1 && n % 2)) {
+        return false;
+    }
+    var today = a.gmt
+        ? [a.now.getUTCFullYear(), a.now.getUTCMonth(), a.now.getUTCDate()]
+        : [a.now.getFullYear(), a.now.getMonth(), a.now.getDate()];
+    function parse(values) {
+        var spec = [null, null, null];
+        for (var i = 0; i < values.length; i++) {
+            if (values[i] in PAC_MONTHS) {
+                spec[1] = PAC_MONTHS[values[i]];
+            } else if (Number(values[i]) < 32) {
+                spec[2] = Number(values[i]);
+            } else {
+                spec[0] = Number(values[i]);
+            }
+        }
+        return spec;
+    }
+    // Compare only the fields the range gives
+    function value(spec, source) {
+        return (spec[0] == null ? 0 : source[0] * 10000)
+            + (spec[1] == null ? 0 : source[1] * 100)
+            + (spec[2] == null ? 0 : source[2]);
+    }
+    var half = n == 1 ? 1 : n / 2;
+    var start = parse(a.values.slice(0, half));
+    var end = n == 1 ? start : parse(a.values.slice(half));
+    var now = value(start, today);
+    var from = value(start, start);
+    var to = value(start, end);
+    return from <= to ? from <= now && now <= to : now >= from || now <= to;
+}
+
+// Hours, hours and minutes, or hours, minutes and seconds
+function timeRange() {
+    var a = pacArguments(arguments);
+    var n = a.values.length;
+    var now = a.gmt
+        ? [a.now.getUTCHours(), a.now.getUTCMinutes(), a.now.getUTCSeconds()]
+        : [a.now.getHours(), a.now.getMinutes(), a.now.getSeconds()];
+    if (n == 1) {
+        return now[0] == Number(a.values[0]);
+    }
+    if (n != 2 && n != 4 && n != 6) {
+        return false;
+    }
+    var fields = n / 2;
+    function seconds(values) {
+        var total = 0;
+        for (var i = 0; i < fields; i++) {
+            total = total * 60 + Number(values[i]);
+        }
+        return total;
+    }
+    var from = seconds(a.values.slice(0, fields));
+    var to = seconds(a.values.slice(fields));
+    var current = seconds(now);
+    return from <= to ? from <= current && current <= to : current >= from || current <= to;
+}
+"#;
+
+type Query = (String, String, oneshot::Sender<Result<String>>);
+
+/// A proxy auto-config script.
+///
+/// The JavaScript engine runs on its own thread, since it cannot move between
+/// tasks. Scripts only get the standard PAC helpers: there is no access to
+/// files, the network or timers, and loops and recursion are bounded.
+pub struct PacScript {
+    queries: mpsc::Sender<Query>,
+}
+
+impl PacScript {
+    /// Fetch a script from an `http(s)://` or `file://` URL and compile it
+    pub async fn load(url: &str) -> Result<Self> {
+        let source = tokio::time::timeout(FETCH_TIMEOUT, fetch(url))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out fetching PAC script {}", url))?
+            .map_err(|e| anyhow::anyhow!("Failed to fetch PAC script {}: {}", url, e))?;
+        Self::new(source).await
+    }
+
+    pub async fn new(source: String) -> Result<Self> {
+        let (queries, receiver) = mpsc::channel();
+        let (ready_tx, ready_rx) = oneshot::channel();
+        std::thread::Builder::new()
+            .name(String::from("pac"))
+            .spawn(move || run(source, ready_tx, receiver))?;
+
+        ready_rx
+            .await
+            .map_err(|_| anyhow::anyhow!("PAC evaluator stopped"))??;
+        Ok(Self { queries })
+    }
+
+    /// Call `FindProxyForURL`, returning its result such as `PROXY a:8080; DIRECT`
+    pub async fn find_proxy(&self, url: &str, host: &str) -> Result<String> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.queries
+            .send((url.to_string(), host.to_string(), reply_tx))
+            .map_err(|_| anyhow::anyhow!("PAC evaluator stopped"))?;
+        tokio::time::timeout(EVALUATE_TIMEOUT, reply_rx)
+            .await
+            .map_err(|_| anyhow::anyhow!("PAC script took too long for {}", host))?
+            .map_err(|_| anyhow::anyhow!("PAC evaluator stopped"))?
+    }
+}
+
+/// Evaluator thread: compile the script, then answer queries until the script is dropped
+fn run(source: String, ready: oneshot::Sender<Result<()>>, queries: mpsc::Receiver<Query>) {
+    let mut context = Context::default();
+    if let Err(e) = setup(&mut context, &source) {
+        let _ = ready.send(Err(anyhow::anyhow!("Invalid PAC script: {}", e)));
+        return;
+    }
+    let _ = ready.send(Ok(()));
+
+    for (url, host, reply) in queries {
+        let result = find_proxy(&mut context, &url, &host)
+            .map_err(|e| anyhow::anyhow!("PAC script failed for {}: {}", host, e));
+        let _ = reply.send(result);
+    }
+}
+
+fn setup(context: &mut Context, source: &str) -> JsResult<()> {
+    let limits = context.runtime_limits_mut();
+    limits.set_loop_iteration_limit(LOOP_ITERATION_LIMIT);
+    limits.set_recursion_limit(RECURSION_LIMIT);
+
+    context.register_global_callable(js_string!("dnsResolve"), 1, NativeFunction::from_fn_ptr(dns_resolve))?;
+    context.register_global_callable(js_string!("isResolvable"), 1, NativeFunction::from_fn_ptr(is_resolvable))?;
+    context.register_global_callable(js_string!("myIpAddress"), 0, NativeFunction::from_fn_ptr(my_ip_address))?;
+    context.eval(Source::from_bytes(PAC_UTILS))?;
+    context.eval(Source::from_bytes(source))?;
+
+    let function = context.global_object().get(js_string!("FindProxyForURL"), context)?;
+    if !function.is_callable() {
+        return Err(boa_engine::JsNativeError::typ()
+            .with_message("FindProxyForURL is not defined")
+            .into());
+    }
+    Ok(())
+}
+
+fn find_proxy(context: &mut Context, url: &str, host: &str) -> JsResult<String> {
+    let function = context.global_object().get(js_string!("FindProxyForURL"), context)?;
+    let function = function.as_callable().cloned().ok_or_else(|| {
+        boa_engine::JsError::from(boa_engine::JsNativeError::typ().with_message("FindProxyForURL is not defined"))
+    })?;
+    let result = function.call(
+        &JsValue::undefined(),
+        &[JsValue::from(JsString::from(url)), JsValue::from(JsString::from(host))],
+        context,
+    )?;
+    // A script returning nothing means connect directly
+    if result.is_null_or_undefined() {
+        return Ok(String::new());
+    }
+    Ok(result.to_string(context)?.to_std_string_escaped())
+}
+
+/// First IPv4 address of a host, as PAC scripts compare dotted quads
+fn resolve_ipv4(host: &str) -> Option<IpAddr> {
+    (host, 0)
+        .to_socket_addrs()
+        .ok()?
+        .map(|addr| addr.ip())
+        .find(IpAddr::is_ipv4)
+}
+
+fn dns_resolve(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+    Ok(match resolve_ipv4(&host) {
+        Some(ip) => JsValue::from(JsString::from(ip.to_string().as_str())),
+        None => JsValue::null(),
+    })
+}
+
+fn is_resolvable(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+    let host = args.get_or_undefined(0).to_string(context)?.to_std_string_escaped();
+    Ok(JsValue::from(resolve_ipv4(&host).is_some()))
+}
+
+fn my_ip_address(_this: &JsValue, _args: &[JsValue], _context: &mut Context) -> JsResult<JsValue> {
+    // Connecting a UDP socket picks the outgoing interface without sending anything
+    let ip = UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| {
+            socket.connect("192.0.2.1:80")?;
+            socket.local_addr()
+        })
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|_| String::from("127.0.0.1"));
+    Ok(JsValue::from(JsString::from(ip.as_str())))
+}
+
+/// Read a PAC script from a file or over HTTP(S)
+async fn fetch(url: &str) -> Result<String> {
+    let url = url::Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid PAC URL: {}", e))?;
+    let body = match url.scheme() {
+        "file" => {
+            let path = url
+                .to_file_path()
+                .map_err(|_| anyhow::anyhow!("Invalid PAC file URL"))?;
+            tokio::fs::read(path).await?
+        }
+        "http" | "https" => {
+            let host = url
+                .host_str()
+                .ok_or_else(|| anyhow::anyhow!("PAC URL has no host"))?
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string();
+            let port = url.port_or_known_default().unwrap_or(80);
+            let stream = TcpStream::connect((host.as_str(), port)).await?;
+            let request = Request::get(url.as_str())
+                .header(HOST, url.host_str().unwrap_or(&host))
+                .body(Empty::<Bytes>::new())?;
+
+            if url.scheme() == "https" {
+                let mut roots = rustls::RootCertStore::empty();
+                roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+                let config = rustls::ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth();
+                let server_name = ServerName::try_from(host.clone())
+                    .map_err(|e| anyhow::anyhow!("Invalid PAC server name {}: {}", host, e))?;
+                let stream = TlsConnector::from(Arc::new(config)).connect(server_name, stream).await?;
+                get(stream, request).await?
+            } else {
+                get(stream, request).await?
+            }
+        }
+        other => return Err(anyhow::anyhow!("Unsupported PAC URL scheme {}", other)),
+    };
+
+    if body.len() > MAX_SCRIPT_SIZE {
+        return Err(anyhow::anyhow!("PAC script is larger than {} bytes", MAX_SCRIPT_SIZE));
+    }
+    String::from_utf8(body).map_err(|_| anyhow::anyhow!("PAC script is not UTF-8"))
+}
+
+async fn get<S>(stream: S, request: Request<Empty<Bytes>>) -> Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    let response = sender.send_request(request).await?;
+    if response.status() != StatusCode::OK {
+        return Err(anyhow::anyhow!("server returned {}", response.status()));
+    }
+    Ok(response.into_body().collect().await?.to_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        function FindProxyForURL(url, host) {
+            if (isPlainHostName(host) || dnsDomainIs(host, ".corp.example")) return "DIRECT";
+            if (isInNet(host, "10.0.0.0", "255.0.0.0")) return "SOCKS5 10.0.0.1:1080";
+            if (shExpMatch(url, "http://*.example.org/*")) return "PROXY org-proxy:3128; DIRECT";
+            if (localHostOrDomainIs(host, "www.example.net")) return "PROXY net-proxy:" + dnsDomainLevels(host);
+            if (host == "quiet.example") return null;
+            return "PROXY proxy:8080";
+        }
+    "#;
+
+    #[tokio::test]
+    async fn evaluates_with_pac_helpers() {
+        let script = PacScript::new(SCRIPT.to_string()).await.unwrap();
+        for (url, host, expected) in [
+            ("http://intranet/", "intranet", "DIRECT"),
+            ("https://wiki.corp.example/", "wiki.corp.example", "DIRECT"),
+            ("http://10.1.2.3/", "10.1.2.3", "SOCKS5 10.0.0.1:1080"),
+            ("http://www.example.org/page", "www.example.org", "PROXY org-proxy:3128; DIRECT"),
+            ("http://www/", "www.example.net", "PROXY net-proxy:2"),
+            ("http://quiet.example/", "quiet.example", ""),
+            ("https://example.com/", "example.com", "PROXY proxy:8080"),
+        ] {
+            assert_eq!(script.find_proxy(url, host).await.unwrap(), expected, "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_broken_scripts() {
+        assert!(PacScript::new(String::from("function FindProxyForURL(url, host) {")).await.is_err());
+        assert!(PacScript::new(String::from("var proxy = 'DIRECT';")).await.is_err());
+
+        let script = PacScript::new(String::from("function FindProxyForURL(url, host) { return undefined.x; }"))
+            .await
+            .unwrap();
+        assert!(script.find_proxy("http://a/", "a").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn bounds_runaway_scripts() {
+        let script = PacScript::new(String::from(
+            "function FindProxyForURL(url, host) { if (host == 'loop') { while (true) {} } \
+             return FindProxyForURL(url, host); }",
+        ))
+        .await
+        .unwrap();
+        assert!(script.find_proxy("http://loop/", "loop").await.is_err());
+        assert!(script.find_proxy("http://deep/", "deep").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn loads_from_file_url() {
+        let path = std::env::temp_dir().join(format!("pac-test-{}.js", std::process::id()));
+        tokio::fs::write(&path, SCRIPT).await.unwrap();
+        let url = url::Url::from_file_path(&path).unwrap();
+        let script = PacScript::load(url.as_str()).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        assert_eq!(script.unwrap().find_proxy("http://x/", "x").await.unwrap(), "DIRECT");
+
+        assert!(PacScript::load("ftp://example.com/proxy.pac").await.is_err());
+    }
+}
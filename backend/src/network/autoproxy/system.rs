@@ -0,0 +1,183 @@
+use super::{parse_proxy_url, ManualProxies};
+use crate::ipc::ProxyServer;
+use std::time::Duration;
+use tokio::process::Command;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Proxy configuration of the machine we run on
+pub enum SystemProxy {
+    Pac(String),
+    Manual(Box<ManualProxies>),
+    None,
+}
+
+/// Read the desktop's proxy settings (GNOME or macOS), falling back to the
+/// `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` environment variables
+pub async fn detect() -> SystemProxy {
+    let desktop = match std::env::consts::OS {
+        "macos" => macos().await,
+        "linux" => gnome().await,
+        _ => None,
+    };
+    if let Some(proxy) = desktop {
+        return proxy;
+    }
+
+    let proxies = from_env(|name| std::env::var(name).ok());
+    match proxies.is_empty() {
+        true => SystemProxy::None,
+        false => SystemProxy::Manual(Box::new(proxies)),
+    }
+}
+
+/// Proxies from environment variables, in either case as tools disagree on it
+pub fn from_env(var: impl Fn(&str) -> Option<String>) -> ManualProxies {
+    let lookup = |name: &str| {
+        var(&name.to_ascii_lowercase())
+            .or_else(|| var(name))
+            .filter(|value| !value.trim().is_empty())
+    };
+    let server = |name: &str| {
+        lookup(name).and_then(|value| match parse_proxy_url(&value) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                tracing::warn!("Ignoring {}: {}", name, e);
+                None
+            }
+        })
+    };
+
+    let mut proxies = ManualProxies {
+        http: server("HTTP_PROXY"),
+        https: server("HTTPS_PROXY"),
+        all: server("ALL_PROXY"),
+        ..Default::default()
+    };
+    if let Some(no_proxy) = lookup("NO_PROXY") {
+        proxies.add_bypass(&no_proxy);
+    }
+    proxies
+}
+
+async fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = tokio::time::timeout(COMMAND_TIMEOUT, Command::new(program).args(args).output())
+        .await
+        .ok()?
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// GNOME settings, or None when gsettings is missing or set to no proxy
+async fn gnome() -> Option<SystemProxy> {
+    let get = |schema: &'static str, key: &'static str| async move {
+        output("gsettings", &["get", schema, key])
+            .await
+            .map(|value| value.trim_matches('\'').to_string())
+    };
+
+    match get("org.gnome.system.proxy", "mode").await?.as_str() {
+        "auto" => {
+            let url = get("org.gnome.system.proxy", "autoconfig-url").await?;
+            (!url.is_empty()).then_some(SystemProxy::Pac(url))
+        }
+        "manual" => {
+            let server = |schema, scheme: &'static str| async move {
+                let host = get(schema, "host").await?;
+                let port = get(schema, "port").await?;
+                if host.is_empty() || port == "0" {
+                    return None;
+                }
+                parse_proxy_url(&format!("{}://{}:{}", scheme, host, port)).ok()
+            };
+            let mut proxies = ManualProxies {
+                http: server("org.gnome.system.proxy.http", "http").await,
+                https: server("org.gnome.system.proxy.https", "http").await,
+                all: server("org.gnome.system.proxy.socks", "socks5").await,
+                ..Default::default()
+            };
+            // A list like ['localhost', '127.0.0.0/8']
+            if let Some(ignore) = get("org.gnome.system.proxy", "ignore-hosts").await {
+                proxies.add_bypass(&ignore.trim_matches(['[', ']']).replace('\'', ""));
+            }
+            (!proxies.is_empty()).then(|| SystemProxy::Manual(Box::new(proxies)))
+        }
+        _ => None,
+    }
+}
+
+/// Settings from `scutil --proxy`, a dictionary printed as `Key : value` lines
+async fn macos() -> Option<SystemProxy> {
+    let dump = output("scutil", &["--proxy"]).await?;
+    let mut values = std::collections::HashMap::new();
+    let mut exceptions = Vec::new();
+    let mut in_exceptions = false;
+    for line in dump.lines().map(str::trim) {
+        if line.starts_with("ExceptionsList") {
+            in_exceptions = true;
+            continue;
+        }
+        if in_exceptions {
+            match line.split_once(" : ") {
+                Some((_, value)) => exceptions.push(value.to_string()),
+                None => in_exceptions = false,
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(" : ") {
+            values.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    let enabled = |key: &str| values.get(key).map(String::as_str) == Some("1");
+    if enabled("ProxyAutoConfigEnable") {
+        return values.get("ProxyAutoConfigURLString").cloned().map(SystemProxy::Pac);
+    }
+    let server = |prefix: &str, scheme: &str| -> Option<ProxyServer> {
+        if !enabled(&format!("{}Enable", prefix)) {
+            return None;
+        }
+        let host = values.get(&format!("{}Proxy", prefix))?;
+        let port = values.get(&format!("{}Port", prefix))?;
+        parse_proxy_url(&format!("{}://{}:{}", scheme, host, port)).ok()
+    };
+    let mut proxies = ManualProxies {
+        http: server("HTTP", "http"),
+        https: server("HTTPS", "http"),
+        all: server("SOCKS", "socks5"),
+        ..Default::default()
+    };
+    proxies.add_bypass(&exceptions.join(","));
+    (!proxies.is_empty()).then(|| SystemProxy::Manual(Box::new(proxies)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_proxy_variables_in_either_case() {
+        let vars = [
+            ("http_proxy", "http://lower:3128"),
+            ("HTTP_PROXY", "http://upper:3128"),
+            ("HTTPS_PROXY", "bogus://x"),
+            ("ALL_PROXY", "socks5://all:1080"),
+            ("no_proxy", " "),
+            ("NO_PROXY", "*"),
+        ];
+        let proxies = from_env(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        });
+        // Lowercase wins, invalid values and blank ones are ignored
+        assert_eq!(proxies.http.unwrap().address, "lower:3128");
+        assert!(proxies.https.is_none());
+        assert_eq!(proxies.all.unwrap().address, "all:1080");
+
+        assert!(from_env(|_| None).is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+use super::dns::Resolver;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// Destination requested by the engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetAddr {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl TargetAddr {
+    pub fn host(&self) -> String {
+        match self {
+            TargetAddr::Ip(addr) => addr.ip().to_string(),
+            TargetAddr::Domain(host, _) => host.clone(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            TargetAddr::Ip(addr) => addr.port(),
+            TargetAddr::Domain(_, port) => *port,
+        }
+    }
+}
+
+impl fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetAddr::Ip(addr) => write!(f, "{}", addr),
+            TargetAddr::Domain(host, port) => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+/// A bidirectional byte stream to a destination
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+/// Opens outgoing connections for a network route
+#[async_trait]
+pub trait Connector: Send + Sync {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream>;
+
+    /// Fail, with the reason, when the route can no longer carry traffic
+    async fn check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Refuses every connection, so a tab whose route is down never falls
+/// back to connecting directly
+pub struct BlockedConnector {
+    reason: String,
+}
+
+impl BlockedConnector {
+    pub fn new(reason: String) -> Self {
+        Self { reason }
+    }
+}
+
+#[async_trait]
+impl Connector for BlockedConnector {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream> {
+        Err(anyhow::anyhow!("Blocked connection to {}, route is down: {}", target, self.reason))
+    }
+
+    async fn check(&self) -> Result<()> {
+        Err(anyhow::anyhow!("{}", self.reason))
+    }
+}
+
+/// Connects straight to the destination, resolving names with the given resolver
+pub struct DirectConnector {
+    resolver: Arc<dyn Resolver>,
+}
+
+impl DirectConnector {
+    pub fn new(resolver: Arc<dyn Resolver>) -> Self {
+        Self { resolver }
+    }
+}
+
+#[async_trait]
+impl Connector for DirectConnector {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream> {
+        let addrs = match target {
+            TargetAddr::Ip(addr) => vec![*addr],
+            TargetAddr::Domain(host, port) => self
+                .resolver
+                .resolve(host)
+                .await?
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, *port))
+                .collect(),
+        };
+
+        let mut last_error = None;
+        for addr in addrs {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(Box::new(stream)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(match last_error {
+            Some(e) => anyhow::anyhow!("Failed to connect to {}: {}", target, e),
+            None => anyhow::anyhow!("No addresses for {}", target),
+        })
+    }
+}
@@ -0,0 +1,230 @@
+use super::DnsTransport;
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::header::{ACCEPT, CONTENT_TYPE, HOST};
+use hyper::{Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+const DNS_MESSAGE: &str = "application/dns-message";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// DNS-over-HTTPS transport (RFC 8484) using POST requests.
+///
+/// Plain `http://` provider URLs are accepted so the resolver can be pointed
+/// at a local stand-in server.
+pub struct DohTransport {
+    url: url::Url,
+    host: String,
+    port: u16,
+    /// Addresses of the provider, so looking it up does not leak over system DNS
+    bootstrap: Vec<IpAddr>,
+    tls: TlsConnector,
+}
+
+impl DohTransport {
+    pub fn new(url: &str, bootstrap: Vec<IpAddr>) -> Result<Self> {
+        let url = url::Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid DoH URL {}: {}", url, e))?;
+        if !matches!(url.scheme(), "https" | "http") {
+            return Err(anyhow::anyhow!("DoH URL must use https: {}", url));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("DoH URL has no host: {}", url))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow::anyhow!("DoH URL has no port: {}", url))?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let mut config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Self {
+            url,
+            host,
+            port,
+            bootstrap,
+            tls: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    async fn connect(&self) -> Result<TcpStream> {
+        let addrs: Vec<SocketAddr> = if let Ok(ip) = self.host.parse::<IpAddr>() {
+            vec![SocketAddr::new(ip, self.port)]
+        } else if !self.bootstrap.is_empty() {
+            self.bootstrap.iter().map(|ip| SocketAddr::new(*ip, self.port)).collect()
+        } else {
+            tokio::net::lookup_host((self.host.as_str(), self.port)).await?.collect()
+        };
+
+        let mut last_error = None;
+        for addr in addrs {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(match last_error {
+            Some(e) => anyhow::anyhow!("Failed to connect to DoH server {}: {}", self.host, e),
+            None => anyhow::anyhow!("No addresses for DoH server {}", self.host),
+        })
+    }
+
+    async fn post(&self, query: Vec<u8>) -> Result<Vec<u8>> {
+        let stream = self.connect().await?;
+
+        let request = Request::post(self.url.as_str())
+            .header(HOST, self.url.host_str().unwrap_or(&self.host))
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .header(ACCEPT, DNS_MESSAGE)
+            .body(Full::new(Bytes::from(query)))?;
+
+        if self.url.scheme() == "https" {
+            let server_name = ServerName::try_from(self.host.clone())
+                .map_err(|e| anyhow::anyhow!("Invalid DoH server name {}: {}", self.host, e))?;
+            let stream = self.tls.connect(server_name, stream).await?;
+            send_request(stream, request).await
+        } else {
+            send_request(stream, request).await
+        }
+    }
+}
+
+async fn send_request<S>(stream: S, request: Request<Full<Bytes>>) -> Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    let response = sender.send_request(request).await?;
+    if response.status() != StatusCode::OK {
+        return Err(anyhow::anyhow!("DoH server returned {}", response.status()));
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !content_type.starts_with(DNS_MESSAGE) {
+        return Err(anyhow::anyhow!("DoH server returned unexpected content type {}", content_type));
+    }
+
+    Ok(response.into_body().collect().await?.to_bytes().to_vec())
+}
+
+#[async_trait]
+impl DnsTransport for DohTransport {
+    fn describe(&self) -> String {
+        self.url.to_string()
+    }
+
+    async fn exchange(&self, query: Vec<u8>) -> Result<Vec<u8>> {
+        tokio::time::timeout(QUERY_TIMEOUT, self.post(query))
+            .await
+            .map_err(|_| anyhow::anyhow!("DoH query to {} timed out", self.url))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::dns::message::{encode_query, RecordType};
+    use crate::network::dns::{Resolver, StubResolver};
+    use hyper::body::Incoming;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    /// Answer A queries with 192.0.2.1 and AAAA queries with no records
+    async fn answer(request: Request<Incoming>) -> std::result::Result<Response<Full<Bytes>>, Infallible> {
+        let content_type = request.headers().get(CONTENT_TYPE).cloned();
+        let query = request.into_body().collect().await.unwrap().to_bytes().to_vec();
+        if content_type.as_ref().and_then(|value| value.to_str().ok()) != Some(DNS_MESSAGE) {
+            let mut response = Response::new(Full::new(Bytes::new()));
+            *response.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+            return Ok(response);
+        }
+
+        let mut message = query.clone();
+        message[2] = 0x81;
+        message[3] = 0x80;
+        if query[query.len() - 4..query.len() - 2] == [0, 1] {
+            message[6..8].copy_from_slice(&[0, 1]);
+            message.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0x01, 0x2c, 0, 4, 192, 0, 2, 1]);
+        }
+        let mut response = Response::new(Full::new(Bytes::from(message)));
+        response.headers_mut().insert(CONTENT_TYPE, DNS_MESSAGE.parse().unwrap());
+        Ok(response)
+    }
+
+    /// A local stand-in for a DoH provider, counting the requests it serves
+    async fn stand_in() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let connection = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service_fn(answer));
+                tokio::spawn(connection);
+            }
+        });
+        (addr, requests)
+    }
+
+    #[tokio::test]
+    async fn resolves_through_stand_in_provider() {
+        let (addr, requests) = stand_in().await;
+        let transport = DohTransport::new(&format!("http://{}/dns-query", addr), Vec::new()).unwrap();
+        let resolver = StubResolver::new(Box::new(transport));
+
+        assert_eq!(resolver.resolve("Example.COM.").await.unwrap(), vec![IpAddr::from([192, 0, 2, 1])]);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        // Answered from the cache
+        assert_eq!(resolver.resolve("example.com").await.unwrap(), vec![IpAddr::from([192, 0, 2, 1])]);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        // Addresses need no lookup
+        assert_eq!(resolver.resolve("[::1]").await.unwrap(), vec!["::1".parse::<IpAddr>().unwrap()]);
+
+        let stats = resolver.stats();
+        assert_eq!(stats.resolver, format!("http://{}/dns-query", addr));
+        assert_eq!((stats.queries, stats.cache_hits, stats.failures), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn bootstrap_addresses_replace_lookup_of_provider() {
+        let (addr, _) = stand_in().await;
+        let url = format!("http://doh.invalid:{}/dns-query", addr.port());
+        let transport = DohTransport::new(&url, vec![addr.ip()]).unwrap();
+        let query = encode_query("example.com", RecordType::A).unwrap();
+        assert!(transport.exchange(query).await.is_ok());
+    }
+
+    #[test]
+    fn rejects_unsupported_urls() {
+        assert!(DohTransport::new("ftp://dns.example/dns-query", Vec::new()).is_err());
+        assert!(DohTransport::new("not a url", Vec::new()).is_err());
+        assert!(DohTransport::new("https://dns.example/dns-query", Vec::new()).is_ok());
+    }
+}
@@ -0,0 +1,236 @@
+use super::DnsTransport;
+use crate::ipc::{DotPrivacy, DotUpstream};
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::Engine as _;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, DigitallySignedStruct, SignatureScheme};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Upstream {
+    address: SocketAddr,
+    server_name: ServerName<'static>,
+    tls: TlsConnector,
+}
+
+/// DNS-over-TLS transport (RFC 7858) trying each upstream in order
+pub struct DotTransport {
+    upstreams: Vec<Upstream>,
+}
+
+impl DotTransport {
+    pub fn new(upstreams: &[DotUpstream], privacy: &DotPrivacy) -> Result<Self> {
+        if upstreams.is_empty() {
+            return Err(anyhow::anyhow!("DNS-over-TLS needs at least one upstream"));
+        }
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let roots = Arc::new(roots);
+        let algorithms = rustls::crypto::ring::default_provider().signature_verification_algorithms;
+
+        let upstreams = upstreams
+            .iter()
+            .map(|upstream| {
+                let pins = upstream
+                    .spki_pins
+                    .iter()
+                    .map(|pin| decode_pin(pin))
+                    .collect::<Result<Vec<_>>>()?;
+                let verifier = DotVerifier {
+                    webpki: WebPkiServerVerifier::builder(roots.clone()).build()?,
+                    pins,
+                    authenticate: *privacy == DotPrivacy::Strict,
+                    algorithms,
+                };
+                let config = rustls::ClientConfig::builder()
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(verifier))
+                    .with_no_client_auth();
+
+                Ok(Upstream {
+                    address: upstream.address,
+                    server_name: ServerName::try_from(upstream.server_name.clone())
+                        .map_err(|e| anyhow::anyhow!("Invalid DoT server name {}: {}", upstream.server_name, e))?,
+                    tls: TlsConnector::from(Arc::new(config)),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { upstreams })
+    }
+
+    async fn exchange_with(upstream: &Upstream, query: &[u8]) -> Result<Vec<u8>> {
+        let stream = TcpStream::connect(upstream.address).await?;
+        let mut stream = upstream.tls.connect(upstream.server_name.clone(), stream).await?;
+
+        // Messages over TCP carry a two byte length prefix (RFC 1035 section 4.2.2)
+        let mut framed = Vec::with_capacity(query.len() + 2);
+        framed.extend_from_slice(&(query.len() as u16).to_be_bytes());
+        framed.extend_from_slice(query);
+        stream.write_all(&framed).await?;
+        stream.flush().await?;
+
+        let length = stream.read_u16().await? as usize;
+        let mut response = vec![0u8; length];
+        stream.read_exact(&mut response).await?;
+
+        if response.get(..2) != query.get(..2) {
+            return Err(anyhow::anyhow!("DoT response ID does not match query"));
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl DnsTransport for DotTransport {
+    fn describe(&self) -> String {
+        let upstreams: Vec<String> = self
+            .upstreams
+            .iter()
+            .map(|upstream| format!("tls://{}", upstream.address))
+            .collect();
+        upstreams.join(", ")
+    }
+
+    async fn exchange(&self, query: Vec<u8>) -> Result<Vec<u8>> {
+        let mut last_error = None;
+        for upstream in &self.upstreams {
+            match tokio::time::timeout(QUERY_TIMEOUT, Self::exchange_with(upstream, &query)).await {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(e)) => last_error = Some(e),
+                Err(_) => last_error = Some(anyhow::anyhow!("DoT query to {} timed out", upstream.address)),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No DoT upstream available")))
+    }
+}
+
+/// Decode a base64 SHA-256 SPKI pin as used in RFC 7858 section 4.2
+fn decode_pin(pin: &str) -> Result<[u8; 32]> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(pin.trim_start_matches("sha256/"))
+        .map_err(|e| anyhow::anyhow!("Invalid SPKI pin {}: {}", pin, e))?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("SPKI pin {} is not a SHA-256 digest", pin))
+}
+
+/// Certificate verifier implementing the DoT usage profiles (RFC 8310).
+///
+/// A matching SPKI pin authenticates the server on its own. Without pins,
+/// strict mode requires a certificate valid for the server name while
+/// opportunistic mode accepts any certificate and only gains encryption.
+#[derive(Debug)]
+struct DotVerifier {
+    webpki: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+    authenticate: bool,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for DotVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !self.pins.is_empty() {
+            let spki = subject_public_key_info(end_entity)
+                .ok_or(rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+            let digest: [u8; 32] = Sha256::digest(spki).into();
+            return if self.pins.contains(&digest) {
+                Ok(ServerCertVerified::assertion())
+            } else {
+                Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+            };
+        }
+
+        if self.authenticate {
+            self.webpki
+                .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        } else {
+            Ok(ServerCertVerified::assertion())
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Read a DER TLV header, returning (tag, header length, content length)
+fn der_header(data: &[u8]) -> Option<(u8, usize, usize)> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    if first & 0x80 == 0 {
+        return Some((tag, 2, first));
+    }
+    let count = first & 0x7f;
+    if count == 0 || count > 4 {
+        return None;
+    }
+    let length = data
+        .get(2..2 + count)?
+        .iter()
+        .fold(0usize, |length, &byte| (length << 8) | byte as usize);
+    Some((tag, 2 + count, length))
+}
+
+/// Extract the DER encoded SubjectPublicKeyInfo from an X.509 certificate
+pub fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    // Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { ... }, ... }
+    let (_, header, _) = der_header(certificate)?;
+    let tbs = certificate.get(header..)?;
+    let (_, header, length) = der_header(tbs)?;
+    let mut fields = tbs.get(header..header + length)?;
+
+    // Skip the optional [0] version, then serialNumber, signature, issuer,
+    // validity and subject to reach subjectPublicKeyInfo
+    if fields.first() == Some(&0xa0) {
+        let (_, header, length) = der_header(fields)?;
+        fields = fields.get(header + length..)?;
+    }
+    for _ in 0..5 {
+        let (_, header, length) = der_header(fields)?;
+        fields = fields.get(header + length..)?;
+    }
+
+    let (tag, header, length) = der_header(fields)?;
+    if tag != 0x30 {
+        return None;
+    }
+    fields.get(..header + length)
+}
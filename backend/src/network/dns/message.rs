@@ -0,0 +1,250 @@
+use anyhow::Result;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+/// Record types the resolver asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    A,
+    Aaaa,
+}
+
+impl RecordType {
+    fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Aaaa => 28,
+        }
+    }
+}
+
+const CLASS_IN: u16 = 1;
+const HEADER_LEN: usize = 12;
+
+/// Addresses extracted from a DNS response
+#[derive(Debug, Clone)]
+pub struct Answer {
+    pub addresses: Vec<IpAddr>,
+    /// Smallest TTL among the address records
+    pub ttl: Duration,
+}
+
+/// Encode a recursive query for a single name (RFC 1035 section 4.1).
+///
+/// The ID is left at zero as RFC 8484 recommends for cache friendliness;
+/// transports that need a real ID set it themselves.
+pub fn encode_query(name: &str, record_type: RecordType) -> Result<Vec<u8>> {
+    let mut message = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+
+    // ID, flags (RD set), QDCOUNT = 1, ANCOUNT = NSCOUNT = ARCOUNT = 0
+    message.extend_from_slice(&[0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(anyhow::anyhow!("Invalid DNS name: {}", name));
+        }
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+
+    if message.len() - HEADER_LEN > 255 {
+        return Err(anyhow::anyhow!("DNS name too long: {}", name));
+    }
+
+    message.extend_from_slice(&record_type.code().to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// Decode the address records of a response to a query built by `encode_query`
+pub fn decode_response(message: &[u8], record_type: RecordType) -> Result<Answer> {
+    if message.len() < HEADER_LEN {
+        return Err(anyhow::anyhow!("DNS response too short"));
+    }
+
+    let flags = u16::from_be_bytes([message[2], message[3]]);
+    if flags & 0x8000 == 0 {
+        return Err(anyhow::anyhow!("DNS message is not a response"));
+    }
+    if flags & 0x0200 != 0 {
+        return Err(anyhow::anyhow!("DNS response truncated"));
+    }
+    match flags & 0x000f {
+        0 => {}
+        3 => return Err(anyhow::anyhow!("NXDOMAIN")),
+        rcode => return Err(anyhow::anyhow!("DNS server returned rcode {}", rcode)),
+    }
+
+    let question_count = u16::from_be_bytes([message[4], message[5]]);
+    let answer_count = u16::from_be_bytes([message[6], message[7]]);
+
+    let mut offset = HEADER_LEN;
+    for _ in 0..question_count {
+        offset = skip_name(message, offset)? + 4;
+    }
+
+    let mut addresses = Vec::new();
+    let mut ttl: Option<u32> = None;
+
+    for _ in 0..answer_count {
+        offset = skip_name(message, offset)?;
+        let header = message
+            .get(offset..offset + 10)
+            .ok_or_else(|| anyhow::anyhow!("DNS record header out of bounds"))?;
+        let rtype = u16::from_be_bytes([header[0], header[1]]);
+        let class = u16::from_be_bytes([header[2], header[3]]);
+        let record_ttl = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let length = u16::from_be_bytes([header[8], header[9]]) as usize;
+        offset += 10;
+
+        let data = message
+            .get(offset..offset + length)
+            .ok_or_else(|| anyhow::anyhow!("DNS record data out of bounds"))?;
+        offset += length;
+
+        // CNAME records are followed by the server, only addresses matter here
+        if class != CLASS_IN || rtype != record_type.code() {
+            continue;
+        }
+
+        let address = match (record_type, data.len()) {
+            (RecordType::A, 4) => IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])),
+            (RecordType::Aaaa, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            _ => return Err(anyhow::anyhow!("Malformed address record")),
+        };
+        addresses.push(address);
+        ttl = Some(ttl.map_or(record_ttl, |t| t.min(record_ttl)));
+    }
+
+    Ok(Answer {
+        addresses,
+        ttl: Duration::from_secs(ttl.unwrap_or(0) as u64),
+    })
+}
+
+/// The name a query asks about, lowercased and without the trailing dot
+pub fn decode_query_name(message: &[u8]) -> Result<String> {
+    if message.len() < HEADER_LEN || u16::from_be_bytes([message[4], message[5]]) == 0 {
+        return Err(anyhow::anyhow!("DNS query has no question"));
+    }
+
+    let mut labels = Vec::new();
+    let mut offset = HEADER_LEN;
+    loop {
+        let length = *message
+            .get(offset)
+            .ok_or_else(|| anyhow::anyhow!("DNS name out of bounds"))? as usize;
+        if length == 0 {
+            return Ok(labels.join("."));
+        }
+        // The question is the first name, so nothing comes before it to point at
+        if length & 0xc0 != 0 {
+            return Err(anyhow::anyhow!("Malformed DNS question"));
+        }
+        let label = message
+            .get(offset + 1..offset + 1 + length)
+            .ok_or_else(|| anyhow::anyhow!("DNS name out of bounds"))?;
+        labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+        offset += 1 + length;
+    }
+}
+
+/// Skip over a possibly compressed name, returning the offset after it
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize> {
+    loop {
+        let length = *message
+            .get(offset)
+            .ok_or_else(|| anyhow::anyhow!("DNS name out of bounds"))?;
+        match length {
+            0 => return Ok(offset + 1),
+            // A compression pointer ends the name
+            l if l & 0xc0 == 0xc0 => return Ok(offset + 2),
+            l => offset += 1 + l as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A response to `query` carrying `records` of (type, TTL, data), each
+    /// naming the question through a compression pointer
+    fn response(query: &[u8], rcode: u8, records: &[(u16, u32, &[u8])]) -> Vec<u8> {
+        let mut message = query.to_vec();
+        message[2] = 0x81;
+        message[3] = 0x80 | rcode;
+        message[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for (rtype, ttl, data) in records {
+            message.extend_from_slice(&[0xc0, 0x0c]);
+            message.extend_from_slice(&rtype.to_be_bytes());
+            message.extend_from_slice(&CLASS_IN.to_be_bytes());
+            message.extend_from_slice(&ttl.to_be_bytes());
+            message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            message.extend_from_slice(data);
+        }
+        message
+    }
+
+    #[test]
+    fn encodes_query() {
+        let query = encode_query("www.example.com.", RecordType::Aaaa).unwrap();
+        assert_eq!(&query[..HEADER_LEN], &[0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&query[HEADER_LEN..], b"\x03www\x07example\x03com\x00\x00\x1c\x00\x01");
+
+        assert!(encode_query("a..b", RecordType::A).is_err());
+        assert!(encode_query(&"a".repeat(64), RecordType::A).is_err());
+        assert!(encode_query(&["a".repeat(63).as_str(); 4].join("."), RecordType::A).is_err());
+
+        let query = encode_query("WWW.Example.com.", RecordType::A).unwrap();
+        assert_eq!(decode_query_name(&query).unwrap(), "www.example.com");
+        assert!(decode_query_name(&query[..HEADER_LEN + 4]).is_err());
+    }
+
+    #[test]
+    fn decodes_addresses_skipping_other_records() {
+        let query = encode_query("example.com", RecordType::A).unwrap();
+        let cname: &[u8] = b"\x03cdn\xc0\x0c";
+        let message = response(
+            &query,
+            0,
+            &[(5, 600, cname), (1, 300, &[192, 0, 2, 1]), (1, 60, &[192, 0, 2, 2])],
+        );
+        let answer = decode_response(&message, RecordType::A).unwrap();
+        assert_eq!(answer.addresses, vec![IpAddr::from([192, 0, 2, 1]), IpAddr::from([192, 0, 2, 2])]);
+        assert_eq!(answer.ttl, Duration::from_secs(60));
+
+        let query = encode_query("example.com", RecordType::Aaaa).unwrap();
+        let v6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        let answer = decode_response(&response(&query, 0, &[(28, 30, &v6.octets())]), RecordType::Aaaa).unwrap();
+        assert_eq!(answer.addresses, vec![IpAddr::V6(v6)]);
+
+        let empty = decode_response(&response(&query, 0, &[]), RecordType::Aaaa).unwrap();
+        assert!(empty.addresses.is_empty());
+    }
+
+    #[test]
+    fn rejects_failed_or_malformed_responses() {
+        let query = encode_query("example.com", RecordType::A).unwrap();
+        let nxdomain = decode_response(&response(&query, 3, &[]), RecordType::A).unwrap_err();
+        assert_eq!(nxdomain.to_string(), "NXDOMAIN");
+        assert!(decode_response(&response(&query, 2, &[]), RecordType::A).is_err());
+        // The query itself is not a response
+        assert!(decode_response(&query, RecordType::A).is_err());
+
+        let mut truncated = response(&query, 0, &[]);
+        truncated[2] |= 0x02;
+        assert!(decode_response(&truncated, RecordType::A).is_err());
+
+        let message = response(&query, 0, &[(1, 300, &[192, 0, 2, 1])]);
+        assert!(decode_response(&message[..message.len() - 1], RecordType::A).is_err());
+        assert!(decode_response(&message[..8], RecordType::A).is_err());
+        let short = response(&query, 0, &[(1, 300, &[192, 0, 2])]);
+        assert!(decode_response(&short, RecordType::A).is_err());
+    }
+}
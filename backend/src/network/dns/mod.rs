@@ -0,0 +1,245 @@
+pub mod doh;
+pub mod dot;
+mod message;
+pub mod udp;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use message::{Answer, RecordType};
+
+pub use message::decode_query_name;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Cached answers never outlive this, whatever TTL the server sent
+const MAX_TTL: Duration = Duration::from_secs(3600);
+/// Lower bound so zero-TTL answers do not cause a query per connection
+const MIN_TTL: Duration = Duration::from_secs(5);
+/// How long a failed lookup is remembered
+const NEGATIVE_TTL: Duration = Duration::from_secs(30);
+const MAX_CACHE_ENTRIES: usize = 4096;
+
+/// Resolves host names to addresses
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>>;
+    fn stats(&self) -> ResolverStats;
+}
+
+/// Carries encoded DNS queries to an upstream server
+#[async_trait]
+pub trait DnsTransport: Send + Sync {
+    /// Human readable description of the upstream, e.g. its URL
+    fn describe(&self) -> String;
+    async fn exchange(&self, query: Vec<u8>) -> Result<Vec<u8>>;
+}
+
+/// Resolution statistics reported over IPC
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResolverStats {
+    pub resolver: String,
+    pub queries: u64,
+    pub cache_hits: u64,
+    pub failures: u64,
+    /// Lookups answered by the fallback resolver
+    pub fallbacks: u64,
+    pub average_latency_ms: f64,
+}
+
+/// Resolver using the operating system, i.e. plaintext DNS
+pub struct SystemResolver {
+    stats: Mutex<ResolverStats>,
+}
+
+impl SystemResolver {
+    pub fn new() -> Self {
+        Self {
+            stats: Mutex::new(ResolverStats {
+                resolver: String::from("system"),
+                ..ResolverStats::default()
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let started = Instant::now();
+        let result = tokio::net::lookup_host((host, 0)).await;
+
+        let mut stats = self.stats.lock();
+        record_query(&mut stats, started.elapsed());
+        match result {
+            Ok(addrs) => Ok(addrs.map(|addr| addr.ip()).collect()),
+            Err(e) => {
+                stats.failures += 1;
+                Err(anyhow::anyhow!("Failed to resolve {}: {}", host, e))
+            }
+        }
+    }
+
+    fn stats(&self) -> ResolverStats {
+        self.stats.lock().clone()
+    }
+}
+
+struct CacheEntry {
+    result: std::result::Result<Vec<IpAddr>, String>,
+    expires: Instant,
+}
+
+/// Stub resolver sending A and AAAA queries over an encrypted transport,
+/// caching answers for their TTL
+pub struct StubResolver {
+    transport: Box<dyn DnsTransport>,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+    stats: Mutex<ResolverStats>,
+}
+
+impl StubResolver {
+    pub fn new(transport: Box<dyn DnsTransport>) -> Self {
+        let stats = ResolverStats {
+            resolver: transport.describe(),
+            ..ResolverStats::default()
+        };
+        Self {
+            transport,
+            cache: Mutex::new(HashMap::new()),
+            stats: Mutex::new(stats),
+        }
+    }
+
+    async fn query(&self, host: &str, record_type: RecordType) -> Result<Answer> {
+        let query = message::encode_query(host, record_type)?;
+        let response = self.transport.exchange(query).await?;
+        message::decode_response(&response, record_type)
+    }
+
+    fn cached(&self, host: &str) -> Option<std::result::Result<Vec<IpAddr>, String>> {
+        let cache = self.cache.lock();
+        let entry = cache.get(host)?;
+        (entry.expires > Instant::now()).then(|| entry.result.clone())
+    }
+
+    fn store(&self, host: &str, result: std::result::Result<Vec<IpAddr>, String>, ttl: Duration) {
+        let mut cache = self.cache.lock();
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            let now = Instant::now();
+            cache.retain(|_, entry| entry.expires > now);
+            if cache.len() >= MAX_CACHE_ENTRIES {
+                cache.clear();
+            }
+        }
+        cache.insert(
+            host.to_string(),
+            CacheEntry {
+                result,
+                expires: Instant::now() + ttl,
+            },
+        );
+    }
+}
+
+#[async_trait]
+impl Resolver for StubResolver {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+        if let Some(result) = self.cached(&host) {
+            self.stats.lock().cache_hits += 1;
+            return result.map_err(|e| anyhow::anyhow!("Failed to resolve {}: {}", host, e));
+        }
+
+        let started = Instant::now();
+        let (v4, v6) = tokio::join!(
+            self.query(&host, RecordType::A),
+            self.query(&host, RecordType::Aaaa)
+        );
+        record_query(&mut self.stats.lock(), started.elapsed());
+
+        // Prefer IPv4 first; either family succeeding is enough
+        let mut addresses = Vec::new();
+        let mut ttl = MAX_TTL;
+        let mut error = None;
+        for answer in [v4, v6] {
+            match answer {
+                Ok(answer) if !answer.addresses.is_empty() => {
+                    ttl = ttl.min(answer.ttl);
+                    addresses.extend(answer.addresses);
+                }
+                Ok(_) => {}
+                Err(e) => error = Some(e.to_string()),
+            }
+        }
+
+        if addresses.is_empty() {
+            self.stats.lock().failures += 1;
+            let message = error.unwrap_or_else(|| String::from("no address records"));
+            self.store(&host, Err(message.clone()), NEGATIVE_TTL);
+            return Err(anyhow::anyhow!("Failed to resolve {}: {}", host, message));
+        }
+
+        self.store(&host, Ok(addresses.clone()), ttl.clamp(MIN_TTL, MAX_TTL));
+        Ok(addresses)
+    }
+
+    fn stats(&self) -> ResolverStats {
+        self.stats.lock().clone()
+    }
+}
+
+/// Tries a primary resolver and falls back to another when it fails
+pub struct FallbackResolver {
+    primary: Arc<dyn Resolver>,
+    fallback: Arc<dyn Resolver>,
+    fallbacks: AtomicU64,
+}
+
+impl FallbackResolver {
+    pub fn new(primary: Arc<dyn Resolver>, fallback: Arc<dyn Resolver>) -> Self {
+        Self {
+            primary,
+            fallback,
+            fallbacks: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl Resolver for FallbackResolver {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        match self.primary.resolve(host).await {
+            Ok(addresses) => Ok(addresses),
+            Err(e) => {
+                warn!("{}, falling back to {}", e, self.fallback.stats().resolver);
+                self.fallbacks.fetch_add(1, Ordering::Relaxed);
+                self.fallback.resolve(host).await
+            }
+        }
+    }
+
+    fn stats(&self) -> ResolverStats {
+        let mut stats = self.primary.stats();
+        stats.resolver = format!("{} (fallback: {})", stats.resolver, self.fallback.stats().resolver);
+        stats.fallbacks = self.fallbacks.load(Ordering::Relaxed);
+        stats
+    }
+}
+
+fn record_query(stats: &mut ResolverStats, latency: Duration) {
+    let total = stats.average_latency_ms * stats.queries as f64;
+    stats.queries += 1;
+    stats.average_latency_ms = (total + latency.as_secs_f64() * 1000.0) / stats.queries as f64;
+}
@@ -0,0 +1,62 @@
+use super::DnsTransport;
+use anyhow::Result;
+use async_trait::async_trait;
+use rand_core::{OsRng, RngCore};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest response accepted; longer answers come back truncated
+const MAX_RESPONSE: usize = 4096;
+
+/// Plaintext DNS over UDP, meant for resolvers on this machine such as `hnsd`
+pub struct UdpTransport {
+    server: SocketAddr,
+}
+
+impl UdpTransport {
+    pub fn new(server: SocketAddr) -> Self {
+        Self { server }
+    }
+
+    async fn query(&self, mut query: Vec<u8>) -> Result<Vec<u8>> {
+        // A random ID, since anyone on the path could otherwise forge answers
+        let id = (OsRng.next_u32() as u16).to_be_bytes();
+        query
+            .get_mut(..2)
+            .ok_or_else(|| anyhow::anyhow!("DNS query too short"))?
+            .copy_from_slice(&id);
+
+        let bind = match self.server {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(self.server).await?;
+        socket.send(&query).await?;
+
+        let mut response = vec![0u8; MAX_RESPONSE];
+        loop {
+            let length = socket.recv(&mut response).await?;
+            // Stray datagrams are skipped rather than failing the lookup
+            if length >= 2 && response[..2] == id {
+                response.truncate(length);
+                return Ok(response);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl DnsTransport for UdpTransport {
+    fn describe(&self) -> String {
+        format!("dns://{}", self.server)
+    }
+
+    async fn exchange(&self, query: Vec<u8>) -> Result<Vec<u8>> {
+        tokio::time::timeout(QUERY_TIMEOUT, self.query(query))
+            .await
+            .map_err(|_| anyhow::anyhow!("DNS query to {} timed out", self.server))?
+    }
+}
@@ -0,0 +1,334 @@
+use super::connector::{BoxedStream, Connector, TargetAddr};
+use anyhow::Result;
+use async_trait::async_trait;
+use rand_core::{OsRng, RngCore};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+/// Port of the SAM bridge in I2P and i2pd routers
+pub const DEFAULT_SAM_PORT: u16 = 7656;
+/// Building the session's tunnels can take a while on a fresh router
+const SESSION_TIMEOUT: Duration = Duration::from_secs(120);
+/// Looking up and reaching a destination goes through several tunnels
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Longest SAM reply accepted; private keys and destinations run to a few kilobytes
+const MAX_REPLY: u64 = 64 * 1024;
+
+/// A SAM v3 line split into its leading words and `KEY=VALUE` pairs
+struct Reply {
+    words: Vec<String>,
+    values: HashMap<String, String>,
+}
+
+impl Reply {
+    fn parse(line: &str) -> Self {
+        let mut words = Vec::new();
+        let mut values = HashMap::new();
+        let mut rest = line.trim();
+        while !rest.is_empty() {
+            // Values may be quoted when they contain spaces, like MESSAGE="..."
+            let end = match rest.find('=') {
+                Some(eq) if !rest[..eq].contains(' ') && rest[eq + 1..].starts_with('"') => {
+                    rest[eq + 2..].find('"').map_or(rest.len(), |close| eq + 3 + close)
+                }
+                _ => rest.find(' ').unwrap_or(rest.len()),
+            };
+            let token = &rest[..end];
+            match token.split_once('=') {
+                Some((key, value)) => {
+                    values.insert(key.to_string(), value.trim_matches('"').to_string());
+                }
+                None => words.push(token.to_string()),
+            }
+            rest = rest[end..].trim_start();
+        }
+        Self { words, values }
+    }
+
+    /// Fail unless the reply starts with `expected` and has RESULT=OK
+    fn ok(self, expected: &[&str]) -> Result<Self> {
+        if self.words.iter().map(String::as_str).ne(expected.iter().copied()) {
+            return Err(anyhow::anyhow!("Unexpected SAM reply {}", self.words.join(" ")));
+        }
+        match self.values.get("RESULT").map(String::as_str) {
+            Some("OK") => Ok(self),
+            Some(result) => Err(anyhow::anyhow!(
+                "{}{}",
+                result,
+                self.values
+                    .get("MESSAGE")
+                    .map(|message| format!(": {}", message))
+                    .unwrap_or_default()
+            )),
+            None => Err(anyhow::anyhow!("SAM reply without a result")),
+        }
+    }
+}
+
+/// Send a command and read its one-line reply. Lines are read byte by byte
+/// so nothing past the reply is taken from a stream that carries data next.
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, line: &str) -> Result<Reply> {
+    stream.write_all(format!("{}\n", line).as_bytes()).await?;
+    let mut reply = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            b'\n' => break,
+            byte => reply.push(byte),
+        }
+        if reply.len() as u64 > MAX_REPLY {
+            return Err(anyhow::anyhow!("SAM reply too long"));
+        }
+    }
+    Ok(Reply::parse(&String::from_utf8_lossy(&reply)))
+}
+
+async fn hello<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Result<()> {
+    command(stream, "HELLO VERSION MIN=3.1 MAX=3.3")
+        .await?
+        .ok(&["HELLO", "REPLY"])
+        .map_err(|e| anyhow::anyhow!("SAM handshake failed: {}", e))?;
+    Ok(())
+}
+
+/// A streaming session with a local I2P router over its SAM v3 bridge.
+///
+/// The session exists for as long as its control connection stays open; it
+/// has a transient destination, so nothing links it to earlier sessions.
+pub struct SamSession {
+    sam: SocketAddr,
+    id: String,
+    closed: Arc<AtomicBool>,
+    /// Keeps the control connection open, answering the router's pings
+    task: JoinHandle<()>,
+}
+
+impl SamSession {
+    pub async fn create(sam: SocketAddr) -> Result<Self> {
+        let mut control = TcpStream::connect(sam)
+            .await
+            .map_err(|e| anyhow::anyhow!("I2P router is not reachable on {}: {}", sam, e))?;
+        hello(&mut control).await?;
+
+        let mut bytes = [0u8; 8];
+        OsRng.fill_bytes(&mut bytes);
+        let id: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let id = format!("solace-{}", id);
+        let create = format!("SESSION CREATE STYLE=STREAM ID={} DESTINATION=TRANSIENT SIGNATURE_TYPE=7", id);
+        tokio::time::timeout(SESSION_TIMEOUT, command(&mut control, &create))
+            .await
+            .map_err(|_| anyhow::anyhow!("I2P router took too long to create a session"))??
+            .ok(&["SESSION", "STATUS"])
+            .map_err(|e| anyhow::anyhow!("I2P session failed: {}", e))?;
+        info!("I2P session ready through SAM bridge {}", sam);
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let task = tokio::spawn(keep_alive(BufReader::new(control), closed.clone()));
+        Ok(Self { sam, id, closed, task })
+    }
+
+    /// Open a stream to an I2P destination, a `.i2p` host name or `.b32.i2p` address
+    pub async fn connect(&self, host: &str) -> Result<TcpStream> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(anyhow::anyhow!("I2P session was closed by the router"));
+        }
+        let mut stream = TcpStream::connect(self.sam).await?;
+        hello(&mut stream).await?;
+
+        let open = async {
+            // Resolved by the router's address book, never by DNS
+            let lookup = command(&mut stream, &format!("NAMING LOOKUP NAME={}", host))
+                .await?
+                .ok(&["NAMING", "REPLY"])
+                .map_err(|e| anyhow::anyhow!("I2P lookup of {} failed: {}", host, e))?;
+            let destination = lookup
+                .values
+                .get("VALUE")
+                .ok_or_else(|| anyhow::anyhow!("I2P lookup of {} returned no destination", host))?;
+
+            command(
+                &mut stream,
+                &format!("STREAM CONNECT ID={} DESTINATION={} SILENT=false", self.id, destination),
+            )
+            .await?
+            .ok(&["STREAM", "STATUS"])
+            .map_err(|e| anyhow::anyhow!("I2P connection to {} failed: {}", host, e))?;
+            Ok::<_, anyhow::Error>(())
+        };
+        tokio::time::timeout(CONNECT_TIMEOUT, open)
+            .await
+            .map_err(|_| anyhow::anyhow!("I2P connection to {} timed out", host))??;
+        Ok(stream)
+    }
+
+    pub fn check(&self) -> Result<()> {
+        match self.closed.load(Ordering::Relaxed) {
+            true => Err(anyhow::anyhow!("I2P session was closed by the router")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Drop for SamSession {
+    fn drop(&mut self) {
+        // Closing the control connection ends the session
+        self.task.abort();
+    }
+}
+
+/// Answer the router's pings until it closes the control connection
+async fn keep_alive<S: AsyncBufRead + AsyncWrite + Unpin>(mut control: S, closed: Arc<AtomicBool>) {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match (&mut control).take(MAX_REPLY).read_line(&mut line).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if let Some(data) = line.trim_end().strip_prefix("PING") {
+            if control.write_all(format!("PONG{}\n", data).as_bytes()).await.is_err() {
+                break;
+            }
+        } else {
+            debug!("SAM: {}", line.trim_end());
+        }
+    }
+    warn!("I2P router closed the SAM session");
+    closed.store(true, Ordering::Relaxed);
+}
+
+/// Connects to `.i2p` sites through a SAM session; anything else is refused,
+/// as the route has no outproxy to the regular web
+pub struct I2pConnector {
+    session: SamSession,
+}
+
+impl I2pConnector {
+    pub fn new(session: SamSession) -> Self {
+        Self { session }
+    }
+}
+
+#[async_trait]
+impl Connector for I2pConnector {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream> {
+        let host = target.host().trim_end_matches('.').to_ascii_lowercase();
+        if !is_i2p(&host) {
+            return Err(anyhow::anyhow!("The I2P route only reaches .i2p sites, not {}", target));
+        }
+        Ok(Box::new(self.session.connect(&host).await?))
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.session.check()
+    }
+}
+
+pub fn is_i2p(host: &str) -> bool {
+    host.ends_with(".i2p")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn parses_replies() {
+        let reply = Reply::parse("SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"Tunnel build failed\" ID=x");
+        assert_eq!(reply.words, ["SESSION", "STATUS"]);
+        assert_eq!(reply.values["MESSAGE"], "Tunnel build failed");
+        assert_eq!(reply.values["ID"], "x");
+        let error = reply.ok(&["SESSION", "STATUS"]).err().unwrap();
+        assert_eq!(error.to_string(), "I2P_ERROR: Tunnel build failed");
+
+        assert!(Reply::parse("HELLO REPLY RESULT=OK VERSION=3.3").ok(&["HELLO", "REPLY"]).is_ok());
+        assert!(Reply::parse("HELLO REPLY RESULT=OK").ok(&["SESSION", "STATUS"]).is_err());
+        assert!(Reply::parse("HELLO REPLY").ok(&["HELLO", "REPLY"]).is_err());
+    }
+
+    /// Serve one SAM connection like a router that knows only `site.i2p`,
+    /// whose streams send a greeting. The control connection pings once and
+    /// is closed when `close` fires.
+    async fn serve(stream: TcpStream, pongs: mpsc::UnboundedSender<String>, close: Arc<tokio::sync::Notify>) {
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let command = Reply::parse(&line);
+            let reply = match command.words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+                ["HELLO", "VERSION"] => String::from("HELLO REPLY RESULT=OK VERSION=3.3"),
+                ["SESSION", "CREATE"] => {
+                    write.write_all(b"SESSION STATUS RESULT=OK DESTINATION=key\nPING 42\n").await.unwrap();
+                    let pong = lines.next_line().await.unwrap().unwrap();
+                    pongs.send(pong).unwrap();
+                    close.notified().await;
+                    return;
+                }
+                ["NAMING", "LOOKUP"] if command.values["NAME"] == "site.i2p" => {
+                    String::from("NAMING REPLY RESULT=OK NAME=site.i2p VALUE=destination")
+                }
+                ["NAMING", "LOOKUP"] => format!("NAMING REPLY RESULT=KEY_NOT_FOUND NAME={}", command.values["NAME"]),
+                ["STREAM", "CONNECT"] => {
+                    assert_eq!(command.values["DESTINATION"], "destination");
+                    write.write_all(b"STREAM STATUS RESULT=OK\nhello from i2p").await.unwrap();
+                    return;
+                }
+                _ => String::from("ERROR"),
+            };
+            write.write_all(format!("{}\n", reply).as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_through_stub_sam_bridge() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sam = listener.local_addr().unwrap();
+        let (pongs, mut received) = mpsc::unbounded_channel();
+        let close = Arc::new(tokio::sync::Notify::new());
+        let notify = close.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, pongs.clone(), notify.clone()));
+            }
+        });
+
+        let connector = I2pConnector::new(SamSession::create(sam).await.unwrap());
+        assert_eq!(received.recv().await.unwrap(), "PONG 42");
+
+        let mut stream = connector.connect(&TargetAddr::Domain("Site.I2P.".into(), 80)).await.unwrap();
+        let mut greeting = String::new();
+        stream.read_to_string(&mut greeting).await.unwrap();
+        assert_eq!(greeting, "hello from i2p");
+
+        let missing = connector.connect(&TargetAddr::Domain("missing.i2p".into(), 80)).await;
+        assert!(missing.err().unwrap().to_string().contains("KEY_NOT_FOUND"));
+        assert!(connector.connect(&TargetAddr::Domain("example.com".into(), 80)).await.is_err());
+
+        // The session ends with its control connection
+        assert!(Connector::check(&connector).await.is_ok());
+        close.notify_one();
+        for _ in 0..100 {
+            if Connector::check(&connector).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(Connector::check(&connector).await.is_err());
+        assert!(connector.connect(&TargetAddr::Domain("site.i2p".into(), 80)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unreachable_bridge_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sam = listener.local_addr().unwrap();
+        drop(listener);
+        assert!(SamSession::create(sam).await.is_err());
+    }
+}
@@ -1,36 +1,741 @@
-use crate::ipc::NetworkRoute;
+pub mod autoproxy;
+pub mod connector;
+pub mod dns;
+pub mod i2p;
+pub mod names;
+pub mod proxy;
+pub mod rules;
+pub mod tor;
+pub mod upstream;
+pub mod wireguard;
+
+use crate::ipc::{DnsFallback, DnsMode, DotPrivacy, IPCEvent, NetworkRoute, ProxyProtocol, RouteHop, TabId};
 use anyhow::Result;
 use async_trait::async_trait;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use autoproxy::{pac::PacScript, system::SystemProxy, AutoProxyConnector, ProxySource};
+use connector::{BlockedConnector, BoxedStream, Connector, DirectConnector, TargetAddr};
+use dns::{
+    doh::DohTransport, dot::DotTransport, FallbackResolver, Resolver, ResolverStats, StubResolver, SystemResolver,
+};
+use i2p::{I2pConnector, SamSession};
+use names::BlockchainNames;
+use proxy::{ConnectorSlot, LocalProxy};
+use rules::RoutingRules;
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, OnceCell, RwLock};
+use tokio::time::MissedTickBehavior;
+use tor::{TorConnector, TorProcess};
+use upstream::ProxyConnector;
+use wireguard::{TunnelDnsTransport, WireGuardConfig, WireGuardConnector, WireGuardTunnel};
 
 #[async_trait]
 pub trait NetworkManager: Send + Sync {
-    async fn set_route(&self, route: &NetworkRoute) -> Result<()>;
-    async fn get_current_route(&self) -> NetworkRoute;
+    /// Start the local proxy a tab's engine is launched against, returning its URL.
+    /// A tab that already has one keeps it and only switches route.
+    async fn open_endpoint(&self, tab: TabId, route: &NetworkRoute) -> Result<String>;
+    
+    /// Have a tab's engine use the proxy of another tab, for tabs sharing an
+    /// engine. They share its route from then on.
+    async fn share_endpoint(&self, tab: TabId, with: TabId) -> Result<()>;
+    
+    /// Stop a closed tab's proxy, unless other tabs still use it
+    async fn close_endpoint(&self, tab: TabId);
+    
+    async fn set_route(&self, tab: TabId, route: &NetworkRoute) -> Result<()>;
+    async fn get_route(&self, tab: TabId) -> Option<NetworkRoute>;
+    
+    /// Get statistics of the resolver used by a tab's route
+    async fn resolver_stats(&self, tab: TabId) -> Result<ResolverStats>;
+}
+
+/// Longest chain a route may have
+const MAX_HOPS: usize = 4;
+/// How often routes other than Direct are checked
+const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+/// How long a down route waits between reconnect attempts
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Resolver and connector of one route, shared by every tab on an equal route
+struct RouteConnection {
+    /// None when names are resolved by the last hop, e.g. a Tor exit
+    resolver: Option<Arc<dyn Resolver>>,
+    connector: Arc<dyn Connector>,
+    path: String,
+}
+
+/// A route's connection, set by the first tab to bring it up
+type RouteSlot = Arc<OnceCell<Weak<RouteConnection>>>;
+
+/// Route connections in use, so tabs on the same VPN share one tunnel
+struct RouteCache {
+    connections: parking_lot::Mutex<Vec<(NetworkRoute, RouteSlot)>>,
+    names: Arc<BlockchainNames>,
+}
+
+impl RouteCache {
+    /// Reuse the connection of a tab already on this route, or set one up.
+    /// Tabs connecting to a route at the same time wait for one tunnel, while
+    /// other routes connect alongside it.
+    async fn connect(&self, route: &NetworkRoute) -> Result<Arc<RouteConnection>> {
+        loop {
+            let slot = {
+                let mut connections = self.connections.lock();
+                // Routes still connecting stay, those whose tabs are all gone go
+                connections.retain(|(_, slot)| slot.get().is_none_or(|connection| connection.strong_count() > 0));
+                match connections.iter().find(|(existing, _)| existing == route) {
+                    Some((_, slot)) => slot.clone(),
+                    None => {
+                        let slot = RouteSlot::default();
+                        connections.push((route.clone(), slot.clone()));
+                        slot
+                    }
+                }
+            };
+            
+            // A failed attempt leaves the slot empty for the next tab to try
+            let mut built = None;
+            let built_ref = &mut built;
+            let connection = slot
+                .get_or_try_init(|| async move {
+                    let connection = Arc::new(build_connection(route, &self.names).await?);
+                    let weak = Arc::downgrade(&connection);
+                    *built_ref = Some(connection);
+                    Ok::<_, anyhow::Error>(weak)
+                })
+                .await?;
+            if let Some(connection) = built.or_else(|| connection.upgrade()) {
+                return Ok(connection);
+            }
+            // The route's last tab let go of it since, so it is set up again
+        }
+    }
+}
+
+/// A tab's connector: destinations matching a routing rule go over that
+/// rule's route, everything else over the tab's own route
+struct RuleConnector {
+    route: NetworkRoute,
+    connection: Arc<RouteConnection>,
+    rules: Arc<RoutingRules>,
+    routes: Arc<RouteCache>,
+    /// Connections of rule routes this tab used, kept up while it exists
+    held: parking_lot::Mutex<Vec<Arc<RouteConnection>>>,
 }
 
+#[async_trait]
+impl Connector for RuleConnector {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream> {
+        let route = self.rules.route_for(target, &self.route)?;
+        if route == self.route {
+            return self.connection.connector.connect(target).await;
+        }
+        
+        let connection = self.routes.connect(&route).await?;
+        {
+            let mut held = self.held.lock();
+            if !held.iter().any(|existing| Arc::ptr_eq(existing, &connection)) {
+                held.push(connection.clone());
+            }
+        }
+        connection.connector.connect(target).await
+    }
+    
+    async fn check(&self) -> Result<()> {
+        self.connection.connector.check().await
+    }
+}
+
+/// Why a tab's route is down, and when it was last reconnected
+struct Outage {
+    reason: String,
+    attempted: Instant,
+}
+
+/// A tab's local proxy and the route it forwards to
+struct TabEndpoint {
+    /// Tabs whose engines use the proxy, more than one when they share an engine
+    tabs: Vec<TabId>,
+    route: NetworkRoute,
+    /// None while the route is down
+    connection: Option<Arc<RouteConnection>>,
+    slot: ConnectorSlot,
+    proxy: LocalProxy,
+    outage: Option<Outage>,
+}
+
+impl TabEndpoint {
+    /// Send the tabs' traffic over a connection, or block it when the route
+    /// could not be connected. Returns an event for each tab when the route
+    /// went down or came back up.
+    fn apply(
+        &mut self,
+        result: std::result::Result<Arc<RouteConnection>, String>,
+        rules: &Arc<RoutingRules>,
+        routes: &Arc<RouteCache>,
+    ) -> Vec<IPCEvent> {
+        match result {
+            Ok(connection) => {
+                // New connections pick up the new connector, open ones are left alone
+                *self.slot.write() = Arc::new(RuleConnector {
+                    route: self.route.clone(),
+                    connection: connection.clone(),
+                    rules: rules.clone(),
+                    routes: routes.clone(),
+                    held: parking_lot::Mutex::new(Vec::new()),
+                });
+                self.connection = Some(connection);
+                if self.outage.take().is_none() {
+                    return Vec::new();
+                }
+                tracing::info!("Route of tabs {:?} is back up", self.tabs);
+                self.route_up()
+            }
+            Err(reason) => {
+                *self.slot.write() = Arc::new(BlockedConnector::new(reason.clone()));
+                self.connection = None;
+                let was_up = self.outage.is_none();
+                self.outage = Some(Outage {
+                    reason: reason.clone(),
+                    attempted: Instant::now(),
+                });
+                if !was_up {
+                    return Vec::new();
+                }
+                tracing::warn!("Route of tabs {:?} is down, blocking their traffic: {}", self.tabs, reason);
+                self.tabs
+                    .iter()
+                    .map(|tab| IPCEvent::RouteDown {
+                        tab: *tab,
+                        route: self.route.clone(),
+                        reason: reason.clone(),
+                    })
+                    .collect()
+            }
+        }
+    }
+    
+    fn route_up(&self) -> Vec<IPCEvent> {
+        self.tabs
+            .iter()
+            .map(|tab| IPCEvent::RouteUp {
+                tab: *tab,
+                route: self.route.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Routes each tab's traffic, failing closed: a tab whose route is not
+/// Direct never connects directly when that route is down
 pub struct DefaultNetworkManager {
-    current_route: Arc<RwLock<NetworkRoute>>,
+    endpoints: RwLock<Vec<TabEndpoint>>,
+    routes: Arc<RouteCache>,
+    rules: Arc<RoutingRules>,
+    event_tx: broadcast::Sender<IPCEvent>,
 }
 
 impl DefaultNetworkManager {
-    pub fn new() -> Self {
+    pub fn new(
+        rules: Arc<RoutingRules>,
+        names: Arc<BlockchainNames>,
+        event_tx: broadcast::Sender<IPCEvent>,
+    ) -> Self {
         Self {
-            current_route: Arc::new(RwLock::new(NetworkRoute::Direct)),
+            endpoints: RwLock::new(Vec::new()),
+            routes: Arc::new(RouteCache {
+                connections: parking_lot::Mutex::new(Vec::new()),
+                names,
+            }),
+            rules,
+            event_tx,
+        }
+    }
+    
+    /// Check routes in the background for as long as the manager lives
+    pub fn start_monitor(self: &Arc<Self>) {
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEALTH_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                manager.check_routes().await;
+            }
+        });
+    }
+    
+    /// Block tabs whose route went down, and reconnect them once it is back
+    async fn check_routes(&self) {
+        // Each route is checked once, however many tabs share it
+        let mut routes: Vec<(NetworkRoute, Option<Arc<RouteConnection>>)> = Vec::new();
+        for endpoint in self.endpoints.read().await.iter() {
+            let due = endpoint
+                .outage
+                .as_ref()
+                .is_none_or(|outage| outage.attempted.elapsed() >= RECONNECT_INTERVAL);
+            if !due
+                || matches!(endpoint.route, NetworkRoute::Direct { .. })
+                || routes.iter().any(|(route, _)| *route == endpoint.route)
+            {
+                continue;
+            }
+            routes.push((endpoint.route.clone(), endpoint.connection.clone()));
+        }
+        
+        for (route, connection) in routes {
+            let result = match connection {
+                Some(connection) => match connection.connector.check().await {
+                    Ok(()) => continue,
+                    Err(e) => Err(e.to_string()),
+                },
+                // The old connection was dropped when the route went down, so this builds a new one
+                None => self.routes.connect(&route).await.map_err(|e| e.to_string()),
+            };
+            
+            let mut endpoints = self.endpoints.write().await;
+            for endpoint in endpoints.iter_mut().filter(|endpoint| endpoint.route == route) {
+                for event in endpoint.apply(result.clone(), &self.rules, &self.routes) {
+                    let _ = self.event_tx.send(event);
+                }
+            }
+        }
+    }
+    
+    /// Connect a route for a tab. Invalid routes and Direct failures are
+    /// errors; any other route that fails to connect leaves the tab blocked.
+    async fn connect_route(&self, route: &NetworkRoute) -> Result<std::result::Result<Arc<RouteConnection>, String>> {
+        validate_route(route)?;
+        match self.routes.connect(route).await {
+            Err(e) if matches!(route, NetworkRoute::Direct { .. }) => Err(e),
+            result => Ok(result.map_err(|e| e.to_string())),
+        }
+    }
+}
+
+/// Blockchain names are plugged into the Direct, PAC and System routes; the
+/// others resolve names through a tunnel or proxy, which a lookup here would bypass
+async fn build_connection(route: &NetworkRoute, names: &Arc<BlockchainNames>) -> Result<RouteConnection> {
+    let path = describe_path(route);
+    if let NetworkRoute::Direct { dns } = route {
+        let resolver = names.resolver(build_resolver(dns)?);
+        return Ok(RouteConnection {
+            resolver: Some(resolver.clone()),
+            connector: Arc::new(DirectConnector::new(resolver)),
+            path,
+        });
+    }
+    if matches!(route, NetworkRoute::Pac { .. } | NetworkRoute::System) {
+        return build_auto_connection(route, path, names).await;
+    }
+    if let NetworkRoute::I2p { sam } = route {
+        let session = SamSession::create(*sam).await?;
+        tracing::info!("Route ready: {}", path);
+        // Names are looked up in the router's address book
+        return Ok(RouteConnection {
+            resolver: None,
+            connector: Arc::new(I2pConnector::new(session)),
+            path,
+        });
+    }
+
+    let hops = route.hops();
+    // Each hop reaches its own server through the hops before it
+    let mut resolver: Option<Arc<dyn Resolver>> = Some(Arc::new(SystemResolver::new()));
+    let mut connector: Arc<dyn Connector> = Arc::new(DirectConnector::new(Arc::new(SystemResolver::new())));
+    for (index, hop) in hops.iter().enumerate() {
+        match hop {
+            RouteHop::Vpn { config } => {
+                let config = WireGuardConfig::load(config).await?;
+                let tunnel = Arc::new(WireGuardTunnel::start(config).await?);
+                let tunnel_resolver: Arc<dyn Resolver> =
+                    Arc::new(StubResolver::new(Box::new(TunnelDnsTransport::new(tunnel.clone()))));
+                connector = Arc::new(WireGuardConnector::new(tunnel, tunnel_resolver.clone()));
+                resolver = Some(tunnel_resolver);
+            }
+            RouteHop::Proxy { server } => {
+                connector = Arc::new(ProxyConnector::new(server.clone(), connector)?);
+                resolver = None;
+            }
+            RouteHop::Tor => {
+                let upstream = (index > 0).then(|| connector.clone());
+                let process = TorProcess::start(tor_data_dir(&hops[..index]), upstream).await?;
+                connector = Arc::new(TorConnector::new(process)?);
+                resolver = None;
+            }
+        }
+    }
+
+    tracing::info!("Route ready: {}", path);
+    Ok(RouteConnection { resolver, connector, path })
+}
+
+/// PAC and System routes pick a proxy per connection, or none at all
+async fn build_auto_connection(
+    route: &NetworkRoute,
+    path: String,
+    names: &Arc<BlockchainNames>,
+) -> Result<RouteConnection> {
+    let source = match route {
+        NetworkRoute::Pac { url } => Some(ProxySource::Pac(PacScript::load(url).await?)),
+        _ => match autoproxy::system::detect().await {
+            SystemProxy::Pac(url) => Some(ProxySource::Pac(PacScript::load(&url).await?)),
+            SystemProxy::Manual(proxies) => Some(ProxySource::Manual(proxies)),
+            SystemProxy::None => None,
+        },
+    };
+
+    // Names are resolved here for direct connections and by the proxy otherwise
+    let resolver = names.resolver(Arc::new(SystemResolver::new()));
+    let direct: Arc<dyn Connector> = Arc::new(DirectConnector::new(resolver.clone()));
+    let connector: Arc<dyn Connector> = match source {
+        Some(source) => Arc::new(AutoProxyConnector::new(source, direct)),
+        None => {
+            tracing::info!("No system proxy configured, connecting directly");
+            direct
         }
+    };
+    tracing::info!("Route ready: {}", path);
+    Ok(RouteConnection {
+        resolver: Some(resolver),
+        connector,
+        path,
+    })
+}
+
+/// Check that a route's hops can be composed before connecting anything
+pub fn validate_route(route: &NetworkRoute) -> Result<()> {
+    match route {
+        NetworkRoute::Direct { .. } | NetworkRoute::System | NetworkRoute::I2p { .. } => Ok(()),
+        NetworkRoute::Pac { url } => {
+            let parsed = url::Url::parse(url).map_err(|e| anyhow::anyhow!("Invalid PAC URL {}: {}", url, e))?;
+            match parsed.scheme() {
+                "http" | "https" | "file" => Ok(()),
+                other => Err(anyhow::anyhow!("Unsupported PAC URL scheme {}", other)),
+            }
+        }
+        _ => validate_hops(&route.hops()),
+    }
+}
+
+fn validate_hops(hops: &[RouteHop]) -> Result<()> {
+    if hops.is_empty() {
+        return Err(anyhow::anyhow!("A route chain needs at least one hop"));
+    }
+    if hops.len() > MAX_HOPS {
+        return Err(anyhow::anyhow!("Route chains are limited to {} hops", MAX_HOPS));
+    }
+    for (index, hop) in hops.iter().enumerate() {
+        match hop {
+            // Proxies and Tor only carry TCP, so a WireGuard tunnel cannot run behind them
+            RouteHop::Vpn { .. } if index > 0 => {
+                return Err(anyhow::anyhow!("A VPN hop must come first, its UDP cannot pass through earlier hops"));
+            }
+            RouteHop::Tor if hops[..index].contains(&RouteHop::Tor) => {
+                return Err(anyhow::anyhow!("Tor can only appear once in a route"));
+            }
+            RouteHop::Proxy { server } => {
+                upstream::parse_address(&server.address)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Tor keeps its guards per data directory, so a daemon behind other hops
+/// gets its own and never shares guard state with a direct one
+fn tor_data_dir(before: &[RouteHop]) -> std::path::PathBuf {
+    let base = crate::storage::data_dir().join("tor");
+    if before.is_empty() {
+        return base;
+    }
+    let digest = Sha256::digest(format!("{:?}", before).as_bytes());
+    let name: String = digest[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+    base.join(name)
+}
+
+/// Human readable path traffic takes on a route, e.g. `WireGuard (home.conf) → Tor → site`
+pub fn describe_path(route: &NetworkRoute) -> String {
+    let mut parts: Vec<String> = match route {
+        NetworkRoute::Direct { dns } => vec![match dns {
+            DnsMode::System => String::from("Direct"),
+            DnsMode::Doh { .. } => String::from("Direct (DNS-over-HTTPS)"),
+            DnsMode::Dot { .. } => String::from("Direct (DNS-over-TLS)"),
+        }],
+        NetworkRoute::Pac { url } => vec![format!("PAC ({})", url)],
+        NetworkRoute::System => vec![String::from("System proxy")],
+        NetworkRoute::I2p { .. } => vec![String::from("I2P")],
+        _ => route
+            .hops()
+            .iter()
+            .map(|hop| match hop {
+                RouteHop::Vpn { config } => format!(
+                    "WireGuard ({})",
+                    config.file_name().map(|name| name.to_string_lossy()).unwrap_or_default()
+                ),
+                RouteHop::Proxy { server } => match server.protocol {
+                    ProxyProtocol::Socks5 => format!("SOCKS5 proxy {}", server.address),
+                    ProxyProtocol::Http => format!("HTTP proxy {}", server.address),
+                },
+                RouteHop::Tor => String::from("Tor"),
+            })
+            .collect(),
+    };
+    parts.push(String::from("site"));
+    parts.join(" → ")
+}
+
+fn build_resolver(dns: &DnsMode) -> Result<Arc<dyn Resolver>> {
+    Ok(match dns {
+        DnsMode::System => Arc::new(SystemResolver::new()),
+        DnsMode::Doh { url, bootstrap } => {
+            Arc::new(StubResolver::new(Box::new(DohTransport::new(url, bootstrap.clone())?)))
+        }
+        DnsMode::Dot { upstreams, privacy, fallback } => {
+            let resolver: Arc<dyn Resolver> =
+                Arc::new(StubResolver::new(Box::new(DotTransport::new(upstreams, privacy)?)));
+            match (fallback, privacy) {
+                (DnsFallback::None, _) => resolver,
+                (DnsFallback::System, DotPrivacy::Opportunistic) => {
+                    Arc::new(FallbackResolver::new(resolver, Arc::new(SystemResolver::new())))
+                }
+                (DnsFallback::System, DotPrivacy::Strict) => {
+                    return Err(anyhow::anyhow!("Strict DNS-over-TLS cannot fall back to plaintext DNS"));
+                }
+            }
+        }
+    })
+}
+
+/// Whether engines on this route talk to their endpoint as an HTTP proxy
+/// rather than SOCKS. I2P sites are plain HTTP, which the engine sends to an
+/// HTTP proxy in absolute form.
+pub fn http_proxy(route: &NetworkRoute) -> bool {
+    matches!(route, NetworkRoute::I2p { .. })
+}
+
+fn proxy_url(proxy: &LocalProxy, route: &NetworkRoute) -> String {
+    match http_proxy(route) {
+        true => proxy.http_url(),
+        false => proxy.url(),
     }
 }
 
 #[async_trait]
 impl NetworkManager for DefaultNetworkManager {
-    async fn set_route(&self, route: &NetworkRoute) -> Result<()> {
-        let mut current = self.current_route.write().await;
-        *current = route.clone();
+    async fn open_endpoint(&self, tab: TabId, route: &NetworkRoute) -> Result<String> {
+        let existing = self
+            .endpoints
+            .read()
+            .await
+            .iter()
+            .find(|endpoint| endpoint.tabs.contains(&tab))
+            .map(|endpoint| proxy_url(&endpoint.proxy, route));
+        if let Some(url) = existing {
+            self.set_route(tab, route).await?;
+            return Ok(url);
+        }
+        
+        let result = self.connect_route(route).await?;
+        let slot: ConnectorSlot = Arc::new(parking_lot::RwLock::new(Arc::new(BlockedConnector::new(
+            String::from("Route not connected yet"),
+        ))));
+        let proxy = LocalProxy::start(SocketAddr::from(([127, 0, 0, 1], 0)), slot.clone()).await?;
+        let url = proxy_url(&proxy, route);
+        tracing::info!("Network endpoint for tab {} listening on {}", tab, proxy.addr());
+        
+        let mut endpoint = TabEndpoint {
+            tabs: vec![tab],
+            route: route.clone(),
+            connection: None,
+            slot,
+            proxy,
+            outage: None,
+        };
+        for event in endpoint.apply(result, &self.rules, &self.routes) {
+            let _ = self.event_tx.send(event);
+        }
+        self.endpoints.write().await.push(endpoint);
+        Ok(url)
+    }
+    
+    async fn share_endpoint(&self, tab: TabId, with: TabId) -> Result<()> {
+        let mut endpoints = self.endpoints.write().await;
+        if !endpoints.iter().any(|endpoint| endpoint.tabs.contains(&with)) {
+            return Err(anyhow::anyhow!("No network endpoint for tab {}", with));
+        }
+        for endpoint in endpoints.iter_mut() {
+            endpoint.tabs.retain(|other| *other != tab);
+            if endpoint.tabs.contains(&with) {
+                endpoint.tabs.push(tab);
+            }
+        }
+        endpoints.retain(|endpoint| !endpoint.tabs.is_empty());
+        Ok(())
+    }
+    
+    async fn close_endpoint(&self, tab: TabId) {
+        // Dropping the endpoint stops its proxy; a tunnel closes with its last tab
+        let mut endpoints = self.endpoints.write().await;
+        for endpoint in endpoints.iter_mut() {
+            endpoint.tabs.retain(|other| *other != tab);
+        }
+        endpoints.retain(|endpoint| !endpoint.tabs.is_empty());
+    }
+    
+    async fn set_route(&self, tab: TabId, route: &NetworkRoute) -> Result<()> {
+        // Setting the route a tab already has retries it if it is down
+        let unchanged = self
+            .endpoints
+            .read()
+            .await
+            .iter()
+            .find(|endpoint| endpoint.tabs.contains(&tab))
+            .is_some_and(|endpoint| endpoint.route == *route && endpoint.outage.is_none());
+        if unchanged {
+            return Ok(());
+        }
+        let result = self.connect_route(route).await?;
+        
+        let mut endpoints = self.endpoints.write().await;
+        let endpoint = endpoints
+            .iter_mut()
+            .find(|endpoint| endpoint.tabs.contains(&tab))
+            .ok_or_else(|| anyhow::anyhow!("No network endpoint for tab {}", tab))?;
+        
+        // Leaving a route that was down, e.g. downgrading to Direct, lifts the block
+        let mut events = Vec::new();
+        if endpoint.route != *route {
+            endpoint.route = route.clone();
+            if endpoint.outage.take().is_some() && result.is_ok() {
+                events.extend(endpoint.route_up());
+            }
+        }
+        events.extend(endpoint.apply(result, &self.rules, &self.routes));
+        for event in events {
+            let _ = self.event_tx.send(event);
+        }
         Ok(())
     }
 
-    async fn get_current_route(&self) -> NetworkRoute {
-        self.current_route.read().await.clone()
+    async fn get_route(&self, tab: TabId) -> Option<NetworkRoute> {
+        self.endpoints
+            .read()
+            .await
+            .iter()
+            .find(|endpoint| endpoint.tabs.contains(&tab))
+            .map(|endpoint| endpoint.route.clone())
+    }
+    
+    async fn resolver_stats(&self, tab: TabId) -> Result<ResolverStats> {
+        self.endpoints
+            .read()
+            .await
+            .iter()
+            .find(|endpoint| endpoint.tabs.contains(&tab))
+            .ok_or_else(|| anyhow::anyhow!("No network endpoint for tab {}", tab))
+            .and_then(|endpoint| match (&endpoint.connection, &endpoint.outage) {
+                (Some(connection), _) => Ok(connection),
+                (None, outage) => Err(anyhow::anyhow!(
+                    "Route of tab {} is down: {}",
+                    tab,
+                    outage.as_ref().map_or("not connected", |outage| outage.reason.as_str())
+                )),
+            })
+            .map(|connection| match &connection.resolver {
+                Some(resolver) => resolver.stats(),
+                None => ResolverStats {
+                    resolver: format!("Resolved remotely ({})", connection.path),
+                    ..Default::default()
+                },
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn manager() -> (DefaultNetworkManager, broadcast::Receiver<IPCEvent>) {
+        let (event_tx, events) = broadcast::channel(16);
+        let manager = DefaultNetworkManager::new(
+            Arc::new(RoutingRules::new(PathBuf::from("rules.json"))),
+            Arc::new(BlockchainNames::new(PathBuf::from("names.json"))),
+            event_tx,
+        );
+        (manager, events)
+    }
+
+    #[tokio::test]
+    async fn tabs_sharing_an_endpoint_share_its_route() {
+        let (manager, mut events) = manager();
+        let direct = NetworkRoute::default();
+        let first = manager.open_endpoint(1, &direct).await.unwrap();
+        manager.share_endpoint(2, 1).await.unwrap();
+        assert!(manager.share_endpoint(3, 9).await.is_err());
+        // Reopening a shared endpoint keeps its proxy
+        assert_eq!(manager.open_endpoint(2, &direct).await.unwrap(), first);
+
+        // No SAM bridge listens here, so the route goes down for both tabs
+        let i2p = NetworkRoute::I2p {
+            sam: SocketAddr::from(([127, 0, 0, 1], 1)),
+        };
+        manager.set_route(2, &i2p).await.unwrap();
+        assert_eq!(manager.get_route(1).await, Some(i2p.clone()));
+        let mut down = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let IPCEvent::RouteDown { tab, route, .. } = event {
+                assert_eq!(route, i2p);
+                down.push(tab);
+            }
+        }
+        down.sort();
+        assert_eq!(down, [1, 2]);
+        assert!(manager.resolver_stats(2).await.is_err());
+
+        // The endpoint outlives the tab that opened it, until its last tab closes
+        manager.close_endpoint(1).await;
+        assert_eq!(manager.get_route(1).await, None);
+        assert_eq!(manager.get_route(2).await, Some(i2p));
+        manager.close_endpoint(2).await;
+        assert_eq!(manager.get_route(2).await, None);
+        assert!(manager.endpoints.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn routes_connect_alongside_one_another() {
+        let cache = Arc::new(RouteCache {
+            connections: parking_lot::Mutex::new(Vec::new()),
+            names: Arc::new(BlockchainNames::new(PathBuf::from("names.json"))),
+        });
+        // A SAM bridge that never answers keeps its route connecting
+        let bridge = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let i2p = NetworkRoute::I2p {
+            sam: bridge.local_addr().unwrap(),
+        };
+        let connect = |route: NetworkRoute| {
+            let cache = cache.clone();
+            tokio::spawn(async move { cache.connect(&route).await.map(|_| ()) })
+        };
+        let first = connect(i2p.clone());
+        let (_session, _) = bridge.accept().await.unwrap();
+        // A second tab on the route waits for the same session
+        let second = connect(i2p);
+        let accepted = tokio::time::timeout(Duration::from_millis(200), bridge.accept()).await;
+        assert!(accepted.is_err());
+
+        let direct = NetworkRoute::default();
+        let (a, b) = tokio::join!(cache.connect(&direct), cache.connect(&direct));
+        assert!(Arc::ptr_eq(&a.unwrap(), &b.unwrap()));
+        assert!(!first.is_finished() && !second.is_finished());
+        first.abort();
+        second.abort();
     }
-} 
\ No newline at end of file
+}
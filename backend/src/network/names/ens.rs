@@ -0,0 +1,386 @@
+use super::keccak::keccak256;
+use crate::ipfs::cid::{hex_decode, read_varint, Cid, LIBP2P_KEY};
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::{Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// The ENS registry, at the same address on mainnet and the testnets
+const REGISTRY: &str = "0x00000000000c2e074ec69a0dfb2997ba6c7d2e1e";
+/// Selectors of `resolver(bytes32)`, `contenthash(bytes32)` and `addr(bytes32)`
+const RESOLVER: [u8; 4] = [0x01, 0x78, 0xb8, 0xbf];
+const CONTENTHASH: [u8; 4] = [0xbc, 0x1c, 0x58, 0xd1];
+const ADDR: [u8; 4] = [0x3b, 0x3b, 0x57, 0xde];
+/// Content hash namespaces (ENSIP-7)
+const IPFS_NS: u64 = 0xe3;
+const IPNS_NS: u64 = 0xe5;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// Where an ENS name's website is published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentHash {
+    Ipfs(Cid),
+    /// An IPNS key in base36, or a DNSLink domain
+    Ipns(String),
+}
+
+impl ContentHash {
+    pub fn url(&self) -> String {
+        match self {
+            ContentHash::Ipfs(cid) => format!("ipfs://{}", cid.to_v1()),
+            ContentHash::Ipns(name) => format!("ipns://{}", name),
+        }
+    }
+}
+
+/// Looks up ENS records with `eth_call`s to an Ethereum JSON-RPC endpoint.
+///
+/// Names are resolved through the registry and the resolver it names;
+/// wildcard and offchain (CCIP-Read) resolvers are not followed.
+pub struct EnsResolver {
+    url: url::Url,
+    host: String,
+    port: u16,
+    tls: TlsConnector,
+}
+
+impl EnsResolver {
+    pub fn new(rpc: &str) -> Result<Self> {
+        let url = url::Url::parse(rpc).map_err(|e| anyhow::anyhow!("Invalid Ethereum RPC URL {}: {}", rpc, e))?;
+        if !matches!(url.scheme(), "https" | "http") {
+            return Err(anyhow::anyhow!("Ethereum RPC URL must be http(s): {}", url));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("Ethereum RPC URL has no host: {}", url))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| anyhow::anyhow!("Ethereum RPC URL has no port: {}", url))?;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let mut config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Self {
+            url,
+            host,
+            port,
+            tls: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    /// The name's content hash record, if it has one
+    pub async fn contenthash(&self, name: &str) -> Result<Option<ContentHash>> {
+        let (resolver, node) = self.resolver(name).await?;
+        let result = self.call(&resolver, CONTENTHASH, &node).await?;
+        decode_bytes(&result)?.map(|bytes| decode_contenthash(&bytes)).transpose()
+    }
+
+    /// The name's Ethereum address record, with an EIP-55 checksum
+    pub async fn address(&self, name: &str) -> Result<Option<String>> {
+        let (resolver, node) = self.resolver(name).await?;
+        let result = self.call(&resolver, ADDR, &node).await?;
+        Ok(decode_address(&result).map(|address| checksum_address(&address)))
+    }
+
+    /// The resolver contract the registry names for `name`, and its node
+    async fn resolver(&self, name: &str) -> Result<(String, [u8; 32])> {
+        let node = namehash(&normalize(name));
+        let result = self.call(REGISTRY, RESOLVER, &node).await?;
+        let resolver = decode_address(&result).ok_or_else(|| anyhow::anyhow!("{} is not registered in ENS", name))?;
+        Ok((format!("0x{}", to_hex(&resolver)), node))
+    }
+
+    async fn call(&self, to: &str, selector: [u8; 4], node: &[u8; 32]) -> Result<Vec<u8>> {
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_call",
+            "params": [{ "to": to, "data": format!("0x{}{}", to_hex(&selector), to_hex(node)) }, "latest"],
+        });
+        let reply = tokio::time::timeout(REQUEST_TIMEOUT, self.post(body.to_string()))
+            .await
+            .map_err(|_| anyhow::anyhow!("Ethereum RPC request to {} timed out", self.host))??;
+
+        let reply: serde_json::Value = serde_json::from_slice(&reply)?;
+        if let Some(error) = reply.get("error") {
+            return Err(anyhow::anyhow!(
+                "Ethereum RPC error: {}",
+                error["message"].as_str().unwrap_or("unknown error")
+            ));
+        }
+        let result = reply["result"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("Ethereum RPC reply has no result"))?;
+        hex_decode(result.trim_start_matches("0x"))
+    }
+
+    async fn post(&self, body: String) -> Result<Vec<u8>> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to connect to Ethereum RPC {}: {}", self.host, e))?;
+        let request = Request::post(self.url.as_str())
+            .header(HOST, self.url.host_str().unwrap_or(&self.host))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body)))?;
+
+        if self.url.scheme() == "https" {
+            let server_name = ServerName::try_from(self.host.clone())
+                .map_err(|e| anyhow::anyhow!("Invalid server name {}: {}", self.host, e))?;
+            send_request(self.tls.connect(server_name, stream).await?, request).await
+        } else {
+            send_request(stream, request).await
+        }
+    }
+}
+
+async fn send_request<S>(stream: S, request: Request<Full<Bytes>>) -> Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    let response = sender.send_request(request).await?;
+    if response.status() != StatusCode::OK {
+        return Err(anyhow::anyhow!("Ethereum RPC returned {}", response.status()));
+    }
+    let body = Limited::new(response.into_body(), MAX_RESPONSE_SIZE)
+        .collect()
+        .await
+        .map_err(|e| anyhow::anyhow!("Reading the Ethereum RPC reply failed: {}", e))?;
+    Ok(body.to_bytes().to_vec())
+}
+
+/// Names are hashed as written in Unicode, lowercased
+fn normalize(name: &str) -> String {
+    let (name, _) = idna::domain_to_unicode(name.trim_end_matches('.'));
+    name.to_lowercase()
+}
+
+/// The node a name is registered under (ENSIP-1)
+pub fn namehash(name: &str) -> [u8; 32] {
+    let mut node = [0u8; 32];
+    for label in name.rsplit('.').filter(|label| !label.is_empty()) {
+        let mut joined = node.to_vec();
+        joined.extend_from_slice(&keccak256(label.as_bytes()));
+        node = keccak256(&joined);
+    }
+    node
+}
+
+/// An address returned as one ABI word; the zero address means none is set
+fn decode_address(result: &[u8]) -> Option<[u8; 20]> {
+    let address: [u8; 20] = result.get(12..32)?.try_into().ok()?;
+    address.iter().any(|&byte| byte != 0).then_some(address)
+}
+
+/// ABI-encoded `bytes`: an offset to a length-prefixed value
+fn decode_bytes(result: &[u8]) -> Result<Option<Vec<u8>>> {
+    if result.is_empty() {
+        return Ok(None);
+    }
+    let word = |at: usize| -> Result<usize> {
+        let word = result
+            .get(at..at + 32)
+            .ok_or_else(|| anyhow::anyhow!("Truncated ABI value"))?;
+        if word[..24].iter().any(|&byte| byte != 0) {
+            return Err(anyhow::anyhow!("ABI length out of range"));
+        }
+        Ok(u64::from_be_bytes(word[24..].try_into()?) as usize)
+    };
+    let offset = word(0)?;
+    let length = word(offset)?;
+    let value = result
+        .get(offset + 32..offset + 32 + length)
+        .ok_or_else(|| anyhow::anyhow!("Truncated ABI value"))?;
+    Ok((!value.is_empty()).then(|| value.to_vec()))
+}
+
+fn decode_contenthash(bytes: &[u8]) -> Result<ContentHash> {
+    let mut reader = bytes;
+    match read_varint(&mut reader)? {
+        IPFS_NS => Ok(ContentHash::Ipfs(Cid::from_bytes(reader)?)),
+        IPNS_NS => {
+            let cid = Cid::from_bytes(reader)?;
+            // A DNSLink domain may be held inline instead of a key
+            match cid.inline_data() {
+                Some(domain) if cid.codec != LIBP2P_KEY => Ok(ContentHash::Ipns(String::from_utf8(domain.to_vec())?)),
+                _ => Ok(ContentHash::Ipns(cid.with_codec(LIBP2P_KEY).to_base36())),
+            }
+        }
+        other => Err(anyhow::anyhow!("Unsupported content hash codec 0x{:x}", other)),
+    }
+}
+
+/// Mixed-case checksum encoding of an address (EIP-55)
+fn checksum_address(address: &[u8; 20]) -> String {
+    let hex = to_hex(address);
+    let hash = keccak256(hex.as_bytes());
+    let checksummed: String = hex
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::Incoming;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use std::convert::Infallible;
+    use tokio::net::TcpListener;
+
+    const RESOLVER_ADDRESS: &str = "0x4976fb03c32e5b8cfe2b6ccb31c09ba78ebaba41";
+    const OWNER: &str = "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed";
+    const SITE: &str = "bafybeiczsscdsbs7ffqz55asqdf3smv6klcw3gofszvwlyarci47bgf354";
+
+    fn word(value: usize) -> String {
+        format!("{:064x}", value)
+    }
+
+    /// ABI encoding of `bytes`
+    fn abi_bytes(value: &[u8]) -> String {
+        let mut encoded = format!("{}{}{}", word(32), word(value.len()), to_hex(value));
+        encoded.push_str(&"0".repeat((64 - encoded.len() % 64) % 64));
+        encoded
+    }
+
+    /// Answer `eth_call`s like a chain where only `site.eth` is registered
+    async fn answer(request: Request<Incoming>) -> std::result::Result<Response<Full<Bytes>>, Infallible> {
+        let body = request.into_body().collect().await.unwrap().to_bytes();
+        let call: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(call["method"], "eth_call");
+        let to = call["params"][0]["to"].as_str().unwrap();
+        let data = call["params"][0]["data"].as_str().unwrap();
+        let (selector, node) = data.trim_start_matches("0x").split_at(8);
+        let registered = node == to_hex(&namehash("site.eth"));
+
+        let result = match (to, selector) {
+            (REGISTRY, "0178b8bf") if registered => format!("{:0>64}", RESOLVER_ADDRESS.trim_start_matches("0x")),
+            (REGISTRY, "0178b8bf") => word(0),
+            (RESOLVER_ADDRESS, "bc1c58d1") => {
+                let mut content = vec![0xe3, 0x01];
+                content.extend(Cid::parse(SITE).unwrap().to_bytes());
+                abi_bytes(&content)
+            }
+            (RESOLVER_ADDRESS, "3b3b57de") => format!("{:0>64}", OWNER),
+            _ => {
+                let error = serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "error": { "message": "execution reverted" },
+                });
+                return Ok(Response::new(Full::new(Bytes::from(error.to_string()))));
+            }
+        };
+        let reply = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "result": format!("0x{}", result) });
+        Ok(Response::new(Full::new(Bytes::from(reply.to_string()))))
+    }
+
+    /// A local stand-in for an Ethereum JSON-RPC endpoint
+    async fn stand_in() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let connection = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service_fn(answer));
+                tokio::spawn(connection);
+            }
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[tokio::test]
+    async fn resolves_records_through_stand_in_rpc() {
+        let ens = EnsResolver::new(&stand_in().await).unwrap();
+
+        let content = ens.contenthash("Site.ETH.").await.unwrap().unwrap();
+        assert_eq!(content, ContentHash::Ipfs(Cid::parse(SITE).unwrap()));
+        assert_eq!(content.url(), format!("ipfs://{}", SITE));
+        assert_eq!(
+            ens.address("site.eth").await.unwrap().as_deref(),
+            Some("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed")
+        );
+
+        let missing = ens.contenthash("missing.eth").await.unwrap_err();
+        assert_eq!(missing.to_string(), "missing.eth is not registered in ENS");
+    }
+
+    #[test]
+    fn namehash_vectors() {
+        assert_eq!(namehash(""), [0; 32]);
+        assert_eq!(to_hex(&namehash("eth")), "93cdeb708b7545dc668eb9280176169d1c33cfd8ed6f04690a0bcc88a93fc4ae");
+        assert_eq!(
+            to_hex(&namehash("foo.eth")),
+            "de9b09fd7c5f901e23a3f19fecc54828e9c848539801e86591bd9801b019f84f"
+        );
+        assert_eq!(normalize("Foo.ETH."), "foo.eth");
+    }
+
+    #[test]
+    fn selectors_match_signatures() {
+        assert_eq!(keccak256(b"resolver(bytes32)")[..4], RESOLVER);
+        assert_eq!(keccak256(b"contenthash(bytes32)")[..4], CONTENTHASH);
+        assert_eq!(keccak256(b"addr(bytes32)")[..4], ADDR);
+    }
+
+    #[test]
+    fn checksums_addresses() {
+        for expected in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        ] {
+            let address: [u8; 20] = hex_decode(&expected[2..].to_ascii_lowercase()).unwrap().try_into().unwrap();
+            assert_eq!(checksum_address(&address), expected);
+        }
+    }
+
+    #[test]
+    fn decodes_abi_values() {
+        assert_eq!(decode_address(&hex_decode(&word(0)).unwrap()), None);
+        assert_eq!(decode_bytes(&[]).unwrap(), None);
+        assert_eq!(decode_bytes(&hex_decode(&abi_bytes(&[])).unwrap()).unwrap(), None);
+        assert_eq!(decode_bytes(&hex_decode(&abi_bytes(b"ab")).unwrap()).unwrap(), Some(b"ab".to_vec()));
+        // Lengths past the end of the result
+        assert!(decode_bytes(&hex_decode(&format!("{}{}", word(32), word(64))).unwrap()).is_err());
+
+        let mut dnslink = vec![0xe5, 0x01, 0x01, 0x55, 0x00, 11];
+        dnslink.extend(b"example.com");
+        assert_eq!(decode_contenthash(&dnslink).unwrap(), ContentHash::Ipns(String::from("example.com")));
+        assert!(decode_contenthash(&[0xe4, 0x01]).is_err());
+    }
+}
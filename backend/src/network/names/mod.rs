@@ -0,0 +1,192 @@
+pub mod ens;
+mod keccak;
+
+use super::dns::{doh::DohTransport, udp::UdpTransport, Resolver, ResolverStats, StubResolver};
+use crate::ipc::{HandshakeServer, NameSettings};
+use crate::storage;
+use anyhow::Result;
+use async_trait::async_trait;
+use ens::{ContentHash, EnsResolver};
+use parking_lot::RwLock;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// What a blockchain domain points to
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NameRecords {
+    pub name: String,
+    /// `ipfs://` or `ipns://` URL of the name's website, from ENS
+    pub content: Option<String>,
+    /// Ethereum address, from ENS
+    pub address: Option<String>,
+    /// Addresses from Handshake DNS records
+    pub addresses: Vec<IpAddr>,
+}
+
+pub fn is_ens(host: &str) -> bool {
+    host.trim_end_matches('.').to_ascii_lowercase().ends_with(".eth")
+}
+
+/// Resolves blockchain domains: `.eth` names through ENS, and Handshake
+/// names on a resolver that knows their TLDs
+pub struct BlockchainNames {
+    settings: RwLock<NameSettings>,
+    ens: RwLock<Option<Arc<EnsResolver>>>,
+    handshake: RwLock<Option<Arc<dyn Resolver>>>,
+    path: PathBuf,
+}
+
+impl BlockchainNames {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            settings: RwLock::new(NameSettings::default()),
+            ens: RwLock::new(None),
+            handshake: RwLock::new(None),
+            path,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        if let Some(settings) = storage::load_json::<NameSettings>(&self.path).await? {
+            self.apply(settings)?;
+        }
+        Ok(())
+    }
+
+    pub async fn set(&self, settings: NameSettings) -> Result<()> {
+        self.apply(settings.clone())?;
+        storage::save_json(&self.path, &settings).await
+    }
+
+    pub fn settings(&self) -> NameSettings {
+        self.settings.read().clone()
+    }
+
+    /// Build both resolvers before replacing either, so a bad setting changes nothing
+    fn apply(&self, settings: NameSettings) -> Result<()> {
+        let ens = settings
+            .ens_rpc
+            .as_deref()
+            .map(EnsResolver::new)
+            .transpose()?
+            .map(Arc::new);
+        let handshake: Option<Arc<dyn Resolver>> = match &settings.handshake {
+            Some(HandshakeServer::Doh { url, bootstrap }) => Some(Arc::new(StubResolver::new(Box::new(
+                DohTransport::new(url, bootstrap.clone())?,
+            )))),
+            Some(HandshakeServer::Dns { address }) => {
+                Some(Arc::new(StubResolver::new(Box::new(UdpTransport::new(*address)))))
+            }
+            None => None,
+        };
+
+        *self.ens.write() = ens;
+        *self.handshake.write() = handshake;
+        *self.settings.write() = settings;
+        Ok(())
+    }
+
+    pub fn ens_enabled(&self) -> bool {
+        self.ens.read().is_some()
+    }
+
+    fn ens(&self) -> Result<Arc<EnsResolver>> {
+        self.ens
+            .read()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("ENS is not set up; it needs an Ethereum RPC endpoint"))
+    }
+
+    /// Where an ENS name's website is published
+    pub async fn content(&self, name: &str) -> Result<ContentHash> {
+        self.ens()?
+            .contenthash(name)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("{} has no content hash", name))
+    }
+
+    /// All records of a name, for showing rather than loading it
+    pub async fn lookup(&self, name: &str) -> Result<NameRecords> {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        let mut records = NameRecords {
+            name: name.clone(),
+            ..NameRecords::default()
+        };
+
+        if is_ens(&name) {
+            let ens = self.ens()?;
+            let (content, address) = tokio::join!(ens.contenthash(&name), ens.address(&name));
+            // A resolver may lack either record; only fail when both lookups did
+            match (content, address) {
+                (Err(e), Err(_)) => return Err(e),
+                (content, address) => {
+                    records.content = content.ok().flatten().map(|content| content.url());
+                    records.address = address.ok().flatten();
+                }
+            }
+            return Ok(records);
+        }
+
+        let handshake = self
+            .handshake
+            .read()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Handshake resolution is not set up"))?;
+        records.addresses = handshake.resolve(&name).await?;
+        Ok(records)
+    }
+
+    /// Plug blockchain names into a route's resolver
+    pub fn resolver(self: &Arc<Self>, inner: Arc<dyn Resolver>) -> Arc<dyn Resolver> {
+        Arc::new(NamedResolver {
+            inner,
+            names: self.clone(),
+            handshake_lookups: AtomicU64::new(0),
+        })
+    }
+}
+
+/// A route's resolver with blockchain names plugged in: `.eth` names never
+/// reach DNS, and names DNS cannot find are looked up on Handshake
+struct NamedResolver {
+    inner: Arc<dyn Resolver>,
+    names: Arc<BlockchainNames>,
+    handshake_lookups: AtomicU64,
+}
+
+#[async_trait]
+impl Resolver for NamedResolver {
+    async fn resolve(&self, host: &str) -> Result<Vec<IpAddr>> {
+        if is_ens(host) {
+            return Err(anyhow::anyhow!("{} is an ENS name, which only has IPFS content", host));
+        }
+        let error = match self.inner.resolve(host).await {
+            Ok(addresses) => return Ok(addresses),
+            Err(e) => e,
+        };
+
+        let handshake = self.names.handshake.read().clone();
+        match handshake {
+            Some(handshake) if host.parse::<IpAddr>().is_err() => {
+                self.handshake_lookups.fetch_add(1, Ordering::Relaxed);
+                handshake
+                    .resolve(host)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("{}; on Handshake: {}", error, e))
+            }
+            _ => Err(error),
+        }
+    }
+
+    fn stats(&self) -> ResolverStats {
+        let mut stats = self.inner.stats();
+        if let Some(handshake) = self.names.handshake.read().as_ref() {
+            stats.resolver = format!("{} (Handshake: {})", stats.resolver, handshake.stats().resolver);
+            stats.fallbacks += self.handshake_lookups.load(Ordering::Relaxed);
+        }
+        stats
+    }
+}
@@ -0,0 +1,278 @@
+use super::connector::{Connector, TargetAddr};
+use anyhow::Result;
+use parking_lot::RwLock;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, error};
+
+pub(super) const SOCKS_VERSION: u8 = 5;
+pub(super) const NO_AUTH: u8 = 0x00;
+pub(super) const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+pub(super) const CMD_CONNECT: u8 = 0x01;
+pub(super) const ATYP_IPV4: u8 = 0x01;
+pub(super) const ATYP_DOMAIN: u8 = 0x03;
+pub(super) const ATYP_IPV6: u8 = 0x04;
+pub(super) const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+/// Longest HTTP proxy request header accepted from the engine
+const MAX_REQUEST_HEADER: u64 = 16 * 1024;
+/// Hop-by-hop headers meant for the proxy, not the site
+const PROXY_HEADERS: &[&str] = &["proxy-connection", "proxy-authorization", "connection", "keep-alive"];
+
+/// The connector currently used by a proxy; swapped when the route changes
+pub type ConnectorSlot = Arc<RwLock<Arc<dyn Connector>>>;
+
+/// Local endpoint the engine is launched against, speaking SOCKS5 and
+/// HTTP proxy requests on the same port.
+///
+/// The engine hands host names to the proxy unresolved, so every lookup and
+/// connection goes through the connector of the active route.
+pub struct LocalProxy {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl LocalProxy {
+    pub async fn start(bind: SocketAddr, connector: ConnectorSlot) -> Result<Self> {
+        let listener = TcpListener::bind(bind).await?;
+        let addr = listener.local_addr()?;
+
+        let task = tokio::spawn(async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("Local proxy accept failed: {}", e);
+                        continue;
+                    }
+                };
+
+                // Only the engine on this machine may use the proxy
+                if !peer.ip().is_loopback() {
+                    continue;
+                }
+
+                let connector = connector.read().clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, connector).await {
+                        debug!("Proxy connection from {} ended: {}", peer, e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self { addr, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL to pass to the engine's proxy setting
+    pub fn url(&self) -> String {
+        format!("socks5://{}", self.addr)
+    }
+
+    /// The same endpoint as an HTTP proxy, for routes that carry plain HTTP sites
+    pub fn http_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+impl Drop for LocalProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(mut client: TcpStream, connector: Arc<dyn Connector>) -> Result<()> {
+    let mut first = [0u8; 1];
+    if client.peek(&mut first).await? == 1 && first[0] != SOCKS_VERSION {
+        return serve_http(client, connector).await;
+    }
+    let target = handshake(&mut client).await?;
+
+    let mut upstream = match connector.connect(&target).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            reply(&mut client, REPLY_HOST_UNREACHABLE).await?;
+            return Err(e);
+        }
+    };
+    reply(&mut client, REPLY_SUCCEEDED).await?;
+
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+/// Perform the SOCKS5 greeting and read the CONNECT request (RFC 1928)
+async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(client: &mut S) -> Result<TargetAddr> {
+    let mut header = [0u8; 2];
+    client.read_exact(&mut header).await?;
+    if header[0] != SOCKS_VERSION {
+        return Err(anyhow::anyhow!("Unsupported SOCKS version {}", header[0]));
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    client.read_exact(&mut methods).await?;
+
+    if !methods.contains(&NO_AUTH) {
+        client.write_all(&[SOCKS_VERSION, NO_ACCEPTABLE_METHOD]).await?;
+        return Err(anyhow::anyhow!("Client offered no supported authentication method"));
+    }
+    client.write_all(&[SOCKS_VERSION, NO_AUTH]).await?;
+
+    let mut request = [0u8; 4];
+    client.read_exact(&mut request).await?;
+    if request[1] != CMD_CONNECT {
+        reply(client, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Err(anyhow::anyhow!("Unsupported SOCKS command {}", request[1]));
+    }
+
+    let target = match request[3] {
+        ATYP_IPV4 => {
+            let mut octets = [0u8; 4];
+            client.read_exact(&mut octets).await?;
+            let port = client.read_u16().await?;
+            TargetAddr::Ip(SocketAddr::new(Ipv4Addr::from(octets).into(), port))
+        }
+        ATYP_IPV6 => {
+            let mut octets = [0u8; 16];
+            client.read_exact(&mut octets).await?;
+            let port = client.read_u16().await?;
+            TargetAddr::Ip(SocketAddr::new(Ipv6Addr::from(octets).into(), port))
+        }
+        ATYP_DOMAIN => {
+            let length = client.read_u8().await? as usize;
+            let mut name = vec![0u8; length];
+            client.read_exact(&mut name).await?;
+            let port = client.read_u16().await?;
+            let name = String::from_utf8(name).map_err(|_| anyhow::anyhow!("Invalid host name"))?;
+            TargetAddr::Domain(name, port)
+        }
+        other => {
+            reply(client, REPLY_ADDRESS_NOT_SUPPORTED).await?;
+            return Err(anyhow::anyhow!("Unsupported SOCKS address type {}", other));
+        }
+    };
+
+    Ok(target)
+}
+
+/// Send a reply; the bound address is not meaningful to the engine so it is zeroed
+async fn reply<S: AsyncWrite + Unpin>(client: &mut S, code: u8) -> Result<()> {
+    client
+        .write_all(&[SOCKS_VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+/// Serve an HTTP proxy request: a CONNECT tunnel, or a plain HTTP request in
+/// absolute form, which is sent on in origin form over a fresh connection
+async fn serve_http(client: TcpStream, connector: Arc<dyn Connector>) -> Result<()> {
+    let mut client = BufReader::new(client);
+    let mut lines = Vec::new();
+    let mut remaining = MAX_REQUEST_HEADER;
+    loop {
+        let mut line = String::new();
+        let read = (&mut client).take(remaining).read_line(&mut line).await?;
+        if read == 0 && remaining == 0 {
+            return http_error(&mut client, "431 Request Header Fields Too Large", "Request header too long").await;
+        }
+        if read == 0 {
+            return Err(anyhow::anyhow!("Connection closed before the request header ended"));
+        }
+        remaining -= read as u64;
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line.to_string());
+    }
+
+    let mut request_line = lines.first().map(|line| line.split_whitespace()).into_iter().flatten();
+    let (Some(method), Some(uri), Some(version)) = (request_line.next(), request_line.next(), request_line.next()) else {
+        return http_error(&mut client, "400 Bad Request", "Malformed request line").await;
+    };
+
+    let (target, head) = if method.eq_ignore_ascii_case("CONNECT") {
+        // A non-special scheme keeps the port even when it is a default one
+        let Some(target) = url::Url::parse(&format!("connect://{}", uri))
+            .ok()
+            .and_then(|url| Some(http_target(url.host_str()?, url.port()?)))
+        else {
+            return http_error(&mut client, "400 Bad Request", "CONNECT needs host:port").await;
+        };
+        (target, None)
+    } else {
+        let Some(url) = url::Url::parse(uri).ok().filter(|url| url.scheme() == "http") else {
+            return http_error(&mut client, "400 Bad Request", "Only http:// URLs can be proxied").await;
+        };
+        let Some(host) = url.host_str() else {
+            return http_error(&mut client, "400 Bad Request", "URL has no host").await;
+        };
+        let target = http_target(host, url.port_or_known_default().unwrap_or(80));
+
+        // One request per connection, so a reused connection can never reach another host
+        let mut head = format!(
+            "{} {} {}\r\n",
+            method,
+            &url[url::Position::BeforePath..url::Position::AfterQuery],
+            version
+        );
+        for line in &lines[1..] {
+            let name = line.split(':').next().unwrap_or_default().trim().to_ascii_lowercase();
+            if !PROXY_HEADERS.contains(&name.as_str()) {
+                head.push_str(line);
+                head.push_str("\r\n");
+            }
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        (target, Some(head))
+    };
+
+    let mut upstream = match connector.connect(&target).await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            http_error(&mut client, "502 Bad Gateway", &e.to_string()).await?;
+            return Err(e);
+        }
+    };
+    match head {
+        Some(head) => upstream.write_all(head.as_bytes()).await?,
+        None => {
+            client
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await?
+        }
+    }
+
+    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+fn http_target(host: &str, port: u16) -> TargetAddr {
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => TargetAddr::Ip(SocketAddr::new(ip, port)),
+        Err(_) => TargetAddr::Domain(host.to_string(), port),
+    }
+}
+
+async fn http_error<S: AsyncWrite + Unpin>(client: &mut S, status: &str, message: &str) -> Result<()> {
+    client
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                message.len(),
+                message
+            )
+            .as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
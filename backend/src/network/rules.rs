@@ -0,0 +1,204 @@
+use super::connector::TargetAddr;
+use super::i2p;
+use crate::ipc::{default_sam_address, NetworkRoute, RouteHop, RoutingRule};
+use crate::storage;
+use anyhow::Result;
+use ipnet::IpNet;
+use parking_lot::RwLock;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+/// Destinations a rule applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    /// A domain and its subdomains, from `example.com`
+    Domain(String),
+    /// Only names below a suffix, from `*.example.com` or a TLD like `.onion`
+    Suffix(String),
+    /// IP destinations inside a network, from `10.0.0.0/8` or a bare address
+    Net(IpNet),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        let pattern = pattern.trim().trim_end_matches('.').to_ascii_lowercase();
+        if let Ok(net) = pattern.parse::<IpNet>() {
+            return Ok(Pattern::Net(net.trunc()));
+        }
+        if let Ok(ip) = pattern.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            return Ok(Pattern::Net(IpNet::from(ip)));
+        }
+
+        let (suffix, name) = match pattern.strip_prefix("*.").or_else(|| pattern.strip_prefix('.')) {
+            Some(name) => (true, name),
+            None => (false, pattern.as_str()),
+        };
+        let valid = !name.is_empty()
+            && name
+                .split('.')
+                .all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        if !valid {
+            return Err(anyhow::anyhow!("Invalid routing rule pattern: {}", pattern));
+        }
+        Ok(match suffix {
+            true => Pattern::Suffix(name.to_string()),
+            false => Pattern::Domain(name.to_string()),
+        })
+    }
+
+    fn matches(&self, host: &str, ip: Option<IpAddr>) -> bool {
+        match (self, ip) {
+            (Pattern::Net(net), Some(ip)) => net.contains(&ip),
+            (Pattern::Net(_), None) | (_, Some(_)) => false,
+            (Pattern::Domain(domain), None) => crate::sites::host_matches(host, domain),
+            (Pattern::Suffix(suffix), None) => {
+                host.len() > suffix.len() + 1
+                    && host.ends_with(suffix.as_str())
+                    && host.as_bytes()[host.len() - suffix.len() - 1] == b'.'
+            }
+        }
+    }
+
+    /// The overlay network every name the pattern matches belongs to
+    fn overlay(&self) -> Option<Overlay> {
+        match self {
+            Pattern::Domain(name) | Pattern::Suffix(name) => Overlay::of(name),
+            Pattern::Net(_) => None,
+        }
+    }
+}
+
+/// Networks whose names mean nothing outside them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overlay {
+    Onion,
+    I2p,
+}
+
+impl Overlay {
+    fn of(host: &str) -> Option<Self> {
+        if host == "onion" || host.ends_with(".onion") {
+            Some(Overlay::Onion)
+        } else if host == "i2p" || i2p::is_i2p(host) {
+            Some(Overlay::I2p)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Overlay::Onion => "Tor",
+            Overlay::I2p => "I2P",
+        }
+    }
+
+    fn reached_by(self, route: &NetworkRoute) -> bool {
+        match self {
+            Overlay::Onion => route.hops().last() == Some(&RouteHop::Tor),
+            Overlay::I2p => matches!(route, NetworkRoute::I2p { .. }),
+        }
+    }
+}
+
+/// Rules sending destinations over another route than the tab's, e.g.
+/// onion services through Tor, `.i2p` sites through I2P and intranet hosts directly.
+///
+/// Rules are checked in order and the first match wins; CIDR rules match
+/// destinations given as IP addresses, since names are resolved by the route.
+pub struct RoutingRules {
+    rules: RwLock<Vec<(Pattern, RoutingRule)>>,
+    path: PathBuf,
+}
+
+impl RoutingRules {
+    pub fn new(path: PathBuf) -> Self {
+        let rules = compile(&default_rules()).expect("default routing rules are valid");
+        Self {
+            rules: RwLock::new(rules),
+            path,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        if let Some(rules) = storage::load_json::<Vec<RoutingRule>>(&self.path).await? {
+            *self.rules.write() = compile(&rules)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the rules, rejecting the whole set if any rule is invalid
+    pub async fn set(&self, rules: Vec<RoutingRule>) -> Result<()> {
+        *self.rules.write() = compile(&rules)?;
+        storage::save_json(&self.path, &rules).await
+    }
+
+    pub fn rules(&self) -> Vec<RoutingRule> {
+        self.rules.read().iter().map(|(_, rule)| rule.clone()).collect()
+    }
+
+    /// Pick the route for a destination: the first matching rule, otherwise
+    /// the tab's route. Onion services are refused unless the route ends in
+    /// Tor, and `.i2p` sites unless it is I2P.
+    pub fn route_for(&self, target: &TargetAddr, tab_route: &NetworkRoute) -> Result<NetworkRoute> {
+        let host = target.host().trim_end_matches('.').to_ascii_lowercase();
+        let ip = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok();
+
+        // A tab whose route already reaches the overlay, e.g. Onion-over-VPN, keeps it
+        let overlay = Overlay::of(&host);
+        if overlay.is_some_and(|overlay| overlay.reached_by(tab_route)) {
+            return Ok(tab_route.clone());
+        }
+
+        let route = self
+            .rules
+            .read()
+            .iter()
+            .find(|(pattern, _)| pattern.matches(&host, ip))
+            .map(|(_, rule)| rule.route.clone())
+            .unwrap_or_else(|| tab_route.clone());
+
+        // Never hand an onion or I2P name to a resolver or a route that cannot reach it
+        if let Some(overlay) = overlay.filter(|overlay| !overlay.reached_by(&route)) {
+            return Err(anyhow::anyhow!(
+                "{} can only be reached through {}, not {}",
+                host,
+                overlay.name(),
+                super::describe_path(&route)
+            ));
+        }
+        Ok(route)
+    }
+}
+
+/// Onion services go through Tor and `.i2p` sites through the local I2P
+/// router unless the user removes the rules
+pub fn default_rules() -> Vec<RoutingRule> {
+    vec![
+        RoutingRule {
+            pattern: String::from(".onion"),
+            route: NetworkRoute::Tor,
+        },
+        RoutingRule {
+            pattern: String::from(".i2p"),
+            route: NetworkRoute::I2p {
+                sam: default_sam_address(),
+            },
+        },
+    ]
+}
+
+fn compile(rules: &[RoutingRule]) -> Result<Vec<(Pattern, RoutingRule)>> {
+    rules
+        .iter()
+        .map(|rule| {
+            let pattern = Pattern::parse(&rule.pattern)?;
+            super::validate_route(&rule.route)
+                .map_err(|e| anyhow::anyhow!("Invalid route for {}: {}", rule.pattern, e))?;
+            if let Some(overlay) = pattern.overlay().filter(|overlay| !overlay.reached_by(&rule.route)) {
+                return Err(anyhow::anyhow!("{} can only be routed through {}", rule.pattern, overlay.name()));
+            }
+            Ok((pattern, rule.clone()))
+        })
+        .collect()
+}
@@ -0,0 +1,163 @@
+use super::connector::{BoxedStream, Connector, DirectConnector, TargetAddr};
+use super::dns::SystemResolver;
+use super::proxy::{ConnectorSlot, LocalProxy};
+use super::upstream::ProxyConnector;
+use crate::ipc::{ProxyProtocol, ProxyServer};
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tracing::{debug, info, warn};
+
+const BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(120);
+const SOCKS_READY: &str = "Opened Socks listener connection (ready) on ";
+const BOOTSTRAPPED: &str = "Bootstrapped 100%";
+
+/// A `tor` daemon owned by the browser, exposing a SOCKS port on loopback.
+///
+/// When the route has hops before Tor, the daemon is pointed at a local
+/// SOCKS endpoint for those hops, so its connections to the Tor network
+/// go through them (e.g. Onion-over-VPN).
+pub struct TorProcess {
+    /// Killed when dropped
+    child: Mutex<Child>,
+    socks: SocketAddr,
+    /// Hops before Tor, and the endpoint the daemon reaches them through
+    upstream: Option<(Arc<dyn Connector>, LocalProxy)>,
+}
+
+impl TorProcess {
+    pub async fn start(data_dir: PathBuf, upstream: Option<Arc<dyn Connector>>) -> Result<Self> {
+        let binary = which::which("tor").map_err(|_| anyhow::anyhow!("Tor is not installed: no tor binary in PATH"))?;
+        tokio::fs::create_dir_all(&data_dir)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to create Tor data directory {}: {}", data_dir.display(), e))?;
+
+        let upstream = match upstream {
+            Some(connector) => {
+                let slot: ConnectorSlot = Arc::new(parking_lot::RwLock::new(connector.clone()));
+                Some((connector, LocalProxy::start(SocketAddr::from(([127, 0, 0, 1], 0)), slot).await?))
+            }
+            None => None,
+        };
+
+        let mut command = Command::new(binary);
+        command
+            .arg("--ignore-missing-torrc")
+            .arg("-f")
+            .arg(data_dir.join("torrc"))
+            .arg("--DataDirectory")
+            .arg(&data_dir)
+            .args(["--SocksPort", "127.0.0.1:auto", "--ClientOnly", "1", "--Log", "notice stdout"])
+            // Exit together with the browser, even if it is killed
+            .arg("--__OwningControllerProcess")
+            .arg(std::process::id().to_string());
+        if let Some((_, proxy)) = &upstream {
+            command.arg("--Socks5Proxy").arg(proxy.addr().to_string());
+        }
+        command.stdout(Stdio::piped()).stderr(Stdio::null()).kill_on_drop(true);
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start tor: {}", e))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Failed to read tor output"))?;
+        let mut lines = BufReader::new(stdout).lines();
+
+        // Wait for the SOCKS port and a complete bootstrap
+        let bootstrap = async {
+            let mut socks = None;
+            while let Some(line) = lines.next_line().await? {
+                debug!("tor: {}", line);
+                if let Some(addr) = line.split(SOCKS_READY).nth(1) {
+                    socks = addr.trim().parse::<SocketAddr>().ok();
+                }
+                if line.contains(BOOTSTRAPPED) {
+                    return socks.ok_or_else(|| anyhow::anyhow!("tor did not report its SOCKS port"));
+                }
+            }
+            Err(anyhow::anyhow!("tor exited while bootstrapping"))
+        };
+        let socks = tokio::time::timeout(BOOTSTRAP_TIMEOUT, bootstrap)
+            .await
+            .map_err(|_| anyhow::anyhow!("Tor did not bootstrap within {}s", BOOTSTRAP_TIMEOUT.as_secs()))??;
+        info!("Tor bootstrapped, SOCKS port {}", socks);
+
+        // Keep draining the log so the daemon never blocks on a full pipe
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.contains("[warn]") || line.contains("[err]") {
+                    warn!("tor: {}", line);
+                } else {
+                    debug!("tor: {}", line);
+                }
+            }
+        });
+
+        Ok(Self {
+            child: Mutex::new(child),
+            socks,
+            upstream,
+        })
+    }
+
+    pub fn socks_addr(&self) -> SocketAddr {
+        self.socks
+    }
+
+    /// Fail when the daemon exited or the hops before it are down
+    pub async fn check(&self) -> Result<()> {
+        if let Ok(Some(status)) = self.child.lock().try_wait() {
+            return Err(anyhow::anyhow!("tor exited: {}", status));
+        }
+        if let Some((connector, _)) = &self.upstream {
+            connector.check().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Connects through a Tor daemon's SOCKS port; names are resolved by the exit
+pub struct TorConnector {
+    process: TorProcess,
+    socks: ProxyConnector,
+}
+
+impl TorConnector {
+    pub fn new(process: TorProcess) -> Result<Self> {
+        let server = ProxyServer {
+            protocol: ProxyProtocol::Socks5,
+            address: process.socks_addr().to_string(),
+            username: None,
+            password: None,
+        };
+        // The daemon listens on loopback, so its port is reached directly
+        let direct: Arc<dyn Connector> = Arc::new(DirectConnector::new(Arc::new(SystemResolver::new())));
+        Ok(Self {
+            process,
+            socks: ProxyConnector::new(server, direct)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Connector for TorConnector {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream> {
+        self.socks
+            .connect(target)
+            .await
+            .map_err(|e| anyhow::anyhow!("Tor connection to {} failed (tor at {}): {}", target, self.process.socks, e))
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.process.check().await
+    }
+}
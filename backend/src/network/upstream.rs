@@ -0,0 +1,202 @@
+use super::connector::{BoxedStream, Connector, TargetAddr};
+use super::proxy::{
+    ATYP_DOMAIN, ATYP_IPV4, ATYP_IPV6, CMD_CONNECT, NO_ACCEPTABLE_METHOD, NO_AUTH, REPLY_SUCCEEDED, SOCKS_VERSION,
+};
+use crate::ipc::{ProxyProtocol, ProxyServer};
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::Engine as _;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const USERNAME_PASSWORD: u8 = 0x02;
+const AUTH_VERSION: u8 = 0x01;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest CONNECT response header accepted from an HTTP proxy
+const MAX_RESPONSE_HEADER: usize = 16 * 1024;
+
+/// Connects through an upstream SOCKS5 or HTTP proxy, which is itself
+/// reached through the previous hop of the route.
+///
+/// Host names are handed to the proxy unresolved so lookups happen at the
+/// far end, not on this machine.
+pub struct ProxyConnector {
+    server: ProxyServer,
+    target: TargetAddr,
+    upstream: Arc<dyn Connector>,
+}
+
+impl ProxyConnector {
+    pub fn new(server: ProxyServer, upstream: Arc<dyn Connector>) -> Result<Self> {
+        let target = parse_address(&server.address)?;
+        if let Some(username) = &server.username {
+            if username.len() > 255 || server.password.as_deref().unwrap_or_default().len() > 255 {
+                return Err(anyhow::anyhow!("Proxy credentials for {} are too long", server.address));
+            }
+        }
+        Ok(Self { server, target, upstream })
+    }
+}
+
+#[async_trait]
+impl Connector for ProxyConnector {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream> {
+        let mut stream = self.upstream.connect(&self.target).await?;
+
+        let handshake = async {
+            match self.server.protocol {
+                ProxyProtocol::Socks5 => socks5_connect(&mut stream, &self.server, target).await,
+                ProxyProtocol::Http => http_connect(&mut stream, &self.server, target).await,
+            }
+        };
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .map_err(|_| anyhow::anyhow!("Proxy {} timed out", self.server.address))?
+            .map_err(|e| anyhow::anyhow!("Proxy {} could not reach {}: {}", self.server.address, target, e))?;
+        Ok(stream)
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.upstream.check().await?;
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, self.upstream.connect(&self.target))
+            .await
+            .map_err(|_| anyhow::anyhow!("Proxy {} timed out", self.server.address))?
+            .map_err(|e| anyhow::anyhow!("Proxy {} is unreachable: {}", self.server.address, e))?;
+        Ok(())
+    }
+}
+
+/// Parse a proxy's `host:port`, accepting bracketed IPv6 addresses
+pub fn parse_address(address: &str) -> Result<TargetAddr> {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return Ok(TargetAddr::Ip(addr));
+    }
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Proxy address {} has no port", address))?;
+    let port = port
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Proxy address {} has an invalid port", address))?;
+    if host.is_empty() || host.contains(':') || host.len() > 255 {
+        return Err(anyhow::anyhow!("Proxy address {} has an invalid host", address));
+    }
+    Ok(TargetAddr::Domain(host.to_string(), port))
+}
+
+/// SOCKS5 CONNECT as a client, with optional username/password auth (RFC 1928, RFC 1929)
+async fn socks5_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    server: &ProxyServer,
+    target: &TargetAddr,
+) -> Result<()> {
+    // Offer credentials only when we have some
+    match &server.username {
+        Some(_) => stream.write_all(&[SOCKS_VERSION, 2, NO_AUTH, USERNAME_PASSWORD]).await?,
+        None => stream.write_all(&[SOCKS_VERSION, 1, NO_AUTH]).await?,
+    }
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != SOCKS_VERSION {
+        return Err(anyhow::anyhow!("Unsupported SOCKS version {}", choice[0]));
+    }
+
+    match (choice[1], &server.username) {
+        (NO_AUTH, _) => {}
+        (USERNAME_PASSWORD, Some(username)) => {
+            let password = server.password.as_deref().unwrap_or_default();
+            let mut request = vec![AUTH_VERSION, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                return Err(anyhow::anyhow!("Proxy rejected the credentials"));
+            }
+        }
+        (NO_ACCEPTABLE_METHOD, _) => return Err(anyhow::anyhow!("Proxy requires authentication")),
+        (other, _) => return Err(anyhow::anyhow!("Proxy chose unsupported method {}", other)),
+    }
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match target {
+        TargetAddr::Ip(SocketAddr::V4(addr)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        TargetAddr::Ip(SocketAddr::V6(addr)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        TargetAddr::Domain(host, _) => {
+            if host.len() > 255 {
+                return Err(anyhow::anyhow!("Host name too long"));
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(anyhow::anyhow!("SOCKS error {}", reply[1]));
+    }
+
+    // Skip the bound address, it is of no use to us
+    let length = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        other => return Err(anyhow::anyhow!("Unsupported SOCKS address type {}", other)),
+    };
+    let mut bound = vec![0u8; length + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// HTTP CONNECT tunnel, with optional basic auth
+async fn http_connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    server: &ProxyServer,
+    target: &TargetAddr,
+) -> Result<()> {
+    let authority = match target {
+        TargetAddr::Ip(addr) => addr.to_string(),
+        TargetAddr::Domain(host, port) => format!("{}:{}", host, port),
+    };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some(username) = &server.username {
+        let credentials = format!("{}:{}", username, server.password.as_deref().unwrap_or_default());
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read byte by byte so nothing past the header is taken from the tunnel
+    let mut header = Vec::new();
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() >= MAX_RESPONSE_HEADER {
+            return Err(anyhow::anyhow!("Proxy response header too long"));
+        }
+        header.push(stream.read_u8().await?);
+    }
+
+    let status_line = String::from_utf8_lossy(&header);
+    let status_line = status_line.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        Some("407") => Err(anyhow::anyhow!("Proxy requires authentication")),
+        _ => Err(anyhow::anyhow!("Proxy answered {}", status_line)),
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::Result;
+use base64::Engine as _;
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::path::Path;
+
+const DEFAULT_MTU: u16 = 1420;
+
+/// A WireGuard configuration in the `wg-quick` file format
+#[derive(Debug, Clone)]
+pub struct WireGuardConfig {
+    pub private_key: [u8; 32],
+    /// Tunnel addresses of this end
+    pub addresses: Vec<IpNet>,
+    /// Resolvers reached through the tunnel; search domains are ignored
+    pub dns: Vec<IpAddr>,
+    pub listen_port: Option<u16>,
+    pub mtu: u16,
+    pub peers: Vec<PeerConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerConfig {
+    pub public_key: [u8; 32],
+    pub preshared_key: Option<[u8; 32]>,
+    /// Destinations routed to this peer, and sources accepted from it
+    pub allowed_ips: Vec<IpNet>,
+    /// `host:port`, resolved when the tunnel comes up
+    pub endpoint: Option<String>,
+    pub persistent_keepalive: Option<u16>,
+}
+
+enum Section {
+    None,
+    Interface,
+    Peer,
+}
+
+impl WireGuardConfig {
+    pub async fn load(path: &Path) -> Result<Self> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read WireGuard config {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| anyhow::anyhow!("Invalid WireGuard config {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut section = Section::None;
+        let mut private_key = None;
+        let mut addresses = Vec::new();
+        let mut dns = Vec::new();
+        let mut listen_port = None;
+        let mut mtu = DEFAULT_MTU;
+        let mut peers: Vec<PeerConfig> = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                section = match line.to_ascii_lowercase().as_str() {
+                    "[interface]" => Section::Interface,
+                    "[peer]" => {
+                        peers.push(PeerConfig {
+                            public_key: [0; 32],
+                            preshared_key: None,
+                            allowed_ips: Vec::new(),
+                            endpoint: None,
+                            persistent_keepalive: None,
+                        });
+                        Section::Peer
+                    }
+                    _ => return Err(anyhow::anyhow!("line {}: unknown section {}", number + 1, line)),
+                };
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("line {}: expected key = value", number + 1))?;
+            let (key, value) = (key.trim().to_ascii_lowercase(), value.trim());
+            let invalid = |e: String| anyhow::anyhow!("line {}: invalid {}: {}", number + 1, key, e);
+
+            match (&section, key.as_str()) {
+                (Section::Interface, "privatekey") => private_key = Some(parse_key(value).map_err(invalid)?),
+                (Section::Interface, "address") => {
+                    for address in split_list(value) {
+                        addresses.push(parse_net(address).map_err(invalid)?);
+                    }
+                }
+                (Section::Interface, "dns") => {
+                    dns.extend(split_list(value).filter_map(|entry| entry.parse::<IpAddr>().ok()));
+                }
+                (Section::Interface, "listenport") => {
+                    listen_port = Some(value.parse().map_err(|e| invalid(format!("{}", e)))?)
+                }
+                (Section::Interface, "mtu") => mtu = value.parse().map_err(|e| invalid(format!("{}", e)))?,
+                // wg-quick only settings that have no meaning for a userspace tunnel
+                (Section::Interface, "table" | "preup" | "postup" | "predown" | "postdown" | "saveconfig") => {}
+                (Section::Peer, _) => {
+                    let peer = peers.last_mut().expect("peer section has a peer");
+                    match key.as_str() {
+                        "publickey" => peer.public_key = parse_key(value).map_err(invalid)?,
+                        "presharedkey" => peer.preshared_key = Some(parse_key(value).map_err(invalid)?),
+                        "allowedips" => {
+                            for net in split_list(value) {
+                                peer.allowed_ips.push(parse_net(net).map_err(invalid)?);
+                            }
+                        }
+                        "endpoint" => peer.endpoint = Some(value.to_string()),
+                        "persistentkeepalive" => {
+                            peer.persistent_keepalive = match value {
+                                "off" => None,
+                                _ => Some(value.parse().map_err(|e| invalid(format!("{}", e)))?),
+                            }
+                        }
+                        _ => return Err(anyhow::anyhow!("line {}: unknown peer setting {}", number + 1, key)),
+                    }
+                }
+                _ => return Err(anyhow::anyhow!("line {}: unexpected setting {}", number + 1, key)),
+            }
+        }
+
+        let private_key = private_key.ok_or_else(|| anyhow::anyhow!("missing Interface PrivateKey"))?;
+        if addresses.is_empty() {
+            return Err(anyhow::anyhow!("missing Interface Address"));
+        }
+        if peers.is_empty() {
+            return Err(anyhow::anyhow!("no Peer sections"));
+        }
+        if let Some(peer) = peers.iter().find(|peer| peer.public_key == [0; 32]) {
+            return Err(anyhow::anyhow!("peer {:?} has no PublicKey", peer.endpoint));
+        }
+
+        Ok(Self {
+            private_key,
+            addresses,
+            dns,
+            listen_port,
+            mtu,
+            peers,
+        })
+    }
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty())
+}
+
+fn parse_key(value: &str) -> std::result::Result<[u8; 32], String> {
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| e.to_string())?;
+    bytes.try_into().map_err(|_| String::from("key is not 32 bytes"))
+}
+
+/// Parse `10.0.0.2/32`, treating a bare address as a host route
+fn parse_net(value: &str) -> std::result::Result<IpNet, String> {
+    if let Ok(net) = value.parse::<IpNet>() {
+        return Ok(net);
+    }
+    value
+        .parse::<IpAddr>()
+        .map(IpNet::from)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE_KEY: &str = "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+    const PUBLIC_KEY: &str = "AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=";
+
+    #[test]
+    fn parses_wg_quick_file() {
+        let text = format!(
+            "[Interface]\n\
+             # comment\n\
+             PrivateKey = {PRIVATE_KEY}\n\
+             Address = 10.0.0.2/32, fd00::2\n\
+             DNS = 10.0.0.1, corp.example\n\
+             MTU = 1380\n\
+             PostUp = iptables -A FORWARD\n\
+             \n\
+             [Peer]\n\
+             PublicKey = {PUBLIC_KEY}\n\
+             PresharedKey = {PRIVATE_KEY}\n\
+             AllowedIPs = 0.0.0.0/0, ::/0\n\
+             Endpoint = vpn.example:51820 # inline comment\n\
+             PersistentKeepalive = 25\n"
+        );
+        let config = WireGuardConfig::parse(&text).unwrap();
+        assert_eq!(config.private_key, [1; 32]);
+        assert_eq!(
+            config.addresses,
+            vec!["10.0.0.2/32".parse::<IpNet>().unwrap(), "fd00::2/128".parse().unwrap()]
+        );
+        // Search domains are ignored
+        assert_eq!(config.dns, vec!["10.0.0.1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(config.mtu, 1380);
+        assert_eq!(config.listen_port, None);
+
+        let peer = &config.peers[0];
+        assert_eq!(peer.public_key, [2; 32]);
+        assert_eq!(peer.preshared_key, Some([1; 32]));
+        assert_eq!(peer.allowed_ips.len(), 2);
+        assert_eq!(peer.endpoint.as_deref(), Some("vpn.example:51820"));
+        assert_eq!(peer.persistent_keepalive, Some(25));
+    }
+
+    #[test]
+    fn rejects_incomplete_or_unknown_settings() {
+        let interface = format!("[Interface]\nPrivateKey = {PRIVATE_KEY}\nAddress = 10.0.0.2\n");
+        let peer = format!("[Peer]\nPublicKey = {PUBLIC_KEY}\n");
+        assert!(WireGuardConfig::parse(&format!("{interface}{peer}")).is_ok());
+
+        for text in [
+            interface.clone(),
+            peer.clone(),
+            format!("{interface}[Peer]\nAllowedIPs = 0.0.0.0/0\n"),
+            format!("{interface}{peer}Bogus = 1\n"),
+            format!("{interface}{peer}[Wat]\n"),
+            format!("[Interface]\nPrivateKey = c2hvcnQ=\nAddress = 10.0.0.2\n{peer}"),
+            format!("{interface}MTU = big\n{peer}"),
+        ] {
+            assert!(WireGuardConfig::parse(&text).is_err(), "{text}");
+        }
+    }
+}
@@ -0,0 +1,535 @@
+use super::config::WireGuardConfig;
+use super::noise::{self, Identity, InitiatorState, PeerKeys, Session};
+use ipnet::IpNet;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+// Timers from the WireGuard whitepaper section 6
+const REKEY_AFTER_TIME: Duration = Duration::from_secs(120);
+const REJECT_AFTER_TIME: Duration = Duration::from_secs(180);
+const REKEY_ATTEMPT_TIME: Duration = Duration::from_secs(90);
+const REKEY_TIMEOUT: Duration = Duration::from_secs(5);
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Packets held per peer while a handshake is in progress
+const MAX_QUEUED: usize = 1024;
+
+/// An encrypted message to send to a peer's endpoint
+pub struct Datagram {
+    pub to: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+struct Handshake {
+    state: InitiatorState,
+    started: Instant,
+    last_sent: Instant,
+}
+
+struct Peer {
+    keys: PeerKeys,
+    allowed_ips: Vec<IpNet>,
+    endpoint: Option<SocketAddr>,
+    persistent_keepalive: Option<Duration>,
+    handshake: Option<Handshake>,
+    current: Option<Session>,
+    previous: Option<Session>,
+    /// Session we answered as responder, used once the initiator sends on it
+    next: Option<Session>,
+    queued: VecDeque<Vec<u8>>,
+    last_sent: Instant,
+    /// Set when data arrived that we have not answered yet
+    unanswered_since: Option<Instant>,
+    /// Set when we sent data the peer has not answered yet
+    unreplied_since: Option<Instant>,
+    latest_timestamp: [u8; 12],
+}
+
+/// The WireGuard side of a tunnel: turns IP packets into encrypted
+/// datagrams and back, without doing any I/O itself
+pub struct Device {
+    identity: Identity,
+    peers: Vec<Peer>,
+}
+
+impl Device {
+    pub fn new(config: &WireGuardConfig, endpoints: Vec<Option<SocketAddr>>) -> Self {
+        let peers = config
+            .peers
+            .iter()
+            .zip(endpoints)
+            .map(|(peer, endpoint)| Peer {
+                keys: PeerKeys::new(peer.public_key, peer.preshared_key),
+                allowed_ips: peer.allowed_ips.clone(),
+                endpoint,
+                persistent_keepalive: peer
+                    .persistent_keepalive
+                    .filter(|seconds| *seconds > 0)
+                    .map(|seconds| Duration::from_secs(seconds as u64)),
+                handshake: None,
+                current: None,
+                previous: None,
+                next: None,
+                queued: VecDeque::new(),
+                last_sent: Instant::now(),
+                unanswered_since: None,
+                unreplied_since: None,
+                latest_timestamp: [0; 12],
+            })
+            .collect();
+
+        Self {
+            identity: Identity::new(config.private_key),
+            peers,
+        }
+    }
+
+    /// Whether any peer has a session that can carry traffic
+    pub fn is_up(&self) -> bool {
+        self.peers.iter().any(|peer| peer.usable_session().is_some())
+    }
+
+    /// Whether any peer has a session and still answers what we send it
+    pub fn is_healthy(&self) -> bool {
+        self.peers
+            .iter()
+            .any(|peer| peer.usable_session().is_some() && !peer.is_silent())
+    }
+
+    /// Start handshakes with every peer whose endpoint is known
+    pub fn connect(&mut self, out: &mut Vec<Datagram>) {
+        for peer in &mut self.peers {
+            if peer.endpoint.is_some() {
+                peer.initiate(&self.identity, out);
+            }
+        }
+    }
+
+    /// Encrypt an IP packet from the stack for the peer its destination is routed to
+    pub fn send(&mut self, packet: Vec<u8>, out: &mut Vec<Datagram>) {
+        let Some(destination) = address(&packet, Field::Destination) else {
+            return;
+        };
+        let Some(peer) = route(&mut self.peers, destination) else {
+            debug!("No WireGuard peer for {}", destination);
+            return;
+        };
+
+        if let Some(session) = peer.usable_session() {
+            let rekey = session.initiator && session.created.elapsed() >= REKEY_AFTER_TIME;
+            peer.transmit(&packet, out);
+            if rekey && peer.handshake.is_none() {
+                peer.initiate(&self.identity, out);
+            }
+            return;
+        }
+
+        if peer.queued.len() >= MAX_QUEUED {
+            peer.queued.pop_front();
+        }
+        peer.queued.push_back(packet);
+        if peer.handshake.is_none() {
+            peer.initiate(&self.identity, out);
+        }
+    }
+
+    /// Handle a datagram from the network, returning decrypted IP packets in `packets`
+    pub fn receive(&mut self, message: &[u8], from: SocketAddr, out: &mut Vec<Datagram>, packets: &mut Vec<Vec<u8>>) {
+        match message.first() {
+            Some(&noise::HANDSHAKE_INITIATION) => self.receive_initiation(message, from, out),
+            Some(&noise::HANDSHAKE_RESPONSE) => self.receive_response(message, from, out),
+            // Cookies are only sent by peers under load; the initiation is retried instead
+            Some(&noise::COOKIE_REPLY) => debug!("Ignoring WireGuard cookie reply from {}", from),
+            Some(&noise::TRANSPORT_DATA) => self.receive_data(message, from, out, packets),
+            _ => debug!("Ignoring unknown datagram from {}", from),
+        }
+    }
+
+    fn receive_initiation(&mut self, message: &[u8], from: SocketAddr, out: &mut Vec<Datagram>) {
+        let received = match noise::consume_initiation(&self.identity, message) {
+            Ok(received) => received,
+            Err(e) => return debug!("Rejected handshake initiation from {}: {}", from, e),
+        };
+        let Some(peer) = self
+            .peers
+            .iter_mut()
+            .find(|peer| *peer.keys.public.as_bytes() == received.remote_static)
+        else {
+            return debug!("Handshake initiation from unknown peer at {}", from);
+        };
+        // Timestamps compare as big-endian byte strings
+        if received.timestamp <= peer.latest_timestamp {
+            return debug!("Replayed handshake initiation from {}", from);
+        }
+        peer.latest_timestamp = received.timestamp;
+        peer.unreplied_since = None;
+
+        let (session, response) = noise::create_response(received, &peer.keys, noise::new_index());
+        peer.next = Some(session);
+        peer.endpoint = Some(from);
+        peer.last_sent = Instant::now();
+        out.push(Datagram {
+            to: from,
+            payload: response,
+        });
+    }
+
+    fn receive_response(&mut self, message: &[u8], from: SocketAddr, out: &mut Vec<Datagram>) {
+        let Some(index) = noise::read_index(message, 8) else {
+            return;
+        };
+        let Some(peer) = self
+            .peers
+            .iter_mut()
+            .find(|peer| peer.handshake.as_ref().map(|h| h.state.local_index) == Some(index))
+        else {
+            return debug!("Handshake response for unknown index from {}", from);
+        };
+        let handshake = peer.handshake.as_ref().expect("peer was found by its handshake");
+        let session = match noise::consume_response(&handshake.state, &self.identity, &peer.keys, message) {
+            Ok(session) => session,
+            Err(e) => return debug!("Rejected handshake response from {}: {}", from, e),
+        };
+
+        info!("WireGuard handshake with {} complete", from);
+        peer.handshake = None;
+        peer.endpoint = Some(from);
+        peer.unreplied_since = None;
+        peer.previous = peer.current.replace(session);
+        peer.next = None;
+
+        // Send what waited for the handshake, or a keepalive to confirm the session
+        if peer.queued.is_empty() {
+            peer.transmit(&[], out);
+        }
+        while let Some(packet) = peer.queued.pop_front() {
+            peer.transmit(&packet, out);
+        }
+    }
+
+    fn receive_data(&mut self, message: &[u8], from: SocketAddr, out: &mut Vec<Datagram>, packets: &mut Vec<Vec<u8>>) {
+        let Some(index) = noise::read_index(message, 4) else {
+            return;
+        };
+        let Some(peer) = self.peers.iter_mut().find(|peer| peer.has_session(index)) else {
+            return debug!("Transport message for unknown index from {}", from);
+        };
+
+        let confirms = peer.next.as_ref().map(|session| session.local_index) == Some(index);
+        let session = [&mut peer.current, &mut peer.previous, &mut peer.next]
+            .into_iter()
+            .flatten()
+            .find(|session| session.local_index == index)
+            .expect("peer was found by its session");
+        if session.created.elapsed() >= REJECT_AFTER_TIME {
+            return;
+        }
+        let mut packet = match session.decrypt(message) {
+            Ok(packet) => packet,
+            Err(e) => return debug!("Dropped transport message from {}: {}", from, e),
+        };
+
+        // The initiator sending on the session we answered confirms it, once
+        // its message proved to be genuine
+        if confirms {
+            peer.previous = peer.current.take();
+            peer.current = peer.next.take();
+            peer.handshake = None;
+            while let Some(queued) = peer.queued.pop_front() {
+                peer.transmit(&queued, out);
+            }
+        }
+        peer.endpoint = Some(from);
+        peer.unreplied_since = None;
+
+        // An empty packet is a keepalive
+        if packet.is_empty() {
+            return;
+        }
+        peer.unanswered_since.get_or_insert_with(Instant::now);
+
+        // Cryptokey routing: the peer may only send from its allowed IPs
+        let Some(length) = packet_length(&packet).filter(|length| *length <= packet.len()) else {
+            return debug!("Dropped malformed packet from {}", from);
+        };
+        packet.truncate(length);
+        match address(&packet, Field::Source) {
+            Some(source) if peer.allowed_ips.iter().any(|net| net.contains(&source)) => packets.push(packet),
+            source => debug!("Dropped packet from {:?} not allowed for peer {}", source, from),
+        }
+    }
+
+    /// Run timers: retransmit handshakes, expire sessions and send keepalives
+    pub fn tick(&mut self, out: &mut Vec<Datagram>) {
+        for peer in &mut self.peers {
+            if let Some(handshake) = &peer.handshake {
+                if handshake.started.elapsed() >= REKEY_ATTEMPT_TIME {
+                    warn!("WireGuard handshake with {:?} timed out", peer.endpoint);
+                    peer.handshake = None;
+                    peer.queued.clear();
+                } else if handshake.last_sent.elapsed() >= REKEY_TIMEOUT {
+                    let started = handshake.started;
+                    peer.initiate(&self.identity, out);
+                    if let Some(handshake) = &mut peer.handshake {
+                        handshake.started = started;
+                    }
+                }
+            }
+
+            for slot in [&mut peer.current, &mut peer.previous, &mut peer.next] {
+                if slot.as_ref().is_some_and(|session| session.created.elapsed() >= REJECT_AFTER_TIME) {
+                    *slot = None;
+                }
+            }
+
+            // Rekey when the peer stopped answering (it may have restarted), and
+            // keep idle sessions fresh so the tunnel stays ready for traffic
+            let stale = peer
+                .current
+                .as_ref()
+                .is_none_or(|session| session.created.elapsed() >= REKEY_AFTER_TIME);
+            if peer.endpoint.is_some() && peer.handshake.is_none() && (stale || peer.is_silent()) {
+                peer.initiate(&self.identity, out);
+            }
+
+            if let Some(interval) = peer.persistent_keepalive {
+                if peer.last_sent.elapsed() >= interval {
+                    if peer.usable_session().is_some() {
+                        peer.transmit(&[], out);
+                    } else if peer.handshake.is_none() {
+                        peer.initiate(&self.identity, out);
+                    }
+                }
+            }
+
+            if peer
+                .unanswered_since
+                .is_some_and(|since| since.elapsed() >= KEEPALIVE_TIMEOUT)
+                && peer.usable_session().is_some()
+            {
+                peer.transmit(&[], out);
+            }
+        }
+    }
+}
+
+impl Peer {
+    fn usable_session(&self) -> Option<&Session> {
+        self.current
+            .as_ref()
+            .filter(|session| session.created.elapsed() < REJECT_AFTER_TIME)
+    }
+
+    /// Data went unanswered for longer than the peer's keepalive would take
+    fn is_silent(&self) -> bool {
+        self.unreplied_since
+            .is_some_and(|since| since.elapsed() >= KEEPALIVE_TIMEOUT + REKEY_TIMEOUT)
+    }
+
+    fn has_session(&self, index: u32) -> bool {
+        [&self.current, &self.previous, &self.next]
+            .into_iter()
+            .flatten()
+            .any(|session| session.local_index == index)
+    }
+
+    fn initiate(&mut self, identity: &Identity, out: &mut Vec<Datagram>) {
+        let Some(endpoint) = self.endpoint else {
+            return;
+        };
+        let (state, message) = noise::create_initiation(identity, &self.keys, noise::new_index());
+        let now = Instant::now();
+        self.handshake = Some(Handshake {
+            state,
+            started: now,
+            last_sent: now,
+        });
+        self.last_sent = now;
+        out.push(Datagram {
+            to: endpoint,
+            payload: message,
+        });
+    }
+
+    fn transmit(&mut self, packet: &[u8], out: &mut Vec<Datagram>) {
+        let (Some(endpoint), Some(session)) = (self.endpoint, self.current.as_mut()) else {
+            return;
+        };
+        match session.encrypt(packet) {
+            Ok(payload) => {
+                self.last_sent = Instant::now();
+                self.unanswered_since = None;
+                if !packet.is_empty() {
+                    self.unreplied_since.get_or_insert_with(Instant::now);
+                }
+                out.push(Datagram { to: endpoint, payload });
+            }
+            Err(e) => {
+                warn!("{}", e);
+                self.current = None;
+            }
+        }
+    }
+}
+
+/// Pick the peer whose allowed IPs most specifically contain `destination`
+fn route(peers: &mut [Peer], destination: IpAddr) -> Option<&mut Peer> {
+    peers
+        .iter_mut()
+        .filter_map(|peer| {
+            let prefix = peer
+                .allowed_ips
+                .iter()
+                .filter(|net| net.contains(&destination))
+                .map(IpNet::prefix_len)
+                .max()?;
+            Some((prefix, peer))
+        })
+        .max_by_key(|(prefix, _)| *prefix)
+        .map(|(_, peer)| peer)
+}
+
+enum Field {
+    Source,
+    Destination,
+}
+
+fn address(packet: &[u8], field: Field) -> Option<IpAddr> {
+    match packet.first()? >> 4 {
+        4 => {
+            let offset = match field {
+                Field::Source => 12,
+                Field::Destination => 16,
+            };
+            let octets: [u8; 4] = packet.get(offset..offset + 4)?.try_into().ok()?;
+            Some(Ipv4Addr::from(octets).into())
+        }
+        6 => {
+            let offset = match field {
+                Field::Source => 8,
+                Field::Destination => 24,
+            };
+            let octets: [u8; 16] = packet.get(offset..offset + 16)?.try_into().ok()?;
+            Some(Ipv6Addr::from(octets).into())
+        }
+        _ => None,
+    }
+}
+
+/// Length of an IP packet from its header, so transport padding can be removed
+fn packet_length(packet: &[u8]) -> Option<usize> {
+    match packet.first()? >> 4 {
+        4 => Some(u16::from_be_bytes(packet.get(2..4)?.try_into().ok()?) as usize),
+        6 => Some(40 + u16::from_be_bytes(packet.get(4..6)?.try_into().ok()?) as usize),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::wireguard::config::PeerConfig;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    fn config(private_key: [u8; 32], peer_key: [u8; 32], address: &str, peer_address: &str) -> WireGuardConfig {
+        WireGuardConfig {
+            private_key,
+            addresses: vec![address.parse().unwrap()],
+            dns: Vec::new(),
+            listen_port: None,
+            mtu: 1420,
+            peers: vec![PeerConfig {
+                public_key: PublicKey::from(&StaticSecret::from(peer_key)).to_bytes(),
+                preshared_key: None,
+                allowed_ips: vec![peer_address.parse().unwrap()],
+                endpoint: None,
+                persistent_keepalive: None,
+            }],
+        }
+    }
+
+    fn ipv4_packet(source: [u8; 4], destination: [u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0];
+        packet[2..4].copy_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&source);
+        packet.extend_from_slice(&destination);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// Hand `datagrams` to `device`, returning what it sends back and the packets it decrypted
+    fn deliver(device: &mut Device, from: SocketAddr, datagrams: Vec<Datagram>) -> (Vec<Datagram>, Vec<Vec<u8>>) {
+        let (mut out, mut packets) = (Vec::new(), Vec::new());
+        for datagram in datagrams {
+            device.receive(&datagram.payload, from, &mut out, &mut packets);
+        }
+        (out, packets)
+    }
+
+    #[test]
+    fn loopback_handshake_data_and_rekey() {
+        let a_addr: SocketAddr = "192.0.2.1:51820".parse().unwrap();
+        let b_addr: SocketAddr = "192.0.2.2:51820".parse().unwrap();
+        let mut a = Device::new(&config([1; 32], [2; 32], "10.0.0.1/32", "10.0.0.2/32"), vec![Some(b_addr)]);
+        let mut b = Device::new(&config([2; 32], [1; 32], "10.0.0.2/32", "10.0.0.1/32"), vec![None]);
+
+        // A packet sent before the tunnel is up waits for the handshake
+        let request = ipv4_packet([10, 0, 0, 1], [10, 0, 0, 2], b"request");
+        let mut out = Vec::new();
+        a.send(request.clone(), &mut out);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].to, b_addr);
+        assert!(!a.is_up());
+
+        let (response, _) = deliver(&mut b, a_addr, out);
+        assert!(!b.is_up());
+        let (data, _) = deliver(&mut a, b_addr, response);
+        assert!(a.is_up());
+        let (_, packets) = deliver(&mut b, a_addr, data);
+        assert_eq!(packets, vec![request]);
+        assert!(b.is_up());
+
+        let reply = ipv4_packet([10, 0, 0, 2], [10, 0, 0, 1], b"reply");
+        let mut out = Vec::new();
+        b.send(reply.clone(), &mut out);
+        assert_eq!(out[0].to, a_addr);
+        let (_, packets) = deliver(&mut a, b_addr, out);
+        assert_eq!(packets, vec![reply.clone()]);
+
+        // Packets from addresses not allowed for the peer are dropped
+        let mut out = Vec::new();
+        b.send(ipv4_packet([10, 0, 0, 9], [10, 0, 0, 1], b"spoofed"), &mut out);
+        assert!(deliver(&mut a, b_addr, out).1.is_empty());
+
+        // Rekey
+        let mut initiation = Vec::new();
+        a.connect(&mut initiation);
+        let old_index = b.peers[0].current.as_ref().unwrap().local_index;
+        let (response, _) = deliver(&mut b, a_addr, initiation);
+        let new_index = noise::read_index(&response[0].payload, 4).unwrap();
+        assert_eq!(b.peers[0].next.as_ref().map(|session| session.local_index), Some(new_index));
+
+        // A forged message on the answered session does not make B switch to it
+        let mut forged = vec![noise::TRANSPORT_DATA, 0, 0, 0];
+        forged.extend_from_slice(&new_index.to_le_bytes());
+        forged.extend_from_slice(&[0; 40]);
+        deliver(&mut b, a_addr, vec![Datagram { to: b_addr, payload: forged }]);
+        assert_eq!(b.peers[0].current.as_ref().unwrap().local_index, old_index);
+        let mut out = Vec::new();
+        b.send(reply.clone(), &mut out);
+        assert_eq!(deliver(&mut a, b_addr, out).1, vec![reply.clone()]);
+
+        // The initiator's keepalive on the new session confirms it
+        let (keepalive, _) = deliver(&mut a, b_addr, response);
+        assert_eq!(keepalive.len(), 1);
+        let (_, packets) = deliver(&mut b, a_addr, keepalive);
+        assert!(packets.is_empty());
+        assert_eq!(b.peers[0].current.as_ref().unwrap().local_index, new_index);
+        assert_eq!(b.peers[0].previous.as_ref().unwrap().local_index, old_index);
+
+        let mut out = Vec::new();
+        b.send(reply.clone(), &mut out);
+        assert_eq!(deliver(&mut a, b_addr, out).1, vec![reply]);
+    }
+}
@@ -0,0 +1,402 @@
+pub mod config;
+mod device;
+mod noise;
+mod stack;
+
+use super::connector::{BoxedStream, Connector, TargetAddr};
+use super::dns::{DnsTransport, Resolver};
+use anyhow::Result;
+use async_trait::async_trait;
+pub use config::WireGuardConfig;
+use device::{Datagram, Device};
+use parking_lot::Mutex;
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::tcp;
+use stack::Stack;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::net::UdpSocket;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often WireGuard timers run
+const TICK: Duration = Duration::from_millis(250);
+const PUMP_BUFFER: usize = 64 * 1024;
+
+/// Tunnel state shared by the driver task and the connections
+struct Shared {
+    state: Mutex<State>,
+    socket: UdpSocket,
+    /// Wakes the driver after a connection touched its socket
+    wake: Notify,
+    /// Signalled by the driver after every poll
+    changed: Notify,
+    closed: AtomicBool,
+}
+
+struct State {
+    device: Device,
+    stack: Stack,
+    last_tick: Instant,
+}
+
+/// A userspace WireGuard tunnel.
+///
+/// Peers are reached over an ordinary UDP socket and TCP runs on a userspace
+/// stack, so no TUN device or elevated privileges are needed.
+pub struct WireGuardTunnel {
+    shared: Arc<Shared>,
+    dns: Vec<IpAddr>,
+    /// Whether we dial out to a peer, rather than waiting to be contacted
+    dials: bool,
+    driver: JoinHandle<()>,
+}
+
+impl WireGuardTunnel {
+    /// Bring the tunnel up, waiting for the first handshake to complete
+    pub async fn start(config: WireGuardConfig) -> Result<Self> {
+        let mut endpoints = Vec::with_capacity(config.peers.len());
+        for peer in &config.peers {
+            let endpoint = match &peer.endpoint {
+                Some(endpoint) => Some(
+                    tokio::net::lookup_host(endpoint.as_str())
+                        .await
+                        .map_err(|e| anyhow::anyhow!("Failed to resolve WireGuard endpoint {}: {}", endpoint, e))?
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("No addresses for WireGuard endpoint {}", endpoint))?,
+                ),
+                None => None,
+            };
+            endpoints.push(endpoint);
+        }
+
+        // One socket serves every peer, so it takes the family of the first endpoint
+        let ipv6 = endpoints.iter().flatten().next().is_some_and(SocketAddr::is_ipv6);
+        let port = config.listen_port.unwrap_or(0);
+        let bind = if ipv6 {
+            SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port)
+        } else {
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port)
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        let endpoints = endpoints
+            .into_iter()
+            .map(|endpoint| endpoint.map(|endpoint| match (ipv6, endpoint) {
+                (true, SocketAddr::V4(v4)) => SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port()),
+                _ => endpoint,
+            }))
+            .collect();
+
+        let mut device = Device::new(&config, endpoints);
+        let mut out = Vec::new();
+        device.connect(&mut out);
+        send_datagrams(&socket, out);
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                device,
+                stack: Stack::new(&config.addresses, config.mtu)?,
+                last_tick: Instant::now(),
+            }),
+            socket,
+            wake: Notify::new(),
+            changed: Notify::new(),
+            closed: AtomicBool::new(false),
+        });
+        let driver = tokio::spawn(drive(shared.clone()));
+        let tunnel = Self {
+            shared,
+            dns: config.dns,
+            dials: config.peers.iter().any(|peer| peer.endpoint.is_some()),
+            driver,
+        };
+
+        // A peer without an endpoint has to contact us first, so there is nothing to wait for
+        if tunnel.dials {
+            tokio::time::timeout(HANDSHAKE_TIMEOUT, tunnel.shared.wait_for(|state| state.device.is_up().then_some(())))
+                .await
+                .map_err(|_| anyhow::anyhow!("WireGuard handshake did not complete"))?;
+        }
+        Ok(tunnel)
+    }
+
+    /// Fail when the tunnel cannot carry traffic
+    pub fn check(&self) -> Result<()> {
+        if self.driver.is_finished() {
+            return Err(anyhow::anyhow!("WireGuard tunnel stopped"));
+        }
+        if self.dials && !self.shared.state.lock().device.is_healthy() {
+            return Err(anyhow::anyhow!("WireGuard peer is not responding"));
+        }
+        Ok(())
+    }
+
+    /// Open a TCP connection through the tunnel
+    pub async fn connect(&self, addr: SocketAddr) -> Result<DuplexStream> {
+        let handle = self.shared.state.lock().stack.open(addr)?;
+        self.shared.wake.notify_one();
+
+        let established = tokio::time::timeout(
+            CONNECT_TIMEOUT,
+            self.shared.wait_for(|state| match state.stack.socket(handle).state() {
+                tcp::State::Established => Some(true),
+                tcp::State::Closed => Some(false),
+                _ => None,
+            }),
+        )
+        .await;
+        if established != Ok(true) {
+            self.shared.state.lock().stack.abort(handle);
+            self.shared.wake.notify_one();
+            return Err(match established {
+                Ok(_) => anyhow::anyhow!("Connection to {} refused through tunnel", addr),
+                Err(_) => anyhow::anyhow!("Connection to {} through tunnel timed out", addr),
+            });
+        }
+
+        let (stream, local) = tokio::io::duplex(PUMP_BUFFER);
+        tokio::spawn(pump(self.shared.clone(), handle, local));
+        Ok(stream)
+    }
+}
+
+impl Drop for WireGuardTunnel {
+    fn drop(&mut self) {
+        self.driver.abort();
+        self.shared.closed.store(true, Ordering::Relaxed);
+        self.shared.changed.notify_waiters();
+    }
+}
+
+impl Shared {
+    /// Wait until `check` returns a value, re-checking after every poll
+    async fn wait_for<T>(&self, mut check: impl FnMut(&mut State) -> Option<T>) -> T {
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            if let Some(value) = check(&mut self.state.lock()) {
+                return value;
+            }
+            changed.await;
+        }
+    }
+}
+
+impl State {
+    fn receive(&mut self, message: &[u8], from: SocketAddr, socket: &UdpSocket) {
+        let mut out = Vec::new();
+        let mut packets = Vec::new();
+        self.device.receive(message, from, &mut out, &mut packets);
+        self.stack.queues.inbound.extend(packets);
+        send_datagrams(socket, out);
+    }
+
+    fn poll(&mut self, socket: &UdpSocket) -> Duration {
+        let delay = self.stack.poll();
+
+        let mut out = Vec::new();
+        while let Some(packet) = self.stack.queues.outbound.pop_front() {
+            self.device.send(packet, &mut out);
+        }
+        if self.last_tick.elapsed() >= TICK {
+            self.device.tick(&mut out);
+            self.last_tick = Instant::now();
+        }
+        send_datagrams(socket, out);
+
+        delay.map_or(TICK, |delay| delay.min(TICK))
+    }
+}
+
+/// UDP is lossy anyway, so datagrams that do not fit the send buffer are dropped
+fn send_datagrams(socket: &UdpSocket, datagrams: Vec<Datagram>) {
+    for datagram in datagrams {
+        if let Err(e) = socket.try_send_to(&datagram.payload, datagram.to) {
+            debug!("Failed to send WireGuard datagram to {}: {}", datagram.to, e);
+        }
+    }
+}
+
+/// Move datagrams between the UDP socket, the WireGuard device and the stack
+async fn drive(shared: Arc<Shared>) {
+    let mut buffer = vec![0u8; 65536];
+    let mut delay = Duration::ZERO;
+    loop {
+        tokio::select! {
+            received = shared.socket.recv_from(&mut buffer) => match received {
+                Ok((length, from)) => {
+                    // Take everything already queued before polling the stack again
+                    let mut state = shared.state.lock();
+                    state.receive(&buffer[..length], from, &shared.socket);
+                    while let Ok((length, from)) = shared.socket.try_recv_from(&mut buffer) {
+                        state.receive(&buffer[..length], from, &shared.socket);
+                    }
+                }
+                Err(e) => debug!("WireGuard socket receive failed: {}", e),
+            },
+            _ = shared.wake.notified() => {}
+            _ = tokio::time::sleep(delay) => {}
+        }
+        delay = shared.state.lock().poll(&shared.socket);
+        shared.changed.notify_waiters();
+    }
+}
+
+/// Copy bytes between a tunnel socket and the stream handed to the caller
+async fn pump(shared: Arc<Shared>, handle: SocketHandle, mut local: DuplexStream) {
+    let mut outbound = Vec::new();
+    let mut buffer = vec![0u8; PUMP_BUFFER];
+    let mut local_eof = false;
+    let mut remote_eof = false;
+    let mut fin_sent = false;
+
+    while !shared.closed.load(Ordering::Relaxed) {
+        let changed = shared.changed.notified();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+
+        let mut received = Vec::new();
+        let (remote_closed, open) = {
+            let mut state = shared.state.lock();
+            let socket = state.stack.socket(handle);
+            while socket.can_recv() {
+                match socket.recv_slice(&mut buffer) {
+                    Ok(length) if length > 0 => received.extend_from_slice(&buffer[..length]),
+                    _ => break,
+                }
+            }
+            if !outbound.is_empty() && socket.can_send() {
+                if let Ok(sent) = socket.send_slice(&outbound) {
+                    outbound.drain(..sent);
+                }
+            }
+            if local_eof && outbound.is_empty() && !fin_sent {
+                socket.close();
+                fin_sent = true;
+            }
+            (!socket.may_recv(), socket.is_open())
+        };
+        shared.wake.notify_one();
+
+        if !received.is_empty() && local.write_all(&received).await.is_err() {
+            break;
+        }
+        if remote_closed && !remote_eof {
+            remote_eof = true;
+            let _ = local.shutdown().await;
+        }
+        if !open || (remote_eof && fin_sent) {
+            break;
+        }
+
+        tokio::select! {
+            read = local.read(&mut buffer), if !local_eof && outbound.len() < PUMP_BUFFER => match read {
+                Ok(0) | Err(_) => local_eof = true,
+                Ok(length) => outbound.extend_from_slice(&buffer[..length]),
+            },
+            _ = changed => {}
+        }
+    }
+
+    shared.state.lock().stack.release(handle);
+    shared.wake.notify_one();
+}
+
+/// Connects through a WireGuard tunnel, resolving names with its DNS servers
+pub struct WireGuardConnector {
+    tunnel: Arc<WireGuardTunnel>,
+    resolver: Arc<dyn Resolver>,
+}
+
+impl WireGuardConnector {
+    pub fn new(tunnel: Arc<WireGuardTunnel>, resolver: Arc<dyn Resolver>) -> Self {
+        Self { tunnel, resolver }
+    }
+}
+
+#[async_trait]
+impl Connector for WireGuardConnector {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream> {
+        let addrs = match target {
+            TargetAddr::Ip(addr) => vec![*addr],
+            TargetAddr::Domain(host, port) => self
+                .resolver
+                .resolve(host)
+                .await?
+                .into_iter()
+                .map(|ip| SocketAddr::new(ip, *port))
+                .collect(),
+        };
+
+        let mut last_error = None;
+        for addr in addrs {
+            match self.tunnel.connect(addr).await {
+                Ok(stream) => return Ok(Box::new(stream)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No addresses for {}", target)))
+    }
+
+    async fn check(&self) -> Result<()> {
+        self.tunnel.check()
+    }
+}
+
+/// DNS over TCP to the resolvers named in the WireGuard config, inside the
+/// tunnel, so lookups never leave it in plaintext
+pub struct TunnelDnsTransport {
+    tunnel: Arc<WireGuardTunnel>,
+}
+
+impl TunnelDnsTransport {
+    pub fn new(tunnel: Arc<WireGuardTunnel>) -> Self {
+        Self { tunnel }
+    }
+
+    async fn exchange_with(&self, server: IpAddr, query: &[u8]) -> Result<Vec<u8>> {
+        let mut stream = self.tunnel.connect(SocketAddr::new(server, 53)).await?;
+
+        let mut framed = Vec::with_capacity(query.len() + 2);
+        framed.extend_from_slice(&(query.len() as u16).to_be_bytes());
+        framed.extend_from_slice(query);
+        stream.write_all(&framed).await?;
+
+        let length = stream.read_u16().await? as usize;
+        let mut response = vec![0u8; length];
+        stream.read_exact(&mut response).await?;
+
+        if response.get(..2) != query.get(..2) {
+            return Err(anyhow::anyhow!("DNS response ID does not match query"));
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl DnsTransport for TunnelDnsTransport {
+    fn describe(&self) -> String {
+        let servers: Vec<String> = self.tunnel.dns.iter().map(|server| format!("wireguard://{}", server)).collect();
+        servers.join(", ")
+    }
+
+    async fn exchange(&self, query: Vec<u8>) -> Result<Vec<u8>> {
+        let mut last_error = None;
+        for server in &self.tunnel.dns {
+            match tokio::time::timeout(DNS_TIMEOUT, self.exchange_with(*server, &query)).await {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(e)) => last_error = Some(e),
+                Err(_) => last_error = Some(anyhow::anyhow!("DNS query to {} timed out", server)),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("WireGuard config names no DNS server")))
+    }
+}
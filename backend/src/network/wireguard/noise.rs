@@ -0,0 +1,523 @@
+//! The WireGuard protocol: Noise_IKpsk2 handshake and transport data
+//! messages, as described in the WireGuard whitepaper section 5.
+
+use anyhow::Result;
+use blake2::digest::{Digest, KeyInit, Mac};
+use blake2::{Blake2s256, Blake2sMac};
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::SimpleHmac;
+use rand_core::{OsRng, RngCore};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const CONSTRUCTION: &[u8] = b"Noise_IKpsk2_25519_ChaChaPoly_BLAKE2s";
+const IDENTIFIER: &[u8] = b"WireGuard v1 zx2c4 Jason@zx2c4.com";
+const LABEL_MAC1: &[u8] = b"mac1----";
+
+pub const HANDSHAKE_INITIATION: u8 = 1;
+pub const HANDSHAKE_RESPONSE: u8 = 2;
+pub const COOKIE_REPLY: u8 = 3;
+pub const TRANSPORT_DATA: u8 = 4;
+
+pub const INITIATION_LEN: usize = 148;
+pub const RESPONSE_LEN: usize = 92;
+const DATA_HEADER_LEN: usize = 16;
+const TAG_LEN: usize = 16;
+
+/// Sessions may not encrypt more messages than this
+const REJECT_AFTER_MESSAGES: u64 = u64::MAX - (1 << 13);
+/// Number of counters tracked behind the highest one seen
+const REPLAY_WINDOW: u64 = 128;
+
+/// This end's long-term key pair
+pub struct Identity {
+    secret: StaticSecret,
+    pub public: PublicKey,
+    /// Key for mac1 of messages addressed to us
+    mac1_key: [u8; 32],
+}
+
+impl Identity {
+    pub fn new(private_key: [u8; 32]) -> Self {
+        let secret = StaticSecret::from(private_key);
+        let public = PublicKey::from(&secret);
+        Self {
+            mac1_key: hash(&[LABEL_MAC1, public.as_bytes()]),
+            secret,
+            public,
+        }
+    }
+}
+
+/// What this end knows about a peer ahead of a handshake
+pub struct PeerKeys {
+    pub public: PublicKey,
+    psk: [u8; 32],
+    /// Key for mac1 of messages addressed to the peer
+    mac1_key: [u8; 32],
+}
+
+impl PeerKeys {
+    pub fn new(public_key: [u8; 32], preshared_key: Option<[u8; 32]>) -> Self {
+        Self {
+            public: PublicKey::from(public_key),
+            psk: preshared_key.unwrap_or([0; 32]),
+            mac1_key: hash(&[LABEL_MAC1, &public_key]),
+        }
+    }
+}
+
+/// An initiation we sent and are waiting to see answered
+pub struct InitiatorState {
+    pub local_index: u32,
+    chaining_key: [u8; 32],
+    hash: [u8; 32],
+    ephemeral: StaticSecret,
+}
+
+/// An initiation received from a peer, authenticated but not yet answered
+pub struct ReceivedInitiation {
+    pub remote_index: u32,
+    pub remote_static: [u8; 32],
+    /// TAI64N timestamp, used to reject replayed initiations
+    pub timestamp: [u8; 12],
+    chaining_key: [u8; 32],
+    hash: [u8; 32],
+    remote_ephemeral: PublicKey,
+}
+
+/// Keys derived by a completed handshake
+pub struct Session {
+    pub local_index: u32,
+    pub remote_index: u32,
+    pub created: Instant,
+    /// Whether we sent the initiation; only the initiator rekeys on age
+    pub initiator: bool,
+    send: ChaCha20Poly1305,
+    receive: ChaCha20Poly1305,
+    send_counter: u64,
+    replay: ReplayWindow,
+}
+
+/// Random index identifying a handshake or session on our end
+pub fn new_index() -> u32 {
+    OsRng.next_u32()
+}
+
+/// Read the little-endian u32 at `offset`, e.g. a receiver index
+pub fn read_index(message: &[u8], offset: usize) -> Option<u32> {
+    let bytes = message.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+pub fn create_initiation(local: &Identity, peer: &PeerKeys, local_index: u32) -> (InitiatorState, Vec<u8>) {
+    let mut chaining_key = hash(&[CONSTRUCTION]);
+    let mut h = hash(&[&chaining_key, IDENTIFIER]);
+    h = hash(&[&h, peer.public.as_bytes()]);
+
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    [chaining_key] = kdf(&chaining_key, ephemeral_public.as_bytes());
+    h = hash(&[&h, ephemeral_public.as_bytes()]);
+
+    let [ck, key] = kdf(&chaining_key, ephemeral.diffie_hellman(&peer.public).as_bytes());
+    chaining_key = ck;
+    let encrypted_static = seal(&key, 0, local.public.as_bytes(), &h);
+    h = hash(&[&h, &encrypted_static]);
+
+    let [ck, key] = kdf(&chaining_key, local.secret.diffie_hellman(&peer.public).as_bytes());
+    chaining_key = ck;
+    let encrypted_timestamp = seal(&key, 0, &tai64n(), &h);
+    h = hash(&[&h, &encrypted_timestamp]);
+
+    let mut message = Vec::with_capacity(INITIATION_LEN);
+    message.extend_from_slice(&[HANDSHAKE_INITIATION, 0, 0, 0]);
+    message.extend_from_slice(&local_index.to_le_bytes());
+    message.extend_from_slice(ephemeral_public.as_bytes());
+    message.extend_from_slice(&encrypted_static);
+    message.extend_from_slice(&encrypted_timestamp);
+    append_macs(&mut message, &peer.mac1_key);
+
+    let state = InitiatorState {
+        local_index,
+        chaining_key,
+        hash: h,
+        ephemeral,
+    };
+    (state, message)
+}
+
+pub fn consume_initiation(local: &Identity, message: &[u8]) -> Result<ReceivedInitiation> {
+    if message.len() != INITIATION_LEN || message[0] != HANDSHAKE_INITIATION {
+        return Err(anyhow::anyhow!("Malformed handshake initiation"));
+    }
+    check_mac1(&local.mac1_key, message)?;
+
+    let mut chaining_key = hash(&[CONSTRUCTION]);
+    let mut h = hash(&[&chaining_key, IDENTIFIER]);
+    h = hash(&[&h, local.public.as_bytes()]);
+
+    let remote_index = read_index(message, 4).expect("length checked");
+    let remote_ephemeral = public_key(&message[8..40]);
+    [chaining_key] = kdf(&chaining_key, remote_ephemeral.as_bytes());
+    h = hash(&[&h, remote_ephemeral.as_bytes()]);
+
+    let [ck, key] = kdf(&chaining_key, local.secret.diffie_hellman(&remote_ephemeral).as_bytes());
+    chaining_key = ck;
+    let remote_static: [u8; 32] = open(&key, 0, &message[40..88], &h)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Malformed initiator static key"))?;
+    h = hash(&[&h, &message[40..88]]);
+
+    let [ck, key] = kdf(&chaining_key, local.secret.diffie_hellman(&PublicKey::from(remote_static)).as_bytes());
+    chaining_key = ck;
+    let timestamp: [u8; 12] = open(&key, 0, &message[88..116], &h)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Malformed initiation timestamp"))?;
+    h = hash(&[&h, &message[88..116]]);
+
+    Ok(ReceivedInitiation {
+        remote_index,
+        remote_static,
+        timestamp,
+        chaining_key,
+        hash: h,
+        remote_ephemeral,
+    })
+}
+
+pub fn create_response(
+    received: ReceivedInitiation,
+    peer: &PeerKeys,
+    local_index: u32,
+) -> (Session, Vec<u8>) {
+    let mut chaining_key = received.chaining_key;
+    let mut h = received.hash;
+
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    [chaining_key] = kdf(&chaining_key, ephemeral_public.as_bytes());
+    h = hash(&[&h, ephemeral_public.as_bytes()]);
+    [chaining_key] = kdf(&chaining_key, ephemeral.diffie_hellman(&received.remote_ephemeral).as_bytes());
+    [chaining_key] = kdf(&chaining_key, ephemeral.diffie_hellman(&peer.public).as_bytes());
+
+    let [ck, tau, key] = kdf(&chaining_key, &peer.psk);
+    chaining_key = ck;
+    h = hash(&[&h, &tau]);
+    let encrypted_nothing = seal(&key, 0, &[], &h);
+
+    let mut message = Vec::with_capacity(RESPONSE_LEN);
+    message.extend_from_slice(&[HANDSHAKE_RESPONSE, 0, 0, 0]);
+    message.extend_from_slice(&local_index.to_le_bytes());
+    message.extend_from_slice(&received.remote_index.to_le_bytes());
+    message.extend_from_slice(ephemeral_public.as_bytes());
+    message.extend_from_slice(&encrypted_nothing);
+    append_macs(&mut message, &peer.mac1_key);
+
+    let [receive, send] = kdf(&chaining_key, &[]);
+    let session = Session::new(local_index, received.remote_index, false, send, receive);
+    (session, message)
+}
+
+pub fn consume_response(
+    state: &InitiatorState,
+    local: &Identity,
+    peer: &PeerKeys,
+    message: &[u8],
+) -> Result<Session> {
+    if message.len() != RESPONSE_LEN || message[0] != HANDSHAKE_RESPONSE {
+        return Err(anyhow::anyhow!("Malformed handshake response"));
+    }
+    if read_index(message, 8) != Some(state.local_index) {
+        return Err(anyhow::anyhow!("Handshake response for an unknown initiation"));
+    }
+    check_mac1(&local.mac1_key, message)?;
+
+    let mut chaining_key = state.chaining_key;
+    let mut h = state.hash;
+
+    let remote_index = read_index(message, 4).expect("length checked");
+    let remote_ephemeral = public_key(&message[12..44]);
+    [chaining_key] = kdf(&chaining_key, remote_ephemeral.as_bytes());
+    h = hash(&[&h, remote_ephemeral.as_bytes()]);
+    [chaining_key] = kdf(&chaining_key, state.ephemeral.diffie_hellman(&remote_ephemeral).as_bytes());
+    [chaining_key] = kdf(&chaining_key, local.secret.diffie_hellman(&remote_ephemeral).as_bytes());
+
+    let [ck, tau, key] = kdf(&chaining_key, &peer.psk);
+    chaining_key = ck;
+    h = hash(&[&h, &tau]);
+    open(&key, 0, &message[44..60], &h)?;
+
+    let [send, receive] = kdf(&chaining_key, &[]);
+    Ok(Session::new(state.local_index, remote_index, true, send, receive))
+}
+
+impl Session {
+    fn new(local_index: u32, remote_index: u32, initiator: bool, send: [u8; 32], receive: [u8; 32]) -> Self {
+        Self {
+            local_index,
+            remote_index,
+            created: Instant::now(),
+            initiator,
+            send: ChaCha20Poly1305::new(&send.into()),
+            receive: ChaCha20Poly1305::new(&receive.into()),
+            send_counter: 0,
+            replay: ReplayWindow::default(),
+        }
+    }
+
+    /// Encrypt an IP packet into a transport data message; an empty packet is a keepalive
+    pub fn encrypt(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        if self.send_counter >= REJECT_AFTER_MESSAGES {
+            return Err(anyhow::anyhow!("Session exhausted its message counter"));
+        }
+        let counter = self.send_counter;
+        self.send_counter += 1;
+
+        // Pad to a multiple of 16 to hide exact packet lengths
+        let mut padded = packet.to_vec();
+        padded.resize(packet.len().div_ceil(16) * 16, 0);
+        let sealed = self
+            .send
+            .encrypt(&nonce(counter).into(), padded.as_slice())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt transport message"))?;
+
+        let mut message = Vec::with_capacity(DATA_HEADER_LEN + sealed.len());
+        message.extend_from_slice(&[TRANSPORT_DATA, 0, 0, 0]);
+        message.extend_from_slice(&self.remote_index.to_le_bytes());
+        message.extend_from_slice(&counter.to_le_bytes());
+        message.extend_from_slice(&sealed);
+        Ok(message)
+    }
+
+    /// Decrypt a transport data message, returning the padded IP packet
+    pub fn decrypt(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        if message.len() < DATA_HEADER_LEN + TAG_LEN || message[0] != TRANSPORT_DATA {
+            return Err(anyhow::anyhow!("Malformed transport message"));
+        }
+        let counter = u64::from_le_bytes(message[8..16].try_into().expect("length checked"));
+        if !self.replay.check(counter) {
+            return Err(anyhow::anyhow!("Replayed transport message"));
+        }
+        let packet = self
+            .receive
+            .decrypt(&nonce(counter).into(), &message[DATA_HEADER_LEN..])
+            .map_err(|_| anyhow::anyhow!("Failed to authenticate transport message"))?;
+        self.replay.update(counter);
+        Ok(packet)
+    }
+}
+
+/// Sliding window of received counters (RFC 6479 style, simplified)
+#[derive(Default)]
+struct ReplayWindow {
+    highest: u64,
+    /// Bit `n` set means `highest - n` was received
+    seen: u128,
+    any: bool,
+}
+
+impl ReplayWindow {
+    fn check(&self, counter: u64) -> bool {
+        if counter >= REJECT_AFTER_MESSAGES {
+            return false;
+        }
+        if !self.any || counter > self.highest {
+            return true;
+        }
+        let behind = self.highest - counter;
+        behind < REPLAY_WINDOW && self.seen & (1 << behind) == 0
+    }
+
+    fn update(&mut self, counter: u64) {
+        if !self.any {
+            self.any = true;
+            self.highest = counter;
+            self.seen = 1;
+        } else if counter > self.highest {
+            let shift = counter - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = counter;
+        } else {
+            self.seen |= 1 << (self.highest - counter);
+        }
+    }
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Blake2s256::new();
+    for part in parts {
+        Digest::update(&mut hasher, part);
+    }
+    hasher.finalize().into()
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <SimpleHmac<Blake2s256> as KeyInit>::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in parts {
+        Mac::update(&mut mac, part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// HKDF with BLAKE2s as used by Noise, producing `N` keys
+fn kdf<const N: usize>(chaining_key: &[u8; 32], input: &[u8]) -> [[u8; 32]; N] {
+    let secret = hmac(chaining_key, &[input]);
+    let mut outputs = [[0u8; 32]; N];
+    let mut previous: Vec<u8> = Vec::new();
+    for (i, output) in outputs.iter_mut().enumerate() {
+        *output = hmac(&secret, &[&previous, &[i as u8 + 1]]);
+        previous = output.to_vec();
+    }
+    outputs
+}
+
+fn nonce(counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+fn seal(key: &[u8; 32], counter: u64, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(key.into())
+        .encrypt(&nonce(counter).into(), Payload { msg: plaintext, aad })
+        .expect("encryption with a valid key cannot fail")
+}
+
+fn open(key: &[u8; 32], counter: u64, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(&nonce(counter).into(), Payload { msg: ciphertext, aad })
+        .map_err(|_| anyhow::anyhow!("Handshake message failed authentication"))
+}
+
+fn public_key(bytes: &[u8]) -> PublicKey {
+    let bytes: [u8; 32] = bytes.try_into().expect("slice is 32 bytes");
+    PublicKey::from(bytes)
+}
+
+/// Append mac1 and an empty mac2; cookies are only sent by peers under load
+fn append_macs(message: &mut Vec<u8>, mac1_key: &[u8; 32]) {
+    let mac1 = keyed_mac(mac1_key, message);
+    message.extend_from_slice(&mac1);
+    message.extend_from_slice(&[0; 16]);
+}
+
+fn check_mac1(mac1_key: &[u8; 32], message: &[u8]) -> Result<()> {
+    let offset = message.len() - 32;
+    if keyed_mac(mac1_key, &message[..offset]) != message[offset..offset + 16] {
+        return Err(anyhow::anyhow!("Handshake message has an invalid mac1"));
+    }
+    Ok(())
+}
+
+fn keyed_mac(key: &[u8; 32], data: &[u8]) -> [u8; 16] {
+    let mut mac = <Blake2sMac<blake2::digest::consts::U16> as KeyInit>::new(key.into());
+    Mac::update(&mut mac, data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Current time as TAI64N, which only has to increase between initiations
+fn tai64n() -> [u8; 12] {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut timestamp = [0u8; 12];
+    timestamp[..8].copy_from_slice(&(0x4000_0000_0000_000a + now.as_secs()).to_be_bytes());
+    timestamp[8..].copy_from_slice(&now.subsec_nanos().to_be_bytes());
+    timestamp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipfs::cid::hex_decode;
+
+    fn hex32(text: &str) -> [u8; 32] {
+        hex_decode(text).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn initial_chaining_key_and_hash() {
+        let chaining_key = hash(&[CONSTRUCTION]);
+        assert_eq!(
+            chaining_key,
+            hex32("60e26daef327efc02ec335e2a025d2d016eb4206f87277f52d38d1988b78cd36")
+        );
+        assert_eq!(
+            hash(&[&chaining_key, IDENTIFIER]),
+            hex32("2211b361081ac566691243db458ad5322d9c6c662293e8b70ee19c65ba079ef3")
+        );
+    }
+
+    #[test]
+    fn kdf_matches_hkdf_blake2s() {
+        let chaining_key = hash(&[CONSTRUCTION]);
+        let input: Vec<u8> = (0..32).collect();
+        let [first, second, third] = kdf(&chaining_key, &input);
+        assert_eq!(first, hex32("6ff66e10ab4a55ac8144a867fcd9f8ee64d4bd6239223338cb3437cf085ab031"));
+        assert_eq!(second, hex32("203b22d63d63e35cebb9e40210cf8c1d7f90879428a4c766cda9ef34bb299398"));
+        assert_eq!(third, hex32("35a7b2fc3e4f59db341dbe588e3b75897980c463d5bbb855f8ed8134ff40133a"));
+        // Fewer outputs are a prefix of more
+        assert_eq!(kdf::<1>(&chaining_key, &input), [first]);
+    }
+
+    #[test]
+    fn mac1_is_keyed_by_the_receiver_public_key() {
+        let mut public_key = [0u8; 32];
+        public_key[0] = 9;
+        let keys = PeerKeys::new(public_key, None);
+        let mut message = vec![HANDSHAKE_INITIATION, 0, 0, 0];
+        message.extend(0..112u8);
+        append_macs(&mut message, &keys.mac1_key);
+
+        assert_eq!(message.len(), INITIATION_LEN);
+        assert_eq!(message[116..132], hex_decode("48fc086f00725f22c6a3e6193731a50c").unwrap()[..]);
+        assert_eq!(message[132..], [0; 16]);
+        assert!(check_mac1(&keys.mac1_key, &message).is_ok());
+        message[20] ^= 1;
+        assert!(check_mac1(&keys.mac1_key, &message).is_err());
+    }
+
+    #[test]
+    fn handshake_derives_matching_sessions() {
+        let initiator = Identity::new([1; 32]);
+        let responder = Identity::new([2; 32]);
+        let psk = Some([3; 32]);
+        let responder_keys = PeerKeys::new(*responder.public.as_bytes(), psk);
+        let initiator_keys = PeerKeys::new(*initiator.public.as_bytes(), psk);
+
+        let (state, initiation) = create_initiation(&initiator, &responder_keys, 7);
+        let received = consume_initiation(&responder, &initiation).unwrap();
+        assert_eq!(received.remote_static, *initiator.public.as_bytes());
+        let (mut answered, response) = create_response(received, &initiator_keys, 8);
+        let mut confirmed = consume_response(&state, &initiator, &responder_keys, &response).unwrap();
+        assert_eq!((confirmed.local_index, confirmed.remote_index), (7, 8));
+
+        let message = confirmed.encrypt(b"ping").unwrap();
+        assert_eq!(read_index(&message, 4), Some(8));
+        assert_eq!(&answered.decrypt(&message).unwrap()[..4], b"ping");
+        // Replays are dropped
+        assert!(answered.decrypt(&message).is_err());
+        let reply = answered.encrypt(b"pong").unwrap();
+        assert_eq!(&confirmed.decrypt(&reply).unwrap()[..4], b"pong");
+
+        // A responder with another preshared key cannot complete the handshake
+        let (state, initiation) = create_initiation(&initiator, &responder_keys, 9);
+        let received = consume_initiation(&responder, &initiation).unwrap();
+        let (_, response) = create_response(received, &PeerKeys::new(*initiator.public.as_bytes(), None), 10);
+        assert!(consume_response(&state, &initiator, &responder_keys, &response).is_err());
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::default();
+        for counter in [0, 2, 1, 200] {
+            assert!(window.check(counter));
+            window.update(counter);
+            assert!(!window.check(counter));
+        }
+        // Too far behind the highest counter
+        assert!(!window.check(50));
+        assert!(window.check(199));
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::Result;
+use ipnet::IpNet;
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
+use smoltcp::socket::tcp;
+use smoltcp::time::Instant as SmolInstant;
+use smoltcp::wire::{HardwareAddress, IpAddress, IpCidr, IpEndpoint};
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+const SOCKET_BUFFER: usize = 256 * 1024;
+const FIRST_EPHEMERAL_PORT: u16 = 49152;
+/// How long a closed socket may keep retransmitting its FIN
+const CLOSE_LINGER: Duration = Duration::from_secs(30);
+
+/// IP packets waiting to enter or leave the userspace stack
+pub struct PacketQueues {
+    /// Decrypted packets from peers
+    pub inbound: VecDeque<Vec<u8>>,
+    /// Packets produced by the stack, to be encrypted for a peer
+    pub outbound: VecDeque<Vec<u8>>,
+    mtu: usize,
+}
+
+pub struct QueueRxToken(Vec<u8>);
+
+pub struct QueueTxToken<'a>(&'a mut VecDeque<Vec<u8>>);
+
+impl RxToken for QueueRxToken {
+    fn consume<R, F: FnOnce(&[u8]) -> R>(self, f: F) -> R {
+        f(&self.0)
+    }
+}
+
+impl TxToken for QueueTxToken<'_> {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+        let mut packet = vec![0u8; len];
+        let result = f(&mut packet);
+        self.0.push_back(packet);
+        result
+    }
+}
+
+impl Device for PacketQueues {
+    type RxToken<'a> = QueueRxToken;
+    type TxToken<'a> = QueueTxToken<'a>;
+
+    fn receive(&mut self, _timestamp: SmolInstant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let packet = self.inbound.pop_front()?;
+        Some((QueueRxToken(packet), QueueTxToken(&mut self.outbound)))
+    }
+
+    fn transmit(&mut self, _timestamp: SmolInstant) -> Option<Self::TxToken<'_>> {
+        Some(QueueTxToken(&mut self.outbound))
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ip;
+        capabilities.max_transmission_unit = self.mtu;
+        capabilities
+    }
+}
+
+/// Userspace TCP/IP stack holding the tunnel's addresses, so connections
+/// can be made through the tunnel without a TUN device or root
+pub struct Stack {
+    iface: Interface,
+    sockets: SocketSet<'static>,
+    pub queues: PacketQueues,
+    next_port: u16,
+    /// Sockets closed by their owner that may still be sending a FIN
+    closing: Vec<(SocketHandle, Instant)>,
+}
+
+impl Stack {
+    pub fn new(addresses: &[IpNet], mtu: u16) -> Result<Self> {
+        let mut queues = PacketQueues {
+            inbound: VecDeque::new(),
+            outbound: VecDeque::new(),
+            mtu: mtu as usize,
+        };
+        let mut iface = Interface::new(Config::new(HardwareAddress::Ip), &mut queues, SmolInstant::now());
+
+        let mut result = Ok(());
+        iface.update_ip_addrs(|addrs| {
+            for address in addresses {
+                let cidr = IpCidr::new(IpAddress::from(address.addr()), address.prefix_len());
+                if addrs.push(cidr).is_err() {
+                    result = Err(anyhow::anyhow!("Too many tunnel addresses"));
+                }
+            }
+        });
+        result?;
+
+        // Everything not on a local subnet goes to the peers; with an IP medium
+        // the gateway is never looked up, so our own address serves
+        for address in addresses {
+            match address.addr() {
+                IpAddr::V4(ip) => iface.routes_mut().add_default_ipv4_route(ip).map(|_| ()),
+                IpAddr::V6(ip) => iface.routes_mut().add_default_ipv6_route(ip).map(|_| ()),
+            }
+            .map_err(|_| anyhow::anyhow!("Route table full"))?;
+        }
+
+        Ok(Self {
+            iface,
+            sockets: SocketSet::new(Vec::new()),
+            queues,
+            next_port: FIRST_EPHEMERAL_PORT,
+            closing: Vec::new(),
+        })
+    }
+
+    /// Start connecting a TCP socket to `remote`
+    pub fn open(&mut self, remote: SocketAddr) -> Result<SocketHandle> {
+        let mut socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; SOCKET_BUFFER]),
+            tcp::SocketBuffer::new(vec![0; SOCKET_BUFFER]),
+        );
+        // Peers are reached over lossy UDP, so back off on loss instead of resending whole windows
+        socket.set_congestion_control(tcp::CongestionControl::Cubic);
+        let port = self.next_port;
+        self.next_port = self.next_port.checked_add(1).unwrap_or(FIRST_EPHEMERAL_PORT);
+
+        let endpoint = IpEndpoint::new(IpAddress::from(remote.ip()), remote.port());
+        socket
+            .connect(self.iface.context(), endpoint, port)
+            .map_err(|e| anyhow::anyhow!("Failed to connect to {} through tunnel: {}", remote, e))?;
+        Ok(self.sockets.add(socket))
+    }
+
+    pub fn socket(&mut self, handle: SocketHandle) -> &mut tcp::Socket<'static> {
+        self.sockets.get_mut::<tcp::Socket>(handle)
+    }
+
+    /// Close a socket gracefully; it is removed once the close completes
+    pub fn release(&mut self, handle: SocketHandle) {
+        self.socket(handle).close();
+        self.closing.push((handle, Instant::now()));
+    }
+
+    /// Reset a socket; it is removed once the reset has been sent
+    pub fn abort(&mut self, handle: SocketHandle) {
+        self.socket(handle).abort();
+        self.closing.push((handle, Instant::now()));
+    }
+
+    /// Process queued packets and timers, returning when to poll next
+    pub fn poll(&mut self) -> Option<Duration> {
+        let now = SmolInstant::now();
+        self.iface.poll(now, &mut self.queues, &mut self.sockets);
+
+        let sockets = &mut self.sockets;
+        self.closing.retain(|(handle, since)| {
+            let state = sockets.get::<tcp::Socket>(*handle).state();
+            let done = matches!(state, tcp::State::Closed | tcp::State::TimeWait) || since.elapsed() >= CLOSE_LINGER;
+            if done {
+                sockets.remove(*handle);
+            }
+            !done
+        });
+
+        self.iface
+            .poll_delay(now, &self.sockets)
+            .map(Duration::from)
+    }
+}
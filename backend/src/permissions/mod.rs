@@ -0,0 +1,228 @@
+use crate::engine::{PermissionHandler, PermissionPrompt};
+use crate::ipc::{IPCEvent, Permission, PermissionDecision, PrivacyMode, SharedState, SitePermission, TabId};
+use crate::storage;
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, oneshot};
+use tracing::warn;
+
+/// How long a prompt waits for an answer before the request is denied
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(120);
+
+type DecisionKey = (String, Permission);
+
+struct Answer {
+    allow: bool,
+    remember: bool,
+    expires_in: Option<u64>,
+}
+
+/// Decides which origins may use the camera, microphone, location and other
+/// guarded capabilities, prompting through the UI and remembering answers.
+/// Decisions made in Private tabs are never written to disk.
+pub struct PermissionManager {
+    decisions: RwLock<BTreeMap<DecisionKey, SitePermission>>,
+    private_decisions: RwLock<BTreeMap<DecisionKey, SitePermission>>,
+    /// Prompts waiting for `RespondPermission`
+    pending: Mutex<HashMap<u64, oneshot::Sender<Answer>>>,
+    next_id: AtomicU64,
+    path: PathBuf,
+    shared_state: SharedState,
+    event_tx: broadcast::Sender<IPCEvent>,
+}
+
+impl PermissionManager {
+    pub fn new(path: PathBuf, shared_state: SharedState, event_tx: broadcast::Sender<IPCEvent>) -> Self {
+        Self {
+            decisions: RwLock::new(BTreeMap::new()),
+            private_decisions: RwLock::new(BTreeMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            path,
+            shared_state,
+            event_tx,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        if let Some(decisions) = storage::load_json::<Vec<SitePermission>>(&self.path).await? {
+            *self.decisions.write() = decisions
+                .into_iter()
+                .map(|decision| ((decision.origin.clone(), decision.permission), decision))
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Remembered decisions that have not expired, Private ones included
+    pub fn permissions(&self) -> Vec<SitePermission> {
+        let now = unix_time();
+        let decisions = self.decisions.read();
+        let private_decisions = self.private_decisions.read();
+        decisions
+            .values()
+            .chain(private_decisions.values())
+            .filter(|decision| !is_expired(decision, now))
+            .cloned()
+            .collect()
+    }
+
+    /// Remember a decision for an origin, as if the user had answered a prompt
+    pub async fn set(
+        &self,
+        origin: &str,
+        permission: Permission,
+        decision: PermissionDecision,
+        expires_in: Option<u64>,
+    ) -> Result<()> {
+        let origin = normalize_origin(origin)?;
+        self.remember(origin, permission, decision, expires_in, false).await
+    }
+
+    pub fn respond(&self, id: u64, allow: bool, remember: bool, expires_in: Option<u64>) -> Result<()> {
+        let sender = self
+            .pending
+            .lock()
+            .remove(&id)
+            .ok_or_else(|| anyhow::anyhow!("No permission request {} is waiting", id))?;
+        let _ = sender.send(Answer {
+            allow,
+            remember,
+            expires_in,
+        });
+        Ok(())
+    }
+
+    pub async fn reset(&self, origin: Option<&str>, permission: Option<Permission>) -> Result<()> {
+        let origin = origin.map(normalize_origin).transpose()?;
+        let keep = |(key_origin, key_permission): &DecisionKey| {
+            origin.as_ref().is_some_and(|origin| origin != key_origin)
+                || permission.is_some_and(|permission| permission != *key_permission)
+        };
+        self.private_decisions.write().retain(|key, _| keep(key));
+        self.decisions.write().retain(|key, _| keep(key));
+        self.save().await
+    }
+
+    fn is_private(&self, tab: TabId) -> bool {
+        self.shared_state
+            .read()
+            .tabs
+            .iter()
+            .any(|info| info.id == tab && info.privacy_mode == PrivacyMode::Private)
+    }
+
+    /// Private tabs see their own decisions first, then the persisted ones
+    fn remembered(&self, origin: &str, permission: Permission, private: bool) -> Option<PermissionDecision> {
+        let now = unix_time();
+        let key = (origin.to_string(), permission);
+        let private_decision = match private {
+            true => self.private_decisions.read().get(&key).cloned(),
+            false => None,
+        };
+        private_decision
+            .or_else(|| self.decisions.read().get(&key).cloned())
+            .filter(|decision| !is_expired(decision, now))
+            .map(|decision| decision.decision)
+    }
+
+    async fn remember(
+        &self,
+        origin: String,
+        permission: Permission,
+        decision: PermissionDecision,
+        expires_in: Option<u64>,
+        private: bool,
+    ) -> Result<()> {
+        let key = (origin.clone(), permission);
+        let decision = SitePermission {
+            origin,
+            permission,
+            decision,
+            expires: expires_in.map(|seconds| unix_time().saturating_add(seconds)),
+            private,
+        };
+        if private {
+            self.private_decisions.write().insert(key, decision);
+            return Ok(());
+        }
+        self.decisions.write().insert(key, decision);
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<()> {
+        let now = unix_time();
+        let decisions: Vec<SitePermission> = {
+            let mut decisions = self.decisions.write();
+            decisions.retain(|_, decision| !is_expired(decision, now));
+            decisions.values().cloned().collect()
+        };
+        storage::save_json(&self.path, &decisions).await
+    }
+}
+
+#[async_trait]
+impl PermissionHandler for PermissionManager {
+    async fn decide(&self, prompt: PermissionPrompt) -> bool {
+        let private = self.is_private(prompt.tab);
+        match self.remembered(&prompt.origin, prompt.permission, private) {
+            Some(PermissionDecision::Allow) => return true,
+            Some(PermissionDecision::Deny) => return false,
+            Some(PermissionDecision::Ask) | None => {}
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().insert(id, sender);
+        let _ = self.event_tx.send(IPCEvent::PermissionRequest {
+            id,
+            tab: prompt.tab,
+            origin: prompt.origin.clone(),
+            permission: prompt.permission,
+        });
+
+        let answer = tokio::time::timeout(PROMPT_TIMEOUT, receiver).await;
+        self.pending.lock().remove(&id);
+        let Ok(Ok(answer)) = answer else {
+            return false;
+        };
+        if answer.remember {
+            let decision = match answer.allow {
+                true => PermissionDecision::Allow,
+                false => PermissionDecision::Deny,
+            };
+            if let Err(e) = self
+                .remember(prompt.origin, prompt.permission, decision, answer.expires_in, private)
+                .await
+            {
+                warn!("Failed to save permission decision: {}", e);
+            }
+        }
+        answer.allow
+    }
+}
+
+/// `scheme://host[:port]` of a URL or origin
+fn normalize_origin(origin: &str) -> Result<String> {
+    let url = url::Url::parse(origin.trim()).map_err(|e| anyhow::anyhow!("Invalid origin {}: {}", origin, e))?;
+    match url.origin() {
+        origin @ url::Origin::Tuple(..) => Ok(origin.ascii_serialization()),
+        url::Origin::Opaque(_) => Err(anyhow::anyhow!("{} has no origin permissions can be kept for", origin)),
+    }
+}
+
+fn is_expired(decision: &SitePermission, now: u64) -> bool {
+    decision.expires.is_some_and(|expires| expires <= now)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,420 @@
+use crate::ipc::PrivacyMode;
+use base64::Engine as _;
+use rand_core::{OsRng, RngCore};
+use serde::Serialize;
+
+/// Letterboxing steps: reported sizes are rounded down to these multiples
+const WIDTH_STEP: u32 = 200;
+const HEIGHT_STEP: u32 = 100;
+const MAX_SCREEN: (u32, u32) = (1400, 900);
+
+/// How much of the fingerprint a privacy mode normalizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Protection {
+    /// Uniform user agent, hardware and screen values, and noise on canvas,
+    /// WebGL and audio readback
+    Standard,
+    /// Standard, plus the UTC timezone and an `en-US` locale as in Tor Browser
+    Strict,
+}
+
+impl Protection {
+    pub fn for_mode(mode: &PrivacyMode) -> Option<Self> {
+        match mode {
+            PrivacyMode::Normal => None,
+            PrivacyMode::Private | PrivacyMode::Vpn => Some(Protection::Standard),
+            PrivacyMode::Tor => Some(Protection::Strict),
+        }
+    }
+}
+
+/// What an engine reports to pages instead of the real machine's values.
+///
+/// The user agent keeps the engine's real major version, since feature
+/// detection would give a different one away, but everything else about the
+/// platform is the same for every user.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FingerprintProfile {
+    pub protection: Protection,
+    /// `Accept-Language` and `navigator.languages`
+    pub accept_language: Option<String>,
+    /// ICU locale used by `Intl`
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    /// Viewport and screen size, letterboxed from the window size
+    pub screen: (u32, u32),
+    pub hardware_concurrency: u32,
+    pub device_memory: u32,
+    /// Seeds the noise, which is stable per site for as long as the engine runs
+    #[serde(skip)]
+    pub seed: u32,
+}
+
+impl FingerprintProfile {
+    pub fn new(protection: Protection, window: (u32, u32)) -> Self {
+        let strict = protection == Protection::Strict;
+        Self {
+            protection,
+            accept_language: strict.then(|| "en-US,en;q=0.9".to_string()),
+            locale: strict.then(|| "en-US".to_string()),
+            timezone: strict.then(|| "UTC".to_string()),
+            screen: letterbox(window),
+            hardware_concurrency: 4,
+            device_memory: 8,
+            seed: OsRng.next_u32(),
+        }
+    }
+
+    pub fn for_mode(mode: &PrivacyMode, window: (u32, u32)) -> Option<Self> {
+        Protection::for_mode(mode).map(|protection| Self::new(protection, window))
+    }
+
+    pub fn user_agent(&self, major: &str) -> String {
+        format!(
+            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/{}.0.0.0 Safari/537.36",
+            major
+        )
+    }
+
+    /// `navigator.platform`
+    pub fn platform(&self) -> &'static str {
+        "Win32"
+    }
+
+    /// `Sec-CH-UA` brands and versions
+    pub fn brands(&self, major: &str) -> Vec<(String, String)> {
+        vec![
+            ("Chromium".to_string(), major.to_string()),
+            ("Not_A Brand".to_string(), "24".to_string()),
+        ]
+    }
+
+    /// Client hint platform, platform version, architecture and bitness
+    pub fn client_hints(&self) -> (&'static str, &'static str, &'static str, &'static str) {
+        ("Windows", "10.0.0", "x86", "64")
+    }
+
+    /// Script run in every frame before the page's own scripts
+    pub fn init_script(&self) -> String {
+        NOISE_SCRIPT
+            .replace("__SEED__", &self.seed.to_string())
+            .replace("__DEVICE_MEMORY__", &self.device_memory.to_string())
+    }
+}
+
+/// Round a window size down to the letterboxing steps, within the largest screen
+fn letterbox((width, height): (u32, u32)) -> (u32, u32) {
+    let width = (width.min(MAX_SCREEN.0) / WIDTH_STEP).max(1) * WIDTH_STEP;
+    let height = (height.min(MAX_SCREEN.1) / HEIGHT_STEP).max(1) * HEIGHT_STEP;
+    (width, height)
+}
+
+/// A `data:` page that reads what the engine reports and checks it against
+/// the profile; its title ends in PASS or FAIL. Without a profile it only
+/// shows the values.
+pub fn test_page(profile: Option<&FingerprintProfile>) -> String {
+    let expected = profile.map(|profile| {
+        let (platform, _, _, _) = profile.client_hints();
+        serde_json::json!({
+            "userAgent": profile.user_agent("{major}"),
+            "platform": profile.platform(),
+            "uaPlatform": platform,
+            "language": profile.locale,
+            "timezone": profile.timezone,
+            "screenWidth": profile.screen.0,
+            "screenHeight": profile.screen.1,
+            "innerWidth": profile.screen.0,
+            "innerHeight": profile.screen.1,
+            "outerWidth": profile.screen.0,
+            "hardwareConcurrency": profile.hardware_concurrency,
+            "deviceMemory": profile.device_memory,
+            "canvasNoise": true,
+            "audioNoise": true,
+        })
+    });
+    let html = TEST_PAGE.replace("__EXPECTED__", &expected.unwrap_or(serde_json::Value::Null).to_string());
+    format!(
+        "data:text/html;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(html)
+    )
+}
+
+const NOISE_SCRIPT: &str = r#"(() => {
+  const SEED = __SEED__;
+  const DEVICE_MEMORY = __DEVICE_MEMORY__;
+
+  // Noise depends on the site, so sites cannot link a visitor by it
+  let hash = SEED >>> 0;
+  for (const c of String(self.origin)) hash = Math.imul(hash ^ c.charCodeAt(0), 16777619) >>> 0;
+  const random = (index) => {
+    let t = (hash + Math.imul(index, 0x6d2b79f5)) >>> 0;
+    t = Math.imul(t ^ (t >>> 15), t | 1);
+    t ^= t + Math.imul(t ^ (t >>> 7), t | 61);
+    return ((t ^ (t >>> 14)) >>> 0) / 4294967296;
+  };
+  const getter = (object, name, get) =>
+    Object.defineProperty(object, name, { get, configurable: true, enumerable: true });
+  const wrap = (object, name, make) => {
+    if (!object || typeof object[name] !== 'function') return;
+    const original = object[name];
+    object[name] = make(original);
+  };
+
+  getter(Navigator.prototype, 'deviceMemory', () => DEVICE_MEMORY);
+  // The window frame would give away the real window size and position
+  getter(window, 'outerWidth', () => window.innerWidth);
+  getter(window, 'outerHeight', () => window.innerHeight);
+  for (const name of ['screenX', 'screenY', 'screenLeft', 'screenTop']) getter(window, name, () => 0);
+
+  // Flip the low bit of one channel in about a tenth of the pixels
+  const noisePixels = (data) => {
+    for (let i = 0; i < data.length; i += 4) {
+      if (random(i) < 0.1) data[i + Math.floor(random(i + 1) * 3)] ^= 1;
+    }
+  };
+
+  const getImageData = CanvasRenderingContext2D.prototype.getImageData;
+  wrap(CanvasRenderingContext2D.prototype, 'getImageData', (original) => function (...args) {
+    const image = original.apply(this, args);
+    noisePixels(image.data);
+    return image;
+  });
+  // Exports come from a noisy copy, leaving the visible canvas untouched
+  const noisyCopy = (canvas) => {
+    const copy = document.createElement('canvas');
+    copy.width = canvas.width;
+    copy.height = canvas.height;
+    const context = copy.getContext('2d');
+    context.drawImage(canvas, 0, 0);
+    const image = getImageData.call(context, 0, 0, copy.width, copy.height);
+    noisePixels(image.data);
+    context.putImageData(image, 0, 0);
+    return copy;
+  };
+  for (const name of ['toDataURL', 'toBlob']) {
+    wrap(HTMLCanvasElement.prototype, name, (original) => function (...args) {
+      const source = this.width && this.height ? noisyCopy(this) : this;
+      return original.apply(source, args);
+    });
+  }
+
+  for (const context of [self.WebGLRenderingContext, self.WebGL2RenderingContext]) {
+    if (!context) continue;
+    wrap(context.prototype, 'readPixels', (original) => function (...args) {
+      original.apply(this, args);
+      const pixels = args[6];
+      if (pixels instanceof Uint8Array || pixels instanceof Uint8ClampedArray) noisePixels(pixels);
+    });
+    // UNMASKED_VENDOR_WEBGL and UNMASKED_RENDERER_WEBGL name the real GPU
+    wrap(context.prototype, 'getParameter', (original) => function (name) {
+      if (name === 0x9245) return 'WebKit';
+      if (name === 0x9246) return 'WebKit WebGL';
+      return original.call(this, name);
+    });
+  }
+
+  // Audio is scaled by at most 1e-4, far below hearing
+  const noisedAudio = new WeakSet();
+  wrap(self.AudioBuffer && AudioBuffer.prototype, 'getChannelData', (original) => function (channel) {
+    const data = original.call(this, channel);
+    if (!noisedAudio.has(data)) {
+      noisedAudio.add(data);
+      for (let i = 0; i < data.length; i++) data[i] *= 1 + (random(i) - 0.5) * 1e-4;
+    }
+    return data;
+  });
+  wrap(self.AnalyserNode && AnalyserNode.prototype, 'getFloatFrequencyData', (original) => function (array) {
+    original.call(this, array);
+    for (let i = 0; i < array.length; i++) array[i] += (random(i) - 0.5) * 0.1;
+  });
+})();
+"#;
+
+const TEST_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Fingerprint check</title>
+<style>body{font:14px sans-serif;margin:2em}td{padding:.2em 1em;border-bottom:1px solid #ccc}.fail{color:#b00}.pass{color:#070}</style>
+</head><body><h1>Fingerprint check</h1><table id="results"></table>
+<script>
+(async () => {
+  const expected = __EXPECTED__;
+  const major = ((navigator.userAgentData && navigator.userAgentData.brands) || [])
+    .filter((brand) => brand.brand === 'Chromium')
+    .map((brand) => brand.version)[0];
+
+  const canvasNoise = () => {
+    const canvas = document.createElement('canvas');
+    canvas.width = canvas.height = 16;
+    const context = canvas.getContext('2d');
+    context.fillStyle = 'rgb(10, 20, 30)';
+    context.fillRect(0, 0, 16, 16);
+    const data = context.getImageData(0, 0, 16, 16).data;
+    for (let i = 0; i < data.length; i += 4) {
+      if (data[i] !== 10 || data[i + 1] !== 20 || data[i + 2] !== 30) return true;
+    }
+    return false;
+  };
+  const audioNoise = async () => {
+    const context = new OfflineAudioContext(1, 128, 44100);
+    const source = context.createConstantSource();
+    source.offset.value = 0.5;
+    source.connect(context.destination);
+    source.start();
+    const samples = (await context.startRendering()).getChannelData(0);
+    return samples.some((sample) => sample !== 0.5);
+  };
+
+  const actual = {
+    userAgent: navigator.userAgent,
+    platform: navigator.platform,
+    uaPlatform: navigator.userAgentData && navigator.userAgentData.platform,
+    language: navigator.language,
+    timezone: Intl.DateTimeFormat().resolvedOptions().timeZone,
+    screenWidth: screen.width,
+    screenHeight: screen.height,
+    innerWidth: window.innerWidth,
+    innerHeight: window.innerHeight,
+    outerWidth: window.outerWidth,
+    hardwareConcurrency: navigator.hardwareConcurrency,
+    deviceMemory: navigator.deviceMemory,
+    canvasNoise: canvasNoise(),
+    audioNoise: await audioNoise(),
+  };
+
+  let failures = 0;
+  const table = document.getElementById('results');
+  for (const [name, value] of Object.entries(actual)) {
+    let want = expected && expected[name];
+    if (typeof want === 'string') want = want.replace('{major}', major);
+    const checked = want !== undefined && want !== null;
+    const ok = !checked || String(value) === String(want);
+    if (!ok) failures++;
+    const row = table.insertRow();
+    row.className = checked ? (ok ? 'pass' : 'fail') : '';
+    row.insertCell().textContent = name;
+    row.insertCell().textContent = String(value);
+    row.insertCell().textContent = checked ? 'expected ' + want : '';
+  }
+  document.title = !expected
+    ? 'Fingerprint check: not protected'
+    : 'Fingerprint check: ' + (failures ? 'FAIL (' + failures + ')' : 'PASS');
+  document.querySelector('h1').textContent = document.title;
+})();
+</script></body></html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use boa_engine::{js_string, Context, Source};
+
+    #[test]
+    fn profiles_per_mode() {
+        assert_eq!(FingerprintProfile::for_mode(&PrivacyMode::Normal, (1920, 1080)), None);
+
+        let private = FingerprintProfile::for_mode(&PrivacyMode::Private, (1920, 1080)).unwrap();
+        assert_eq!(private.protection, Protection::Standard);
+        assert_eq!((private.timezone.as_deref(), private.locale.as_deref()), (None, None));
+
+        let tor = FingerprintProfile::for_mode(&PrivacyMode::Tor, (1920, 1080)).unwrap();
+        assert_eq!(tor.protection, Protection::Strict);
+        assert_eq!(tor.timezone.as_deref(), Some("UTC"));
+        assert_eq!(tor.accept_language.as_deref(), Some("en-US,en;q=0.9"));
+        assert!(tor.user_agent("126").contains("Chrome/126.0.0.0"));
+        assert!(tor.init_script().contains(&format!("const SEED = {};", tor.seed)));
+    }
+
+    #[test]
+    fn letterboxes_window_sizes() {
+        assert_eq!(letterbox((1920, 1080)), (1400, 900));
+        assert_eq!(letterbox((1366, 768)), (1200, 700));
+        assert_eq!(letterbox((150, 50)), (200, 100));
+    }
+
+    /// Run the test page's script against a stand-in for the browser that
+    /// reports `reported`, returning the page title it settles on
+    fn run_test_page(profile: Option<&FingerprintProfile>, reported: &str) -> String {
+        let page = test_page(profile);
+        let html = base64::engine::general_purpose::STANDARD
+            .decode(page.strip_prefix("data:text/html;base64,").unwrap())
+            .unwrap();
+        let html = String::from_utf8(html).unwrap();
+        let start = html.find("<script>").unwrap() + "<script>".len();
+        let script = &html[start..html.find("</script>").unwrap()];
+
+        let browser = r#"
+            var reported = __REPORTED__;
+            var navigator = {
+                userAgent: reported.userAgent,
+                platform: reported.platform,
+                userAgentData: { platform: reported.uaPlatform, brands: [{ brand: 'Chromium', version: '126' }] },
+                language: reported.language,
+                hardwareConcurrency: reported.hardwareConcurrency,
+                deviceMemory: reported.deviceMemory,
+            };
+            var screen = { width: reported.screenWidth, height: reported.screenHeight };
+            var window = {
+                innerWidth: reported.screenWidth,
+                innerHeight: reported.screenHeight,
+                outerWidth: reported.screenWidth,
+            };
+            var Intl = { DateTimeFormat: () => ({ resolvedOptions: () => ({ timeZone: reported.timezone }) }) };
+            var OfflineAudioContext = function () {
+                this.destination = {};
+                this.createConstantSource = () => ({ offset: {}, connect() {}, start() {} });
+                this.startRendering = async () => ({ getChannelData: () => [0.5, reported.noise ? 0.50001 : 0.5] });
+            };
+            var document = {
+                title: '',
+                createElement: () => ({
+                    getContext: () => ({
+                        fillRect() {},
+                        getImageData: () => ({ data: [reported.noise ? 11 : 10, 20, 30, 255] }),
+                    }),
+                }),
+                getElementById: () => ({ insertRow: () => ({ insertCell: () => ({}) }) }),
+                querySelector: () => ({}),
+            };
+        "#;
+        let mut context = Context::default();
+        context
+            .eval(Source::from_bytes(&browser.replace("__REPORTED__", reported)))
+            .unwrap();
+        context.eval(Source::from_bytes(script)).unwrap();
+        context.run_jobs();
+        let document = context.global_object().get(js_string!("document"), &mut context).unwrap();
+        let title = document.as_object().unwrap().get(js_string!("title"), &mut context).unwrap();
+        title.to_string(&mut context).unwrap().to_std_string_escaped()
+    }
+
+    fn reported(profile: &FingerprintProfile, noise: bool) -> serde_json::Value {
+        serde_json::json!({
+            "userAgent": profile.user_agent("126"),
+            "platform": profile.platform(),
+            "uaPlatform": profile.client_hints().0,
+            "language": "en-US",
+            "timezone": "UTC",
+            "screenWidth": profile.screen.0,
+            "screenHeight": profile.screen.1,
+            "hardwareConcurrency": profile.hardware_concurrency,
+            "deviceMemory": profile.device_memory,
+            "noise": noise,
+        })
+    }
+
+    #[test]
+    fn test_page_asserts_reported_values() {
+        let profile = FingerprintProfile::new(Protection::Strict, (1366, 768));
+        let title = run_test_page(Some(&profile), &reported(&profile, true).to_string());
+        assert_eq!(title, "Fingerprint check: PASS");
+
+        // A leaked timezone and screen size, and missing noise, are each caught
+        let mut leaky = reported(&profile, false);
+        leaky["timezone"] = "Europe/Berlin".into();
+        leaky["screenWidth"] = 1366.into();
+        let title = run_test_page(Some(&profile), &leaky.to_string());
+        assert_eq!(title, "Fingerprint check: FAIL (6)");
+
+        let title = run_test_page(None, &reported(&profile, false).to_string());
+        assert_eq!(title, "Fingerprint check: not protected");
+    }
+}
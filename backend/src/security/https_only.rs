@@ -0,0 +1,229 @@
+use crate::engine::{InterceptedRequest, RequestAction, RequestInterceptor, ResourceType};
+use crate::ipc::{IPCEvent, TabId};
+use crate::sites::{host_matches, host_of, is_local_host, normalize_site};
+use crate::storage;
+use anyhow::Result;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::broadcast;
+
+/// Whether HTTPS-Only mode is on, and the sites it leaves alone, as saved
+#[derive(Serialize, Deserialize)]
+struct HttpsOnlySettings {
+    enabled: bool,
+    exceptions: BTreeSet<String>,
+}
+
+/// Builds before the toggle was saved wrote only the exceptions
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedSettings {
+    Settings(HttpsOnlySettings),
+    Exceptions(BTreeSet<String>),
+}
+
+/// Upgrades plain HTTP navigations and subresources to HTTPS
+pub struct HttpsOnlyMode {
+    enabled: AtomicBool,
+    /// Sites the user has allowed to load over plain HTTP
+    exceptions: RwLock<BTreeSet<String>>,
+    path: PathBuf,
+    /// Each tab's upgraded document, as the HTTPS and original URLs
+    upgraded_documents: RwLock<HashMap<TabId, (String, String)>>,
+    event_tx: broadcast::Sender<IPCEvent>,
+}
+
+impl HttpsOnlyMode {
+    pub fn new(path: PathBuf, event_tx: broadcast::Sender<IPCEvent>) -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            exceptions: RwLock::new(BTreeSet::new()),
+            path,
+            upgraded_documents: RwLock::new(HashMap::new()),
+            event_tx,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        match storage::load_json::<SavedSettings>(&self.path).await? {
+            Some(SavedSettings::Settings(settings)) => {
+                self.enabled.store(settings.enabled, Ordering::SeqCst);
+                *self.exceptions.write() = settings.exceptions;
+            }
+            Some(SavedSettings::Exceptions(sites)) => *self.exceptions.write() = sites,
+            None => {}
+        }
+        Ok(())
+    }
+
+    pub async fn set_enabled(&self, enabled: bool) -> Result<()> {
+        self.enabled.store(enabled, Ordering::SeqCst);
+        self.save().await
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Allow or stop allowing a site to load over plain HTTP
+    pub async fn set_exception(&self, site: &str, allowed: bool) -> Result<()> {
+        let site = normalize_site(site)?;
+        {
+            let mut exceptions = self.exceptions.write();
+            if allowed {
+                exceptions.insert(site);
+            } else {
+                exceptions.remove(&site);
+            }
+        }
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<()> {
+        let settings = HttpsOnlySettings {
+            enabled: self.is_enabled(),
+            exceptions: self.exceptions.read().clone(),
+        };
+        storage::save_json(&self.path, &settings).await
+    }
+
+    pub fn exceptions(&self) -> Vec<String> {
+        self.exceptions.read().iter().cloned().collect()
+    }
+
+    /// Get the HTTPS version of a URL, or `None` if it should load unchanged
+    pub fn upgrade(&self, url: &str) -> Option<String> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let mut parsed = url::Url::parse(url).ok()?;
+        if parsed.scheme() != "http" {
+            return None;
+        }
+
+        let host = host_of(url)?;
+        // Local services rarely have certificates and onion services are
+        // already end-to-end encrypted
+        if is_local_host(&host) || host.ends_with(".onion") || host.ends_with(".i2p") {
+            return None;
+        }
+        if self.exceptions.read().iter().any(|site| host_matches(&host, site)) {
+            return None;
+        }
+
+        parsed.set_scheme("https").ok()?;
+        if parsed.port() == Some(80) {
+            parsed.set_port(None).ok()?;
+        }
+        Some(parsed.into())
+    }
+}
+
+impl RequestInterceptor for HttpsOnlyMode {
+    fn intercept(&self, request: &InterceptedRequest) -> RequestAction {
+        let upgraded = self.upgrade(&request.url);
+        if request.resource_type == ResourceType::Document {
+            let mut documents = self.upgraded_documents.write();
+            match &upgraded {
+                Some(url) => documents.insert(request.tab, (url.clone(), request.url.clone())),
+                None => documents.remove(&request.tab),
+            };
+        }
+        match upgraded {
+            Some(url) => RequestAction::Redirect { url },
+            None => RequestAction::Continue,
+        }
+    }
+
+    /// Links and redirects the engine followed get the same interstitial as
+    /// typed navigations when their upgrade fails
+    fn document_failed(&self, request: &InterceptedRequest, error: &str) {
+        let upgraded = {
+            let mut documents = self.upgraded_documents.write();
+            match documents.get(&request.tab) {
+                Some((upgraded_url, _)) if *upgraded_url == request.url => documents.remove(&request.tab),
+                _ => None,
+            }
+        };
+        if let Some((upgraded_url, url)) = upgraded {
+            // Having no subscribers yet is not an error
+            let _ = self.event_tx.send(IPCEvent::HttpsUpgradeFailed {
+                tab: request.tab,
+                url,
+                upgraded_url,
+                error: error.to_string(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(tab: TabId, url: &str) -> InterceptedRequest {
+        InterceptedRequest {
+            tab,
+            url: url.to_string(),
+            first_party_url: None,
+            resource_type: ResourceType::Document,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_upgrade_of_followed_link_is_reported() {
+        let (event_tx, mut events) = broadcast::channel(8);
+        let path = std::env::temp_dir().join(format!("https-only-upgrade-test-{}.json", std::process::id()));
+        let https_only = HttpsOnlyMode::new(path.clone(), event_tx);
+        let enabled = https_only.set_enabled(true).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        enabled.unwrap();
+
+        let action = https_only.intercept(&document(1, "http://example.com:80/a"));
+        assert_eq!(action, RequestAction::Redirect { url: "https://example.com/a".to_string() });
+        assert_eq!(https_only.upgrade("http://localhost/"), None);
+        assert_eq!(https_only.upgrade("http://abc.onion/"), None);
+
+        // Another tab's failure, or a different URL, is not this upgrade's
+        https_only.document_failed(&document(2, "https://example.com/a"), "net::ERR_FAILED");
+        https_only.document_failed(&document(1, "https://example.com/b"), "net::ERR_FAILED");
+        assert!(events.try_recv().is_err());
+
+        https_only.document_failed(&document(1, "https://example.com/a"), "net::ERR_CONNECTION_REFUSED");
+        match events.try_recv() {
+            Ok(IPCEvent::HttpsUpgradeFailed { tab, url, upgraded_url, error }) => {
+                assert_eq!(tab, 1);
+                assert_eq!(url, "http://example.com:80/a");
+                assert_eq!(upgraded_url, "https://example.com/a");
+                assert_eq!(error, "net::ERR_CONNECTION_REFUSED");
+            }
+            other => panic!("unexpected event: {:?}", other.map(|_| ())),
+        }
+        https_only.document_failed(&document(1, "https://example.com/a"), "net::ERR_FAILED");
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn toggle_is_saved_with_the_exceptions() {
+        let path = std::env::temp_dir().join(format!("https-only-test-{}.json", std::process::id()));
+        // Written by builds that saved only the exceptions
+        tokio::fs::write(&path, r#"["example.com"]"#).await.unwrap();
+        let (event_tx, _) = broadcast::channel(8);
+        let https_only = HttpsOnlyMode::new(path.clone(), event_tx.clone());
+        https_only.load().await.unwrap();
+        assert!(!https_only.is_enabled());
+        assert_eq!(https_only.exceptions(), ["example.com"]);
+
+        https_only.set_enabled(true).await.unwrap();
+        let reloaded = HttpsOnlyMode::new(path.clone(), event_tx);
+        let loaded = reloaded.load().await;
+        let _ = tokio::fs::remove_file(&path).await;
+        loaded.unwrap();
+        assert!(reloaded.is_enabled());
+        assert_eq!(reloaded.exceptions(), ["example.com"]);
+    }
+}
@@ -0,0 +1,417 @@
+use crate::network::connector::{BoxedStream, Connector, TargetAddr};
+use crate::network::dns::{decode_query_name, udp::UdpTransport, Resolver, StubResolver};
+use crate::network::proxy::{ConnectorSlot, LocalProxy};
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use parking_lot::Mutex;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::error;
+
+/// How long the page gets to gather ICE candidates and report back
+const REPORT_TIMEOUT: Duration = Duration::from_secs(20);
+/// Time left for STUN packets still in flight after the report
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+const STUN_BINDING_REQUEST: u16 = 0x0001;
+const STUN_BINDING_SUCCESS: u16 = 0x0101;
+const STUN_MAGIC_COOKIE: u32 = 0x2112_a442;
+const STUN_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LeakKind {
+    /// WebRTC sent UDP outside the proxy
+    WebRtcUdp,
+    /// An ICE candidate gave away an IP address of this machine
+    WebRtcAddress,
+    /// The engine resolved a name itself instead of handing it to the route
+    Dns,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Leak {
+    pub kind: LeakKind,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeakReport {
+    /// Whether the engine was launched with leak protection
+    pub protected: bool,
+    pub leaks: Vec<Leak>,
+    /// ICE candidates the page gathered
+    pub candidates: Vec<String>,
+}
+
+/// What the test page sends back
+#[derive(Debug, Deserialize)]
+struct PageReport {
+    #[serde(default)]
+    candidates: Vec<String>,
+}
+
+#[derive(Default)]
+struct Observed {
+    stun_requests: Vec<SocketAddr>,
+    targets: Vec<TargetAddr>,
+    /// Names the route's resolver was asked for
+    dns_queries: Vec<String>,
+}
+
+struct PageState {
+    page: String,
+    report: Mutex<Option<oneshot::Sender<PageReport>>>,
+}
+
+/// A self-test against loopback stand-ins: a STUN server that should never
+/// hear from the engine, a proxy standing in for the route, which should
+/// receive every name unresolved, and a DNS server standing in for the
+/// route's resolver, recording the names the proxy looks up. The engine
+/// under test is launched against `proxy_url` and loads `page_url`.
+pub struct LeakCheck {
+    nonce: String,
+    observed: Arc<Mutex<Observed>>,
+    proxy: LocalProxy,
+    page_port: u16,
+    report: oneshot::Receiver<PageReport>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl LeakCheck {
+    pub async fn start() -> Result<Self> {
+        let nonce = format!("{:016x}", OsRng.next_u64());
+        let observed = Arc::new(Mutex::new(Observed::default()));
+        let loopback = SocketAddr::from(([127, 0, 0, 1], 0));
+
+        let stun = UdpSocket::bind(loopback).await?;
+        let stun_port = stun.local_addr()?.port();
+        let stun_task = tokio::spawn(serve_stun(stun, observed.clone()));
+
+        let dns = UdpSocket::bind(loopback).await?;
+        let dns_addr = dns.local_addr()?;
+        let dns_task = tokio::spawn(serve_dns(dns, observed.clone()));
+
+        let slot: ConnectorSlot = Arc::new(parking_lot::RwLock::new(Arc::new(StandInRoute {
+            observed: observed.clone(),
+            resolver: StubResolver::new(Box::new(UdpTransport::new(dns_addr))),
+        })));
+        let proxy = LocalProxy::start(loopback, slot).await?;
+
+        // The page is on loopback, which the engine never sends through its proxy
+        let (report_tx, report) = oneshot::channel();
+        let state = Arc::new(PageState {
+            page: TEST_PAGE
+                .replace("__NONCE__", &nonce)
+                .replace("__STUN_PORT__", &stun_port.to_string()),
+            report: Mutex::new(Some(report_tx)),
+        });
+        let listener = tokio::net::TcpListener::bind(loopback).await?;
+        let page_port = listener.local_addr()?.port();
+        let app = Router::new()
+            .route("/", get(serve_page))
+            .route("/report", post(receive_report))
+            .with_state(state);
+        let page_task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                error!("Leak check page server stopped: {}", e);
+            }
+        });
+
+        Ok(Self {
+            nonce,
+            observed,
+            proxy,
+            page_port,
+            report,
+            tasks: vec![stun_task, dns_task, page_task],
+        })
+    }
+
+    /// Proxy the engine under test is launched against
+    pub fn proxy_url(&self) -> String {
+        self.proxy.url()
+    }
+
+    pub fn page_url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.page_port)
+    }
+
+    /// Wait for the page to report, then list what reached the stand-ins
+    pub async fn finish(mut self, protected: bool) -> Result<LeakReport> {
+        let report = tokio::time::timeout(REPORT_TIMEOUT, &mut self.report)
+            .await
+            .map_err(|_| anyhow::anyhow!("The leak check page did not report back"))?
+            .map_err(|_| anyhow::anyhow!("The leak check page server stopped"))?;
+        tokio::time::sleep(SETTLE_TIME).await;
+
+        let mut leaks = Vec::new();
+        let observed = self.observed.lock();
+        for source in &observed.stun_requests {
+            leaks.push(Leak {
+                kind: LeakKind::WebRtcUdp,
+                detail: format!("STUN request from {} bypassed the proxy", source),
+            });
+        }
+        for candidate in &report.candidates {
+            if let Some(address) = candidate_address(candidate) {
+                leaks.push(Leak {
+                    kind: LeakKind::WebRtcAddress,
+                    detail: format!("ICE candidate exposes {}", address),
+                });
+            }
+        }
+        for target in &observed.targets {
+            if let TargetAddr::Ip(addr) = target {
+                leaks.push(Leak {
+                    kind: LeakKind::Dns,
+                    detail: format!("The engine connected to {} by address, having resolved the name itself", addr),
+                });
+            }
+        }
+        let probe = |kind: &str| format!("{}-{}.leak-check.invalid", self.nonce, kind);
+        // Requests and preconnects hand their names to the route to resolve
+        for name in [probe("fetch"), probe("preconnect")] {
+            if !observed.dns_queries.contains(&name) {
+                leaks.push(Leak {
+                    kind: LeakKind::Dns,
+                    detail: format!("{} never reached the route's resolver", name),
+                });
+            }
+        }
+        // Leak protection turns DNS prefetching off, so the hint is not looked up anywhere
+        let prefetch = probe("prefetch");
+        if protected && observed.dns_queries.contains(&prefetch) {
+            leaks.push(Leak {
+                kind: LeakKind::Dns,
+                detail: format!("{} was prefetched despite leak protection", prefetch),
+            });
+        }
+
+        Ok(LeakReport {
+            protected,
+            leaks,
+            candidates: report.candidates,
+        })
+    }
+}
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Records every destination the engine hands over, looks names up with the
+/// stand-in resolver as a route would, and connects none of them
+struct StandInRoute {
+    observed: Arc<Mutex<Observed>>,
+    resolver: StubResolver,
+}
+
+#[async_trait]
+impl Connector for StandInRoute {
+    async fn connect(&self, target: &TargetAddr) -> Result<BoxedStream> {
+        self.observed.lock().targets.push(target.clone());
+        if let TargetAddr::Domain(host, _) = target {
+            // The stand-in knows no names, so this only records the lookup
+            let _ = self.resolver.resolve(host).await;
+        }
+        Err(anyhow::anyhow!("The leak check stand-in connects nowhere"))
+    }
+}
+
+async fn serve_page(State(state): State<Arc<PageState>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], state.page.clone())
+}
+
+async fn receive_report(State(state): State<Arc<PageState>>, Json(report): Json<PageReport>) {
+    if let Some(sender) = state.report.lock().take() {
+        let _ = sender.send(report);
+    }
+}
+
+/// Answer binding requests as a STUN server would, recording who sent them
+async fn serve_stun(socket: UdpSocket, observed: Arc<Mutex<Observed>>) {
+    let mut buffer = [0u8; 1500];
+    loop {
+        let Ok((length, source)) = socket.recv_from(&mut buffer).await else {
+            return;
+        };
+        let request = &buffer[..length];
+        if length < 20 || u16::from_be_bytes([request[0], request[1]]) != STUN_BINDING_REQUEST {
+            continue;
+        }
+        observed.lock().stun_requests.push(source);
+        let _ = socket.send_to(&binding_success(&request[4..20], source), source).await;
+    }
+}
+
+/// Record the names asked for, answering each query with NXDOMAIN
+async fn serve_dns(socket: UdpSocket, observed: Arc<Mutex<Observed>>) {
+    let mut buffer = [0u8; 1500];
+    loop {
+        let Ok((length, source)) = socket.recv_from(&mut buffer).await else {
+            return;
+        };
+        let query = &buffer[..length];
+        let Ok(name) = decode_query_name(query) else {
+            continue;
+        };
+        observed.lock().dns_queries.push(name);
+        let mut response = query.to_vec();
+        // QR and RD, then RA and rcode 3
+        response[2] = 0x81;
+        response[3] = 0x83;
+        let _ = socket.send_to(&response, source).await;
+    }
+}
+
+/// A binding success response carrying the source address (RFC 5389)
+fn binding_success(cookie_and_transaction: &[u8], source: SocketAddr) -> Vec<u8> {
+    let port = source.port() ^ (STUN_MAGIC_COOKIE >> 16) as u16;
+    let mut attribute = vec![0u8];
+    match source.ip() {
+        IpAddr::V4(ip) => {
+            attribute.push(0x01);
+            attribute.extend_from_slice(&port.to_be_bytes());
+            attribute.extend_from_slice(&(u32::from(ip) ^ STUN_MAGIC_COOKIE).to_be_bytes());
+        }
+        IpAddr::V6(ip) => {
+            attribute.push(0x02);
+            attribute.extend_from_slice(&port.to_be_bytes());
+            attribute.extend(ip.octets().iter().zip(cookie_and_transaction).map(|(a, b)| a ^ b));
+        }
+    }
+
+    let mut response = Vec::with_capacity(24 + attribute.len());
+    response.extend_from_slice(&STUN_BINDING_SUCCESS.to_be_bytes());
+    response.extend_from_slice(&(4 + attribute.len() as u16).to_be_bytes());
+    response.extend_from_slice(cookie_and_transaction);
+    response.extend_from_slice(&STUN_XOR_MAPPED_ADDRESS.to_be_bytes());
+    response.extend_from_slice(&(attribute.len() as u16).to_be_bytes());
+    response.extend_from_slice(&attribute);
+    response
+}
+
+/// The IP address in an ICE candidate line; mDNS `.local` names hide it
+fn candidate_address(candidate: &str) -> Option<IpAddr> {
+    candidate.split_whitespace().nth(4)?.parse().ok()
+}
+
+const TEST_PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>Leak check</title>
+<link rel="dns-prefetch" href="//__NONCE__-prefetch.leak-check.invalid">
+<link rel="preconnect" href="http://__NONCE__-preconnect.leak-check.invalid">
+</head><body><h1>Leak check</h1><p id="status">Running…</p>
+<script>
+(async () => {
+  const candidates = [];
+  try {
+    const connection = new RTCPeerConnection({ iceServers: [{ urls: 'stun:127.0.0.1:__STUN_PORT__' }] });
+    connection.createDataChannel('leak-check');
+    connection.onicecandidate = (event) => {
+      if (event.candidate && event.candidate.candidate) candidates.push(event.candidate.candidate);
+    };
+    await connection.setLocalDescription(await connection.createOffer());
+    await new Promise((resolve) => {
+      connection.onicegatheringstatechange = () => {
+        if (connection.iceGatheringState === 'complete') resolve();
+      };
+      setTimeout(resolve, 5000);
+    });
+    connection.close();
+  } catch (e) {
+    // WebRTC disabled entirely leaks nothing
+  }
+  await fetch('http://__NONCE__-fetch.leak-check.invalid/', { mode: 'no-cors' }).catch(() => {});
+  await fetch('/report', {
+    method: 'POST',
+    headers: { 'content-type': 'application/json' },
+    body: JSON.stringify({ candidates }),
+  });
+  document.getElementById('status').textContent = 'Done';
+  document.title = 'Leak check: done';
+})();
+</script></body></html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The source address a binding success response carries, undone from
+    /// its XOR with the magic cookie and transaction ID
+    fn mapped_address(response: &[u8]) -> SocketAddr {
+        assert_eq!(u16::from_be_bytes([response[0], response[1]]), STUN_BINDING_SUCCESS);
+        assert_eq!(u16::from_be_bytes([response[2], response[3]]) as usize, response.len() - 20);
+        assert_eq!(u16::from_be_bytes([response[20], response[21]]), STUN_XOR_MAPPED_ADDRESS);
+        let value = &response[24..];
+        let port = u16::from_be_bytes([value[2], value[3]]) ^ (STUN_MAGIC_COOKIE >> 16) as u16;
+        let ip = match value[1] {
+            0x01 => {
+                let ip = u32::from_be_bytes(value[4..8].try_into().unwrap()) ^ STUN_MAGIC_COOKIE;
+                IpAddr::from(std::net::Ipv4Addr::from(ip))
+            }
+            0x02 => {
+                let octets: Vec<u8> = value[4..20].iter().zip(&response[4..20]).map(|(a, b)| a ^ b).collect();
+                IpAddr::from(<[u8; 16]>::try_from(octets).unwrap())
+            }
+            family => panic!("unexpected address family {}", family),
+        };
+        SocketAddr::new(ip, port)
+    }
+
+    #[test]
+    fn binding_success_maps_the_source_address() {
+        let mut cookie_and_transaction = STUN_MAGIC_COOKIE.to_be_bytes().to_vec();
+        cookie_and_transaction.extend_from_slice(&[7; 12]);
+
+        let v4: SocketAddr = "192.0.2.7:50000".parse().unwrap();
+        let response = binding_success(&cookie_and_transaction, v4);
+        assert_eq!(response.len(), 32);
+        assert_eq!(&response[4..20], &cookie_and_transaction[..]);
+        assert_eq!(mapped_address(&response), v4);
+
+        let v6: SocketAddr = "[2001:db8::7]:50000".parse().unwrap();
+        let response = binding_success(&cookie_and_transaction, v6);
+        assert_eq!(response.len(), 44);
+        assert_eq!(mapped_address(&response), v6);
+    }
+
+    #[test]
+    fn candidate_addresses_exclude_mdns_names() {
+        let host = "candidate:1 1 udp 2122260223 192.168.1.20 54321 typ host generation 0";
+        assert_eq!(candidate_address(host), Some(IpAddr::from([192, 168, 1, 20])));
+        let srflx = "candidate:2 1 udp 1686052607 2001:db8::1 61000 typ srflx raddr 0.0.0.0 rport 0";
+        assert_eq!(candidate_address(srflx), "2001:db8::1".parse().ok());
+        let mdns = "candidate:3 1 udp 2122260223 0b3c9e2a-1d2f-4a6b-9c1e-7f0e2d3c4b5a.local 54321 typ host";
+        assert_eq!(candidate_address(mdns), None);
+        assert_eq!(candidate_address("candidate:4 1 udp"), None);
+    }
+
+    #[tokio::test]
+    async fn dns_stand_in_records_lookups() {
+        let observed = Arc::new(Mutex::new(Observed::default()));
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = StubResolver::new(Box::new(UdpTransport::new(socket.local_addr().unwrap())));
+        let task = tokio::spawn(serve_dns(socket, observed.clone()));
+
+        assert!(resolver.resolve("Probe.Leak-Check.invalid").await.is_err());
+        task.abort();
+        // One query for A and one for AAAA records
+        assert_eq!(observed.lock().dns_queries, ["probe.leak-check.invalid"; 2]);
+    }
+}
@@ -1,3 +1,7 @@
+pub mod fingerprint;
+pub mod https_only;
+pub mod leak_check;
+
 use crate::ipc::PrivacyMode;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -43,7 +47,7 @@ impl SecurityManager for DefaultSecurityManager {
                 self.clear_data().await?;
             }
             PrivacyMode::Vpn => {
-                // Route through VPN
+                // Traffic goes through the WireGuard route selected with this mode
             }
         }
         
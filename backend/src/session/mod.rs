@@ -0,0 +1,241 @@
+use crate::containers::ContainerManager;
+use crate::engine::{EngineManager, ProtocolHandlers, Tab, TabHistory, TabOptions};
+use crate::ipc::{BrowserEngine, ContainerId, NetworkRoute, PrivacyMode, ProxyServer, RouteHop, TabId};
+use crate::sites::host_of;
+use crate::storage;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SESSION_VERSION: u32 = 1;
+
+/// A tab as recorded in the session journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedTab {
+    pub url: String,
+    pub title: Option<String>,
+    pub engine: BrowserEngine,
+    pub privacy_mode: PrivacyMode,
+    pub network_route: NetworkRoute,
+    pub pinned: bool,
+    pub history: Vec<String>,
+    pub history_index: usize,
+    #[serde(default)]
+    pub container: Option<ContainerId>,
+}
+
+/// The tab set written to disk so it can be offered for restore on startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSnapshot {
+    pub version: u32,
+    pub saved_at: u64,
+    pub active_index: Option<usize>,
+    pub tabs: Vec<SavedTab>,
+}
+
+/// Private and Tor tabs must never touch the disk, nor may tabs routed
+/// through Tor or I2P or that have visited onion or I2P sites
+pub fn is_persistable(mode: &PrivacyMode, route: &NetworkRoute, urls: &[String]) -> bool {
+    if matches!(mode, PrivacyMode::Private | PrivacyMode::Tor) {
+        return false;
+    }
+    if matches!(route, NetworkRoute::I2p { .. }) || route.hops().contains(&RouteHop::Tor) {
+        return false;
+    }
+    !urls
+        .iter()
+        .filter_map(|url| host_of(url))
+        .any(|host| host.ends_with(".onion") || host.ends_with(".i2p"))
+}
+
+/// The route with its proxy passwords left out, which the journal must not
+/// hold in plain text. A restored tab's proxy asks for them again.
+fn without_passwords(route: &NetworkRoute) -> NetworkRoute {
+    let strip = |server: &ProxyServer| ProxyServer {
+        password: None,
+        ..server.clone()
+    };
+    match route {
+        NetworkRoute::Proxy { server } => NetworkRoute::Proxy { server: strip(server) },
+        NetworkRoute::Chain { hops } => NetworkRoute::Chain {
+            hops: hops
+                .iter()
+                .map(|hop| match hop {
+                    RouteHop::Proxy { server } => RouteHop::Proxy { server: strip(server) },
+                    hop => hop.clone(),
+                })
+                .collect(),
+        },
+        route => route.clone(),
+    }
+}
+
+impl SessionSnapshot {
+    /// Snapshot the tabs, saving handled URLs like `ipfs://` rather than the
+    /// gateway URLs their engines load
+    pub fn capture(tabs: &[Tab], active_tab: Option<TabId>, protocols: &ProtocolHandlers) -> Self {
+        let persisted: Vec<&Tab> = tabs
+            .iter()
+            .filter(|tab| {
+                let mut urls = tab.history.entries.clone();
+                urls.push(protocols.display_url(tab.engine.current_url()));
+                is_persistable(&tab.privacy_mode, &tab.network_route, &urls)
+            })
+            .collect();
+
+        let active_index = persisted
+            .iter()
+            .position(|tab| Some(tab.id) == active_tab);
+
+        let tabs = persisted
+            .into_iter()
+            .map(|tab| SavedTab {
+                url: protocols.display_url(tab.engine.current_url()),
+                title: tab.engine.title(),
+                engine: tab.engine.engine_type(),
+                privacy_mode: tab.privacy_mode.clone(),
+                network_route: without_passwords(&tab.network_route),
+                pinned: tab.pinned,
+                history: tab.history.entries.clone(),
+                history_index: tab.history.index,
+                container: tab.container,
+            })
+            .collect();
+
+        Self {
+            version: SESSION_VERSION,
+            saved_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            active_index,
+            tabs,
+        }
+    }
+
+    /// Reopen the saved tabs in the active window, returning their new IDs.
+    /// Tabs of containers deleted since are reopened outside any container.
+    pub async fn restore_into(&self, manager: &EngineManager, containers: &ContainerManager) -> Result<Vec<TabId>> {
+        let mut restored = Vec::with_capacity(self.tabs.len());
+
+        for saved in &self.tabs {
+            // Guard against journals written by older builds
+            let mut urls = saved.history.clone();
+            urls.push(saved.url.clone());
+            if !is_persistable(&saved.privacy_mode, &saved.network_route, &urls) {
+                continue;
+            }
+
+            let mut history = TabHistory::new(saved.history.clone(), saved.history_index);
+            if history.entries.is_empty() {
+                history.push(saved.url.clone());
+            }
+
+            let options = TabOptions {
+                engine: saved.engine.clone(),
+                privacy_mode: saved.privacy_mode.clone(),
+                network_route: saved.network_route.clone(),
+                pinned: saved.pinned,
+                history,
+                container: saved.container.filter(|container| containers.check(*container).is_ok()),
+                window: manager.active_window(),
+            };
+            restored.push(manager.open_tab(options, None).await?);
+        }
+
+        if let Some(id) = self.active_index.and_then(|index| restored.get(index)) {
+            manager.activate_tab(*id)?;
+        }
+
+        Ok(restored)
+    }
+}
+
+/// Journals the tab set to a JSON file in the data directory
+pub struct SessionStore {
+    path: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub async fn load(&self) -> Result<Option<SessionSnapshot>> {
+        let snapshot: Option<SessionSnapshot> = storage::load_json(&self.path).await?;
+        Ok(snapshot.filter(|s| s.version == SESSION_VERSION && !s.tabs.is_empty()))
+    }
+
+    pub async fn save(&self, snapshot: &SessionSnapshot) -> Result<()> {
+        storage::save_json(&self.path, snapshot).await
+    }
+
+    pub async fn clear(&self) -> Result<()> {
+        storage::remove(&self.path).await
+    }
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new(storage::data_dir().join("session.json"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anonymous_routes_and_sites_are_not_persisted() {
+        let urls = vec!["https://example.com/".to_string()];
+        let direct = NetworkRoute::default();
+        assert!(is_persistable(&PrivacyMode::Normal, &direct, &urls));
+        assert!(!is_persistable(&PrivacyMode::Private, &direct, &urls));
+        assert!(!is_persistable(&PrivacyMode::Tor, &direct, &urls));
+
+        assert!(!is_persistable(&PrivacyMode::Normal, &NetworkRoute::Tor, &urls));
+        let proxy = RouteHop::Proxy {
+            server: ProxyServer {
+                protocol: crate::ipc::ProxyProtocol::Socks5,
+                address: "127.0.0.1:1080".to_string(),
+                username: None,
+                password: None,
+            },
+        };
+        let chain = NetworkRoute::Chain { hops: vec![proxy.clone(), RouteHop::Tor] };
+        assert!(!is_persistable(&PrivacyMode::Normal, &chain, &urls));
+        let chain = NetworkRoute::Chain { hops: vec![proxy] };
+        assert!(is_persistable(&PrivacyMode::Normal, &chain, &urls));
+        let i2p = NetworkRoute::I2p { sam: crate::ipc::default_sam_address() };
+        assert!(!is_persistable(&PrivacyMode::Normal, &i2p, &urls));
+
+        let visited = vec!["http://abcdef.onion/".to_string(), "https://example.com/".to_string()];
+        assert!(!is_persistable(&PrivacyMode::Normal, &direct, &visited));
+        let visited = vec!["http://stats.i2p/".to_string()];
+        assert!(!is_persistable(&PrivacyMode::Normal, &direct, &visited));
+    }
+
+    #[test]
+    fn proxy_passwords_are_not_persisted() {
+        let server = ProxyServer {
+            protocol: crate::ipc::ProxyProtocol::Socks5,
+            address: "127.0.0.1:1080".to_string(),
+            username: Some("user".to_string()),
+            password: Some("secret".to_string()),
+        };
+        let saved = without_passwords(&NetworkRoute::Proxy { server: server.clone() });
+        let NetworkRoute::Proxy { server: saved } = saved else {
+            panic!("route changed kind");
+        };
+        assert_eq!(saved.username.as_deref(), Some("user"));
+        assert_eq!(saved.password, None);
+
+        let chain = NetworkRoute::Chain {
+            hops: vec![RouteHop::Vpn { config: "wg0.conf".into() }, RouteHop::Proxy { server }],
+        };
+        let json = serde_json::to_string(&without_passwords(&chain)).unwrap();
+        assert!(!json.contains("secret"));
+        assert!(json.contains("wg0.conf"));
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::Result;
+
+/// Get the lowercased host of a URL
+pub fn host_of(url: &str) -> Option<String> {
+    url::Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(|host| host.trim_end_matches('.').to_ascii_lowercase())
+}
+
+/// Turn a URL or bare host into the site key used by per-site settings
+pub fn normalize_site(site: &str) -> Result<String> {
+    let host = if site.contains("://") {
+        host_of(site).ok_or_else(|| anyhow::anyhow!("Invalid site: {}", site))?
+    } else {
+        site.trim().trim_end_matches('.').to_ascii_lowercase()
+    };
+    if host.is_empty() {
+        return Err(anyhow::anyhow!("Invalid site: {}", site));
+    }
+    Ok(host.strip_prefix("www.").map(String::from).unwrap_or(host))
+}
+
+/// Check whether `host` is `domain` or one of its subdomains
+pub fn host_matches(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.len() > domain.len()
+            && host.ends_with(domain)
+            && host.as_bytes()[host.len() - domain.len() - 1] == b'.')
+}
+
+/// Approximate the registrable domain without a public suffix list: the last
+/// two labels, or three when the second-level label is a common registry
+/// suffix such as `co.uk`
+pub fn base_domain(host: &str) -> &str {
+    let labels: Vec<&str> = host.rsplitn(4, '.').collect();
+    if labels.len() < 2 || host.parse::<std::net::IpAddr>().is_ok() {
+        return host;
+    }
+
+    let keep = if labels.len() >= 3
+        && labels[0].len() == 2
+        && matches!(labels[1], "co" | "com" | "net" | "org" | "gov" | "ac" | "edu")
+    {
+        3
+    } else {
+        2
+    };
+    let suffix_len: usize = labels[..keep].iter().map(|l| l.len()).sum::<usize>() + keep - 1;
+    &host[host.len() - suffix_len..]
+}
+
+/// Hosts that never leave the machine or the local network
+pub fn is_local_host(host: &str) -> bool {
+    if host == "localhost" || host.ends_with(".localhost") || host.ends_with(".local") {
+        return true;
+    }
+    match host.trim_start_matches('[').trim_end_matches(']').parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(ip)) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        Ok(std::net::IpAddr::V6(ip)) => ip.is_loopback() || (ip.segments()[0] & 0xfe00) == 0xfc00,
+        Err(_) => false,
+    }
+}
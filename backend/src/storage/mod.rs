@@ -0,0 +1,55 @@
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Get the directory used for persistent browser data.
+///
+/// Honours `SOLACE_DATA_DIR` so tests and portable installs can relocate it.
+pub fn data_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("SOLACE_DATA_DIR") {
+        return PathBuf::from(dir);
+    }
+
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("solace")
+}
+
+/// Load a JSON document, returning `None` if the file does not exist
+pub async fn load_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => {
+            let value = serde_json::from_slice(&bytes)
+                .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))?;
+            Ok(Some(value))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow::anyhow!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Save a JSON document atomically by writing a temporary file and renaming it
+pub async fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let bytes = serde_json::to_vec_pretty(value)?;
+    let tmp_path = path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, bytes)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", tmp_path.display(), e))?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to replace {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+/// Remove a file, ignoring it if it does not exist
+pub async fn remove(path: &Path) -> Result<()> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(anyhow::anyhow!("Failed to remove {}: {}", path.display(), e)),
+    }
+}
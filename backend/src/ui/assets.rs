@@ -0,0 +1,141 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/ui_assets.rs"));
+}
+
+/// Directives every page gets; inline scripts are allowed by hash only
+const CSP: &str = "default-src 'none'; script-src 'self'{scripts}; style-src 'self' 'unsafe-inline'; \
+img-src 'self' data:; font-src 'self'; connect-src 'self'; manifest-src 'self'; \
+base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
+
+/// A file of the UI's static export
+pub struct Asset {
+    pub body: Bytes,
+    pub content_type: String,
+    /// Quoted SHA-256 of the body
+    pub etag: String,
+    /// Content Security Policy of an HTML page, covering its inline scripts
+    pub csp: Option<String>,
+}
+
+/// The frontend's static export, served by the UI router
+pub struct UiAssets {
+    files: HashMap<String, Asset>,
+}
+
+impl UiAssets {
+    /// Read the export from `SOLACE_UI_DIR` when set, else use the one
+    /// embedded at build time
+    pub async fn load() -> Result<Self> {
+        let assets = match std::env::var("SOLACE_UI_DIR") {
+            Ok(dir) => Self::from_dir(Path::new(&dir)).await?,
+            Err(_) => Self::from_files(
+                embedded::EMBEDDED
+                    .iter()
+                    .map(|(name, body)| (name.to_string(), Bytes::from_static(body))),
+            ),
+        };
+        info!("Serving {} UI files", assets.files.len());
+        Ok(assets)
+    }
+
+    pub async fn from_dir(root: &Path) -> Result<Self> {
+        let mut files = Vec::new();
+        let mut dirs = vec![PathBuf::from(root)];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = tokio::fs::read_dir(&dir)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to read UI directory {}: {}", dir.display(), e))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let name = path
+                    .strip_prefix(root)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                let body = tokio::fs::read(&path)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+                files.push((name, Bytes::from(body)));
+            }
+        }
+        Ok(Self::from_files(files))
+    }
+
+    pub fn from_files(files: impl IntoIterator<Item = (String, Bytes)>) -> Self {
+        let files = files
+            .into_iter()
+            .map(|(name, body)| {
+                let content_type = mime_guess::from_path(&name).first_or_octet_stream();
+                let csp = (content_type == mime_guess::mime::TEXT_HTML).then(|| page_csp(&body));
+                let asset = Asset {
+                    content_type: content_type.to_string(),
+                    etag: format!("\"{:x}\"", Sha256::digest(&body)),
+                    csp,
+                    body,
+                };
+                (name, asset)
+            })
+            .collect();
+        Self { files }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The file for a request path, trying `index.html` for directories and
+    /// `.html` for pages exported without a trailing slash
+    pub fn get(&self, path: &str) -> Option<&Asset> {
+        let path = path.trim_start_matches('/');
+        if path.split('/').any(|segment| segment == "..") {
+            return None;
+        }
+        if path.is_empty() || path.ends_with('/') {
+            return self.files.get(&format!("{}index.html", path));
+        }
+        self.files
+            .get(path)
+            .or_else(|| self.files.get(&format!("{}.html", path)))
+            .or_else(|| self.files.get(&format!("{}/index.html", path)))
+    }
+}
+
+/// The policy for a page, with a hash for each of its inline scripts
+fn page_csp(html: &[u8]) -> String {
+    let html = String::from_utf8_lossy(html);
+    let mut scripts = String::new();
+    let mut rest = html.as_ref();
+    while let Some(start) = rest.find("<script") {
+        rest = &rest[start..];
+        let Some(tag_end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..tag_end];
+        let body = &rest[tag_end + 1..];
+        let Some(body_end) = body.find("</script") else {
+            break;
+        };
+        if !tag.contains(" src=") {
+            let hash = STANDARD.encode(Sha256::digest(&body.as_bytes()[..body_end]));
+            let source = format!(" 'sha256-{}'", hash);
+            if !scripts.contains(&source) {
+                scripts.push_str(&source);
+            }
+        }
+        rest = &body[body_end..];
+    }
+    CSP.replace("{scripts}", &scripts)
+}
@@ -1,24 +1,110 @@
+mod assets;
+mod window;
+
+pub use assets::UiAssets;
+pub use window::{NativeWindows, WindowHandler, WindowHost, WindowRequest};
+
 use crate::ipc::{IPCCommand, IPCHandler};
 use axum::{
-    extract::State,
-    response::sse::{Event, Sse},
+    body::Body,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
     Json, Router,
 };
 use std::sync::Arc;
-use tower_http::cors::{Any, CorsLayer};
 
-pub fn router<H: IPCHandler + 'static>(handler: Arc<H>) -> Router {
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods(Any)
-        .allow_headers(Any);
+/// Next.js puts content hashes in the names of these files
+const IMMUTABLE_PREFIX: &str = "/_next/static/";
 
-    Router::new()
+/// The API, answering only pages of the UI's `origins` (e.g.
+/// `http://127.0.0.1:3001`), and the UI's static export for every other path
+pub fn router<H: IPCHandler + 'static>(handler: Arc<H>, assets: Arc<UiAssets>, origins: Vec<String>) -> Router {
+    let api = Router::new()
         .route("/api/command", post(handle_command::<H>))
         .route("/api/subscribe", get(subscribe::<H>))
-        .layer(cors)
-        .with_state(handler)
+        .layer(middleware::from_fn_with_state(Arc::new(origins), ui_only))
+        .with_state(handler);
+    let ui = Router::new().fallback(serve_asset).with_state(assets);
+
+    api.merge(ui).layer(middleware::map_response(security_headers))
+}
+
+/// Turn away requests from any page but the UI's, including those rendered
+/// in the engines, which reach loopback around their proxy
+async fn ui_only(State(origins): State<Arc<Vec<String>>>, request: Request, next: Next) -> Response {
+    if !from_ui(request.headers(), &origins) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether a request is addressed to the UI's host, against DNS rebinding,
+/// and comes from one of its pages. Browsers send `Origin` with every
+/// cross-origin request, so one without it is the UI's own.
+fn from_ui(headers: &HeaderMap, origins: &[String]) -> bool {
+    let value = |name| headers.get(name).map(|value| value.to_str().unwrap_or_default());
+    let host = value(header::HOST).unwrap_or_default();
+    let host_allowed = origins
+        .iter()
+        .any(|origin| origin.split_once("://").is_some_and(|(_, authority)| authority == host));
+    host_allowed && value(header::ORIGIN).is_none_or(|origin| origins.iter().any(|allowed| allowed == origin))
+}
+
+async fn security_headers(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+    response
+}
+
+async fn serve_asset(State(assets): State<Arc<UiAssets>>, method: Method, uri: Uri, headers: HeaderMap) -> Response {
+    if method != Method::GET && method != Method::HEAD {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    let (status, asset) = match assets.get(uri.path()) {
+        Some(asset) => (StatusCode::OK, asset),
+        None => match assets.get("/404.html") {
+            Some(asset) => (StatusCode::NOT_FOUND, asset),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        },
+    };
+
+    let cache_control = match uri.path().starts_with(IMMUTABLE_PREFIX) {
+        true => "public, max-age=31536000, immutable",
+        false => "no-cache",
+    };
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, &asset.content_type)
+        .header(header::CACHE_CONTROL, cache_control)
+        .header(header::ETAG, &asset.etag);
+    if let Some(csp) = &asset.csp {
+        response = response.header(header::CONTENT_SECURITY_POLICY, csp);
+    }
+
+    let unchanged = status == StatusCode::OK
+        && headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == asset.etag));
+    let (status, body) = match (unchanged, method) {
+        (true, _) => (StatusCode::NOT_MODIFIED, Body::empty()),
+        (false, Method::HEAD) => {
+            response = response.header(header::CONTENT_LENGTH, asset.body.len());
+            (status, Body::empty())
+        }
+        (false, _) => (status, Body::from(asset.body.clone())),
+    };
+    response
+        .status(status)
+        .body(body)
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
 }
 
 async fn handle_command<H: IPCHandler>(
@@ -50,4 +136,34 @@ async fn subscribe<H: IPCHandler>(
     };
     
     Sse::new(stream)
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn api_answers_only_the_ui() {
+        let origins = vec![String::from("http://127.0.0.1:3001"), String::from("http://localhost:3000")];
+        let allowed = |pairs: &[(header::HeaderName, &str)]| from_ui(&headers(pairs), &origins);
+
+        assert!(allowed(&[(header::HOST, "127.0.0.1:3001")]));
+        assert!(allowed(&[(header::HOST, "127.0.0.1:3001"), (header::ORIGIN, "http://127.0.0.1:3001")]));
+        assert!(allowed(&[(header::HOST, "localhost:3000"), (header::ORIGIN, "http://localhost:3000")]));
+
+        // Another page, one loaded in an engine included
+        assert!(!allowed(&[(header::HOST, "127.0.0.1:3001"), (header::ORIGIN, "https://example.com")]));
+        assert!(!allowed(&[(header::HOST, "127.0.0.1:3001"), (header::ORIGIN, "null")]));
+        assert!(!allowed(&[(header::HOST, "127.0.0.1:3001"), (header::ORIGIN, "http://127.0.0.1:8080")]));
+        // A rebound name, or none
+        assert!(!allowed(&[(header::HOST, "attacker.example:3001")]));
+        assert!(!allowed(&[]));
+    }
+}
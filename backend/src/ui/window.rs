@@ -0,0 +1,168 @@
+use crate::ipc::WindowId;
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tao::dpi::LogicalSize;
+use tao::event::{Event, WindowEvent};
+use tao::event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy, EventLoopWindowTarget};
+use tao::window::{Window, WindowBuilder};
+use tokio::runtime::Handle;
+use tracing::error;
+use wry::{WebView, WebViewBuilder};
+
+/// What the backend asks of the event loop
+#[derive(Debug)]
+pub enum WindowRequest {
+    Open(WindowId),
+    /// Close a window whose tabs are already closed, or quit if it is the last
+    Close(WindowId),
+    Focus(WindowId),
+}
+
+/// Called as the user focuses and closes windows
+#[async_trait]
+pub trait WindowHandler: Send + Sync {
+    async fn window_focused(&self, window: WindowId);
+
+    /// A window other than the last was closed; its tabs are still open
+    async fn window_closed(&self, window: WindowId);
+
+    /// The last window was closed; the process exits once this returns
+    async fn shutdown(&self);
+}
+
+/// Sends requests to the event loop from any thread
+pub struct NativeWindows {
+    proxy: Mutex<EventLoopProxy<WindowRequest>>,
+}
+
+impl NativeWindows {
+    pub fn request(&self, request: WindowRequest) -> Result<()> {
+        self.proxy
+            .lock()
+            .send_event(request)
+            .map_err(|e| anyhow::anyhow!("Window event loop has stopped: {}", e))
+    }
+}
+
+/// Native windows, each a webview showing the UI for one browser window.
+/// Owns the event loop, so it must be created and run on the main thread.
+pub struct WindowHost {
+    event_loop: EventLoop<WindowRequest>,
+}
+
+impl WindowHost {
+    pub fn new() -> (Self, NativeWindows) {
+        let event_loop = EventLoopBuilder::<WindowRequest>::with_user_event().build();
+        let windows = NativeWindows {
+            proxy: Mutex::new(event_loop.create_proxy()),
+        };
+        (Self { event_loop }, windows)
+    }
+
+    /// Run the event loop until the last window closes. Handler calls are
+    /// spawned on `runtime`, except `shutdown`, which the loop waits for.
+    pub fn run(self, ui_url: String, handler: Arc<dyn WindowHandler>, runtime: Handle) -> ! {
+        let mut windows: HashMap<tao::window::WindowId, (WindowId, Window, WebView)> = HashMap::new();
+
+        self.event_loop.run(move |event, target, control_flow| {
+            *control_flow = ControlFlow::Wait;
+
+            let closed = match event {
+                Event::UserEvent(WindowRequest::Open(id)) => {
+                    match open_window(target, &ui_url, id) {
+                        Ok((window, webview)) => {
+                            windows.insert(window.id(), (id, window, webview));
+                        }
+                        Err(e) => error!("Failed to open window {}: {}", id, e),
+                    }
+                    None
+                }
+                Event::UserEvent(WindowRequest::Close(id)) => {
+                    windows.retain(|_, (window, ..)| *window != id);
+                    Some((id, false))
+                }
+                Event::UserEvent(WindowRequest::Focus(id)) => {
+                    if let Some((_, window, _)) = windows.values().find(|(window, ..)| *window == id) {
+                        window.set_focus();
+                    }
+                    None
+                }
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::CloseRequested,
+                    ..
+                } => windows.remove(&window_id).map(|(id, ..)| (id, true)),
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::Focused(true),
+                    ..
+                } => {
+                    if let Some((id, ..)) = windows.get(&window_id) {
+                        let (handler, id) = (handler.clone(), *id);
+                        runtime.spawn(async move { handler.window_focused(id).await });
+                    }
+                    None
+                }
+                _ => None,
+            };
+
+            let Some((id, by_user)) = closed else {
+                return;
+            };
+            if windows.is_empty() {
+                runtime.block_on(handler.shutdown());
+                *control_flow = ControlFlow::Exit;
+            } else if by_user {
+                let handler = handler.clone();
+                runtime.spawn(async move { handler.window_closed(id).await });
+            }
+        })
+    }
+}
+
+/// A window whose webview loads the UI for browser window `id`, and may not
+/// navigate anywhere else
+fn open_window(target: &EventLoopWindowTarget<WindowRequest>, ui_url: &str, id: WindowId) -> Result<(Window, WebView)> {
+    let window = WindowBuilder::new()
+        .with_title("Solace")
+        .with_inner_size(LogicalSize::new(1280.0, 800.0))
+        .build(target)
+        .map_err(|e| anyhow::anyhow!("Failed to create window: {}", e))?;
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    )))]
+    let builder = WebViewBuilder::new(&window);
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    let builder = {
+        use tao::platform::unix::WindowExtUnix;
+        use wry::WebViewBuilderExtUnix;
+        let vbox = window
+            .default_vbox()
+            .ok_or_else(|| anyhow::anyhow!("Window has no GTK container"))?;
+        WebViewBuilder::new_gtk(vbox)
+    };
+
+    let origin = format!("{}/", ui_url);
+    let webview = builder
+        .with_url(&format!("{}?window={}", origin, id))
+        .map_err(|e| anyhow::anyhow!("Invalid UI URL: {}", e))?
+        .with_navigation_handler(move |url| url.starts_with(&origin))
+        .with_new_window_req_handler(|_| false)
+        .build()
+        .map_err(|e| anyhow::anyhow!("Failed to create webview: {}", e))?;
+    Ok((window, webview))
+}
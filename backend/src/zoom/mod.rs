@@ -0,0 +1,78 @@
+use crate::engine::ZoomLevels;
+use crate::sites::normalize_site;
+use crate::storage;
+use anyhow::Result;
+use parking_lot::RwLock;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Zoom range engines support, as in Chromium
+const MIN_ZOOM: f64 = 0.25;
+const MAX_ZOOM: f64 = 5.0;
+
+/// Zoom levels the user picked, per site. Sites at the default size have
+/// no entry.
+pub struct ZoomManager {
+    levels: RwLock<BTreeMap<String, f64>>,
+    path: PathBuf,
+}
+
+impl ZoomManager {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            levels: RwLock::new(BTreeMap::new()),
+            path,
+        }
+    }
+
+    pub async fn load(&self) -> Result<()> {
+        if let Some(levels) = storage::load_json::<BTreeMap<String, f64>>(&self.path).await? {
+            *self.levels.write() = levels;
+        }
+        Ok(())
+    }
+
+    pub fn levels(&self) -> BTreeMap<String, f64> {
+        self.levels.read().clone()
+    }
+
+    /// Remember a site's zoom level; 1.0 forgets it
+    pub async fn set(&self, site: &str, level: f64) -> Result<()> {
+        let site = normalize_site(site)?;
+        check_level(level)?;
+        {
+            let mut levels = self.levels.write();
+            if level == 1.0 {
+                levels.remove(&site);
+            } else {
+                levels.insert(site, level);
+            }
+        }
+        storage::save_json(&self.path, &self.levels()).await
+    }
+}
+
+impl ZoomLevels for ZoomManager {
+    fn zoom_for(&self, url: &str) -> f64 {
+        if !url.contains("://") {
+            return 1.0;
+        }
+        let Ok(site) = normalize_site(url) else {
+            return 1.0;
+        };
+        self.levels.read().get(&site).copied().unwrap_or(1.0)
+    }
+}
+
+/// Fail unless engines can zoom to `level`
+pub fn check_level(level: f64) -> Result<()> {
+    match (MIN_ZOOM..=MAX_ZOOM).contains(&level) {
+        true => Ok(()),
+        false => Err(anyhow::anyhow!(
+            "Zoom level must be between {} and {}: {}",
+            MIN_ZOOM,
+            MAX_ZOOM,
+            level
+        )),
+    }
+}
@@ -1,7 +1,8 @@
 import { unstable_cache } from "next/cache";
 import { BrowserState, IPCCommand, IPCEvent, IPCResponse } from "@/types/ipc";
 
-const API_BASE = "http://localhost:3001/api";
+// Same origin as the UI, whether the backend or the dev server serves it
+const API_BASE = process.env.NEXT_PUBLIC_API_BASE ?? "/api";
 
 export async function sendCommand<T = void>(command: IPCCommand): Promise<IPCResponse<T>> {
 	const response = await fetch(`${API_BASE}/command`, {
@@ -1,4 +1,14 @@
 /** @type {import('next').NextConfig} */
-const nextConfig = {};
+const nextConfig = {
+	// Static export into `out/`, which the backend serves or embeds
+	output: "export",
+};
+
+// The dev server passes the API through, so the UI stays same-origin with it
+if (process.env.NODE_ENV === "development") {
+	nextConfig.rewrites = async () => [
+		{ source: "/api/:path*", destination: "http://127.0.0.1:3001/api/:path*" },
+	];
+}
 
 export default nextConfig;
@@ -4,8 +4,194 @@ export type BrowserEngine = "blink" | "gecko" | "webkit" | "servo";
 // Privacy Mode Types
 export type PrivacyMode = "normal" | "private" | "tor" | "vpn";
 
+// DNS Types
+export interface DotUpstream {
+	address: string;
+	serverName: string;
+	spkiPins?: string[];
+}
+
+export type DnsMode = "system" | { doh: { url: string; bootstrap?: string[] } } | { dot: { upstreams: DotUpstream[]; privacy?: "strict" | "opportunistic"; fallback?: "none" | "system" } };
+
+export interface ResolverStats {
+	resolver: string;
+	queries: number;
+	cacheHits: number;
+	failures: number;
+	fallbacks: number;
+	averageLatencyMs: number;
+}
+
 // Network Route Types
-export type NetworkRoute = "direct" | "tor" | "vpn" | "proxy";
+export interface ProxyServer {
+	protocol: "socks5" | "http";
+	address: string;
+	username?: string;
+	password?: string;
+}
+
+export type RouteHop = { proxy: { server: ProxyServer } } | { vpn: { config: string } } | "tor";
+
+export type NetworkRoute = { direct: { dns?: DnsMode } } | "tor" | { vpn: { config: string } } | { proxy: { server: ProxyServer } } | { chain: { hops: RouteHop[] } } | { pac: { url: string } } | "system" | { i2p: { sam?: string } };
+
+// Sends destinations matching `pattern` (a domain, `*.suffix`, `.tld` or CIDR) over `route`
+export interface RoutingRule {
+	pattern: string;
+	route: NetworkRoute;
+}
+
+// IPFS Types
+export type IpfsBackend = { api: { url: string } } | { gateway: { url: string } };
+
+// Blockchain Name Types
+export type HandshakeServer = { doh: { url: string; bootstrap?: string[] } } | { dns: { address: string } };
+
+export interface NameSettings {
+	ensRpc?: string;
+	handshake?: HandshakeServer;
+}
+
+export interface NameRecords {
+	name: string;
+	content?: string;
+	address?: string;
+	addresses: string[];
+}
+
+// Gemini Types
+export type FingerprintProtection = "Standard" | "Strict";
+
+export interface FingerprintProfile {
+	protection: FingerprintProtection;
+	acceptLanguage?: string;
+	locale?: string;
+	timezone?: string;
+	screen: [number, number];
+	hardwareConcurrency: number;
+	deviceMemory: number;
+}
+
+export interface CookieInfo {
+	name: string;
+	value: string;
+	domain: string;
+	path: string;
+	expires?: number;
+	httpOnly: boolean;
+	secure: boolean;
+	partition?: string;
+}
+
+export type ThirdPartyCookies = "Allow" | "Partition" | "Block";
+
+export interface CookieSettings {
+	thirdParty: ThirdPartyCookies;
+	blocked: string[];
+	clearOnClose: boolean;
+	keep: string[];
+}
+
+export type Permission = "Camera" | "Microphone" | "Geolocation" | "Notifications" | "Clipboard" | "Midi";
+
+export type PermissionDecision = "Allow" | "Deny" | "Ask";
+
+export interface SitePermission {
+	origin: string;
+	permission: Permission;
+	decision: PermissionDecision;
+	expires?: number;
+	private: boolean;
+}
+
+export type LeakKind = "WebRtcUdp" | "WebRtcAddress" | "Dns";
+
+export interface Leak {
+	kind: LeakKind;
+	detail: string;
+}
+
+export interface LeakReport {
+	protected: boolean;
+	leaks: Leak[];
+	candidates: string[];
+}
+
+export interface KnownGeminiHost {
+	host: string;
+	fingerprint: string;
+	firstSeen: number;
+}
+
+// Capture Types
+export type ImageFormat = "Png" | "Jpeg" | "Webp";
+
+// Area of a page in CSS pixels, measured from the top of the document
+export interface Clip {
+	x: number;
+	y: number;
+	width: number;
+	height: number;
+}
+
+// Sizes and margins are in inches
+export interface PdfOptions {
+	landscape?: boolean;
+	printBackground?: boolean;
+	scale?: number;
+	paperWidth?: number;
+	paperHeight?: number;
+	marginTop?: number;
+	marginBottom?: number;
+	marginLeft?: number;
+	marginRight?: number;
+	pageRanges?: string;
+	preferCssPageSize?: boolean;
+}
+
+// Device Emulation Types
+export interface DeviceEmulation {
+	width: number;
+	height: number;
+	deviceScaleFactor: number;
+	mobile: boolean;
+	touch: boolean;
+}
+
+export type DevicePreset = "Phone" | "Tablet";
+
+// Tab Types
+export type TabId = number;
+
+export type WindowId = number;
+
+export interface WindowInfo {
+	id: WindowId;
+	activeTab?: TabId;
+}
+
+export type ContainerId = number;
+
+export interface Container {
+	id: ContainerId;
+	name: string;
+	color: string;
+}
+
+export interface TabInfo {
+	id: TabId;
+	url: string;
+	title?: string;
+	engine: BrowserEngine;
+	privacyMode: PrivacyMode;
+	networkRoute: NetworkRoute;
+	networkPath: string;
+	pinned: boolean;
+	httpsUpgraded: boolean;
+	contentOrigin?: string;
+	container?: ContainerId;
+	window: WindowId;
+	emulation?: DeviceEmulation;
+}
 
 // Browser State Interface
 export interface BrowserState {
@@ -14,6 +200,7 @@ export interface BrowserState {
 	currentEngine: BrowserEngine;
 	privacyMode: PrivacyMode;
 	networkRoute: NetworkRoute;
+	networkPath: string;
 	title?: string;
 	favicon?: string;
 	isSecure: boolean;
@@ -22,13 +209,23 @@ export interface BrowserState {
 		validFrom: string;
 		validTo: string;
 	};
+	tabs: TabInfo[];
+	activeTab?: TabId;
+	sessionRestoreAvailable: boolean;
+	shieldsEnabled: boolean;
+	httpsOnly: boolean;
+	httpsUpgraded: boolean;
+	contentOrigin?: string;
+	containers: Container[];
+	windows: WindowInfo[];
+	activeWindow: WindowId;
 }
 
 // IPC Commands
-export type IPCCommand = { type: "NAVIGATE"; payload: { url: string } } | { type: "SWITCH_ENGINE"; payload: { engine: BrowserEngine } } | { type: "SET_PRIVACY_MODE"; payload: { mode: PrivacyMode } } | { type: "SET_NETWORK_ROUTE"; payload: { route: NetworkRoute } } | { type: "RELOAD" } | { type: "STOP" } | { type: "GO_BACK" } | { type: "GO_FORWARD" } | { type: "GET_STATE" };
+export type IPCCommand = { type: "NAVIGATE"; payload: { url: string } } | { type: "SWITCH_ENGINE"; payload: { engine: BrowserEngine } } | { type: "SET_PRIVACY_MODE"; payload: { mode: PrivacyMode } } | { type: "SET_NETWORK_ROUTE"; payload: { tab: TabId; route: NetworkRoute } } | { type: "RELOAD" } | { type: "STOP" } | { type: "GO_BACK" } | { type: "GO_FORWARD" } | { type: "GET_STATE" } | { type: "NEW_TAB"; payload: { url?: string; engine?: BrowserEngine; privacyMode?: PrivacyMode; container?: ContainerId; window?: WindowId } } | { type: "CLOSE_TAB"; payload: { tab: TabId } } | { type: "NEW_WINDOW"; payload: { url?: string } } | { type: "CLOSE_WINDOW"; payload: { window: WindowId } } | { type: "ACTIVATE_WINDOW"; payload: { window: WindowId } } | { type: "ACTIVATE_TAB"; payload: { tab: TabId } } | { type: "PIN_TAB"; payload: { tab: TabId; pinned: boolean } } | { type: "RESTORE_SESSION" } | { type: "DISCARD_SESSION" } | { type: "SET_SHIELDS_ENABLED"; payload: { enabled: boolean } } | { type: "SET_SITE_SHIELDS"; payload: { site: string; enabled: boolean } } | { type: "RELOAD_FILTER_LISTS" } | { type: "SET_HTTPS_ONLY"; payload: { enabled: boolean } } | { type: "SET_HTTPS_EXCEPTION"; payload: { site: string; allowed: boolean } } | { type: "GET_RESOLVER_STATS" } | { type: "SET_ROUTING_RULES"; payload: { rules: RoutingRule[] } } | { type: "GET_ROUTING_RULES" } | { type: "SET_IPFS_BACKEND"; payload: { backend: IpfsBackend } } | { type: "GET_IPFS_BACKEND" } | { type: "SET_NAME_SETTINGS"; payload: { settings: NameSettings } } | { type: "GET_NAME_SETTINGS" } | { type: "RESOLVE_NAME"; payload: { name: string } } | { type: "CHECK_FINGERPRINT" } | { type: "LEAK_CHECK" } | { type: "GET_GEMINI_HOSTS" } | { type: "FORGET_GEMINI_HOST"; payload: { host: string } } | { type: "RESPOND_PERMISSION"; payload: { id: number; allow: boolean; remember: boolean; expiresIn?: number } } | { type: "GET_PERMISSIONS" } | { type: "SET_PERMISSION"; payload: { origin: string; permission: Permission; decision: PermissionDecision; expiresIn?: number } } | { type: "RESET_PERMISSIONS"; payload: { origin?: string; permission?: Permission } } | { type: "GET_COOKIES"; payload: { site?: string } } | { type: "SEARCH_COOKIES"; payload: { query: string } } | { type: "DELETE_COOKIES"; payload: { site: string; name?: string } } | { type: "SET_COOKIE_BLOCKED"; payload: { site: string; blocked: boolean } } | { type: "GET_COOKIE_SETTINGS" } | { type: "SET_COOKIE_SETTINGS"; payload: { settings: CookieSettings } } | { type: "GET_CONTAINERS" } | { type: "CREATE_CONTAINER"; payload: { name: string; color: string } } | { type: "UPDATE_CONTAINER"; payload: { container: Container } } | { type: "REMOVE_CONTAINER"; payload: { id: ContainerId } } | { type: "CAPTURE_SCREENSHOT"; payload: { tab?: TabId; format?: ImageFormat; quality?: number; fullPage?: boolean; clip?: Clip; path?: string } } | { type: "PRINT_TO_PDF"; payload: { tab?: TabId; options?: PdfOptions; path?: string } } | { type: "FIND_IN_PAGE"; payload: { tab?: TabId; text: string; caseSensitive?: boolean; forward?: boolean } } | { type: "FIND_NEXT"; payload: { tab?: TabId } } | { type: "STOP_FINDING"; payload: { tab?: TabId } } | { type: "SET_ZOOM"; payload: { tab?: TabId; level: number } } | { type: "GET_ZOOM_LEVELS" } | { type: "EMULATE_DEVICE"; payload: { tab?: TabId; emulation: DeviceEmulation } } | { type: "EMULATE_DEVICE_PRESET"; payload: { tab?: TabId; preset: DevicePreset } } | { type: "STOP_EMULATION"; payload: { tab?: TabId } };
 
 // IPC Events
-export type IPCEvent = { type: "STATE_UPDATE"; payload: BrowserState } | { type: "ERROR"; payload: { code: string; message: string } } | { type: "DOWNLOAD_PROGRESS"; payload: { id: string; progress: number } } | { type: "CERTIFICATE_ERROR"; payload: { url: string; error: string } };
+export type IPCEvent = { type: "STATE_UPDATE"; payload: BrowserState } | { type: "ERROR"; payload: { code: string; message: string } } | { type: "DOWNLOAD_PROGRESS"; payload: { id: string; progress: number } } | { type: "CERTIFICATE_ERROR"; payload: { url: string; error: string } } | { type: "SESSION_RESTORE_AVAILABLE"; payload: { tabs: number; savedAt: number } } | { type: "BLOCKED_REQUEST"; payload: { tab: TabId; url: string; firstParty?: string; filter: string } } | { type: "HTTPS_UPGRADE_FAILED"; payload: { tab: TabId; url: string; upgradedUrl: string; error: string } } | { type: "ROUTE_DOWN"; payload: { tab: TabId; route: NetworkRoute; reason: string } } | { type: "ROUTE_UP"; payload: { tab: TabId; route: NetworkRoute } } | { type: "PERMISSION_REQUEST"; payload: { id: number; tab: TabId; origin: string; permission: Permission } } | { type: "FIND_RESULT"; payload: { tab: TabId; activeMatch: number; totalMatches: number } };
 
 // Response Types
 export interface IPCResponse<T = void> {